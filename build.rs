@@ -0,0 +1,43 @@
+//! Finds the system libraries of the deflate backends enabled with cargo features through
+//! pkg-config, so that a build without them fails up front, naming what to install, rather than
+//! with a linker error
+
+use std::process::Command;
+
+/// The cargo feature of each backend, as cargo passes it to build scripts, along with its
+/// pkg-config package
+const BACKENDS: [(&str, &str); 2] = [("ZLIB_NG", "zlib-ng"), ("LIBDEFLATE", "libdeflate")];
+
+fn main() {
+    println!("cargo:rerun-if-changed=build.rs");
+    println!("cargo:rerun-if-env-changed=PKG_CONFIG_PATH");
+    for (feature, package) in BACKENDS {
+        if std::env::var_os(format!("CARGO_FEATURE_{}", feature)).is_some() {
+            link(package);
+        }
+    }
+}
+
+/// Links the library of the pkg-config package, or fails the build
+fn link(package: &str) {
+    let output = Command::new("pkg-config")
+        .args(["--libs", package])
+        .output();
+    let libs = match output {
+        Ok(output) if output.status.success() => {
+            String::from_utf8_lossy(&output.stdout).into_owned()
+        }
+        _ => panic!(
+            "the {0} feature needs {0} and its pkg-config file: install its development package \
+             (and pkg-config), or build without the feature",
+            package
+        ),
+    };
+    for flag in libs.split_whitespace() {
+        if let Some(directory) = flag.strip_prefix("-L") {
+            println!("cargo:rustc-link-search=native={}", directory);
+        } else if let Some(library) = flag.strip_prefix("-l") {
+            println!("cargo:rustc-link-lib={}", library);
+        }
+    }
+}
@@ -0,0 +1,74 @@
+//! Adapting the compression level to where a run is held up, like zstd --adapt. Inputs are
+//! compressed in blocks, and each block is compressed at the level of the moment: when the blocks
+//! keep waiting for the CPU, the level goes down to get through the data faster, and when they
+//! keep waiting for the disk (or network), it goes up to make use of the idle CPU for a smaller
+//! output at no cost in throughput.
+
+use std::sync::Mutex;
+use std::time::Duration;
+
+/// The size in KiB of the blocks inputs are compressed in when adapting without a block size
+pub const BLOCK_SIZE: usize = 256;
+
+/// The number of blocks in a row that must wait on the same side before the level moves, so that
+/// a single slow read or busy moment does not
+const STEADY_BLOCKS: i32 = 4;
+
+/// How many times longer a block must wait on one side than on the other to count as held up by
+/// it
+const BOUND_FACTOR: u32 = 2;
+
+#[derive(Debug)]
+struct State {
+    level: u32,
+    /// The number of blocks in a row held up by I/O (positive) or by the CPU (negative)
+    streak: i32,
+}
+
+/// The compression level shared by the blocks of every file of a run, since they compete for
+/// the same CPU and disks
+#[derive(Debug)]
+pub struct AdaptiveLevel {
+    min: u32,
+    max: u32,
+    state: Mutex<State>,
+}
+
+impl AdaptiveLevel {
+    /// Starts at the level, moving between `min` and `max`
+    pub fn new(level: u32, min: u32, max: u32) -> AdaptiveLevel {
+        AdaptiveLevel {
+            min,
+            max,
+            state: Mutex::new(State {
+                level: level.clamp(min, max),
+                streak: 0,
+            }),
+        }
+    }
+
+    /// The level to compress the next block at
+    pub fn level(&self) -> u32 {
+        self.state.lock().unwrap().level
+    }
+
+    /// Records how long a block waited for its data to be read and written, and for a thread to
+    /// compress it, moving the level once enough blocks in a row were held up by the same side
+    pub fn observe(&self, io_wait: Duration, cpu_wait: Duration) {
+        let mut state = self.state.lock().unwrap();
+        state.streak = if cpu_wait > io_wait * BOUND_FACTOR {
+            state.streak.min(0) - 1
+        } else if io_wait > cpu_wait * BOUND_FACTOR {
+            state.streak.max(0) + 1
+        } else {
+            0
+        };
+        if state.streak <= -STEADY_BLOCKS {
+            state.level = state.level.saturating_sub(1).max(self.min);
+            state.streak = 0;
+        } else if state.streak >= STEADY_BLOCKS {
+            state.level = (state.level + 1).min(self.max);
+            state.streak = 0;
+        }
+    }
+}
@@ -0,0 +1,672 @@
+//! Packing directory trees into gzip-compressed tar archives or ZIP archives, and unpacking them
+//! again
+
+use crate::batch::{move_into_place, temp_path};
+use crate::codec::transcode;
+use crate::error::{file_error, Operation, SuperGzipError};
+use crate::settings::{CollisionPolicy, Settings};
+use crate::tar::{self, EntryKind};
+use crate::zip;
+use std::future::Future;
+use std::path::{Component, Path, PathBuf};
+use std::sync::Arc;
+use tokio::io::{
+    AsyncRead, AsyncWrite, AsyncWriteExt, BufWriter as TokioBufWriter, DuplexStream,
+    Error as TokioIOError,
+};
+
+/// The size of the in-memory pipe between the tar writer and the compressor
+const PIPE_SIZE: usize = 1024 * 1024;
+/// The block size used to compress archives in parallel when none is given, in KiB (like pigz)
+pub const DEFAULT_BLOCK_SIZE: usize = 128;
+/// The largest block size that can be given, in KiB (1 GiB)
+pub const MAX_BLOCK_SIZE: usize = 1024 * 1024;
+
+/// What went into an archive
+#[derive(Debug, Default)]
+pub struct ArchiveSummary {
+    pub entries: usize,
+    /// The total size of the archived files
+    pub input_size: u64,
+    /// The size of the compressed archive
+    pub output_size: u64,
+}
+
+/// Resolves a path to an absolute one without requiring the file itself to exist yet
+async fn absolute(path: &Path) -> std::io::Result<PathBuf> {
+    match (path.parent(), path.file_name()) {
+        (Some(parent), Some(name)) if !parent.as_os_str().is_empty() => {
+            Ok(tokio::fs::canonicalize(parent).await?.join(name))
+        }
+        _ => Ok(std::env::current_dir()?.join(path)),
+    }
+}
+
+/// Streams a compressed tar archive of `root` into the writer. The tar stream is produced on its
+/// own task and compressed as it is written, in parallel blocks when using several threads, so
+/// the archive is never held in memory. The paths in `exclude` are left out of the archive.
+pub async fn write_archive<W>(
+    root: &Path,
+    writer: &mut W,
+    exclude: Vec<PathBuf>,
+    settings: &Arc<Settings>,
+) -> Result<ArchiveSummary, SuperGzipError>
+where
+    W: AsyncWrite + Unpin,
+{
+    let root = tokio::fs::canonicalize(root)
+        .await
+        .map_err(file_error(root, Operation::Read))?;
+    let tar_settings = Arc::clone(settings);
+    let (mut summary, compressed) = compress_tar(writer, settings, |mut tar_writer| async move {
+        let mut summary = ArchiveSummary::default();
+        tar::write_tree(&root, &mut tar_writer, &exclude, |entry| {
+            summary.entries += 1;
+            summary.input_size += entry.size;
+            if tar_settings.verbose() {
+                tar_settings.report(&format!("Adding {}", entry.name));
+            }
+        })
+        .await?;
+        tar_writer.shutdown().await?;
+        Ok(summary)
+    })
+    .await?;
+    summary.output_size = compressed;
+    Ok(summary)
+}
+
+/// Compresses the tar stream that `write_tar` writes on its own task into the writer, returning
+/// what `write_tar` returned along with the size of the compressed stream
+pub(crate) async fn compress_tar<W, F, T>(
+    writer: &mut W,
+    settings: &Arc<Settings>,
+    write_tar: impl FnOnce(DuplexStream) -> F,
+) -> Result<(T, u64), SuperGzipError>
+where
+    W: AsyncWrite + Unpin,
+    F: Future<Output = Result<T, TokioIOError>> + Send + 'static,
+    T: Send + 'static,
+{
+    let (tar_writer, tar_reader) = tokio::io::duplex(PIPE_SIZE);
+    let tar_task = tokio::spawn(write_tar(tar_writer));
+
+    // A failed tar stream ends early, so its error takes precedence over the compressor's, while
+    // the tar stream only fails with a broken pipe when writing the compressed data failed
+    let compressed = transcode(tar_reader, writer, None, settings).await;
+    match (tar_task.await?, compressed) {
+        (Err(e), Err(compress_error)) if e.kind() == std::io::ErrorKind::BrokenPipe => {
+            Err(compress_error)
+        }
+        (Err(e), _) => Err(e.into()),
+        (Ok(_), Err(e)) => Err(e),
+        (Ok(written), Ok(compressed)) => Ok((written, compressed)),
+    }
+}
+
+/// Fails if the output archive already exists, unless forced to overwrite it
+pub(crate) async fn refuse_existing(
+    output: &Path,
+    settings: &Settings,
+) -> Result<(), SuperGzipError> {
+    if settings.collision != CollisionPolicy::Force
+        && tokio::fs::symlink_metadata(output).await.is_ok()
+    {
+        return Err(SuperGzipError::IO(TokioIOError::new(
+            std::io::ErrorKind::AlreadyExists,
+            format!("{} already exists", output.to_string_lossy()),
+        )));
+    }
+    Ok(())
+}
+
+/// Writes a compressed tar archive of `root` to the output file, through a temporary file that
+/// is only renamed into place once the archive is complete
+pub async fn archive_to_file(
+    root: &Path,
+    output: &Path,
+    settings: &Arc<Settings>,
+) -> Result<ArchiveSummary, SuperGzipError> {
+    refuse_existing(output, settings).await?;
+
+    // Keep the archive (and its temporary file) out of itself when it is inside the tree
+    let temp_path = temp_path(output);
+    let exclude = vec![absolute(output).await?, absolute(&temp_path).await?];
+    let file = tokio::fs::File::create(&temp_path).await?;
+    let mut writer = TokioBufWriter::with_capacity(settings.write_buffer, file);
+    let result = match write_archive(root, &mut writer, exclude, settings).await {
+        Ok(summary) => writer
+            .shutdown()
+            .await
+            .map(|()| summary)
+            .map_err(Into::into),
+        Err(e) => Err(e),
+    };
+    match result {
+        Ok(summary) => {
+            move_into_place(&temp_path, output).await?;
+            Ok(summary)
+        }
+        Err(e) => {
+            let _ = tokio::fs::remove_file(&temp_path).await;
+            Err(e)
+        }
+    }
+}
+
+/// The default archive name for a directory, e.g. `logs.tar.gz` for `/var/logs`
+pub fn default_archive_name(root: &Path) -> Option<PathBuf> {
+    let name = root.file_name()?;
+    let mut archive_name = name.to_owned();
+    archive_name.push(".tar.gz");
+    Some(PathBuf::from(archive_name))
+}
+
+/// What came out of an archive
+#[derive(Debug, Default)]
+pub struct ExtractSummary {
+    pub entries: usize,
+    /// The entries that were not extracted because they were unsafe, unsupported, or existed
+    pub skipped: usize,
+    /// The total size of the extracted files
+    pub output_size: u64,
+}
+
+/// Resolves an archive entry name to a path inside the destination. Leading `/` are removed like
+/// tar does, and names containing `..` are refused so that entries cannot escape the destination.
+fn safe_path(dest: &Path, name: &str) -> Option<PathBuf> {
+    let mut path = dest.to_path_buf();
+    let mut has_name = false;
+    for component in Path::new(name).components() {
+        match component {
+            Component::Normal(part) => {
+                path.push(part);
+                has_name = true;
+            }
+            Component::CurDir | Component::RootDir | Component::Prefix(_) => {}
+            Component::ParentDir => return None,
+        }
+    }
+    has_name.then_some(path)
+}
+
+/// Whether any directory between the destination and the path is a symlink, through which an
+/// entry could be written outside of the destination
+fn through_symlink(dest: &Path, path: &Path) -> bool {
+    let Ok(relative) = path.strip_prefix(dest) else {
+        return true;
+    };
+    let mut current = dest.to_path_buf();
+    let components: Vec<_> = relative.components().collect();
+    for component in &components[..components.len().saturating_sub(1)] {
+        current.push(component);
+        if std::fs::symlink_metadata(&current)
+            .is_ok_and(|metadata| metadata.file_type().is_symlink())
+        {
+            return true;
+        }
+    }
+    false
+}
+
+/// Sets the permissions and modification time of an extracted file from its entry
+fn restore_metadata(path: &Path, mode: Option<u32>, mtime: u64) -> std::io::Result<()> {
+    let file = std::fs::File::options().write(true).open(path)?;
+    file.set_modified(std::time::UNIX_EPOCH + std::time::Duration::from_secs(mtime))?;
+    #[cfg(unix)]
+    if let Some(mode) = mode {
+        use std::os::unix::fs::PermissionsExt;
+        file.set_permissions(std::fs::Permissions::from_mode(mode & 0o7777))?;
+    }
+    #[cfg(not(unix))]
+    let _ = mode;
+    Ok(())
+}
+
+/// Extracts a single entry, returning why it was skipped if it was not extracted
+async fn extract_entry(
+    reader: &mut tar::Reader<DuplexStream>,
+    entry: &tar::Entry,
+    dest: &Path,
+    settings: &Settings,
+) -> Result<Option<&'static str>, SuperGzipError> {
+    let Some(path) = safe_path(dest, &entry.name) else {
+        return Ok(Some("its path leads outside of the output directory"));
+    };
+    if through_symlink(dest, &path) {
+        return Ok(Some("its path goes through a symlink"));
+    }
+    let write_error = || file_error(&path, Operation::Write);
+    if let Some(parent) = path.parent() {
+        tokio::fs::create_dir_all(parent)
+            .await
+            .map_err(file_error(parent, Operation::Write))?;
+    }
+
+    // Existing directories are reused, anything else is only replaced when forced to
+    let existing = tokio::fs::symlink_metadata(&path).await.ok();
+    if entry.kind == EntryKind::Directory {
+        match existing {
+            Some(metadata) if metadata.is_dir() => return Ok(None),
+            Some(_) => return Ok(Some("a file with its name already exists")),
+            None => {
+                tokio::fs::create_dir(&path).await.map_err(write_error())?;
+                return Ok(None);
+            }
+        }
+    }
+    match existing {
+        Some(_) if settings.collision != CollisionPolicy::Force => {
+            return Ok(Some("it already exists"))
+        }
+        Some(metadata) if metadata.is_dir() => return Ok(Some("a directory with its name exists")),
+        Some(_) => tokio::fs::remove_file(&path).await.map_err(write_error())?,
+        None => {}
+    }
+
+    match &entry.kind {
+        EntryKind::File => {
+            // Leave no truncated file behind when the archive ends early or the disk is full
+            let file = tokio::fs::File::create(&path)
+                .await
+                .map_err(write_error())?;
+            let mut writer = TokioBufWriter::with_capacity(settings.write_buffer, file);
+            let written = match reader.copy_contents(&mut writer).await {
+                Ok(_) => writer.shutdown().await,
+                Err(e) => Err(e),
+            };
+            drop(writer);
+            if let Err(e) = written {
+                let _ = tokio::fs::remove_file(&path).await;
+                return Err(e.into());
+            }
+            if settings.preserve_metadata {
+                let (path, mode, mtime) = (path.clone(), entry.mode, entry.mtime);
+                tokio::task::spawn_blocking(move || restore_metadata(&path, Some(mode), mtime))
+                    .await??;
+            }
+        }
+        #[cfg(unix)]
+        EntryKind::Symlink(target) => tokio::fs::symlink(target, &path)
+            .await
+            .map_err(write_error())?,
+        EntryKind::HardLink(target) => {
+            let Some(target) = safe_path(dest, &target.to_string_lossy()) else {
+                return Ok(Some(
+                    "its link target leads outside of the output directory",
+                ));
+            };
+            // A link to a file reached through a symlink, or to a symlink, could pull a file
+            // from outside of the output directory into it
+            let target_is_symlink = tokio::fs::symlink_metadata(&target)
+                .await
+                .is_ok_and(|metadata| metadata.file_type().is_symlink());
+            if through_symlink(dest, &target) || target_is_symlink {
+                return Ok(Some("its link target goes through a symlink"));
+            }
+            tokio::fs::hard_link(target, &path)
+                .await
+                .map_err(write_error())?;
+        }
+        _ => return Ok(Some("its type of entry is not supported")),
+    }
+    Ok(None)
+}
+
+/// Unpacks every entry of the tar stream into the destination
+async fn extract_entries(
+    mut reader: tar::Reader<DuplexStream>,
+    dest: &Path,
+    settings: &Settings,
+    summary: &mut ExtractSummary,
+) -> Result<(), SuperGzipError> {
+    while let Some(entry) = reader.next_entry().await? {
+        match extract_entry(&mut reader, &entry, dest, settings).await? {
+            None => {
+                if settings.verbose() {
+                    settings.report(&format!("Extracting {}", entry.name));
+                }
+                summary.entries += 1;
+                summary.output_size += entry.size;
+            }
+            Some(reason) => {
+                settings.report(&format!(
+                    "Warning: skipping {} because {}",
+                    entry.name, reason
+                ));
+                summary.skipped += 1;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Unpacks a compressed tar archive into the destination directory. The archive is decompressed
+/// on its own task and unpacked as it streams in. Entries are never written outside of the
+/// destination: names containing `..` and paths through symlinks are skipped with a warning, as
+/// are existing files unless forced to replace them.
+pub async fn extract_archive<R>(
+    reader: R,
+    dest: &Path,
+    settings: &Arc<Settings>,
+) -> Result<ExtractSummary, SuperGzipError>
+where
+    R: AsyncRead + Unpin + Send + 'static,
+{
+    tokio::fs::create_dir_all(dest)
+        .await
+        .map_err(file_error(dest, Operation::Write))?;
+    let (mut tar_writer, tar_reader) = tokio::io::duplex(PIPE_SIZE);
+    let decompress_settings = Arc::clone(settings);
+    let decompress_task = tokio::spawn(async move {
+        transcode(reader, &mut tar_writer, None, &decompress_settings).await?;
+        tar_writer.shutdown().await?;
+        Ok::<_, SuperGzipError>(())
+    });
+
+    let mut summary = ExtractSummary::default();
+    let extracted =
+        extract_entries(tar::Reader::new(tar_reader), dest, settings, &mut summary).await;
+
+    // A corrupt archive cuts the tar stream short, so its error explains any error unpacking it,
+    // while the decompressor only fails with a broken pipe when unpacking stopped early
+    match (decompress_task.await?, extracted) {
+        (Err(SuperGzipError::IO(e)), Err(extract_error))
+            if e.kind() == std::io::ErrorKind::BrokenPipe =>
+        {
+            Err(extract_error)
+        }
+        (Err(e), _) | (Ok(()), Err(e)) => Err(e),
+        (Ok(()), Ok(())) => Ok(summary),
+    }
+}
+
+/// Writes a ZIP archive of the given directory trees (or files) to the output file, deflating
+/// each file at the configured level. Like [`archive_to_file`], the archive is written to a
+/// temporary file that is only renamed into place once it is complete.
+pub async fn zip_to_file(
+    roots: &[PathBuf],
+    output: &Path,
+    settings: &Arc<Settings>,
+) -> Result<ArchiveSummary, SuperGzipError> {
+    refuse_existing(output, settings).await?;
+
+    let temp_path = temp_path(output);
+    let exclude = vec![absolute(output).await?, absolute(&temp_path).await?];
+    let mut absolute_roots = Vec::with_capacity(roots.len());
+    for root in roots {
+        let absolute = tokio::fs::canonicalize(root).await;
+        absolute_roots.push(absolute.map_err(file_error(root, Operation::Read))?);
+    }
+    let zip_settings = Arc::clone(settings);
+    let zip_path = temp_path.clone();
+    let result = settings
+        .cpu_pool()
+        .spawn(move || {
+            let mut entries = Vec::new();
+            for root in &absolute_roots {
+                zip::collect_tree(root, &exclude, &mut entries)?;
+            }
+            zip::write_zip(&entries, &zip_path, zip_settings.level, |name| {
+                if zip_settings.verbose() {
+                    zip_settings.report(&format!("Adding {}", name));
+                }
+            })
+        })
+        .await
+        .await?;
+    match result {
+        Ok(summary) => {
+            move_into_place(&temp_path, output).await?;
+            Ok(ArchiveSummary {
+                entries: summary.entries,
+                input_size: summary.size,
+                output_size: tokio::fs::metadata(output).await?.len(),
+            })
+        }
+        Err(e) => {
+            let _ = tokio::fs::remove_file(&temp_path).await;
+            Err(e.into())
+        }
+    }
+}
+
+/// Extracts a single ZIP entry, returning why it was skipped if it was not extracted
+fn extract_zip_entry(
+    file: &mut std::fs::File,
+    entry: &zip::Entry,
+    dest: &Path,
+    settings: &Settings,
+) -> std::io::Result<Option<&'static str>> {
+    let Some(path) = safe_path(dest, &entry.name) else {
+        return Ok(Some("its path leads outside of the output directory"));
+    };
+    if through_symlink(dest, &path) {
+        return Ok(Some("its path goes through a symlink"));
+    }
+    if !entry.is_supported {
+        return Ok(Some("its type of entry is not supported"));
+    }
+    let write_error = || file_error(&path, Operation::Write);
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent).map_err(file_error(parent, Operation::Write))?;
+    }
+
+    // Existing directories are reused, anything else is only replaced when forced to
+    let existing = std::fs::symlink_metadata(&path).ok();
+    if entry.is_dir {
+        return match existing {
+            Some(metadata) if metadata.is_dir() => Ok(None),
+            Some(_) => Ok(Some("a file with its name already exists")),
+            None => std::fs::create_dir(&path)
+                .map(|()| None)
+                .map_err(write_error()),
+        };
+    }
+    match existing {
+        Some(_) if settings.collision != CollisionPolicy::Force => {
+            return Ok(Some("it already exists"))
+        }
+        Some(metadata) if metadata.is_dir() => return Ok(Some("a directory with its name exists")),
+        Some(_) => std::fs::remove_file(&path).map_err(write_error())?,
+        None => {}
+    }
+
+    // Leave no corrupt file behind when the entry fails its checksum
+    let output = std::fs::File::create(&path).map_err(write_error())?;
+    let mut writer = std::io::BufWriter::new(output);
+    let written = zip::extract_entry(file, entry, &mut writer)
+        .and_then(|_| std::io::Write::flush(&mut writer));
+    drop(writer);
+    if let Err(e) = written {
+        let _ = std::fs::remove_file(&path);
+        return Err(e);
+    }
+    if settings.preserve_metadata {
+        restore_metadata(&path, entry.mode, entry.mtime)?;
+    }
+    Ok(None)
+}
+
+/// Unpacks a ZIP archive into the destination directory, with the same protections as
+/// [`extract_archive`]. Stored and deflated entries are supported, and each is checked against
+/// its CRC32 checksum.
+pub async fn extract_zip(
+    archive: &Path,
+    dest: &Path,
+    settings: &Arc<Settings>,
+) -> Result<ExtractSummary, SuperGzipError> {
+    let (archive, dest, settings) = (
+        archive.to_path_buf(),
+        dest.to_path_buf(),
+        Arc::clone(settings),
+    );
+    let pool = settings.cpu_pool().clone();
+    let summary = pool
+        .spawn(move || {
+            std::fs::create_dir_all(&dest).map_err(file_error(&dest, Operation::Write))?;
+            let mut file = std::fs::File::open(&archive)?;
+            let mut summary = ExtractSummary::default();
+            for entry in zip::read_entries(&mut file)? {
+                match extract_zip_entry(&mut file, &entry, &dest, &settings)? {
+                    None => {
+                        if settings.verbose() {
+                            settings.report(&format!("Extracting {}", entry.name));
+                        }
+                        summary.entries += 1;
+                        summary.output_size += entry.size;
+                    }
+                    Some(reason) => {
+                        settings.report(&format!(
+                            "Warning: skipping {} because {}",
+                            entry.name, reason
+                        ));
+                        summary.skipped += 1;
+                    }
+                }
+            }
+            Ok::<_, TokioIOError>(summary)
+        })
+        .await
+        .await??;
+    Ok(summary)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::codec::Codec;
+    use std::io::Write;
+
+    /// A gzip-compressed tar archive of the entries, each file holding its own name
+    fn archive(entries: &[(&str, EntryKind)]) -> Vec<u8> {
+        let mut tar = Vec::new();
+        for (name, kind) in entries {
+            let size = match kind {
+                EntryKind::File => name.len() as u64,
+                _ => 0,
+            };
+            tar.extend(tar::entry_header(&tar::Entry {
+                name: name.to_string(),
+                kind: kind.clone(),
+                size,
+                mode: 0o644,
+                mtime: 0,
+            }));
+            if size > 0 {
+                tar.extend(name.as_bytes());
+                tar.resize(tar.len() + tar::padding(size), 0);
+            }
+        }
+        tar.extend([0; 2 * tar::BLOCK_SIZE]);
+        let mut encoder = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+        encoder.write_all(&tar).unwrap();
+        encoder.finish().unwrap()
+    }
+
+    async fn extract(entries: &[(&str, EntryKind)], dest: &Path) -> ExtractSummary {
+        let settings = Arc::new(Settings::new(Codec::Gzip, false));
+        let reader = std::io::Cursor::new(archive(entries));
+        extract_archive(reader, dest, &settings).await.unwrap()
+    }
+
+    #[test]
+    fn entry_names_stay_inside_the_destination() {
+        let dest = Path::new("/out");
+        let safe = |name| safe_path(dest, name);
+        assert_eq!(safe("a/b.txt"), Some(dest.join("a/b.txt")));
+        assert_eq!(safe("/etc/passwd"), Some(dest.join("etc/passwd")));
+        assert_eq!(safe("./a//b/"), Some(dest.join("a/b")));
+        assert_eq!(safe("../a"), None);
+        assert_eq!(safe("a/../../b"), None);
+        assert_eq!(safe("a/.."), None);
+        assert_eq!(safe("/"), None);
+        assert_eq!(safe("."), None);
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn hostile_entries_are_skipped() {
+        let directory =
+            std::env::temp_dir().join(format!("super-gunzip-extract-{}", std::process::id()));
+        let dest = directory.join("dest");
+        let outside = directory.join("outside");
+        std::fs::create_dir_all(&outside).unwrap();
+        std::fs::write(outside.join("secret"), "secret").unwrap();
+
+        let summary = extract(
+            &[
+                ("ok.txt", EntryKind::File),
+                ("/absolute.txt", EntryKind::File),
+                ("../escaped.txt", EntryKind::File),
+                ("dir/../../escaped.txt", EntryKind::File),
+                ("out", EntryKind::Symlink(outside.clone())),
+                ("out/planted.txt", EntryKind::File),
+                ("out/", EntryKind::Directory),
+                ("copy", EntryKind::HardLink(PathBuf::from("ok.txt"))),
+                ("stolen", EntryKind::HardLink(PathBuf::from("out/secret"))),
+                ("stolen-link", EntryKind::HardLink(PathBuf::from("out"))),
+                (
+                    "stolen-parent",
+                    EntryKind::HardLink(PathBuf::from("../outside/secret")),
+                ),
+                ("ok.txt", EntryKind::File),
+                ("fifo", EntryKind::Other(b'6')),
+            ],
+            &dest,
+        )
+        .await;
+
+        assert_eq!(std::fs::read(dest.join("ok.txt")).unwrap(), b"ok.txt");
+        assert_eq!(
+            std::fs::read(dest.join("absolute.txt")).unwrap(),
+            b"/absolute.txt"
+        );
+        assert_eq!(std::fs::read(dest.join("copy")).unwrap(), b"ok.txt");
+        assert_eq!(std::fs::read_link(dest.join("out")).unwrap(), outside);
+        let mut outside_names: Vec<_> = std::fs::read_dir(&outside)
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name())
+            .collect();
+        outside_names.sort();
+        assert_eq!(outside_names, ["secret"]);
+        assert!(!directory.join("escaped.txt").exists());
+        for name in ["stolen", "stolen-link", "stolen-parent", "fifo"] {
+            assert!(
+                std::fs::symlink_metadata(dest.join(name)).is_err(),
+                "{}",
+                name
+            );
+        }
+        assert_eq!((summary.entries, summary.skipped), (4, 9));
+        std::fs::remove_dir_all(&directory).unwrap();
+    }
+
+    #[tokio::test]
+    async fn existing_files_are_only_replaced_when_forced() {
+        let dest =
+            std::env::temp_dir().join(format!("super-gunzip-extract-force-{}", std::process::id()));
+        std::fs::create_dir_all(dest.join("kept")).unwrap();
+        std::fs::write(dest.join("a.txt"), "old").unwrap();
+        let entries = [
+            ("a.txt", EntryKind::File),
+            ("kept/", EntryKind::Directory),
+            ("kept", EntryKind::File),
+        ];
+
+        let summary = extract(&entries, &dest).await;
+        assert_eq!(std::fs::read(dest.join("a.txt")).unwrap(), b"old");
+        assert_eq!((summary.entries, summary.skipped), (1, 2));
+
+        let mut settings = Settings::new(Codec::Gzip, false);
+        settings.collision = CollisionPolicy::Force;
+        let reader = std::io::Cursor::new(archive(&entries));
+        let summary = extract_archive(reader, &dest, &Arc::new(settings))
+            .await
+            .unwrap();
+        assert_eq!(std::fs::read(dest.join("a.txt")).unwrap(), b"a.txt");
+        assert!(dest.join("kept").is_dir());
+        assert_eq!((summary.entries, summary.skipped), (2, 1));
+        std::fs::remove_dir_all(&dest).unwrap();
+    }
+}
@@ -0,0 +1,406 @@
+//! The deflate implementations that gzip outputs can be compressed with. miniz_oxide, which flate2
+//! uses, is always there; zlib-ng and libdeflate are linked from the system when the program is
+//! built with the cargo features of the same name, found through pkg-config by the build script.
+//!
+//! libdeflate is much faster than the others but only compresses whole buffers, so streams are
+//! compressed with it in blocks (see [`crate::settings::Settings::gzip_block_size`]).
+
+use flate2::write::{DeflateEncoder, GzEncoder};
+use flate2::Compression;
+use std::io::{Error, ErrorKind, Result, Write};
+
+/// A deflate implementation
+#[derive(clap::ValueEnum, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Backend {
+    /// miniz_oxide, in pure Rust
+    #[default]
+    Miniz,
+    /// zlib-ng, through its native API (libz-ng)
+    ZlibNg,
+    /// libdeflate, for whole buffers
+    Libdeflate,
+}
+
+impl Backend {
+    /// The name of the backend, which is also the cargo feature it is built with
+    pub fn name(self) -> &'static str {
+        match self {
+            Backend::Miniz => "miniz",
+            Backend::ZlibNg => "zlib-ng",
+            Backend::Libdeflate => "libdeflate",
+        }
+    }
+
+    /// Whether the program was built with the backend
+    pub fn is_available(self) -> bool {
+        match self {
+            Backend::Miniz => true,
+            Backend::ZlibNg => cfg!(feature = "zlib-ng"),
+            Backend::Libdeflate => cfg!(feature = "libdeflate"),
+        }
+    }
+
+    /// Whether the backend only compresses whole buffers, rather than streams
+    pub fn whole_buffers(self) -> bool {
+        self == Backend::Libdeflate
+    }
+}
+
+/// The error of a backend the program was not built with
+fn unavailable(backend: Backend) -> Error {
+    Error::new(
+        ErrorKind::Unsupported,
+        format!(
+            "this build has no {} backend: rebuild with --features {}",
+            backend.name(),
+            backend.name()
+        ),
+    )
+}
+
+/// Compresses the whole buffer into raw deflate data at the level (or the default one). Blocks.
+pub fn deflate(backend: Backend, data: &[u8], level: Option<u32>) -> Result<Vec<u8>> {
+    let level = level.unwrap_or(Compression::default().level());
+    match backend {
+        Backend::Miniz => {
+            let mut encoder =
+                DeflateEncoder::new(Vec::with_capacity(data.len()), Compression::new(level));
+            encoder.write_all(data)?;
+            encoder.finish()
+        }
+        #[cfg(feature = "zlib-ng")]
+        Backend::ZlibNg => {
+            let mut encoder = zlib_ng::Encoder::new(Vec::with_capacity(data.len()), level)?;
+            encoder.write_all(data)?;
+            encoder.finish()
+        }
+        #[cfg(feature = "libdeflate")]
+        Backend::Libdeflate => libdeflate::deflate(data, level),
+        #[allow(unreachable_patterns)]
+        backend => Err(unavailable(backend)),
+    }
+}
+
+/// The 10-byte header flate2 starts a gzip member with, which stores no name or time, and says
+/// the operating system is unknown
+pub fn minimal_header(level: Option<u32>) -> [u8; 10] {
+    let level = level.unwrap_or(Compression::default().level());
+    // The extra flags tell whether the slowest (2) or fastest (4) compression was used
+    let xfl = match level {
+        9.. => 2,
+        0 | 1 => 4,
+        _ => 0,
+    };
+    [0x1f, 0x8b, 0x08, 0, 0, 0, 0, 0, xfl, 0xff]
+}
+
+/// Compresses the whole buffer into a complete gzip member with a minimal header. Blocks.
+pub fn gzip_member(backend: Backend, data: &[u8], level: Option<u32>) -> Result<Vec<u8>> {
+    if backend == Backend::Miniz {
+        let compression = level.map_or(Compression::default(), Compression::new);
+        let mut encoder = GzEncoder::new(Vec::new(), compression);
+        encoder.write_all(data)?;
+        return encoder.finish();
+    }
+    let deflated = deflate(backend, data, level)?;
+    let mut member = Vec::with_capacity(deflated.len() + 18);
+    member.extend_from_slice(&minimal_header(level));
+    member.extend_from_slice(&deflated);
+    let mut crc = flate2::Crc::new();
+    crc.update(data);
+    member.extend_from_slice(&crc.sum().to_le_bytes());
+    member.extend_from_slice(&crc.amount().to_le_bytes());
+    Ok(member)
+}
+
+/// A streaming encoder of raw deflate data into the writer, whose `flush` flushes the compressed
+/// stream so far (like flate2's)
+pub trait DeflateWriter<W: Write>: Write {
+    /// Ends the compressed stream, returning the writer
+    fn finish(self: Box<Self>) -> Result<W>;
+}
+
+impl<W: Write> DeflateWriter<W> for DeflateEncoder<W> {
+    fn finish(self: Box<Self>) -> Result<W> {
+        DeflateEncoder::finish(*self)
+    }
+}
+
+/// Starts a stream of raw deflate data into the writer with a streaming backend
+pub fn deflate_writer<'a, W: Write + 'a>(
+    backend: Backend,
+    writer: W,
+    level: Option<u32>,
+) -> Result<Box<dyn DeflateWriter<W> + 'a>> {
+    let level = level.unwrap_or(Compression::default().level());
+    match backend {
+        Backend::Miniz => Ok(Box::new(DeflateEncoder::new(
+            writer,
+            Compression::new(level),
+        ))),
+        #[cfg(feature = "zlib-ng")]
+        Backend::ZlibNg => Ok(Box::new(zlib_ng::Encoder::new(writer, level)?)),
+        #[allow(unreachable_patterns)]
+        backend => Err(unavailable(backend)),
+    }
+}
+
+/// Bindings to the native API of zlib-ng (libz-ng), whose functions are prefixed with `zng_` so
+/// that they never resolve to the system zlib
+#[cfg(feature = "zlib-ng")]
+mod zlib_ng {
+    use super::DeflateWriter;
+    use std::io::{Error, ErrorKind, Result, Write};
+    use std::os::raw::{c_char, c_int, c_ulong, c_void};
+
+    const Z_OK: i32 = 0;
+    const Z_STREAM_END: i32 = 1;
+    const Z_BUF_ERROR: i32 = -5;
+    const Z_NO_FLUSH: i32 = 0;
+    const Z_SYNC_FLUSH: i32 = 2;
+    const Z_FINISH: i32 = 4;
+    const Z_DEFLATED: i32 = 8;
+    /// Negative window bits ask for raw deflate data, without a zlib header
+    const RAW_WINDOW_BITS: i32 = -15;
+    const MEM_LEVEL: i32 = 8;
+    const Z_DEFAULT_STRATEGY: i32 = 0;
+
+    /// The size of the buffer the compressed data is written to before it goes to the writer
+    const OUTPUT_SIZE: usize = 64 * 1024;
+
+    /// `zng_stream`, which counts its input and output in 32 bits and its totals in `size_t`
+    #[repr(C)]
+    struct ZStream {
+        next_in: *const u8,
+        avail_in: u32,
+        total_in: usize,
+        next_out: *mut u8,
+        avail_out: u32,
+        total_out: usize,
+        msg: *const c_char,
+        state: *mut c_void,
+        zalloc: *const c_void,
+        zfree: *const c_void,
+        opaque: *mut c_void,
+        data_type: c_int,
+        adler: u32,
+        reserved: c_ulong,
+    }
+
+    // Linked by the build script
+    extern "C" {
+        fn zng_deflateInit2(
+            stream: *mut ZStream,
+            level: i32,
+            method: i32,
+            window_bits: i32,
+            mem_level: i32,
+            strategy: i32,
+        ) -> i32;
+        fn zng_deflate(stream: *mut ZStream, flush: i32) -> i32;
+        fn zng_deflateEnd(stream: *mut ZStream) -> i32;
+    }
+
+    /// A raw deflate stream into the writer
+    pub struct Encoder<W: Write> {
+        // The library keeps a pointer to the stream, so it must not move
+        stream: Box<ZStream>,
+        writer: Option<W>,
+        output: Vec<u8>,
+    }
+
+    impl<W: Write> Encoder<W> {
+        pub fn new(writer: W, level: u32) -> Result<Encoder<W>> {
+            let mut stream = Box::new(ZStream {
+                next_in: std::ptr::null(),
+                avail_in: 0,
+                total_in: 0,
+                next_out: std::ptr::null_mut(),
+                avail_out: 0,
+                total_out: 0,
+                msg: std::ptr::null(),
+                state: std::ptr::null_mut(),
+                zalloc: std::ptr::null(),
+                zfree: std::ptr::null(),
+                opaque: std::ptr::null_mut(),
+                data_type: 0,
+                adler: 0,
+                reserved: 0,
+            });
+            let result = unsafe {
+                zng_deflateInit2(
+                    &mut *stream,
+                    level as i32,
+                    Z_DEFLATED,
+                    RAW_WINDOW_BITS,
+                    MEM_LEVEL,
+                    Z_DEFAULT_STRATEGY,
+                )
+            };
+            if result != Z_OK {
+                return Err(Error::other(format!(
+                    "zlib-ng failed to start compressing ({})",
+                    result
+                )));
+            }
+            Ok(Encoder {
+                stream,
+                writer: Some(writer),
+                output: vec![0; OUTPUT_SIZE],
+            })
+        }
+
+        /// Compresses the input with the flush mode until the library has nothing more to write
+        /// for it, writing what it produced to the writer
+        fn run(&mut self, input: &[u8], flush: i32) -> Result<()> {
+            let writer = self
+                .writer
+                .as_mut()
+                .expect("the writer is only taken when finishing");
+            self.stream.next_in = input.as_ptr();
+            self.stream.avail_in = input.len() as u32;
+            loop {
+                self.stream.next_out = self.output.as_mut_ptr();
+                self.stream.avail_out = self.output.len() as u32;
+                let result = unsafe { zng_deflate(&mut *self.stream, flush) };
+                let produced = self.output.len() - self.stream.avail_out as usize;
+                writer.write_all(&self.output[..produced])?;
+                match result {
+                    Z_STREAM_END => return Ok(()),
+                    // Everything was consumed and written once the output buffer is not full
+                    Z_OK | Z_BUF_ERROR
+                        if self.stream.avail_in == 0 && self.stream.avail_out != 0 =>
+                    {
+                        return Ok(())
+                    }
+                    Z_OK | Z_BUF_ERROR => {}
+                    result => {
+                        return Err(Error::new(
+                            ErrorKind::InvalidInput,
+                            format!("zlib-ng failed to compress ({})", result),
+                        ))
+                    }
+                }
+            }
+        }
+
+        pub fn finish(mut self) -> Result<W> {
+            self.run(&[], Z_FINISH)?;
+            Ok(self
+                .writer
+                .take()
+                .expect("the writer is only taken when finishing"))
+        }
+    }
+
+    impl<W: Write> Write for Encoder<W> {
+        fn write(&mut self, data: &[u8]) -> Result<usize> {
+            // The input size is counted in 32 bits
+            let data = &data[..data.len().min(u32::MAX as usize)];
+            self.run(data, Z_NO_FLUSH)?;
+            Ok(data.len())
+        }
+
+        fn flush(&mut self) -> Result<()> {
+            self.run(&[], Z_SYNC_FLUSH)?;
+            self.writer
+                .as_mut()
+                .expect("the writer is only taken when finishing")
+                .flush()
+        }
+    }
+
+    impl<W: Write> DeflateWriter<W> for Encoder<W> {
+        fn finish(self: Box<Self>) -> Result<W> {
+            Encoder::finish(*self)
+        }
+    }
+
+    impl<W: Write> Drop for Encoder<W> {
+        fn drop(&mut self) {
+            unsafe { zng_deflateEnd(&mut *self.stream) };
+        }
+    }
+}
+
+/// Bindings to the whole-buffer compression of libdeflate
+#[cfg(feature = "libdeflate")]
+mod libdeflate {
+    use std::cell::RefCell;
+    use std::io::{Error, Result};
+    use std::os::raw::{c_int, c_void};
+    use std::ptr::NonNull;
+
+    #[repr(C)]
+    struct RawCompressor {
+        _private: [u8; 0],
+    }
+
+    // Linked by the build script
+    extern "C" {
+        fn libdeflate_alloc_compressor(level: c_int) -> *mut RawCompressor;
+        fn libdeflate_deflate_compress(
+            compressor: *mut RawCompressor,
+            input: *const c_void,
+            input_size: usize,
+            output: *mut c_void,
+            output_size: usize,
+        ) -> usize;
+        fn libdeflate_deflate_compress_bound(
+            compressor: *mut RawCompressor,
+            input_size: usize,
+        ) -> usize;
+        fn libdeflate_free_compressor(compressor: *mut RawCompressor);
+    }
+
+    /// A compressor at a level, freed when dropped
+    struct Compressor {
+        raw: NonNull<RawCompressor>,
+        level: u32,
+    }
+
+    impl Drop for Compressor {
+        fn drop(&mut self) {
+            unsafe { libdeflate_free_compressor(self.raw.as_ptr()) };
+        }
+    }
+
+    thread_local! {
+        /// The compressor of each thread, kept between blocks since allocating one takes longer
+        /// than compressing a small block
+        static COMPRESSOR: RefCell<Option<Compressor>> = const { RefCell::new(None) };
+    }
+
+    pub fn deflate(data: &[u8], level: u32) -> Result<Vec<u8>> {
+        COMPRESSOR.with(|compressor| {
+            let mut compressor = compressor.borrow_mut();
+            if compressor
+                .as_ref()
+                .is_none_or(|compressor| compressor.level != level)
+            {
+                let raw = unsafe { libdeflate_alloc_compressor(level as c_int) };
+                let raw = NonNull::new(raw)
+                    .ok_or_else(|| Error::other("libdeflate failed to allocate a compressor"))?;
+                *compressor = Some(Compressor { raw, level });
+            }
+            let raw = compressor.as_ref().unwrap().raw.as_ptr();
+            let bound = unsafe { libdeflate_deflate_compress_bound(raw, data.len()) };
+            let mut output = vec![0u8; bound];
+            let size = unsafe {
+                libdeflate_deflate_compress(
+                    raw,
+                    data.as_ptr().cast(),
+                    data.len(),
+                    output.as_mut_ptr().cast(),
+                    output.len(),
+                )
+            };
+            // The bound always fits, so nothing being written means something else went wrong
+            if size == 0 {
+                return Err(Error::other("libdeflate failed to compress"));
+            }
+            output.truncate(size);
+            Ok(output)
+        })
+    }
+}
@@ -0,0 +1,2054 @@
+//! The parallel executor that processes every discovered file of a run
+
+use crate::bgzf;
+use crate::bundle;
+use crate::codec::{
+    decompress, sampled_incompressible, stores_incompressible, tag_origin, transcode, Codec,
+};
+use crate::color::Status;
+use crate::dedupe::{self, Dedupe};
+use crate::discovery::{
+    decompressed_name, discover, discover_stream, is_symlink, skip_reason, sort_paths, Discovered,
+    SkipReason,
+};
+use crate::error::{Operation, SuperGzipError};
+use crate::features::{self, Feature};
+use crate::gzip_header::GzipInfo;
+use crate::gzip_header::GzipOrigin;
+use crate::http;
+use crate::journal::{self, Fingerprint, FingerprintReader, FingerprintWriter, Journal};
+use crate::log::{self, LogLevel};
+use crate::magic;
+use crate::memory;
+use crate::progress::{format_bytes, Progress, ProgressReader};
+use crate::prompt;
+use crate::report::{self, FileReport, FileStatus, Totals};
+use crate::settings::{CollisionPolicy, OutputFormat, Settings, SymlinkPolicy, Verbosity};
+use crate::shred;
+use crate::space;
+use crate::sparse::{SparseReader, SparseWriter};
+use crate::split;
+use crate::throttle::Throttled;
+use crate::timings::{self, Recorder, Stage, Timed};
+use std::collections::{BTreeMap, HashSet, VecDeque};
+use std::ffi::OsString;
+use std::future::Future;
+use std::path::{Component, Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant, SystemTime};
+use tokio::fs::{metadata as async_metadata, File as AsyncFile};
+use tokio::io::{
+    AsyncRead, AsyncWrite, AsyncWriteExt, BufReader, BufWriter as TokioBufWriter,
+    Error as TokioIOError,
+};
+use tokio::sync::mpsc::{self, Receiver};
+use tokio::sync::Semaphore;
+use tokio::task::JoinHandle;
+
+/// The output path of a file next to it: with the suffix appended when compressing, removed
+/// when decompressing, and replaced by the extension of the other codec when recompressing
+fn sibling_path(path: &Path, settings: &Settings) -> PathBuf {
+    if settings.b_zip {
+        let mut sibling_path = path.as_os_str().to_owned();
+        sibling_path.push(format!(".{}", settings.suffix));
+        sibling_path.into()
+    } else if let Some(target) = settings.recompress_to {
+        // Recompressed files swap the extension of their codec for that of the other one
+        let mut sibling_path = match decompressed_name(path, settings) {
+            Some(name) => path.with_file_name(name),
+            None => path.with_extension(""),
+        }
+        .into_os_string();
+        sibling_path.push(format!(".{}", target.extension()));
+        sibling_path.into()
+    } else {
+        // Files recognized by their contents lose their extension, if they have one
+        match decompressed_name(path, settings) {
+            Some(name) => path.with_file_name(name),
+            None if path.extension().is_some() => path.with_extension(""),
+            None => {
+                let mut sibling_path = path.as_os_str().to_owned();
+                sibling_path.push(".out");
+                sibling_path.into()
+            }
+        }
+    }
+}
+
+/// Determines where the (de)compressed version of a file is written
+pub fn output_path(path: &Path, settings: &Settings) -> PathBuf {
+    // Downloads are saved in the current directory under the last segment of the URL
+    let local_name;
+    let path = match http::url(path) {
+        Some(url) => {
+            local_name = PathBuf::from(http::file_name(url));
+            &local_name
+        }
+        None => path,
+    };
+
+    let sibling_path = sibling_path(path, settings);
+
+    // Mirrored trees keep the paths of their files relative to the root
+    let mirrored = settings
+        .mirror_root
+        .as_ref()
+        .and_then(|root| sibling_path.strip_prefix(root).ok());
+    if let (Some(output_dir), Some(relative_path)) = (&settings.output_dir, mirrored) {
+        return output_dir.join(relative_path);
+    }
+
+    // Relocate the output into the output directory, either flattened or mirroring the input path
+    // (minus any root, prefix, or parent directory components)
+    match &settings.output_dir {
+        None => sibling_path,
+        Some(output_dir) if settings.preserve_structure => output_dir.join(
+            sibling_path
+                .components()
+                .filter(|component| matches!(component, Component::Normal(_)))
+                .collect::<PathBuf>(),
+        ),
+        Some(output_dir) => match sibling_path.file_name() {
+            Some(file_name) => output_dir.join(file_name),
+            None => output_dir.join(&sibling_path),
+        },
+    }
+}
+
+/// Where the (de)compressed version of a file ended up
+enum Output {
+    /// The output file and its size
+    File(PathBuf, u64),
+    /// The data to write to stdout once all earlier files have been written
+    Stdout(Vec<u8>),
+    /// The data was only decompressed to check its integrity, producing this many bytes
+    Discarded(u64),
+    /// Replacing the existing output was declined at the prompt, so nothing was kept
+    Declined,
+    /// The output saved too little to replace the original, so it was removed
+    NotCompressed,
+}
+
+/// Inserts a number before the extension of the path, e.g. `file.txt.gz` becomes `file.txt.1.gz`
+fn numbered_path(path: &Path, number: usize) -> PathBuf {
+    let mut name = path.file_stem().unwrap_or_default().to_owned();
+    name.push(format!(".{}", number));
+    if let Some(ext) = path.extension() {
+        name.push(".");
+        name.push(ext);
+    }
+    path.with_file_name(name)
+}
+
+/// The original file name stored in the header of a gzip file, if it is usable as a file name
+async fn stored_name(path: &Path) -> Option<PathBuf> {
+    let name = GzipInfo::read(path).await.ok()?.name?;
+    // The name is stored as the bytes it had, which need not be valid UTF-8
+    #[cfg(unix)]
+    let name = PathBuf::from(<std::ffi::OsStr as std::os::unix::ffi::OsStrExt>::from_bytes(&name));
+    #[cfg(not(unix))]
+    let name = PathBuf::from(String::from_utf8_lossy(&name).into_owned());
+
+    // Only use the last component so that a crafted header cannot write outside the directory
+    let file_name = name.file_name()?;
+    if file_name == ".." {
+        return None;
+    }
+    Some(PathBuf::from(file_name))
+}
+
+/// The output path of a file before accounting for collisions, which uses the original name
+/// stored in the gzip header when restoring names
+async fn planned_output(path: &Path, settings: &Settings) -> PathBuf {
+    let output_path = output_path(path, settings);
+    if settings.restore_name && !settings.b_zip && settings.codec == Codec::Gzip {
+        if let Some(name) = stored_name(path).await {
+            return output_path.with_file_name(name);
+        }
+    }
+    output_path
+}
+
+/// Whether the output path resolves to the file itself, as the name stored in a gzip header can
+/// be the name of the compressed file. Writing that output would destroy the file and then delete
+/// it as the original.
+async fn is_input(path: &Path, output_path: &Path) -> bool {
+    match (
+        tokio::fs::canonicalize(path).await,
+        tokio::fs::canonicalize(output_path).await,
+    ) {
+        (Ok(input), Ok(output)) => input == output,
+        _ => false,
+    }
+}
+
+async fn modified(path: &Path) -> std::io::Result<SystemTime> {
+    async_metadata(path).await?.modified()
+}
+
+/// Whether the existing output of the file is older than the file, and so needs to be redone when
+/// skipping up-to-date outputs. Outputs of downloads are never considered stale.
+async fn is_stale(path: &Path, output_path: &Path) -> bool {
+    if http::url(path).is_some() {
+        return false;
+    }
+    match (modified(path).await, modified(output_path).await) {
+        (Ok(source), Ok(output)) => output < source,
+        _ => true,
+    }
+}
+
+/// Picks the output path of a file according to the collision policy, given the outputs already
+/// claimed by earlier files in the run. Two inputs are never allowed to write the same output.
+/// Files that cannot be written are returned with the reason and the output they would have had.
+pub async fn claim_output(
+    path: &Path,
+    settings: &Settings,
+    claimed: &mut HashSet<PathBuf>,
+) -> Result<PathBuf, (SkipReason, PathBuf)> {
+    let planned = planned_output(path, settings).await;
+    let mut candidate = planned.clone();
+    let mut number = 0;
+    loop {
+        let collides = claimed.contains(&candidate);
+        let exists = !collides && tokio::fs::symlink_metadata(&candidate).await.is_ok();
+        if exists
+            && settings.collision != CollisionPolicy::Rename
+            && is_input(path, &candidate).await
+        {
+            return Err((SkipReason::OutputIsInput, planned));
+        }
+        if exists && settings.skip_newer {
+            // Outputs at least as new as their file are kept, while older ones are redone
+            match is_stale(path, &candidate).await {
+                true => break,
+                false => return Err((SkipReason::UpToDate, planned)),
+            }
+        }
+        match (collides, exists, settings.collision) {
+            (false, false, _) | (false, true, CollisionPolicy::Force) => break,
+            (_, _, CollisionPolicy::Rename) => {
+                number += 1;
+                candidate = numbered_path(&planned, number);
+            }
+            // Whether to replace it is asked once the output is ready
+            (false, true, _) if settings.interactive => break,
+            (true, _, _) => return Err((SkipReason::OutputCollision, planned)),
+            (false, true, _) => return Err((SkipReason::OutputExists, planned)),
+        }
+    }
+    claimed.insert(candidate.clone());
+    Ok(candidate)
+}
+
+/// Copies the access and modification times, permissions, and (where permitted) ownership of the
+/// source file to the target file, like gzip does
+pub(crate) fn copy_metadata(source: &Path, target: &Path) -> std::io::Result<()> {
+    let metadata = std::fs::metadata(source)?;
+    let file = std::fs::File::options().write(true).open(target)?;
+    file.set_times(
+        std::fs::FileTimes::new()
+            .set_accessed(metadata.accessed()?)
+            .set_modified(metadata.modified()?),
+    )?;
+
+    // Changing the owner only works as root, so failing to is not an error
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::MetadataExt;
+        let _ = std::os::unix::fs::fchown(&file, Some(metadata.uid()), Some(metadata.gid()));
+    }
+    file.set_permissions(metadata.permissions())
+}
+
+/// The temporary path an output is written to before being renamed into place
+pub(crate) fn temp_path(output_path: &Path) -> PathBuf {
+    let mut temp_path = output_path.as_os_str().to_owned();
+    temp_path.push(format!(".tmp-{}", std::process::id()));
+    temp_path.into()
+}
+
+/// Moves a finished temporary output into place, removing it if that fails so that nothing is
+/// left behind
+pub(crate) async fn move_into_place(temp_path: &Path, output_path: &Path) -> std::io::Result<()> {
+    let result = tokio::fs::rename(temp_path, output_path).await;
+    if result.is_err() {
+        let _ = tokio::fs::remove_file(temp_path).await;
+    }
+    result
+}
+
+/// Whether the path is a temporary output of this process that is still being written
+pub(crate) fn is_temp_path(path: &Path) -> bool {
+    path.to_string_lossy()
+        .ends_with(&format!(".tmp-{}", std::process::id()))
+}
+
+/// Writes the (de)compressed data to the given file and carries the metadata of the original
+/// file over, if there is one
+async fn write_output<R>(
+    reader: R,
+    source: Option<&Path>,
+    target: &Path,
+    settings: &Settings,
+) -> Result<(), SuperGzipError>
+where
+    R: AsyncRead + Unpin,
+{
+    let file = AsyncFile::create(target).await?;
+    let file: Box<dyn AsyncWrite + Unpin + Send> =
+        match settings.sparse && !settings.writes_compressed() {
+            true => Box::new(SparseWriter::new(file)),
+            false => Box::new(file),
+        };
+    let file: Box<dyn AsyncWrite + Unpin + Send> = match settings.rate_limiter() {
+        Some(limiter) => Box::new(Throttled::new(file, Arc::clone(limiter))),
+        None => Box::new(file),
+    };
+    let mut writer = TokioBufWriter::with_capacity(settings.write_buffer, Timed::new(file));
+    transcode(reader, &mut writer, source, settings).await?;
+    writer.shutdown().await?;
+
+    // Carry the timestamps and permissions of the original over to the output
+    if let (true, Some(source)) = (settings.preserve_metadata, source) {
+        let (source, target) = (source.to_path_buf(), target.to_path_buf());
+        tokio::task::spawn_blocking(move || copy_metadata(&source, &target)).await??;
+    }
+    Ok(())
+}
+
+/// Decompresses the freshly compressed output of a file, checking that it matches the fingerprint
+/// and digest of the original contents. The digest is what makes this more than a repeat of the
+/// CRC32 check in the gzip trailer.
+async fn verify_output(
+    path: &Path,
+    output_path: &Path,
+    original: (Fingerprint, Option<u64>),
+    settings: &Settings,
+) -> Result<(), SuperGzipError> {
+    let mut decompressed = FingerprintWriter::digesting(tokio::io::sink());
+    let result = decompress(
+        settings.codec,
+        AsyncFile::open(output_path).await?,
+        &mut decompressed,
+        settings.cpu_pool(),
+    )
+    .await;
+    match result {
+        Ok(_) if (decompressed.fingerprint(), decompressed.digest()) == original => Ok(()),
+        _ => Err(SuperGzipError::VerifyFailed(path.to_path_buf())),
+    }
+}
+
+/// Where the (de)compressed data of a file goes
+#[derive(Clone)]
+enum Destination {
+    File(PathBuf),
+    Stdout,
+    Discard,
+}
+
+/// How long to wait before trying a file again after a transient error, doubled for each
+/// further retry up to `MAX_RETRY_DELAY`
+const RETRY_DELAY: Duration = Duration::from_millis(500);
+const MAX_RETRY_DELAY: Duration = Duration::from_secs(60);
+
+/// The files a file being processed has open: its input and its output
+const FILES_PER_INPUT: u32 = 2;
+
+/// The most files that can be waiting for a thread, in progress, or done but waiting for the
+/// files matched before them at once
+pub const MAX_PENDING: usize = 4096;
+
+/// An opened input file or download
+pub(crate) type Input = Box<dyn AsyncRead + Unpin + Send>;
+
+/// Opens the file at the path, or starts downloading it if it is a URL, returning it along with
+/// its size (or 0 if the server did not say)
+pub(crate) async fn open_input(
+    path: &Path,
+    settings: &Settings,
+) -> Result<(Input, u64), SuperGzipError> {
+    let (input, size): (Input, u64) = match http::url(path) {
+        Some(url) => {
+            let download = http::get(url).await?;
+            let size = download.length.unwrap_or(0);
+            (Box::new(download), size)
+        }
+        None => {
+            let file = AsyncFile::open(path).await?;
+            let size = file.metadata().await?.len();
+            #[cfg(unix)]
+            if settings.mmap && size >= crate::mmap::MIN_SIZE {
+                let reader = crate::mmap::MmapReader::new(&file.into_std().await)?;
+                return throttle(Box::new(reader), size, settings);
+            }
+            // The holes of sparse files are not read, since they are all zeros
+            let capacity = settings.read_buffer;
+            match SparseReader::new(file, size).await {
+                Ok(reader) => (Box::new(BufReader::with_capacity(capacity, reader)), size),
+                Err(file) => (Box::new(BufReader::with_capacity(capacity, file)), size),
+            }
+        }
+    };
+    throttle(input, size, settings)
+}
+
+/// Makes the input count against the rate limit, if there is one
+fn throttle(input: Input, size: u64, settings: &Settings) -> Result<(Input, u64), SuperGzipError> {
+    match settings.rate_limiter() {
+        Some(limiter) => Ok((Box::new(Throttled::new(input, Arc::clone(limiter))), size)),
+        None => Ok((input, size)),
+    }
+}
+
+/// Runs the future, unless the run is cancelled before it is done
+async fn cancellable<T>(
+    future: impl Future<Output = Result<T, SuperGzipError>>,
+    settings: &Settings,
+) -> Result<T, SuperGzipError> {
+    tokio::select! {
+        result = future => result,
+        () = settings.cancellation.abandon_requested() => Err(SuperGzipError::Interrupted),
+    }
+}
+
+/// Processes a single opened file, returning the (de)compressed data instead of writing it to a
+/// file when writing to stdout
+async fn process_file<R: AsyncRead + Unpin>(
+    path: &Path,
+    input: &mut FingerprintReader<R>,
+    input_size: u64,
+    destination: Destination,
+    settings: &Settings,
+    progress: &Arc<Progress>,
+    progress_id: usize,
+) -> Result<Output, SuperGzipError> {
+    let reader = Timed::new(ProgressReader::new(
+        &mut *input,
+        Arc::clone(progress),
+        progress_id,
+    ));
+    // Downloads have no original file to take the name and metadata from, or to delete
+    let source = http::url(path).is_none().then_some(path);
+
+    // Name the format of files compressed with another codec, rather than calling them corrupt
+    if let (false, Some(source)) = (settings.b_zip, source) {
+        match magic::sniff(source).await? {
+            Some(format) if format != settings.codec.format() => {
+                return Err(SuperGzipError::WrongFormat(format, settings.codec));
+            }
+            _ => {}
+        }
+    }
+
+    let output_path = match destination {
+        Destination::File(output_path) => output_path,
+        Destination::Stdout => {
+            let mut buffer = Vec::new();
+            cancellable(transcode(reader, &mut buffer, source, settings), settings).await?;
+            return Ok(Output::Stdout(buffer));
+        }
+        Destination::Discard => {
+            let mut sink = tokio::io::sink();
+            let written =
+                cancellable(transcode(reader, &mut sink, source, settings), settings).await?;
+            return Ok(Output::Discarded(written));
+        }
+    };
+
+    // Make sure the directory of the output exists
+    if settings.output_dir.is_some() {
+        if let Some(parent) = output_path.parent() {
+            tokio::fs::create_dir_all(parent).await?;
+        }
+    }
+
+    // Write the (de)compressed data to a temporary file next to the output, so that an
+    // interrupted run never leaves a truncated output behind under the real name
+    let temp_path = temp_path(&output_path);
+    if let Err(e) = cancellable(write_output(reader, source, &temp_path, settings), settings).await
+    {
+        let _ = tokio::fs::remove_file(&temp_path).await;
+        return Err(e);
+    }
+
+    // Keep the original rather than replace it with an output that is barely smaller, or larger,
+    // unless the output stores the contents as asked with --incompressible store
+    if let (true, false, Some(source)) = (settings.b_zip, settings.keep_original, source) {
+        let output_size = tokio::fs::metadata(&temp_path).await?.len();
+        let kept = saves_enough(input_size, output_size, settings.min_savings)
+            || stores_incompressible(settings) && sampled_incompressible(source, settings).await?;
+        if !kept {
+            let _ = tokio::fs::remove_file(&temp_path).await;
+            return Ok(Output::NotCompressed);
+        }
+    }
+
+    // Make sure the output decompresses back to what was read before trusting it with the
+    // original
+    if settings.verify && settings.b_zip {
+        let original = (input.fingerprint(), input.digest());
+        let verified = verify_output(path, &temp_path, original, settings);
+        if let Err(e) = cancellable(verified, settings).await {
+            let _ = tokio::fs::remove_file(&temp_path).await;
+            return Err(e);
+        }
+    }
+
+    // Find the blocks of a BGZF output before it replaces anything, so that a broken output
+    // never does
+    let index = match settings.writes_bgzf() {
+        true => {
+            let scanned = temp_path.clone();
+            let scan = async {
+                let pool = settings.cpu_pool();
+                Ok::<_, SuperGzipError>(
+                    pool.spawn(move || bgzf::Index::scan(&scanned))
+                        .await
+                        .await??,
+                )
+            };
+            match scan.await {
+                Ok(index) => Some(index),
+                Err(e) => {
+                    let _ = tokio::fs::remove_file(&temp_path).await;
+                    return Err(e);
+                }
+            }
+        }
+        false => None,
+    };
+
+    let output = match settings.volume_size() {
+        Some(split_size) => {
+            keep_volumes(source, &temp_path, output_path, split_size, settings).await
+        }
+        None => keep_output(path, source, &temp_path, output_path, settings).await,
+    }?;
+    if let (Some(index), Output::File(output_path, _)) = (index, &output) {
+        let output_path = output_path.clone();
+        tokio::task::spawn_blocking(move || index.save(&output_path)).await??;
+    }
+    Ok(output)
+}
+
+/// What the CPU-bound stage of the run is called in its timings
+fn codec_stage(settings: &Settings) -> &'static str {
+    if settings.b_zip {
+        "compressing"
+    } else if settings.recompress_to.is_some() && !settings.test_only {
+        "recompressing"
+    } else {
+        "decompressing"
+    }
+}
+
+/// Whether compressing saved at least `min_savings` percent of the input, and anything at all
+pub(crate) fn saves_enough(input_size: u64, output_size: u64, min_savings: f64) -> bool {
+    let saved = input_size.saturating_sub(output_size) as f64;
+    saved > 0.0 && saved >= input_size as f64 * min_savings / 100.0
+}
+
+/// Opens and processes a single file, recording it in the journal once it is done. The size of
+/// the input is stored in `input_size` as soon as it is known.
+async fn process_input(
+    path: &Path,
+    destination: Destination,
+    settings: &Settings,
+    progress: &Arc<Progress>,
+    journal: Option<&Journal>,
+    input_size: &mut u64,
+) -> Result<Output, SuperGzipError> {
+    // Wait until the input and output of the file fit in the files allowed to be open
+    let start = Instant::now();
+    let _open_files = match settings.open_files() {
+        Some(open_files) => Some(
+            Arc::clone(open_files)
+                .acquire_many_owned(FILES_PER_INPUT)
+                .await
+                .expect("the open files are never closed"),
+        ),
+        None => None,
+    };
+    timings::record(Stage::Queue, start.elapsed());
+    let input = open_input(path, settings).await;
+    *input_size = input.as_ref().map_or(0, |(_, size)| *size);
+
+    // Wait until the buffers of the file fit in the memory budget
+    let start = Instant::now();
+    let _memory = match settings.memory_budget() {
+        Some(budget) => {
+            let estimate = memory::estimate(settings, *input_size);
+            Some(budget.reserve(estimate).await)
+        }
+        None => None,
+    };
+    timings::record(Stage::Queue, start.elapsed());
+    let progress_id = progress.start_file(path.to_string_lossy().into_owned(), *input_size);
+    let result = match input {
+        Ok((input, _)) => {
+            let mut input = if settings.verify && settings.b_zip {
+                FingerprintReader::digesting(input)
+            } else {
+                FingerprintReader::new(input)
+            };
+            let result = process_file(
+                path,
+                &mut input,
+                *input_size,
+                destination,
+                settings,
+                progress,
+                progress_id,
+            )
+            .await;
+
+            // Record the completed file so that resuming skips it
+            match (result, journal) {
+                (Ok(output), Some(journal))
+                    if http::url(path).is_none() && !matches!(output, Output::Declined) =>
+                {
+                    journal
+                        .record(path, input.fingerprint())
+                        .map(|()| output)
+                        .map_err(SuperGzipError::from)
+                }
+                (result, _) => result,
+            }
+        }
+        Err(e) => Err(e),
+    };
+    progress.finish_file(Some(progress_id));
+    result
+}
+
+/// Recreates a symbolic link as a link to the output of the file it points to, which is left
+/// alone, deleting the original link unless it is kept. Relative links stay relative, so they
+/// only resolve if the output of the file ends up at the same relative place.
+#[cfg(unix)]
+async fn preserve_symlink(
+    path: &Path,
+    output_path: PathBuf,
+    settings: &Settings,
+) -> Result<Output, SuperGzipError> {
+    if settings.output_dir.is_some() {
+        if let Some(parent) = output_path.parent() {
+            tokio::fs::create_dir_all(parent).await?;
+        }
+    }
+    let target = sibling_path(&tokio::fs::read_link(path).await?, settings);
+    let temp_path = temp_path(&output_path);
+    tokio::fs::symlink(&target, &temp_path).await?;
+    keep_output(path, Some(path), &temp_path, output_path, settings).await
+}
+
+#[cfg(not(unix))]
+async fn preserve_symlink(
+    _path: &Path,
+    _output_path: PathBuf,
+    _settings: &Settings,
+) -> Result<Output, SuperGzipError> {
+    Err(SuperGzipError::IO(TokioIOError::new(
+        std::io::ErrorKind::Unsupported,
+        "symbolic links can only be preserved on Unix",
+    )))
+}
+
+/// Moves the finished temporary output of a file into place and deletes the original file (the
+/// source, unless it is a download) unless it is kept
+async fn keep_output(
+    path: &Path,
+    source: Option<&Path>,
+    temp_path: &Path,
+    output_path: PathBuf,
+    settings: &Settings,
+) -> Result<Output, SuperGzipError> {
+    // Refuse to replace a file that appeared since the output was claimed unless forced to (or
+    // it is an outdated output being redone), or ask whether to in interactive mode
+    if settings.collision != CollisionPolicy::Force
+        && tokio::fs::symlink_metadata(&output_path).await.is_ok()
+        && !(settings.skip_newer && is_stale(path, &output_path).await)
+    {
+        if settings.interactive {
+            let question = format!("overwrite {}?", output_path.to_string_lossy());
+            if !prompt::confirm(question).await {
+                let _ = tokio::fs::remove_file(temp_path).await;
+                return Ok(Output::Declined);
+            }
+        } else {
+            let _ = tokio::fs::remove_file(temp_path).await;
+            return Err(SuperGzipError::IO(TokioIOError::new(
+                std::io::ErrorKind::AlreadyExists,
+                format!("{} already exists", output_path.to_string_lossy()),
+            )));
+        }
+    }
+    move_into_place(temp_path, &output_path).await?;
+    let output_size = tokio::fs::symlink_metadata(&output_path).await?.len();
+    remove_original(source, &output_path, settings).await?;
+    Ok(Output::File(output_path, output_size))
+}
+
+/// Deletes (or shreds) the original file once its output is in place, or moves it to the trash,
+/// unless it is kept (or a download). Once the run is cancelled, originals are kept.
+async fn remove_original(
+    source: Option<&Path>,
+    output: &Path,
+    settings: &Settings,
+) -> std::io::Result<()> {
+    if settings.cancellation.abandoning() {
+        return Ok(());
+    }
+    if let (false, Some(source)) = (settings.keep_original, source) {
+        let question = match settings.trash {
+            Some(_) => format!("move {} to the trash?", source.to_string_lossy()),
+            None if settings.shred => format!("shred {}?", source.to_string_lossy()),
+            None => format!("delete {}?", source.to_string_lossy()),
+        };
+        if settings.interactive && !prompt::confirm(question).await {
+            return Ok(());
+        }
+        match &settings.trash {
+            Some(trash) => {
+                let (trash, source, output) =
+                    (trash.clone(), source.to_path_buf(), output.to_path_buf());
+                tokio::task::spawn_blocking(move || trash.put(&source, &output)).await??;
+            }
+            None if settings.shred => {
+                let shredded_path = source.to_path_buf();
+                let shredded = tokio::task::spawn_blocking(move || {
+                    // Overwriting a file with other links would wipe them out as well
+                    match shred::has_other_links(&shredded_path)? {
+                        true => std::fs::remove_file(&shredded_path).map(|()| false),
+                        false => shred::shred(&shredded_path).map(|()| true),
+                    }
+                })
+                .await??;
+                if !shredded {
+                    settings.report(&format!(
+                        "Warning: deleted {} without shredding it, since it has other hard links",
+                        source.to_string_lossy()
+                    ));
+                }
+            }
+            None => tokio::fs::remove_file(source).await?,
+        }
+    }
+    Ok(())
+}
+
+/// Splits the finished temporary output of a file into volumes of at most `split_size` bytes
+/// and moves them into place next to where the output would have been, removing any further
+/// volumes left over from an earlier, longer split. Existing volumes are only replaced when
+/// forced to, like existing outputs. Returns the first volume along with the size of all of them.
+async fn keep_volumes(
+    source: Option<&Path>,
+    temp_path: &Path,
+    output_path: PathBuf,
+    split_size: u64,
+    settings: &Settings,
+) -> Result<Output, SuperGzipError> {
+    let existing = split::volumes(&output_path);
+    if !existing.is_empty() && settings.collision != CollisionPolicy::Force {
+        let _ = tokio::fs::remove_file(temp_path).await;
+        return Err(SuperGzipError::IO(TokioIOError::new(
+            std::io::ErrorKind::AlreadyExists,
+            format!("{} already exists", existing[0].to_string_lossy()),
+        )));
+    }
+
+    let (split_from, split_to) = (temp_path.to_path_buf(), output_path.clone());
+    let split = settings
+        .cpu_pool()
+        .spawn(move || split::split_file(&split_from, &split_to, split_size))
+        .await
+        .await;
+    let _ = tokio::fs::remove_file(temp_path).await;
+    let volumes = split??;
+    for volume in &volumes {
+        move_into_place(&volume.temp_path, &volume.path).await?;
+    }
+    for stale in existing.iter().skip(volumes.len()) {
+        tokio::fs::remove_file(stale).await?;
+    }
+    remove_original(source, &output_path, settings).await?;
+    let output_size = volumes.iter().map(|volume| volume.size).sum();
+    let first = split::volume_path(&output_path, 0);
+    Ok(Output::File(first, output_size))
+}
+
+/// Produces the output of a file from the output of an earlier file with the same contents,
+/// instead of processing it again. The output is hard-linked when it would be the same or the
+/// files are hard links to the same file (recreating the links), and copied otherwise (falling
+/// back to a copy where hard links are not supported). Returns the output along with whether it
+/// was hard-linked.
+async fn reuse_output(
+    path: &Path,
+    first_output: &Path,
+    same_file: bool,
+    output_path: PathBuf,
+    settings: &Settings,
+) -> Result<(Output, bool), SuperGzipError> {
+    if settings.output_dir.is_some() {
+        if let Some(parent) = output_path.parent() {
+            tokio::fs::create_dir_all(parent).await?;
+        }
+    }
+    let temp_path = temp_path(&output_path);
+    let stores_origin = settings.codec == Codec::Gzip && settings.b_zip && settings.store_name;
+    let linked = if stores_origin && !same_file {
+        // The header of the first output names the first file, so store this one's instead
+        let mut origin = GzipOrigin::of(path).await?;
+        tag_origin(&mut origin, settings);
+        let (first_output, target) = (first_output.to_path_buf(), temp_path.clone());
+        let copied = tokio::task::spawn_blocking(move || {
+            dedupe::copy_with_origin(&first_output, &target, &origin)
+        })
+        .await?;
+        copied.map(|()| false)
+    } else {
+        match tokio::fs::hard_link(first_output, &temp_path).await {
+            Ok(()) => Ok(true),
+            Err(_) => tokio::fs::copy(first_output, &temp_path)
+                .await
+                .map(|_| false),
+        }
+    };
+    let linked = match linked {
+        Ok(linked) => linked,
+        Err(e) => {
+            let _ = tokio::fs::remove_file(&temp_path).await;
+            return Err(e.into());
+        }
+    };
+
+    // Hard links share the timestamps and permissions of the first output
+    if settings.preserve_metadata && !linked {
+        let (source, target) = (path.to_path_buf(), temp_path.clone());
+        let copied = tokio::task::spawn_blocking(move || copy_metadata(&source, &target)).await?;
+        if let Err(e) = copied {
+            let _ = tokio::fs::remove_file(&temp_path).await;
+            return Err(e.into());
+        }
+    }
+    let output = keep_output(path, Some(path), &temp_path, output_path, settings).await?;
+    Ok((output, linked))
+}
+
+/// Formats an output-to-input size ratio as a percentage of the input size
+pub fn format_ratio(ratio: Option<f64>) -> String {
+    match ratio {
+        Some(ratio) => format!("{:.1}% of original", ratio * 100.0),
+        None => "empty input".to_string(),
+    }
+}
+
+/// The width that the statuses of verbose lines are padded to, so that the columns after them
+/// line up
+const STATUS_WIDTH: usize = 13;
+
+/// A verbose line about a file: its status, in its color if it has one, padded to line up with
+/// the other lines, and then the rest of the line
+pub(crate) fn status_line(
+    settings: &Settings,
+    label: &str,
+    status: Option<Status>,
+    rest: &str,
+) -> String {
+    let label = format!("{:<width$}", label, width = STATUS_WIDTH);
+    match status {
+        Some(status) => format!("{} {}", settings.paint(&label, status), rest),
+        None => format!("{} {}", label, rest),
+    }
+}
+
+/// The verbose line about a skipped file, unless it is skipped quietly: paths that are not files
+/// are only mentioned with `-vvv`
+pub(crate) fn skipped_line(settings: &Settings, path: &Path, reason: SkipReason) -> Option<String> {
+    let shown = match reason {
+        SkipReason::NotAFile => settings.verbosity >= Verbosity::Debug,
+        _ => settings.verbose(),
+    };
+    let message = format!("{}: {}", path.to_string_lossy(), reason.describe());
+    shown.then(|| status_line(settings, "Skipped", Some(Status::Skipped), &message))
+}
+
+/// The warning about a file skipped because its output exists or collides with another one
+pub(crate) fn collision_warning(path: &Path, reason: SkipReason, output_path: &Path) -> String {
+    let hint = match reason {
+        SkipReason::OutputExists => "use --force to overwrite or --rename to keep both",
+        _ => "use --rename to keep both",
+    };
+    format!(
+        "Warning: skipping {}: {} ({}; {})",
+        path.to_string_lossy(),
+        reason.describe(),
+        output_path.to_string_lossy(),
+        hint
+    )
+}
+
+/// What a file is going through, as the verbose line about it starting says
+pub(crate) fn action(settings: &Settings) -> &'static str {
+    if settings.test_only {
+        "Testing"
+    } else if settings.b_zip {
+        "Compressing"
+    } else if settings.recompress_to.is_some() {
+        "Recompressing"
+    } else {
+        "Decompressing"
+    }
+}
+
+/// The verbose line about a processed file: its sizes and ratio, along with the exact sizes and
+/// time with `-vv` and its timings if they were recorded
+pub(crate) fn finished_line(settings: &Settings, report: &FileReport) -> String {
+    let timings = report.timings.map_or_else(String::new, |timings| {
+        format!(
+            ", reading {:.3}s, {} {:.3}s, writing {:.3}s, queued {:.3}s",
+            timings.read.as_secs_f64(),
+            codec_stage(settings),
+            timings.compress.as_secs_f64(),
+            timings.write.as_secs_f64(),
+            timings.queue.as_secs_f64()
+        )
+    });
+    let bytes = match settings.verbosity >= Verbosity::Detailed {
+        true => format!(
+            "; {} -> {} bytes in {:.3}s",
+            report.input_size,
+            report.output_size,
+            report.duration.as_secs_f64()
+        ),
+        false => String::new(),
+    };
+    let message = format!(
+        "{:>10} -> {:>10} {:>17}  {}{}{}",
+        format_bytes(report.input_size),
+        format_bytes(report.output_size),
+        format_ratio(report.ratio()),
+        report.path.to_string_lossy(),
+        bytes,
+        timings
+    );
+    status_line(settings, "Finished", Some(Status::Ok), &message)
+}
+
+/// Prints the totals of a run (or its JSON summary) and where its time went, returning the
+/// totals
+pub(crate) fn report_totals(
+    settings: &Settings,
+    reports: &[FileReport],
+    elapsed: Duration,
+) -> Totals {
+    let totals = Totals::from_reports(reports);
+    if settings.format == OutputFormat::Json {
+        settings.emit(&report::summary_json(reports, elapsed));
+    } else if settings.format == OutputFormat::Text && totals.files > 0 {
+        settings.report(&format!(
+            "{} {} files: {} -> {} ({}) at {:.1} MB/s",
+            if settings.test_only {
+                "Tested"
+            } else {
+                "Processed"
+            },
+            totals.files,
+            format_bytes(totals.input_size),
+            format_bytes(totals.output_size),
+            format_ratio(totals.ratio()),
+            totals.throughput(elapsed)
+        ));
+    }
+    // Tell where the time went, so the bottleneck of the run is plain to see
+    if let (OutputFormat::Text, Some(timings)) = (settings.format, report::total_timings(reports)) {
+        let files = reports.iter().filter(|report| report.timings.is_some());
+        settings.report(&timings.render_text(files.count(), codec_stage(settings)));
+    }
+    totals
+}
+
+/// Prints every error of a run, returning them so that the program exits unsuccessfully.
+/// `succeeded` is the number of files that were processed successfully anyway.
+pub fn summarize_errors(
+    settings: &Settings,
+    errors: Vec<SuperGzipError>,
+    succeeded: usize,
+) -> Result<(), SuperGzipError> {
+    if errors.is_empty() {
+        Ok(())
+    } else {
+        let summary = format!("Finished with {} errors:", errors.len());
+        settings.report_error(&settings.paint(&summary, Status::Failed));
+        // Group the errors by kind, in the order each kind first occurred
+        let mut kinds: Vec<(&str, Vec<&SuperGzipError>)> = Vec::new();
+        for error in &errors {
+            match kinds.iter_mut().find(|(kind, _)| *kind == error.kind()) {
+                Some((_, group)) => group.push(error),
+                None => kinds.push((error.kind(), vec![error])),
+            }
+        }
+        for (kind, group) in kinds {
+            let heading = format!("{} ({}):", kind, group.len());
+            settings.report_error(&settings.paint(&heading, Status::Failed));
+            for error in group {
+                settings.report_error(&format!("  {}", error));
+            }
+        }
+        Err(SuperGzipError::Failed { errors, succeeded })
+    }
+}
+
+/// Whether the journal says an earlier run already completed the path. Downloads are never
+/// skipped, since their contents cannot be checked without downloading them.
+async fn already_done(path: &Path, journal: Option<&Journal>) -> bool {
+    match journal {
+        Some(journal) if http::url(path).is_none() => journal.is_completed(path).await,
+        _ => false,
+    }
+}
+
+/// Prints what a run would do with each discovered path, without writing or removing anything
+pub async fn dry_run(paths: Vec<PathBuf>, settings: &Settings) -> Result<(), SuperGzipError> {
+    // Only read the journal, since nothing is completed
+    let journal = match &settings.journal {
+        Some(journal) => Some(Journal::load(journal).await?),
+        None => None,
+    };
+    let (action, past_action) = if settings.b_zip {
+        ("compress", "compressed")
+    } else if settings.recompress_to.is_some() {
+        ("recompress", "recompressed")
+    } else {
+        ("decompress", "decompressed")
+    };
+    let mut claimed = HashSet::new();
+    let (mut processed, mut skipped) = (0, 0);
+    for path in paths {
+        let reason = match already_done(&path, journal.as_ref()).await {
+            true => Some(SkipReason::AlreadyDone),
+            false => skip_reason(&path, settings).await,
+        };
+        if let Some(reason) = reason {
+            settings.report(&format!(
+                "Would skip {} ({})",
+                path.to_string_lossy(),
+                reason.describe()
+            ));
+            skipped += 1;
+            continue;
+        }
+        processed += 1;
+        if settings.to_stdout {
+            settings.report(&format!(
+                "Would {} {} to stdout",
+                action,
+                path.to_string_lossy()
+            ));
+            continue;
+        }
+
+        // Report the output along with anything it would clash with
+        let output_path = match claim_output(&path, settings, &mut claimed).await {
+            Ok(output_path) => output_path,
+            Err((reason, output_path)) => {
+                settings.report(&format!(
+                    "Would skip {} ({}: {})",
+                    path.to_string_lossy(),
+                    reason.describe(),
+                    output_path.to_string_lossy()
+                ));
+                processed -= 1;
+                skipped += 1;
+                continue;
+            }
+        };
+        settings.report(&format!(
+            "Would {} {} -> {}",
+            action,
+            path.to_string_lossy(),
+            output_path.to_string_lossy()
+        ));
+        let (overwrite, delete) = match settings.interactive {
+            true => ("ask before overwriting", "ask before deleting"),
+            false => ("overwrite", "delete"),
+        };
+        let delete = match settings.trash {
+            Some(_) if settings.interactive => "ask before trashing",
+            Some(_) => "trash",
+            None if settings.shred && settings.interactive => "ask before shredding",
+            None if settings.shred => "shred",
+            None => delete,
+        };
+        if async_metadata(&output_path).await.is_ok() {
+            settings.report(&format!(
+                "  Would {} the existing {}",
+                overwrite,
+                output_path.to_string_lossy()
+            ));
+        }
+        if !settings.keep_original && http::url(&path).is_none() {
+            settings.report(&format!("Would {} {}", delete, path.to_string_lossy()));
+        }
+    }
+    settings.report(&format!(
+        "{} files would be {}, {} skipped",
+        processed, past_action, skipped
+    ));
+    Ok(())
+}
+
+/// Processes every file matching the patterns according to the settings, returning the report of
+/// each discovered path in the order they were matched. A single `-` pattern reads from stdin.
+pub async fn run_batch(
+    settings: Settings,
+    patterns: Vec<OsString>,
+) -> Result<Vec<FileReport>, SuperGzipError> {
+    let start = Instant::now();
+    let settings = Arc::new(settings);
+    if settings.verbosity >= Verbosity::Debug {
+        settings.report(&format!(
+            "Settings: {} {}, level {}, {} files at once, {} CPU threads, {} reads and {} writes",
+            mode(&settings),
+            settings.recompress_to.unwrap_or(settings.codec).name(),
+            settings
+                .level
+                .map_or_else(|| "default".to_string(), |level| level.to_string()),
+            settings.num_threads,
+            settings.cpu_threads,
+            format_bytes(settings.read_buffer as u64),
+            format_bytes(settings.write_buffer as u64)
+        ));
+    }
+
+    // Fail up front, rather than on every file, when the build lacks what the run needs
+    let needed = [Some(settings.codec), settings.recompress_to]
+        .into_iter()
+        .flatten()
+        .filter_map(Codec::feature)
+        .chain(
+            patterns
+                .iter()
+                .any(|pattern| pattern.to_str().is_some_and(http::is_url))
+                .then_some(Feature::Http),
+        );
+    features::require(&needed.collect::<Vec<_>>())?;
+
+    // Act as a filter from stdin to stdout when the pattern is -
+    let from_stdin = patterns == ["-"];
+    if from_stdin && settings.dry_run {
+        settings.report("Would process stdin to stdout");
+        return Ok(Vec::new());
+    } else if from_stdin && settings.test_only {
+        transcode(tokio::io::stdin(), &mut tokio::io::sink(), None, &settings).await?;
+        settings.report("stdin: OK");
+        return Ok(Vec::new());
+    } else if from_stdin {
+        let mut stdout = tokio::io::stdout();
+        transcode(tokio::io::stdin(), &mut stdout, None, &settings).await?;
+        stdout.flush().await?;
+        return Ok(Vec::new());
+    }
+
+    if settings.shred && !settings.keep_original && !settings.dry_run {
+        settings.report(
+            "Warning: --shred is best-effort: copy-on-write file systems (btrfs, ZFS, APFS), SSDs, \
+             snapshots, and backups can keep the original data after it is overwritten",
+        );
+    }
+
+    // Files can only be ordered, and the space their outputs need added up, once all of them
+    // are known
+    if settings.order.is_some() || settings.min_free.is_some() {
+        let mut errors = Vec::new();
+        let mut paths = discover(&patterns, &settings, &mut errors).await;
+        if let Some(order) = settings.order {
+            paths = tokio::task::spawn_blocking(move || sort_paths(paths, order)).await?;
+        }
+        if let Some(min_free) = settings.min_free {
+            check_space(&paths, &settings, min_free).await?;
+        }
+        return process_paths(&settings, paths, errors, start).await;
+    }
+    let paths = discover_stream(patterns, Arc::clone(&settings));
+    process_stream(&settings, paths, Vec::new(), start).await
+}
+
+/// Checks the space that the outputs of the paths may need against the free space of the file
+/// systems they go to, failing if one of them already has less than `min_free` free and warning
+/// if the outputs may not fit. Compressed outputs may need as much as their inputs at worst.
+async fn check_space(
+    paths: &[PathBuf],
+    settings: &Arc<Settings>,
+    min_free: u64,
+) -> Result<(), SuperGzipError> {
+    if settings.to_stdout || settings.test_only {
+        return Ok(());
+    }
+    let mut outputs = Vec::new();
+    for path in paths.iter().filter(|path| http::url(path).is_none()) {
+        let Ok(metadata) = async_metadata(path).await else {
+            continue;
+        };
+        let size = match settings.b_zip || settings.recompress_to.is_some() {
+            true => metadata.len(),
+            false => metadata.len() * memory::ASSUMED_RATIO,
+        };
+        let output_path = output_path(path, settings);
+        let directory = output_path.parent().unwrap_or(Path::new(".")).to_path_buf();
+        outputs.push((directory, size));
+    }
+    let needs = tokio::task::spawn_blocking(move || space::needs(outputs)).await??;
+    for need in needs {
+        if need.free < min_free {
+            return Err(space::low_space_error(&need.directory, need.free, min_free).into());
+        }
+        if need.needed > need.free - min_free {
+            settings.report(&format!(
+                "Warning: the outputs in {} may need up to {}, but only {} is free above \
+                 --min-free; files wait while less than --min-free is free",
+                need.directory.to_string_lossy(),
+                format_bytes(need.needed),
+                format_bytes(need.free - min_free)
+            ));
+        }
+    }
+    Ok(())
+}
+
+/// What a run does to its files
+pub(crate) fn operation(settings: &Settings) -> Operation {
+    if settings.test_only {
+        Operation::Test
+    } else if settings.b_zip {
+        Operation::Compress
+    } else if settings.recompress_to.is_some() {
+        Operation::Recompress
+    } else {
+        Operation::Decompress
+    }
+}
+
+/// What a run does to its files, for its log events
+pub(crate) fn mode(settings: &Settings) -> &'static str {
+    operation(settings).describe()
+}
+
+/// Passes the report of a file that is done to the hooks, the event stream, and the log
+pub(crate) fn record_done(settings: &Settings, run: &str, report: &FileReport) {
+    settings.hooks.file_done(report);
+    if let Some(events) = &settings.progress_json {
+        events.file_done(report);
+    }
+    if let Some(log) = &settings.log {
+        log.file_done(run, report);
+    }
+}
+
+/// Bundles the small files of a directory into bundles of at most `bundle_files` files, removing
+/// the originals once their bundle is in place, and records the report of each file
+async fn bundle_small_files(
+    directory: &Path,
+    files: Vec<PathBuf>,
+    settings: &Arc<Settings>,
+    progress: &Progress,
+    run: &str,
+    reports: &mut Vec<FileReport>,
+    errors: &mut Vec<SuperGzipError>,
+) {
+    let mut record = |report: FileReport| {
+        progress.finish_file(None);
+        record_done(settings, run, &report);
+        if settings.format == OutputFormat::Ndjson {
+            settings.emit(&report.to_json());
+        }
+        reports.push(report);
+    };
+    let per_bundle = settings.bundle_files.unwrap_or(files.len()).max(1);
+    let numbered = files.len() > per_bundle;
+    for (index, chunk) in files.chunks(per_bundle).enumerate() {
+        let bundle_path = bundle::bundle_path(directory, numbered.then_some(index + 1), settings);
+        if settings.cancellation.stopping() {
+            for file in chunk {
+                let mut report = FileReport::new(file.clone());
+                report.status = FileStatus::Skipped(SkipReason::Interrupted.describe());
+                record(report);
+            }
+            continue;
+        }
+        for file in chunk {
+            settings.hooks.file_start(file);
+        }
+        let start = Instant::now();
+        let bundle = match bundle::write(chunk, &bundle_path, settings).await {
+            Ok(bundle) => bundle,
+            Err(e) => {
+                for file in chunk {
+                    let mut report = FileReport::new(file.clone());
+                    report.status = FileStatus::Failed(e.to_string());
+                    record(report);
+                }
+                errors.push(e.for_file(&bundle_path, Operation::Bundle));
+                continue;
+            }
+        };
+        let duration = start.elapsed();
+        let input_size: u64 = bundle.members.iter().map(|member| member.size).sum();
+        if settings.verbose() {
+            progress.suspend(|| {
+                settings.report(&format!(
+                    "Bundled {} files into {}: {} -> {} ({})",
+                    bundle.members.len(),
+                    bundle.path.to_string_lossy(),
+                    format_bytes(input_size),
+                    format_bytes(bundle.size),
+                    format_ratio(report::ratio(input_size, bundle.size))
+                ))
+            });
+        }
+
+        // Each file is credited with its share of the size and time of the bundle
+        for member in bundle.members {
+            let share = match input_size {
+                0 => 0.0,
+                input_size => member.size as f64 / input_size as f64,
+            };
+            let mut report = FileReport::new(member.path);
+            report.output_path = Some(bundle.path.clone());
+            report.input_size = member.size;
+            report.output_size = (bundle.size as f64 * share).round() as u64;
+            report.duration = duration.mul_f64(share);
+            if let Err(e) = remove_original(Some(&report.path), &bundle.path, settings).await {
+                report.status = FileStatus::Failed(e.to_string());
+                errors.push(SuperGzipError::IO(e).for_file(&report.path, Operation::Remove));
+            }
+            record(report);
+        }
+    }
+}
+
+/// Processes the given paths according to the settings, returning the report of each path in the
+/// given order. `errors` holds the errors that already occurred while discovering the paths, and
+/// `start` is when the run started, for the summary.
+pub async fn process_paths(
+    settings: &Arc<Settings>,
+    paths: Vec<PathBuf>,
+    errors: Vec<SuperGzipError>,
+    start: Instant,
+) -> Result<Vec<FileReport>, SuperGzipError> {
+    let (sender, receiver) = mpsc::channel(paths.len().max(1));
+    for path in paths {
+        let _ = sender.try_send(Ok(path));
+    }
+    drop(sender);
+    process_stream(settings, receiver, errors, start).await
+}
+
+/// Waits for the task of a file, writing its output to stdout if it has any and recording its
+/// report and error
+async fn collect_file(
+    handle: JoinHandle<(FileReport, Result<Option<Output>, SuperGzipError>)>,
+    settings: &Settings,
+    stdout: &mut tokio::io::Stdout,
+    reports: &mut Vec<FileReport>,
+    errors: &mut Vec<SuperGzipError>,
+) -> Result<(), SuperGzipError> {
+    match handle.await {
+        Ok((report, result)) => {
+            match result {
+                Ok(Some(Output::Stdout(data))) => {
+                    stdout.write_all(&data).await?;
+                }
+                Ok(_) => {}
+                Err(gzip_error) => {
+                    errors.push(gzip_error);
+                }
+            }
+            if settings.format == OutputFormat::Ndjson {
+                settings.emit(&report.to_json());
+            }
+            reports.push(report);
+        }
+        Err(join_error) => {
+            errors.push(join_error.into());
+        }
+    }
+    Ok(())
+}
+
+/// Processes the paths received from discovery as they arrive, like [`process_paths`]. Once
+/// [`MAX_PENDING`] files are waiting or in progress, no more paths are received until the
+/// first of them is done, so the files of huge trees are never all held in memory.
+pub async fn process_stream(
+    settings: &Arc<Settings>,
+    mut paths: Receiver<Discovered>,
+    mut errors: Vec<SuperGzipError>,
+    start: Instant,
+) -> Result<Vec<FileReport>, SuperGzipError> {
+    let semaphmore = Arc::new(Semaphore::new(settings.num_threads));
+    if settings.dry_run {
+        let mut all_paths = Vec::new();
+        while let Some(discovered) = paths.recv().await {
+            match discovered {
+                Ok(path) => all_paths.push(path),
+                Err(e) => errors.push(e),
+            }
+        }
+        dry_run(all_paths, settings).await?;
+        return summarize_errors(settings, errors, 0).map(|()| Vec::new());
+    }
+    // Questions are asked on the terminal the progress bars would be drawn on, and the total
+    // grows as the paths are discovered
+    let progress = Arc::new(
+        Progress::new(
+            0,
+            !settings.to_stdout && settings.format == OutputFormat::Text && !settings.interactive,
+        )
+        .with_events(settings.progress_json.clone()),
+    );
+    let journal = match &settings.journal {
+        Some(journal) => Some(Arc::new(Journal::open(journal).await?)),
+        None => None,
+    };
+    let mut handles = VecDeque::new();
+    let mut stdout = tokio::io::stdout();
+    let mut reports = Vec::new();
+    let mut claimed = HashSet::new();
+    let mut dedupe = Dedupe::default();
+    let mut small_files: BTreeMap<PathBuf, Vec<PathBuf>> = BTreeMap::new();
+    let run = Arc::new(log::run_id());
+    settings.log(
+        LogLevel::Info,
+        &run,
+        None,
+        "started",
+        &[
+            ("codec", settings.codec.extension().into()),
+            ("mode", mode(settings).into()),
+        ],
+    );
+    // The number of files that failed so far, which stops the run at --max-errors
+    let failures = Arc::new(AtomicUsize::new(0));
+
+    // Tell the user what an interruption does, once it happens
+    let interrupt_notice = {
+        let (settings, progress) = (Arc::clone(settings), Arc::clone(&progress));
+        let run = Arc::clone(&run);
+        tokio::spawn(async move {
+            settings.cancellation.stop_requested().await;
+            if settings.cancellation.is_cancelled() {
+                settings.log(LogLevel::Warn, &run, None, "cancelled", &[]);
+                progress.suspend(|| {
+                    settings.report(
+                        "Cancelled: abandoning the files in progress and removing their outputs",
+                    )
+                });
+                return;
+            }
+            settings.log(LogLevel::Warn, &run, None, "interrupted", &[]);
+            progress.suspend(|| {
+                settings.report(
+                    "Interrupted: finishing the files in progress (press Ctrl-C again to cancel them)",
+                )
+            });
+            settings.cancellation.abandon_requested().await;
+            settings.log(LogLevel::Warn, &run, None, "cancelled", &[]);
+            progress.suspend(|| {
+                settings.report("Cancelling the files in progress and removing their outputs")
+            });
+        })
+    };
+    while let Some(discovered) = paths.recv().await {
+        // Wait for the files in the order they were matched so that stdout output keeps that
+        // order, pausing discovery while too many files are pending
+        while handles.len() >= MAX_PENDING {
+            let handle = handles.pop_front().expect("a pending file");
+            collect_file(handle, settings, &mut stdout, &mut reports, &mut errors).await?;
+        }
+        let path = match discovered {
+            Ok(path) => path,
+            Err(e) => {
+                errors.push(e);
+                continue;
+            }
+        };
+        progress.add_files(1);
+        if let Some(events) = &settings.progress_json {
+            events.discovered(&path);
+        }
+
+        // Decide where each file goes up front, in the order the files were matched, so that
+        // outputs are claimed deterministically
+        let reason = match already_done(&path, journal.as_deref()).await {
+            true => Some(SkipReason::AlreadyDone),
+            false => skip_reason(&path, settings).await,
+        };
+        // Small files are bundled by directory once every file has been discovered
+        if reason.is_none() && bundle::is_small(&path, settings).await {
+            let directory = path.parent().unwrap_or(Path::new("")).to_path_buf();
+            small_files.entry(directory).or_default().push(path);
+            continue;
+        }
+        let destination = match reason {
+            Some(reason) => Err(reason),
+            None if settings.test_only => Ok(Destination::Discard),
+            None if settings.to_stdout => Ok(Destination::Stdout),
+            None => match claim_output(&path, settings, &mut claimed).await {
+                Ok(output_path) => {
+                    settings.log(
+                        LogLevel::Trace,
+                        &run,
+                        Some(&path),
+                        "claimed output",
+                        &[(
+                            "output_path",
+                            output_path.to_string_lossy().into_owned().into(),
+                        )],
+                    );
+                    Ok(Destination::File(output_path))
+                }
+                // Up-to-date outputs are expected when re-running over the same files
+                Err((SkipReason::UpToDate, _)) => Err(SkipReason::UpToDate),
+                Err((reason, output_path)) => {
+                    settings.log(
+                        LogLevel::Warn,
+                        &run,
+                        Some(&path),
+                        "skipped",
+                        &[
+                            ("reason", reason.describe().into()),
+                            (
+                                "output_path",
+                                output_path.to_string_lossy().into_owned().into(),
+                            ),
+                        ],
+                    );
+                    let warning = collision_warning(&path, reason, &output_path);
+                    progress.suspend(|| settings.report(&warning));
+                    Err(reason)
+                }
+            },
+        };
+
+        // Preserved symbolic links are recreated rather than read, whatever they point to
+        let preserve_link = settings.symlinks == SymlinkPolicy::Preserve
+            && matches!(destination, Ok(Destination::File(_)))
+            && is_symlink(&path).await;
+        // Find out whether an earlier file is a hard link to the same file (or has the same
+        // contents, when deduplicating), in which case its output is reused once it is done
+        let (output_sender, earlier) = match &destination {
+            // Volumes are not reused, since the outputs only point to the first one, and neither
+            // are BGZF outputs, whose indexes would be left behind
+            Ok(Destination::File(_))
+                if http::url(&path).is_none()
+                    && !preserve_link
+                    && settings.volume_size().is_none()
+                    && !settings.writes_bgzf() =>
+            {
+                dedupe.register(&path, settings.dedupe).await
+            }
+            _ => (None, None),
+        };
+
+        let resource_lock = Arc::clone(&semaphmore);
+        let settings = Arc::clone(settings);
+        let progress = Arc::clone(&progress);
+        let journal = journal.clone();
+        let failures = Arc::clone(&failures);
+        let run = Arc::clone(&run);
+        let handle = tokio::spawn(async move {
+            let queued = Instant::now();
+            let mut report = FileReport::new(path.clone());
+            let first_output = match earlier {
+                Some(earlier) => dedupe::first_output(earlier.output)
+                    .await
+                    .map(|output| (output, earlier.same_file)),
+                None => None,
+            };
+
+            // Wait for a free thread, leaving the files that have not started yet alone once
+            // the run is interrupted or too many files have failed
+            let permit = match destination {
+                Ok(_) => Some(Arc::clone(&resource_lock).acquire_owned().await.expect("Failed to acquire permit from semaphore. This is a bug in the program. Please report it.")),
+                Err(_) => None,
+            };
+
+            // Wait while the file system of the output is low on space, rather than failing
+            // file after file once it is full
+            if let (Some(guard), Ok(Destination::File(output_path))) =
+                (settings.space_guard(), &destination)
+            {
+                let directory = output_path.parent().unwrap_or(Path::new("."));
+                let report = |message: &str| progress.suspend(|| settings.report(message));
+                let _ = guard
+                    .wait(directory, settings.cancellation.stop_requested(), report)
+                    .await;
+            }
+            let destination = match destination {
+                Ok(_) if settings.cancellation.stopping() => Err(SkipReason::Interrupted),
+                Ok(_)
+                    if settings.max_errors.is_some_and(|max_errors| {
+                        failures.load(Ordering::SeqCst) >= max_errors
+                    }) =>
+                {
+                    Err(SkipReason::Aborted)
+                }
+                destination => destination,
+            };
+
+            // Skip the path if it shouldn't be processed, silently so if it is not a file
+            let destination = match destination {
+                Ok(destination) => destination,
+                Err(reason) => {
+                    if let Some(line) = skipped_line(&settings, &path, reason) {
+                        progress.suspend(|| settings.report(&line));
+                    }
+                    progress.finish_file(None);
+                    report.status = FileStatus::Skipped(reason.describe());
+                    record_done(&settings, &run, &report);
+                    return (report, Ok(None));
+                }
+            };
+
+            if settings.verbose() {
+                let line = status_line(&settings, action(&settings), None, &path.to_string_lossy());
+                progress.suspend(|| settings.report(&line));
+            }
+            settings.hooks.file_start(&path);
+            settings.log(LogLevel::Debug, &run, Some(&path), "started", &[]);
+            let file_start = Instant::now();
+            let recorder = (settings.timings || settings.verbosity >= Verbosity::Detailed)
+                .then(|| Arc::new(Recorder::default()));
+            if let Some(recorder) = &recorder {
+                recorder.record(Stage::Queue, file_start - queued);
+            }
+            let processed = async {
+                match (first_output, destination) {
+                    (_, Destination::File(output_path)) if preserve_link => {
+                        let result = preserve_symlink(&path, output_path, &settings).await;
+                        progress.finish_file(None);
+                        result
+                    }
+                    (Some((first_output, same_file)), Destination::File(output_path)) => {
+                        report.input_size = async_metadata(&path)
+                            .await
+                            .map_or(0, |metadata| metadata.len());
+                        // The original may be deleted once the output is in place
+                        let fingerprint = match &journal {
+                            Some(_) => journal::fingerprint(&path).await.ok(),
+                            None => None,
+                        };
+                        let result =
+                            reuse_output(&path, &first_output, same_file, output_path, &settings)
+                                .await;
+                        progress.finish_file(None);
+                        result.and_then(|(output, hard_linked)| {
+                            if let Output::Declined = output {
+                                return Ok(output);
+                            }
+                            report.duplicate_of = Some(first_output);
+                            report.hard_linked = hard_linked;
+                            if let (Some(journal), Some(fingerprint)) = (&journal, fingerprint) {
+                                journal.record(&path, fingerprint)?;
+                            }
+                            Ok(output)
+                        })
+                    }
+                    (_, destination) => {
+                        let mut retries = 0;
+                        loop {
+                            let result = process_input(
+                                &path,
+                                destination.clone(),
+                                &settings,
+                                &progress,
+                                journal.as_deref(),
+                                &mut report.input_size,
+                            )
+                            .await;
+                            // Running out of file descriptors is fixed by the other files in progress
+                            // closing theirs, so the file waits its turn without using up a retry
+                            let others_running =
+                                resource_lock.available_permits() + 1 < settings.num_threads;
+                            if result.as_ref().is_err_and(|e| e.is_too_many_open_files())
+                                && others_running
+                            {
+                                tokio::select! {
+                                    () = tokio::time::sleep(RETRY_DELAY) => {}
+                                    () = settings.cancellation.stop_requested() => {}
+                                }
+                                if settings.cancellation.stopping() {
+                                    break result;
+                                }
+                                continue;
+                            }
+                            // Transient errors, like those of network file systems, often go away
+                            // when the file is tried again a little later
+                            let e = match result {
+                                Err(e) if retries < settings.retries && e.is_transient() => e,
+                                result => break result,
+                            };
+                            retries += 1;
+                            let delay = (RETRY_DELAY * 2u32.saturating_pow(retries as u32 - 1))
+                                .min(MAX_RETRY_DELAY);
+                            settings.log(
+                                LogLevel::Warn,
+                                &run,
+                                Some(&path),
+                                "retrying",
+                                &[
+                                    ("error", e.to_string().into()),
+                                    ("retry", retries.into()),
+                                    ("delay_secs", delay.into()),
+                                ],
+                            );
+                            progress.suspend(|| {
+                                settings.report(&format!(
+                                    "Warning: retrying {} in {:.1}s ({} of {}): {}",
+                                    path.to_string_lossy(),
+                                    delay.as_secs_f64(),
+                                    retries,
+                                    settings.retries,
+                                    e
+                                ))
+                            });
+                            tokio::select! {
+                                () = tokio::time::sleep(delay) => {}
+                                () = settings.cancellation.stop_requested() => {}
+                            }
+                            if settings.cancellation.stopping() {
+                                break Err(e);
+                            }
+                        }
+                    }
+                }
+            };
+            let result = timings::scope(recorder.clone(), processed).await;
+            report.timings = recorder.map(|recorder| recorder.timings());
+            let result = result.map_err(|e| e.for_file(&path, operation(&settings)));
+            drop(permit);
+            if result.is_err() {
+                let failed = failures.fetch_add(1, Ordering::SeqCst) + 1;
+                if settings.max_errors == Some(failed) {
+                    settings.log(
+                        LogLevel::Warn,
+                        &run,
+                        None,
+                        "aborted",
+                        &[("failed", failed.into())],
+                    );
+                    progress.suspend(|| {
+                        settings.report(&format!(
+                            "Aborting after {} failed files: no more files are started",
+                            failed
+                        ))
+                    });
+                }
+            }
+
+            // Record the outcome for the machine-readable output
+            report.duration = file_start.elapsed();
+            match &result {
+                Ok(Output::File(output_path, output_size)) => {
+                    report.output_path = Some(output_path.clone());
+                    report.output_size = *output_size;
+                }
+                Ok(Output::Stdout(data)) => report.output_size = data.len() as u64,
+                Ok(Output::Discarded(written)) => report.output_size = *written,
+                Ok(Output::Declined) => {
+                    report.status = FileStatus::Skipped(SkipReason::Declined.describe())
+                }
+                Ok(Output::NotCompressed) => {
+                    report.status = FileStatus::Skipped(SkipReason::NoSavings.describe())
+                }
+                Err(e) => report.status = FileStatus::Failed(e.inner().to_string()),
+            }
+
+            // Name the corrupt files when testing, since that is the point of the test
+            if let (true, Err(e)) = (settings.test_only, &result) {
+                let line = settings.paint(&e.to_string(), Status::Failed);
+                progress.suspend(|| settings.report_error(&line));
+            }
+            if settings.verbose() && settings.test_only && result.is_ok() {
+                let line = status_line(&settings, "OK", Some(Status::Ok), &path.to_string_lossy());
+                progress.suspend(|| settings.report(&line));
+            } else if settings.verbose() && matches!(result, Ok(Output::NotCompressed)) {
+                let message = format!(
+                    "{}: not compressed, since compressing it saved too little",
+                    path.to_string_lossy()
+                );
+                let line = status_line(&settings, "Kept", Some(Status::Skipped), &message);
+                progress.suspend(|| settings.report(&line));
+            } else if settings.verbose() && matches!(report.status, FileStatus::Processed) {
+                let line = finished_line(&settings, &report);
+                progress.suspend(|| settings.report(&line));
+            }
+
+            // Let the later files with the same contents reuse the output
+            if let (Some(sender), Ok(Output::File(output_path, _))) = (output_sender, &result) {
+                let _ = sender.send(Some(output_path.clone()));
+            }
+            record_done(&settings, &run, &report);
+            (report, result.map(Some))
+        });
+        handles.push_back(handle);
+    }
+    for handle in handles {
+        collect_file(handle, settings, &mut stdout, &mut reports, &mut errors).await?;
+    }
+    for (directory, files) in small_files {
+        bundle_small_files(
+            &directory,
+            files,
+            settings,
+            &progress,
+            &run,
+            &mut reports,
+            &mut errors,
+        )
+        .await;
+    }
+    stdout.flush().await?;
+    interrupt_notice.abort();
+    let cancelled_files = errors
+        .iter()
+        .any(|error| matches!(error, SuperGzipError::Interrupted));
+    if settings.cancellation.stopping() && !cancelled_files {
+        errors.push(SuperGzipError::Interrupted);
+    }
+    progress.finish();
+    let totals = report_totals(settings, &reports, start.elapsed());
+    // Tell how much deduplicating saved
+    let duplicates: Vec<_> = reports
+        .iter()
+        .filter(|report| report.duplicate_of.is_some())
+        .collect();
+    if settings.dedupe && settings.format == OutputFormat::Text && !duplicates.is_empty() {
+        let reused: u64 = duplicates.iter().map(|report| report.input_size).sum();
+        let linked: u64 = duplicates
+            .iter()
+            .filter(|report| report.hard_linked)
+            .map(|report| report.output_size)
+            .sum();
+        settings.report(&format!(
+            "Deduplicated {} files: {} not processed again, {} of outputs saved by hard links",
+            duplicates.len(),
+            format_bytes(reused),
+            format_bytes(linked)
+        ));
+    }
+    if settings.verbose() {
+        settings.report(&format!(
+            "Finished in {} seconds",
+            start.elapsed().as_secs_f64()
+        ));
+    }
+    if let Some(report_csv) = &settings.report_csv {
+        let csv = report::csv(&reports, mode(settings));
+        if let Err(e) = tokio::fs::write(report_csv, csv).await {
+            errors.push(SuperGzipError::IO(e).for_file(report_csv, Operation::Write));
+        }
+    }
+    let succeeded = reports
+        .iter()
+        .filter(|report| matches!(report.status, FileStatus::Processed))
+        .count();
+    settings.log(
+        match errors.is_empty() {
+            true => LogLevel::Info,
+            false => LogLevel::Error,
+        },
+        &run,
+        None,
+        "finished",
+        &[
+            ("files", reports.len().into()),
+            ("processed", succeeded.into()),
+            ("failed", errors.len().into()),
+            ("input_size", totals.input_size.into()),
+            ("output_size", totals.output_size.into()),
+            ("duration_secs", start.elapsed().into()),
+        ],
+    );
+    summarize_errors(settings, errors, succeeded).map(|()| reports)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Names that are not valid UTF-8 keep their bytes through compressing and decompressing
+    #[cfg(unix)]
+    #[test]
+    fn non_utf8_names_survive_the_round_trip() {
+        use std::ffi::OsStr;
+        use std::os::unix::ffi::OsStrExt;
+
+        let path = Path::new("logs").join(OsStr::from_bytes(b"\xff.log"));
+        let compress = Settings::new(Codec::Gzip, true);
+        let compressed = sibling_path(&path, &compress);
+        assert_eq!(compressed.as_os_str().as_bytes(), b"logs/\xff.log.gz");
+
+        let decompress = Settings::new(Codec::Gzip, false);
+        assert_eq!(sibling_path(&compressed, &decompress), path);
+        assert_eq!(output_path(&compressed, &decompress), path);
+
+        let mut into_directory = Settings::new(Codec::Gzip, true);
+        into_directory.output_dir = Some(PathBuf::from("out"));
+        let relocated = output_path(&path, &into_directory);
+        assert_eq!(relocated.as_os_str().as_bytes(), b"out/\xff.log.gz");
+    }
+
+    /// A disk that fills up while the output is written leaves no temporary output behind and
+    /// keeps the original. Writes to /dev/full fail with ENOSPC, so the temporary output is made
+    /// a symlink to it.
+    #[cfg(target_os = "linux")]
+    #[tokio::test]
+    async fn full_disk_removes_the_temporary_output() {
+        let directory =
+            std::env::temp_dir().join(format!("super-gunzip-enospc-{}", std::process::id()));
+        std::fs::create_dir_all(&directory).unwrap();
+        let source = directory.join("data.txt");
+        let contents = "a line of text that compresses well\n".repeat(100_000);
+        std::fs::write(&source, &contents).unwrap();
+        let output_path = directory.join("data.txt.gz");
+        let temp = temp_path(&output_path);
+        std::os::unix::fs::symlink("/dev/full", &temp).unwrap();
+
+        let settings = Settings::new(Codec::Gzip, true);
+        let progress = Arc::new(Progress::new(1, false));
+        let mut input = FingerprintReader::new(AsyncFile::open(&source).await.unwrap());
+        let result = process_file(
+            &source,
+            &mut input,
+            contents.len() as u64,
+            Destination::File(output_path.clone()),
+            &settings,
+            &progress,
+            0,
+        )
+        .await;
+
+        let error = match result {
+            Err(SuperGzipError::IO(e)) => e,
+            _ => panic!("the write to a full disk did not fail"),
+        };
+        assert_eq!(error.kind(), std::io::ErrorKind::StorageFull);
+        assert!(std::fs::symlink_metadata(&temp).is_err());
+        assert!(std::fs::symlink_metadata(&output_path).is_err());
+        assert_eq!(std::fs::read_to_string(&source).unwrap(), contents);
+        std::fs::remove_dir_all(&directory).unwrap();
+    }
+
+    /// --verify catches an output whose contents differ from the original even though their
+    /// CRC32 and size match, which the gzip trailer alone would let through. XORing data with the
+    /// CRC32 polynomial (0x1DB710641 in the bit order of the checksum) keeps its CRC32.
+    #[tokio::test]
+    async fn verify_catches_a_crc32_collision() {
+        let original = "a line of text that compresses well\n"
+            .repeat(100)
+            .into_bytes();
+        let mut tampered = original.clone();
+        for (byte, flip) in tampered[100..]
+            .iter_mut()
+            .zip([0x41, 0x06, 0x71, 0xdb, 0x01])
+        {
+            *byte ^= flip;
+        }
+        let mut reader = FingerprintReader::digesting(&original[..]);
+        tokio::io::copy(&mut reader, &mut tokio::io::sink())
+            .await
+            .unwrap();
+        let mut collision = FingerprintReader::new(&tampered[..]);
+        tokio::io::copy(&mut collision, &mut tokio::io::sink())
+            .await
+            .unwrap();
+        assert_eq!(collision.fingerprint(), reader.fingerprint());
+
+        let output_path =
+            std::env::temp_dir().join(format!("super-gunzip-verify-{}.gz", std::process::id()));
+        let mut encoder = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+        std::io::Write::write_all(&mut encoder, &tampered).unwrap();
+        std::fs::write(&output_path, encoder.finish().unwrap()).unwrap();
+
+        let settings = Settings::new(Codec::Gzip, true);
+        let original = (reader.fingerprint(), reader.digest());
+        let result = verify_output(Path::new("data.txt"), &output_path, original, &settings).await;
+        std::fs::remove_file(&output_path).unwrap();
+        assert!(matches!(result, Err(SuperGzipError::VerifyFailed(_))));
+    }
+
+    /// A gzip file whose stored name is its own name is not restored over itself, which would
+    /// leave neither the file nor its output
+    #[tokio::test]
+    async fn restoring_the_name_of_the_file_itself_is_refused() {
+        let directory =
+            std::env::temp_dir().join(format!("super-gunzip-restore-{}", std::process::id()));
+        std::fs::create_dir_all(&directory).unwrap();
+        let path = directory.join("data");
+        let mut encoder = flate2::GzBuilder::new()
+            .filename("data")
+            .write(Vec::new(), flate2::Compression::default());
+        std::io::Write::write_all(&mut encoder, b"1\n2\n3\n").unwrap();
+        let compressed = encoder.finish().unwrap();
+        std::fs::write(&path, &compressed).unwrap();
+
+        let mut settings = Settings::new(Codec::Gzip, false);
+        settings.restore_name = true;
+        settings.collision = CollisionPolicy::Force;
+        let claimed = claim_output(&path, &settings, &mut HashSet::new()).await;
+        assert_eq!(claimed, Err((SkipReason::OutputIsInput, path.clone())));
+
+        settings.collision = CollisionPolicy::Rename;
+        let claimed = claim_output(&path, &settings, &mut HashSet::new()).await;
+        assert_eq!(claimed, Ok(directory.join("data.1")));
+        assert_eq!(std::fs::read(&path).unwrap(), compressed);
+        std::fs::remove_dir_all(&directory).unwrap();
+    }
+
+    /// --incompressible store keeps its outputs even though they are a little larger than their
+    /// files, whatever --min-savings asks of compressed outputs
+    #[tokio::test]
+    async fn stored_outputs_are_kept_despite_min_savings() {
+        use crate::settings::Incompressible;
+
+        let directory =
+            std::env::temp_dir().join(format!("super-gunzip-store-{}", std::process::id()));
+        std::fs::create_dir_all(&directory).unwrap();
+        let mut state = 0x2545_f491_4f6c_dd1d_u64;
+        let contents: Vec<u8> = (0..256 * 1024)
+            .map(|_| {
+                state ^= state << 13;
+                state ^= state >> 7;
+                state ^= state << 17;
+                state as u8
+            })
+            .collect();
+        for min_savings in [0.0, 10.0] {
+            let source = directory.join(format!("random.{}", min_savings));
+            std::fs::write(&source, &contents).unwrap();
+            let mut settings = Settings::new(Codec::Gzip, true);
+            settings.incompressible = Incompressible::Store;
+            settings.min_savings = min_savings;
+            let output_path = directory.join(format!("random.{}.gz", min_savings));
+            let mut input = FingerprintReader::new(AsyncFile::open(&source).await.unwrap());
+            let output = process_file(
+                &source,
+                &mut input,
+                contents.len() as u64,
+                Destination::File(output_path.clone()),
+                &settings,
+                &Arc::new(Progress::new(1, false)),
+                0,
+            )
+            .await
+            .unwrap();
+            assert!(matches!(output, Output::File(..)));
+            let stored = std::fs::metadata(&output_path).unwrap().len();
+            assert!(stored > contents.len() as u64);
+        }
+        std::fs::remove_dir_all(&directory).unwrap();
+    }
+}
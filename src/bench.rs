@@ -0,0 +1,118 @@
+//! Measuring how each codec does on a sample of the user's data at several levels, to help choose
+//! the codec and level of a large run before starting it.
+
+use crate::codec::{self, Codec};
+use crate::error::SuperGzipError;
+use crate::memory;
+use crate::settings::Settings;
+use std::path::Path;
+use std::time::{Duration, Instant};
+use tokio::io::AsyncReadExt;
+
+/// How one codec did at one level on the sample
+#[derive(Clone, Debug)]
+pub struct Measurement {
+    /// Whether this is the level used when none is given
+    pub default_level: bool,
+    pub input_size: u64,
+    pub compressed_size: u64,
+    pub compress_time: Duration,
+    pub decompress_time: Duration,
+    /// The memory that `--max_memory` counts for compressing a file the size of the sample
+    pub memory: u64,
+}
+
+/// Bytes per second, given the time it took to process this many bytes
+fn speed(bytes: u64, time: Duration) -> f64 {
+    bytes as f64 / time.as_secs_f64().max(1e-9)
+}
+
+impl Measurement {
+    /// The compressed size as a fraction of the sample size, if the sample is not empty
+    pub fn ratio(&self) -> Option<f64> {
+        crate::report::ratio(self.input_size, self.compressed_size)
+    }
+
+    /// The bytes of the sample compressed per second
+    pub fn compress_speed(&self) -> f64 {
+        speed(self.input_size, self.compress_time)
+    }
+
+    /// The bytes of the sample decompressed per second
+    pub fn decompress_speed(&self) -> f64 {
+        speed(self.input_size, self.decompress_time)
+    }
+}
+
+/// The levels a codec is measured at, from fastest to smallest
+pub fn levels(codec: Codec) -> &'static [u32] {
+    match codec {
+        Codec::Gzip => &[1, 3, 6, 9],
+        Codec::Lz4 => &[1, 4, 8, 12],
+    }
+}
+
+/// Reads up to `size` bytes from the start of the file
+pub async fn read_sample(path: &Path, size: u64) -> Result<Vec<u8>, SuperGzipError> {
+    let mut sample = Vec::new();
+    let read = async {
+        tokio::fs::File::open(path)
+            .await?
+            .take(size)
+            .read_to_end(&mut sample)
+            .await
+    };
+    // Name the file, since it is the only one the subcommand reads
+    read.await.map_err(|e| {
+        std::io::Error::new(e.kind(), format!("cannot read {}: {}", path.display(), e))
+    })?;
+    Ok(sample)
+}
+
+/// Compresses the sample with the codec at the level and decompresses it again, checking that
+/// the result is the sample. Both directions run on a single thread, so the speeds are per core.
+pub async fn measure(
+    sample: &[u8],
+    codec: Codec,
+    level: u32,
+) -> Result<Measurement, SuperGzipError> {
+    let mut settings = Settings::new(codec, true);
+    settings.level = Some(level);
+    settings.store_name = false;
+    settings.cpu_threads = 1;
+
+    let mut compressed = Vec::new();
+    let start = Instant::now();
+    codec::transcode(sample, &mut compressed, None, &settings).await?;
+    let compress_time = start.elapsed();
+
+    let mut decompressed = Vec::with_capacity(sample.len());
+    let start = Instant::now();
+    codec::decompress(
+        codec,
+        compressed.as_slice(),
+        &mut decompressed,
+        settings.cpu_pool(),
+    )
+    .await?;
+    let decompress_time = start.elapsed();
+    if decompressed != sample {
+        return Err(SuperGzipError::IO(std::io::Error::new(
+            std::io::ErrorKind::InvalidData,
+            format!(
+                "{} level {} did not decompress to the sample",
+                codec.name(),
+                level
+            ),
+        )));
+    }
+
+    Ok(Measurement {
+        default_level: level == codec.default_level(),
+        input_size: sample.len() as u64,
+        compressed_size: compressed.len() as u64,
+        compress_time,
+        decompress_time,
+        memory: memory::estimate(&settings, sample.len() as u64),
+    })
+}
@@ -0,0 +1,134 @@
+//! Writing and indexing BGZF files, the blocked gzip format of samtools and htslib. A BGZF file is
+//! a multi-member gzip file whose members each hold at most 64 KiB of data and store their own
+//! compressed size in an extra header field, ending with an empty member that marks the end of
+//! the file. Any gzip tool decompresses it, while tools that know the format use the sizes (or
+//! the `.gzi` index built from them, see [`crate::index`]) to seek to a block without decompressing the ones before.
+
+use crate::backend::{self, Backend};
+use flate2::write::DeflateEncoder;
+use flate2::{Compression, Crc};
+use std::io::{BufReader, Error, ErrorKind, Read, Result, Write};
+use std::path::Path;
+
+/// The most data a block holds, which leaves room for the headers when data does not compress
+/// (the size htslib uses)
+pub const BLOCK_SIZE: usize = 0xff00;
+
+/// The most bytes a compressed block may take, as its size minus one is stored in 16 bits
+const MAX_BLOCK_SIZE: usize = 0x10000;
+
+/// The size of the header of a block, including its extra field
+const HEADER_SIZE: usize = 18;
+
+/// The empty block that ends every BGZF file
+pub const EOF: [u8; 28] = [
+    0x1f, 0x8b, 0x08, 0x04, 0x00, 0x00, 0x00, 0x00, 0x00, 0xff, 0x06, 0x00, 0x42, 0x43, 0x02, 0x00,
+    0x1b, 0x00, 0x03, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+];
+
+fn invalid(message: &str) -> Error {
+    Error::new(ErrorKind::InvalidData, message)
+}
+
+/// Compresses the data (at most `BLOCK_SIZE` bytes) into a BGZF block at the level (or the
+/// default one) with the backend, storing it as it is if it would not fit in a block otherwise
+pub fn block(data: &[u8], level: Option<u32>, backend: Backend) -> Result<Vec<u8>> {
+    let mut deflated = backend::deflate(backend, data, level)?;
+    if HEADER_SIZE + deflated.len() + 8 > MAX_BLOCK_SIZE {
+        let mut encoder = DeflateEncoder::new(Vec::new(), Compression::none());
+        encoder.write_all(data)?;
+        deflated = encoder.finish()?;
+    }
+
+    let size = HEADER_SIZE + deflated.len() + 8;
+    let mut block = Vec::with_capacity(size);
+    block.extend_from_slice(&EOF[..16]);
+    block.extend_from_slice(&((size - 1) as u16).to_le_bytes());
+    block.extend_from_slice(&deflated);
+    let mut crc = Crc::new();
+    crc.update(data);
+    block.extend_from_slice(&crc.sum().to_le_bytes());
+    block.extend_from_slice(&(data.len() as u32).to_le_bytes());
+    Ok(block)
+}
+
+/// The size of the block with this header, from the `BC` field among its extra fields
+fn block_size(header: &[u8; 12], extra: &[u8]) -> Result<u64> {
+    if header[..3] != EOF[..3] || header[3] & 0x04 == 0 {
+        return Err(invalid("not a BGZF file"));
+    }
+    let mut fields = extra;
+    while fields.len() >= 4 {
+        let length = u16::from_le_bytes([fields[2], fields[3]]) as usize;
+        if fields[..2] == *b"BC" && length == 2 && fields.len() >= 6 {
+            return Ok(u16::from_le_bytes([fields[4], fields[5]]) as u64 + 1);
+        }
+        fields = fields.get(4 + length..).unwrap_or_default();
+    }
+    Err(invalid("not a BGZF file"))
+}
+
+/// Where a block starts in the compressed file and in the data it decompresses to
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Entry {
+    pub compressed: u64,
+    pub uncompressed: u64,
+}
+
+/// The start of every block of a BGZF file, found from the sizes in their headers and trailers
+/// without decompressing anything
+#[derive(Clone, Debug, Default)]
+pub struct Index {
+    pub entries: Vec<Entry>,
+}
+
+impl Index {
+    /// Reads the headers and trailers of the blocks of the BGZF file at the path. Blocks.
+    pub fn scan(path: &Path) -> Result<Index> {
+        let mut file = BufReader::new(std::fs::File::open(path)?);
+        let length = file.get_ref().metadata()?.len();
+        let mut index = Index::default();
+        let mut position = Entry {
+            compressed: 0,
+            uncompressed: 0,
+        };
+        while position.compressed < length {
+            // The header ends with the length of the extra fields
+            let mut header = [0; 12];
+            file.read_exact(&mut header)?;
+            let mut extra = vec![0; u16::from_le_bytes([header[10], header[11]]) as usize];
+            file.read_exact(&mut extra)?;
+            let size = block_size(&header, &extra)?;
+            let read = (header.len() + extra.len()) as u64;
+            if size < read + 8 || position.compressed + size > length {
+                return Err(invalid("truncated BGZF block"));
+            }
+            file.seek_relative((size - read - 4) as i64)?;
+            let mut data_size = [0; 4];
+            file.read_exact(&mut data_size)?;
+            index.entries.push(position);
+            position.compressed += size;
+            position.uncompressed += u32::from_le_bytes(data_size) as u64;
+        }
+        Ok(index)
+    }
+
+    /// Writes the index in the `.gzi` format of bgzip: the number of entries followed by the
+    /// compressed and uncompressed offset of each block but the first, as little-endian 64-bit
+    /// integers
+    pub fn write(&self, writer: &mut impl Write) -> Result<()> {
+        let entries = self.entries.get(1..).unwrap_or_default();
+        writer.write_all(&(entries.len() as u64).to_le_bytes())?;
+        for entry in entries {
+            writer.write_all(&entry.compressed.to_le_bytes())?;
+            writer.write_all(&entry.uncompressed.to_le_bytes())?;
+        }
+        Ok(())
+    }
+
+    /// Writes the index next to the BGZF file at the path, replacing any earlier index of it.
+    /// Blocks.
+    pub fn save(&self, path: &Path) -> Result<()> {
+        crate::index::save(path, |writer| self.write(writer))
+    }
+}
@@ -0,0 +1,151 @@
+//! Bundling the small files of a directory into a compressed tar archive, since compressing a
+//! file of a few KiB on its own spends more on the gzip header and trailer, and on a block of
+//! the file system, than it saves.
+//!
+//! Each bundle comes with a manifest that tells where the contents of each file start in the tar
+//! archive, so that a single file can be read back with `index` and `range` without unpacking
+//! the whole bundle.
+
+use crate::archive::{compress_tar, refuse_existing};
+use crate::batch::{move_into_place, output_path, temp_path};
+use crate::error::SuperGzipError;
+use crate::http;
+use crate::settings::Settings;
+use crate::tar;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use tokio::io::{AsyncWriteExt, BufWriter as TokioBufWriter};
+
+/// The name of the bundles, before their number and extension
+const BUNDLE_NAME: &str = "small-files";
+/// The extension of a manifest, after the name of its bundle
+pub(crate) const MANIFEST_EXTENSION: &str = "manifest";
+
+/// A file in a bundle
+#[derive(Debug)]
+pub struct Member {
+    pub path: PathBuf,
+    pub size: u64,
+}
+
+/// A bundle that was written
+#[derive(Debug)]
+pub struct Bundle {
+    pub path: PathBuf,
+    /// The size of the compressed bundle
+    pub size: u64,
+    pub members: Vec<Member>,
+}
+
+/// Where a bundle of the small files of the directory goes: `small-files.tar.gz` when
+/// compressing with gzip, numbered from 1 when the files are split over several bundles, and
+/// relocated into the output directory like any other output
+pub fn bundle_path(directory: &Path, number: Option<usize>, settings: &Settings) -> PathBuf {
+    let name = match number {
+        Some(number) => format!("{}-{}.tar", BUNDLE_NAME, number),
+        None => format!("{}.tar", BUNDLE_NAME),
+    };
+    output_path(&directory.join(name), settings)
+}
+
+/// The path of the manifest of the bundle at the path
+pub fn manifest_path(bundle_path: &Path) -> PathBuf {
+    let mut manifest_path = bundle_path.as_os_str().to_owned();
+    manifest_path.push(".");
+    manifest_path.push(MANIFEST_EXTENSION);
+    manifest_path.into()
+}
+
+/// Whether the path is the manifest of a bundle next to it
+pub async fn is_manifest(path: &Path) -> bool {
+    path.extension()
+        .is_some_and(|extension| extension == MANIFEST_EXTENSION)
+        && tokio::fs::metadata(path.with_extension(""))
+            .await
+            .is_ok_and(|metadata| metadata.is_file())
+}
+
+/// Whether the file is bundled rather than compressed on its own: when compressing into files
+/// with `batch_small` set, and the file is smaller than that
+pub async fn is_small(path: &Path, settings: &Settings) -> bool {
+    let Some(batch_small) = settings.batch_small else {
+        return false;
+    };
+    if !settings.b_zip || settings.to_stdout || settings.test_only || http::url(path).is_some() {
+        return false;
+    }
+    tokio::fs::symlink_metadata(path)
+        .await
+        .is_ok_and(|metadata| metadata.is_file() && metadata.len() < batch_small)
+}
+
+/// Writes the files into a bundle at the path, under their file names, through a temporary file
+/// that is only renamed into place once the bundle is complete. The manifest is then written
+/// next to it: a header and a tab-separated line per file with where its contents start in the
+/// tar archive, its size, and its name.
+pub async fn write(
+    files: &[PathBuf],
+    path: &Path,
+    settings: &Arc<Settings>,
+) -> Result<Bundle, SuperGzipError> {
+    refuse_existing(path, settings).await?;
+    let named: Vec<(PathBuf, String)> = files
+        .iter()
+        .map(|file| {
+            let name = file.file_name().unwrap_or_default().to_string_lossy();
+            (file.clone(), name.into_owned())
+        })
+        .collect();
+
+    let temp_path = temp_path(path);
+    let file = tokio::fs::File::create(&temp_path).await?;
+    let mut writer = TokioBufWriter::with_capacity(settings.write_buffer, file);
+    let compressed = compress_tar(&mut writer, settings, |mut tar_writer| async move {
+        let mut members = Vec::new();
+        tar::write_files(&named, &mut tar_writer, |entry, offset| {
+            members.push((entry.name.clone(), entry.size, offset))
+        })
+        .await?;
+        tar_writer.shutdown().await?;
+        Ok(members)
+    });
+    // A cancelled run abandons the bundle, which removes it below
+    let written = tokio::select! {
+        written = compressed => written,
+        () = settings.cancellation.abandon_requested() => Err(SuperGzipError::Interrupted),
+    };
+    let written = match written {
+        Ok(written) => writer
+            .shutdown()
+            .await
+            .map(|()| written)
+            .map_err(Into::into),
+        Err(e) => Err(e),
+    };
+    let (members, size) = match written {
+        Ok(written) => written,
+        Err(e) => {
+            let _ = tokio::fs::remove_file(&temp_path).await;
+            return Err(e);
+        }
+    };
+    move_into_place(&temp_path, path).await?;
+
+    let mut manifest = String::from("offset\tsize\tname\n");
+    for (name, size, offset) in &members {
+        manifest.push_str(&format!("{}\t{}\t{}\n", offset, size, name));
+    }
+    tokio::fs::write(manifest_path(path), manifest).await?;
+    Ok(Bundle {
+        path: path.to_path_buf(),
+        size,
+        members: files
+            .iter()
+            .zip(members)
+            .map(|(file, (_, size, _))| Member {
+                path: file.clone(),
+                size,
+            })
+            .collect(),
+    })
+}
@@ -0,0 +1,62 @@
+//! Cancelling a run from the program that started it, or a job of the daemon, through a
+//! [`CancellationToken`].
+//!
+//! A cancelled run starts no more files and abandons the files in progress, like a second Ctrl-C
+//! does: their partial outputs are removed, and no original is deleted from then on, so every
+//! original is either still in place or replaced by its complete output. The run then fails
+//! with [`SuperGzipError::Interrupted`](crate::SuperGzipError::Interrupted).
+
+use crate::interrupt;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+/// How often waiting tasks check whether the run was cancelled
+const POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+/// Cancels the runs it is given to, from any thread. Clones share the same state, so a clone
+/// kept by the caller cancels the run that the settings were handed to.
+#[derive(Clone, Debug, Default)]
+pub struct CancellationToken {
+    cancelled: Arc<AtomicBool>,
+}
+
+impl CancellationToken {
+    pub fn new() -> CancellationToken {
+        CancellationToken::default()
+    }
+
+    /// Cancels the runs of the token. Cancelling again does nothing.
+    pub fn cancel(&self) {
+        self.cancelled.store(true, Ordering::SeqCst);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.cancelled.load(Ordering::SeqCst)
+    }
+
+    /// Whether new files should no longer be started, because of the token or a signal
+    pub(crate) fn stopping(&self) -> bool {
+        self.is_cancelled() || interrupt::stopping()
+    }
+
+    /// Whether the files in progress should be abandoned, and no more originals deleted,
+    /// because of the token or a second signal
+    pub(crate) fn abandoning(&self) -> bool {
+        self.is_cancelled() || interrupt::cancelled()
+    }
+
+    /// Waits until new files should no longer be started
+    pub(crate) async fn stop_requested(&self) {
+        while !self.stopping() {
+            tokio::time::sleep(POLL_INTERVAL).await;
+        }
+    }
+
+    /// Waits until the files in progress should be abandoned
+    pub(crate) async fn abandon_requested(&self) {
+        while !self.abandoning() {
+            tokio::time::sleep(POLL_INTERVAL).await;
+        }
+    }
+}
@@ -0,0 +1,108 @@
+//! Decompressing files to stdout one after the other, like zcat, so that compressed files can be
+//! piped into other programs without restoring them to disk first.
+//!
+//! The codec of each file is recognized by its contents, so gzip and LZ4 files can be mixed.
+
+use crate::batch::open_input;
+use crate::codec::{self, Codec, CpuPool};
+use crate::error::{Operation, SuperGzipError};
+use crate::http;
+use crate::magic;
+use crate::settings::Settings;
+use std::path::{Path, PathBuf};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+
+/// Decompresses the stream into the writer with the codec its contents are compressed with,
+/// returning the number of bytes written
+pub async fn decompress_stream<R, W>(
+    mut reader: R,
+    writer: &mut W,
+    pool: &CpuPool,
+) -> Result<u64, SuperGzipError>
+where
+    R: AsyncRead + Unpin,
+    W: AsyncWrite + Unpin,
+{
+    let (format, start) = magic::sniff_reader(&mut reader).await?;
+    let codec = match format {
+        Some(format) => Codec::ALL
+            .into_iter()
+            .find(|codec| codec.format() == format)
+            .ok_or(SuperGzipError::WrongFormat(format, Codec::Gzip))?,
+        None => {
+            return Err(SuperGzipError::IO(std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                "not compressed with gzip or LZ4",
+            )))
+        }
+    };
+    codec::decompress(codec, start.chain(reader), writer, pool)
+        .await
+        .map_err(|e| codec.header_error(&e).unwrap_or(SuperGzipError::IO(e)))
+}
+
+/// Opens the file (or download) at the path and decompresses it into the writer
+pub async fn decompress_path<W>(
+    path: &Path,
+    writer: &mut W,
+    settings: &Settings,
+) -> Result<u64, SuperGzipError>
+where
+    W: AsyncWrite + Unpin,
+{
+    let (input, _) = open_input(path, settings).await?;
+    decompress_stream(input, writer, settings.cpu_pool()).await
+}
+
+/// Whether the error is from the reader of the output going away, e.g. `head` having read enough
+fn is_broken_pipe(error: &SuperGzipError) -> bool {
+    matches!(error, SuperGzipError::IO(e) if e.kind() == std::io::ErrorKind::BrokenPipe)
+}
+
+/// Decompresses the files at the paths to the writer in order, preceding each with a
+/// `==> path <==` line if `header` is set. Files that fail are reported and left out, and their
+/// errors are returned once all files are done. Stops early without an error when the writer is
+/// closed.
+pub async fn cat<W>(
+    paths: Vec<PathBuf>,
+    header: bool,
+    writer: &mut W,
+    settings: &Settings,
+) -> Result<Vec<SuperGzipError>, SuperGzipError>
+where
+    W: AsyncWrite + Unpin,
+{
+    let mut errors = Vec::new();
+    let mut first = true;
+    for path in paths {
+        // Directories matched by the patterns are left out, as when decompressing
+        let is_file = tokio::fs::metadata(&path).await.is_ok_and(|m| m.is_file());
+        if http::url(&path).is_none() && !is_file {
+            continue;
+        }
+        if header {
+            let separator = if first { "" } else { "\n" };
+            let line = format!("{}==> {} <==\n", separator, path.to_string_lossy());
+            match writer.write_all(line.as_bytes()).await {
+                Err(e) if e.kind() == std::io::ErrorKind::BrokenPipe => return Ok(errors),
+                result => result?,
+            }
+        }
+        first = false;
+        let result = decompress_path(&path, writer, settings).await;
+        let result = match result {
+            Ok(_) => writer.flush().await.map_err(SuperGzipError::IO),
+            Err(e) => Err(e),
+        };
+        match result {
+            Ok(()) => {}
+            Err(e) if is_broken_pipe(&e) => return Ok(errors),
+            Err(e) => {
+                let e = e.for_file(&path, Operation::Decompress);
+                eprintln!("{}", e);
+                errors.push(e);
+            }
+        }
+    }
+    Ok(errors)
+}
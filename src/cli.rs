@@ -0,0 +1,2715 @@
+//! The `super-gunzip` command line tool: its arguments, and the subcommands that run on them
+
+use crate::archive;
+use crate::backend::Backend;
+use crate::batch::{format_ratio, run_batch, summarize_errors};
+use crate::bench;
+use crate::cat;
+use crate::codec::Codec;
+use crate::color::ColorChoice;
+use crate::completions::{self, Shell};
+use crate::concat;
+use crate::config::{self, Profile};
+#[cfg(unix)]
+use crate::daemon;
+use crate::discovery::{discover, skip_reason, SkipReason};
+use crate::engine::{self, Engine};
+use crate::estimate;
+use crate::events::{self, EventStream};
+use crate::grep;
+use crate::gzip_header::{self, GzipInfo};
+use crate::index::{self, SeekIndex};
+use crate::info;
+use crate::interrupt;
+use crate::log::{LogFormat, LogLevel, Logger};
+use crate::progress::{format_bytes, parse_duration, parse_size};
+use crate::regex::Regex;
+use crate::report::{self, Totals};
+use crate::settings::{
+    available_threads, CollisionPolicy, Incompressible, Order, OutputFormat, Settings,
+    SymlinkPolicy, Verbosity,
+};
+use crate::split;
+use crate::trash::{self, Trash};
+use crate::watch::{watch, WatchOptions};
+use crate::{Operation, SuperGzipError};
+use clap::{ArgGroup, Args, CommandFactory, Parser, Subcommand};
+use std::ffi::OsString;
+use std::io::Write;
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::sync::Semaphore;
+
+/// A simple utility for compressing and decompressing files using the Gzip algorithm in a multithreaded manner.
+#[derive(Parser, Debug)]
+#[command(name = "super-gunzip", version)]
+struct SuperGunzip {
+    #[command(subcommand)]
+    commands: Commands,
+}
+
+/// Parses a thread count, where `auto` means one thread per CPU
+fn parse_num_threads(value: &str) -> Result<usize, String> {
+    if value.eq_ignore_ascii_case("auto") {
+        return Ok(available_threads());
+    }
+    match value.parse() {
+        Ok(0) => Err("must be at least 1".to_string()),
+        Ok(threads) => Ok(threads),
+        Err(_) => Err(format!("`{}` is not a number or auto", value)),
+    }
+}
+
+/// Parses a block size in KiB, which must be at least 1 and small enough for its size in bytes
+/// to fit
+fn parse_block_size(value: &str) -> Result<usize, String> {
+    match value.parse() {
+        Ok(block_size) if (1..=archive::MAX_BLOCK_SIZE).contains(&block_size) => Ok(block_size),
+        Ok(_) => Err(format!("must be from 1 to {}", archive::MAX_BLOCK_SIZE)),
+        Err(_) => Err(format!("`{}` is not a number", value)),
+    }
+}
+
+/// Parses a glob pattern
+fn parse_pattern(value: &str) -> Result<glob::Pattern, String> {
+    glob::Pattern::new(value).map_err(|e| e.to_string())
+}
+
+/// Parses a rate in bytes per second, e.g. `50M` or `50M/s`
+fn parse_rate(value: &str) -> Result<u64, String> {
+    match parse_size(value.strip_suffix("/s").unwrap_or(value))? {
+        0 => Err("must be more than 0".to_string()),
+        rate => Ok(rate),
+    }
+}
+
+/// Parses a subfield of the gzip extra field, e.g. `JB=nightly-42`, whose id is two letters
+fn parse_subfield(value: &str) -> Result<([u8; 2], Vec<u8>), String> {
+    let (id, data) = value
+        .split_once('=')
+        .ok_or_else(|| format!("`{}` is not of the form ID=VALUE", value))?;
+    match id.as_bytes() {
+        &[first, second] if first.is_ascii_alphabetic() && second.is_ascii_alphanumeric() => {
+            Ok(([first, second], data.as_bytes().to_vec()))
+        }
+        _ => Err(format!(
+            "the id `{}` is not two letters (or a letter and a digit)",
+            id
+        )),
+    }
+}
+
+/// The options shared by every subcommand that reads files matching patterns
+#[derive(Args, Debug)]
+struct InputArgs {
+    /// The paths or glob-like patterns to match files against, or a single - to read from stdin.
+    /// Required unless --files-from (or --mirror) is given
+    patterns: Vec<OsString>,
+
+    /// Also processes the paths listed in this file, one per line, or in stdin if it is -, e.g.
+    /// `find logs -name '*.log' -print0 | super-gunzip gzip --files-from - -0`. The paths are
+    /// taken as they are rather than as patterns.
+    #[arg(long, value_name = "FILE")]
+    files_from: Option<PathBuf>,
+
+    /// Whether the paths of --files-from are separated by NUL characters instead of newlines, as
+    /// printed by find -print0, for paths that contain newlines
+    #[arg(short = '0', long, requires = "files_from", action = clap::ArgAction::SetTrue)]
+    null: bool,
+
+    /// The maximum number of threads to split the work across, or auto for one per CPU. Use 1 to
+    /// process one file at a time. (default: auto)
+    #[arg(short, long, value_parser = parse_num_threads)]
+    num_threads: Option<usize>,
+
+    /// The maximum number of threads doing the CPU-bound (de)compression at once, across all
+    /// files, or auto for one per CPU. Reading and writing files is not limited by this.
+    #[arg(long, default_value = "auto", value_parser = parse_num_threads)]
+    cpu_threads: usize,
+
+    /// The most memory that the buffers of the files being processed at once may use, e.g. 512M
+    /// or 4G. Files wait for their share of it before they start, and a file needing more than
+    /// all of it is processed on its own.
+    #[arg(long, value_name = "SIZE", value_parser = parse_size)]
+    max_memory: Option<u64>,
+
+    /// The most files that the files being processed at once may have open, counting the input
+    /// and output of each, separately from --num-threads. Files that run out of file descriptors
+    /// anyway wait for the others to close theirs instead of failing.
+    #[arg(long, value_name = "COUNT", value_parser = clap::value_parser!(u32).range(2..))]
+    max_open_files: Option<u32>,
+
+    /// Whether to memory-map input files of 1 MiB or more instead of reading them, which can be
+    /// faster for multi-GB files on fast disks (Unix only). Files must not be truncated while
+    /// they are being read.
+    #[arg(long, action = clap::ArgAction::SetTrue)]
+    mmap: bool,
+
+    /// The most bytes per second to read and write, combined across all files, e.g. 50M for
+    /// 50 MiB/s. Useful to keep a background job from saturating disks other programs use.
+    #[arg(long, value_name = "RATE", value_parser = parse_rate)]
+    bwlimit: Option<u64>,
+
+    /// The size of the buffer that each input file is read through, e.g. 1M. Larger buffers mean
+    /// fewer, larger reads, which helps on network file systems and spinning disks.
+    #[arg(long, value_name = "SIZE", value_parser = parse_size, default_value = "256K")]
+    read_buffer: u64,
+
+    /// The size of the buffer that each output is written through, e.g. 1M
+    #[arg(long, value_name = "SIZE", value_parser = parse_size, default_value = "256K")]
+    write_buffer: u64,
+
+    /// Whether to record the time each file spends reading, (de)compressing, writing, and
+    /// waiting its turn, and print a breakdown at the end, to tell whether the run is held up by
+    /// the disks or the CPU
+    #[arg(long, action = clap::ArgAction::SetTrue)]
+    timings: bool,
+
+    /// Prints a line for each file as it starts and once it is done. Given twice (-vv), also
+    /// the exact bytes, duration, and time in each stage of each file; three times (-vvv), also
+    /// the settings the run resolved to and every path that was passed over
+    #[arg(short, long, action = clap::ArgAction::Count)]
+    verbose: u8,
+
+    /// Prints nothing but errors, e.g. for cron jobs
+    #[arg(short, long, action = clap::ArgAction::SetTrue, conflicts_with = "verbose")]
+    quiet: bool,
+
+    /// The format of the results: human-readable text, a single JSON document at the end of the
+    /// run, or one JSON object per file as soon as it is done (NDJSON)
+    #[arg(long, value_enum, default_value_t = OutputFormat::Text)]
+    format: OutputFormat,
+
+    /// When to color the statuses of files and the warnings and errors: only on a terminal
+    /// without NO_COLOR set (auto), always, or never
+    #[arg(long, value_enum, value_name = "WHEN", default_value_t = ColorChoice::Auto)]
+    color: ColorChoice,
+
+    /// Logs what the run does to stderr, one event per line, at this level and above: the run
+    /// and failed files at error, processed files at info, and skipped files at debug
+    #[arg(long, value_enum, value_name = "LEVEL")]
+    log_level: Option<LogLevel>,
+
+    /// Leaves out the files and directories whose name or path matches this glob pattern, e.g.
+    /// '*.tmp' or 'logs/current.log'. Can be given several times.
+    #[arg(long, value_name = "PATTERN", value_parser = parse_pattern)]
+    exclude: Vec<glob::Pattern>,
+
+    /// Leaves out the files and directories that git ignores, following the .gitignore files
+    /// of the repository the paths are in, so that e.g. '**/*' skips build artifacts
+    #[arg(long, action = clap::ArgAction::SetTrue)]
+    respect_gitignore: bool,
+
+    /// The format of the log events: text, or one JSON object per line for log pipelines (implies
+    /// --log-level info unless given)
+    #[arg(long, value_enum, value_name = "FORMAT")]
+    log_format: Option<LogFormat>,
+
+    /// Writes the progress of every file as one JSON object per line (discovered, started,
+    /// progress, finished, skipped, failed) to this open file descriptor, e.g. 3 with '3>events',
+    /// or to this file or named pipe, for wrappers that draw their own progress
+    #[arg(long, value_name = "FD|PATH")]
+    progress_json: Option<String>,
+
+    /// Whether to walk directories matched by the patterns and process every file inside them
+    #[arg(short, long, action = clap::ArgAction::SetTrue)]
+    recursive: bool,
+
+    /// The maximum depth to descend into directories when recursing, where 1 only processes the
+    /// files directly inside the matched directories (default: unlimited)
+    #[arg(long, requires = "recursive")]
+    max_depth: Option<usize>,
+
+    /// Skips files smaller than this size, e.g. 4K, where the gzip overhead outweighs the savings
+    #[arg(long, value_name = "SIZE", value_parser = parse_size)]
+    min_size: Option<u64>,
+
+    /// Skips files larger than this size, e.g. 10G
+    #[arg(long, value_name = "SIZE", value_parser = parse_size)]
+    max_size: Option<u64>,
+
+    /// Only processes files last modified at least this long ago, e.g. 1d, leaving files still
+    /// being written (like today's logs) alone. Takes s, m, h, d, or w units
+    #[arg(long, value_name = "DURATION", value_parser = parse_duration)]
+    older_than: Option<Duration>,
+
+    /// Only processes files last modified at most this long ago, e.g. 12h
+    #[arg(long, value_name = "DURATION", value_parser = parse_duration)]
+    newer_than: Option<Duration>,
+
+    /// Processes the files that symbolic links point to, deleting only the links afterwards
+    /// (default: skip symbolic links)
+    #[arg(long, conflicts_with_all = ["skip_symlinks", "preserve_symlinks"])]
+    follow_symlinks: bool,
+
+    /// Skips symbolic links, leaving the files they point to alone unless they are processed
+    /// themselves (the default)
+    #[arg(long, conflicts_with = "preserve_symlinks")]
+    skip_symlinks: bool,
+
+    /// Replaces symbolic links with links to the outputs of the files they point to, without
+    /// reading those files (e.g. a.txt.gz -> b.txt.gz for a.txt -> b.txt)
+    #[arg(long)]
+    preserve_symlinks: bool,
+
+    /// The suffix of compressed files, like gzip -S: appended when compressing and recognized
+    /// and removed when decompressing (default: gz for gzip, lz4 for LZ4)
+    #[arg(short = 'S', long)]
+    suffix: Option<String>,
+}
+
+/// The options that decide which files are worth compressing, shared by the subcommands that
+/// compress and by estimate
+#[derive(Args, Debug)]
+struct CompressibleArgs {
+    /// Compresses files whose contents are already compressed (gzip, zstd, xz, zip, PNG, JPEG,
+    /// etc.), which are otherwise skipped since compressing them again saves little or nothing
+    #[arg(long)]
+    force_compress: bool,
+
+    /// What to do with files that a few samples show would compress by less than 5%, like
+    /// encrypted or already compressed data that is not recognized by its first bytes: compress
+    /// them anyway, skip them, or store them in the gzip file without compressing them
+    #[arg(long, value_enum, value_name = "POLICY", default_value_t = Incompressible::Compress)]
+    incompressible: Incompressible,
+}
+
+/// The options of the subcommands that compress files
+#[derive(Args, Debug)]
+struct CompressArgs {
+    #[command(flatten)]
+    compressible: CompressibleArgs,
+
+    /// Keeps the original of a file instead of replacing it when compressing saved less than this
+    /// percentage of its size, removing the output (default: 0, any saving will do). Has no
+    /// effect with --keep-original, or on the outputs of --incompressible store
+    #[arg(long, value_name = "PERCENT", value_parser = parse_percent, default_value_t = 0.0)]
+    min_savings: f64,
+}
+
+/// The -1 to -9 shorthands of gzip for --level, hidden from the help since --level documents the
+/// levels
+#[derive(Args, Debug)]
+#[command(group(
+    ArgGroup::new("level_digits")
+        .args(["one", "two", "three", "four", "five", "six", "seven", "eight", "nine"])
+        .conflicts_with("level")
+))]
+struct LevelDigits {
+    #[arg(short = '1', hide = true, action = clap::ArgAction::SetTrue)]
+    one: bool,
+    #[arg(short = '2', hide = true, action = clap::ArgAction::SetTrue)]
+    two: bool,
+    #[arg(short = '3', hide = true, action = clap::ArgAction::SetTrue)]
+    three: bool,
+    #[arg(short = '4', hide = true, action = clap::ArgAction::SetTrue)]
+    four: bool,
+    #[arg(short = '5', hide = true, action = clap::ArgAction::SetTrue)]
+    five: bool,
+    #[arg(short = '6', hide = true, action = clap::ArgAction::SetTrue)]
+    six: bool,
+    #[arg(short = '7', hide = true, action = clap::ArgAction::SetTrue)]
+    seven: bool,
+    #[arg(short = '8', hide = true, action = clap::ArgAction::SetTrue)]
+    eight: bool,
+    #[arg(short = '9', hide = true, action = clap::ArgAction::SetTrue)]
+    nine: bool,
+}
+
+impl LevelDigits {
+    /// The level of the shorthand that was given, if any
+    fn level(&self) -> Option<u32> {
+        let digits = [
+            self.one, self.two, self.three, self.four, self.five, self.six, self.seven, self.eight,
+            self.nine,
+        ];
+        digits
+            .iter()
+            .position(|&given| given)
+            .map(|index| index as u32 + 1)
+    }
+}
+
+/// The options shared by every compression and decompression subcommand
+#[derive(Args, Debug)]
+struct BatchArgs {
+    #[command(flatten)]
+    input: InputArgs,
+
+    /// Processes every file under SRC into the same relative path under DST, creating the
+    /// directories, and never deletes or changes anything in SRC. Takes the place of the
+    /// patterns, --output-dir, and --keep-original.
+    #[arg(
+        long,
+        num_args = 2,
+        value_names = ["SRC", "DST"],
+        conflicts_with_all = ["patterns", "files_from"]
+    )]
+    mirror: Option<Vec<PathBuf>>,
+
+    /// The profile of the configuration file (~/.config/supergzip/config.toml) to take the
+    /// level, thread count, exclude patterns, and whether to keep the original files from.
+    /// The options given on the command line take precedence.
+    #[arg(long, value_name = "NAME")]
+    profile: Option<String>,
+
+    /// Whether to keep the original files after processing. By default, the original files are deleted.
+    #[arg(short, long, action = clap::ArgAction::SetTrue)]
+    keep_original: bool,
+
+    /// Whether to move the original files to the trash instead of deleting them, so that the
+    /// `undo` subcommand can restore them
+    #[arg(long, conflicts_with = "keep_original", action = clap::ArgAction::SetTrue)]
+    trash: bool,
+
+    /// Moves the original files into this directory instead of deleting them (or moving them to
+    /// the trash), creating it if it does not exist
+    #[arg(long, value_name = "DIR", conflicts_with = "keep_original")]
+    trash_dir: Option<PathBuf>,
+
+    /// Whether to overwrite the original files with random data before deleting them, for
+    /// sensitive data. Best-effort: copy-on-write file systems, SSDs, snapshots, and backups can
+    /// keep the original data anyway.
+    #[arg(
+        long,
+        conflicts_with_all = ["keep_original", "trash", "trash_dir"],
+        action = clap::ArgAction::SetTrue
+    )]
+    shred: bool,
+
+    /// Keeps this much space free on the file systems the outputs go to, e.g. 10G. The run
+    /// fails to start if one has less free, warns if the outputs may not fit, and pauses files
+    /// while one is below it, instead of failing them once the disk is full.
+    #[arg(long, value_name = "SIZE", value_parser = parse_size)]
+    min_free: Option<u64>,
+
+    /// Whether to write the (de)compressed data to stdout instead of to files, in the order the
+    /// files were matched. The original files are never deleted in this mode, which is implied
+    /// when reading from stdin.
+    #[arg(short = 'c', long, action = clap::ArgAction::SetTrue)]
+    stdout: bool,
+
+    /// The directory to write the (de)compressed files to, instead of next to the original files.
+    /// It is created if it does not exist.
+    #[arg(short, long)]
+    output_dir: Option<PathBuf>,
+
+    /// Whether to recreate the relative paths of the original files inside the output directory,
+    /// rather than writing every file directly into it
+    #[arg(long, requires = "output_dir", action = clap::ArgAction::SetTrue)]
+    preserve_structure: bool,
+
+    /// Whether to only print which files would be (de)compressed, skipped, and deleted, without
+    /// writing or removing anything
+    #[arg(long, action = clap::ArgAction::SetTrue)]
+    dry_run: bool,
+
+    /// Whether to overwrite existing output files. By default, files whose output already exists
+    /// are skipped with a warning.
+    #[arg(short, long, conflicts_with = "rename", action = clap::ArgAction::SetTrue)]
+    force: bool,
+
+    /// Whether to append a number to output file names that already exist or collide with the
+    /// output of another file, e.g. file.txt.1.gz, instead of skipping those files
+    #[arg(long, action = clap::ArgAction::SetTrue)]
+    rename: bool,
+
+    /// Whether to ask before replacing an existing output or deleting an original file, reading
+    /// the answers from the terminal. Files whose output is not replaced are skipped, and
+    /// originals that are not deleted are kept.
+    #[arg(short, long, conflicts_with = "force", action = clap::ArgAction::SetTrue)]
+    interactive: bool,
+
+    /// Whether to only process files whose output is missing or older than the file, replacing
+    /// outdated outputs and quietly skipping up-to-date ones, so the same command can be re-run
+    /// over a live directory
+    #[arg(long, action = clap::ArgAction::SetTrue)]
+    skip_newer: bool,
+
+    /// Whether to leave the timestamps, permissions, and ownership of output files as created,
+    /// rather than copying them from the original files
+    #[arg(long, action = clap::ArgAction::SetTrue)]
+    no_preserve: bool,
+
+    /// A journal to record completed files in, created if it does not exist. Files that an
+    /// earlier run with the same journal completed, and that have not changed since, are
+    /// skipped, so an interrupted run can pick up where it left off.
+    #[arg(long, value_name = "JOURNAL")]
+    resume: Option<PathBuf>,
+
+    /// A CSV file to write a row per file to once the run is done, replacing it if it exists: its
+    /// path, the action, input and output size, ratio, duration, status, and error (or why it was
+    /// skipped)
+    #[arg(long, value_name = "FILE")]
+    report: Option<PathBuf>,
+
+    /// The order to start processing files in, e.g. largest-first so that a huge file does not
+    /// start last and keep the run going alone on one core. Every file is discovered before the
+    /// first one starts (default: as they are discovered)
+    #[arg(long, value_enum, value_name = "ORDER")]
+    order: Option<Order>,
+
+    /// Whether to decompress each compressed file again after writing it and compare the result
+    /// with the original (by size, CRC32, and a 64-bit SipHash that the compressed file does not
+    /// store), keeping the original and reporting an error if they differ
+    #[arg(long, action = clap::ArgAction::SetTrue)]
+    verify: bool,
+
+    /// Whether to process files with the same contents only once, hard-linking (or copying) the
+    /// output of the first one for the others
+    #[arg(long, action = clap::ArgAction::SetTrue)]
+    dedupe: bool,
+
+    /// Whether to write the blocks of zeros of decompressed files out in full, instead of
+    /// leaving holes in their place that take no space on disk
+    #[arg(long, action = clap::ArgAction::SetTrue)]
+    no_sparse: bool,
+
+    /// Whether to stop starting new files as soon as a file fails, instead of carrying on with
+    /// the rest. The files in progress are finished.
+    #[arg(long, action = clap::ArgAction::SetTrue, conflicts_with = "max_errors")]
+    fail_fast: bool,
+
+    /// Stops starting new files once this many files have failed, to abort runs that go wrong
+    #[arg(long, value_name = "N", value_parser = clap::value_parser!(u64).range(1..))]
+    max_errors: Option<u64>,
+
+    /// Tries a file again up to this many times after a transient error, like a busy file or a
+    /// hiccup of a network file system, waiting 0.5s before the first retry and twice as long
+    /// (up to a minute) before each further one
+    #[arg(long, value_name = "N", default_value_t = 0)]
+    retries: usize,
+
+    /// The engine that runs the batch: tokio (async), or plain threads with blocking I/O (sync),
+    /// which is only built with the sync-engine cargo feature and refuses the options it does
+    /// not support, like --verify, --dedupe, and --resume
+    #[arg(long, value_enum, default_value_t = Engine::Async)]
+    engine: Engine,
+}
+
+#[derive(Subcommand, Debug)]
+enum Commands {
+    /// Compresses all files matching the given patterns using the Gzip algorithm.
+    /// Appends a .gz extension to the compressed files
+    Gzip {
+        #[command(flatten)]
+        batch: BatchArgs,
+
+        #[command(flatten)]
+        compress: CompressArgs,
+
+        /// The compression level, from 1 (fastest) to 9 (smallest output), also given as -1 to -9
+        /// like gzip. Defaults to 6
+        #[arg(short, long, value_parser = clap::value_parser!(u32).range(1..=9))]
+        level: Option<u32>,
+
+        #[command(flatten)]
+        level_digits: LevelDigits,
+
+        /// Splits each file into blocks of this many KiB and compresses the blocks in parallel across
+        /// the threads, producing a multi-member gzip file (like pigz). Useful for single large files.
+        #[arg(short, long, value_parser = parse_block_size)]
+        block_size: Option<usize>,
+
+        /// Whether to leave the original file name and modification time out of the gzip header,
+        /// like gzip -n, so that the output only depends on the file contents
+        #[arg(long, action = clap::ArgAction::SetTrue)]
+        no_name: bool,
+
+        /// Whether to make the output rsync-friendly, like gzip --rsyncable: the stream is flushed
+        /// at points that only depend on the nearby contents, so a change to a file only changes
+        /// the output near it, at the cost of slightly larger output
+        #[arg(long, action = clap::ArgAction::SetTrue, conflicts_with = "block_size")]
+        rsyncable: bool,
+
+        /// Splits each output into volumes of at most this size (e.g. 1G or 700M), written as
+        /// file.gz.000, file.gz.001, and so on. Every volume is a valid gzip file by itself, and
+        /// the `join` subcommand puts them back together
+        #[arg(
+            long,
+            value_name = "SIZE",
+            value_parser = parse_size,
+            conflicts_with_all = ["rsyncable", "stdout", "dedupe"]
+        )]
+        split_size: Option<u64>,
+
+        /// Adapts the compression level to where the run is held up, like zstd --adapt: files
+        /// are compressed in blocks, and the level goes down while the blocks wait for the CPU and
+        /// up while they wait for the disk, starting from --level
+        #[arg(long, action = clap::ArgAction::SetTrue, conflicts_with = "rsyncable")]
+        adaptive: bool,
+
+        /// Writes the outputs in the BGZF format of bgzip, blocks of at most 64 KiB that tools
+        /// like samtools or tabix can seek into, along with a file.gz.gzi index of the blocks
+        #[arg(
+            long,
+            action = clap::ArgAction::SetTrue,
+            conflicts_with_all = ["block_size", "rsyncable", "split_size"]
+        )]
+        bgzf: bool,
+
+        /// Bundles the files smaller than this size (e.g. 64K) into a small-files.tar.gz per
+        /// directory instead of compressing them one by one, along with a manifest of where each
+        /// file starts in the archive
+        #[arg(
+            long,
+            value_name = "SIZE",
+            value_parser = parse_size,
+            conflicts_with_all = ["stdout", "split_size", "bgzf"]
+        )]
+        batch_small: Option<u64>,
+
+        /// The most files to put in a bundle, numbering the bundles of a directory with more
+        /// small files, e.g. small-files-1.tar.gz (default: one bundle per directory)
+        #[arg(long, value_name = "COUNT", requires = "batch_small")]
+        bundle_files: Option<usize>,
+
+        /// Makes the outputs depend only on the contents and options, so that compressing the
+        /// same files twice gives identical bytes: no name is stored, and the time stored is
+        /// SOURCE_DATE_EPOCH if it is set, or else 0
+        #[arg(long, action = clap::ArgAction::SetTrue, conflicts_with = "adaptive")]
+        reproducible: bool,
+
+        /// Stores the text as the comment (FCOMMENT) of the gzip header, e.g. to tag the outputs
+        /// with the id of the job that wrote them
+        #[arg(long, value_name = "TEXT", conflicts_with = "bgzf")]
+        comment: Option<String>,
+
+        /// Stores a subfield in the extra field (FEXTRA) of the gzip header, whose id is two
+        /// letters, e.g. JB=nightly-42. Can be given several times
+        #[arg(
+            long,
+            value_name = "ID=VALUE",
+            value_parser = parse_subfield,
+            conflicts_with = "bgzf"
+        )]
+        extra: Vec<([u8; 2], Vec<u8>)>,
+
+        /// The deflate implementation to compress with. zlib-ng and libdeflate are only there when
+        /// built with the cargo features of the same name; libdeflate only compresses whole
+        /// buffers, so files are compressed in blocks (of 128 KiB unless --block-size is given)
+        #[arg(long, value_enum, default_value_t = Backend::Miniz)]
+        backend: Backend,
+    },
+
+    /// Decompresses all files matching the given patterns using the Gzip algorithm.
+    /// Removes the .gz extension from the decompressed files
+    Unzip {
+        #[command(flatten)]
+        batch: BatchArgs,
+
+        /// Whether to name the output files after the original file name stored in the gzip
+        /// header, like gunzip -N, instead of just removing the .gz extension. Files without a
+        /// stored name fall back to the latter, and name conflicts follow --force and --rename.
+        #[arg(short = 'N', long, action = clap::ArgAction::SetTrue)]
+        restore_name: bool,
+    },
+
+    /// Compresses all files matching the given patterns using the LZ4 algorithm, trading compression
+    /// ratio for speed. Appends a .lz4 extension to the compressed files
+    Lz4 {
+        #[command(flatten)]
+        batch: BatchArgs,
+
+        #[command(flatten)]
+        compress: CompressArgs,
+
+        /// The compression level, from 1 (fastest) to 12 (smallest output), also given as -1 to -9.
+        /// Defaults to 1
+        #[arg(short, long, value_parser = clap::value_parser!(u32).range(1..=Codec::Lz4.max_level() as i64))]
+        level: Option<u32>,
+
+        #[command(flatten)]
+        level_digits: LevelDigits,
+    },
+
+    /// Decompresses all files matching the given patterns using the LZ4 algorithm.
+    /// Removes the .lz4 extension from the decompressed files
+    Unlz4 {
+        #[command(flatten)]
+        batch: BatchArgs,
+    },
+
+    /// Converts all files matching the given patterns from one codec to the other, e.g. `--from gz
+    /// --to lz4`, streaming the decompressed data straight into the compression without an
+    /// intermediate file. The extension of the files is replaced, and their metadata is kept.
+    Recompress {
+        #[command(flatten)]
+        batch: BatchArgs,
+
+        /// The codec the files are compressed with now
+        #[arg(long, value_enum, value_name = "CODEC")]
+        from: Codec,
+
+        /// The codec to compress the files with instead
+        #[arg(long, value_enum, value_name = "CODEC")]
+        to: Codec,
+
+        /// The compression level of the new codec, from 1 (fastest) to 9 for gzip or 12 for LZ4
+        /// (smallest output)
+        #[arg(short, long, value_parser = clap::value_parser!(u32).range(1..=Codec::Lz4.max_level() as i64))]
+        level: Option<u32>,
+
+        /// Splits each file into blocks of this many KiB that are compressed in parallel across
+        /// the threads, when recompressing to gzip
+        #[arg(short, long, value_parser = parse_block_size)]
+        block_size: Option<usize>,
+    },
+
+    /// Tests the integrity of all gzip files matching the given patterns by decompressing them
+    /// without writing anything, validating their CRC32 checksums and sizes. Corrupt files are
+    /// reported by name
+    Test {
+        #[command(flatten)]
+        input: InputArgs,
+    },
+
+    /// Decompresses all files matching the given patterns to stdout in order, like zcat, without
+    /// writing anything to disk. gzip and LZ4 files are recognized by their contents
+    Cat {
+        #[command(flatten)]
+        input: InputArgs,
+
+        /// Whether to print a `==> file <==` line before the contents of each file
+        #[arg(long, action = clap::ArgAction::SetTrue)]
+        header: bool,
+    },
+
+    /// Searches all files matching the given patterns for lines matching a regular expression,
+    /// like zgrep, decompressing and searching the files in parallel without writing anything to
+    /// disk. Prints each matching line as file:line:text
+    Grep {
+        /// The extended regular expression to search for, e.g. 'ERROR|WARN' or '^\d{4}-'
+        regex: String,
+
+        #[command(flatten)]
+        input: InputArgs,
+
+        /// Whether letters match regardless of their case
+        #[arg(short = 'i', long, action = clap::ArgAction::SetTrue)]
+        ignore_case: bool,
+    },
+
+    /// Joins all gzip files matching the given patterns into a single multi-member gzip file, in
+    /// order, without recompressing them. Every file is checked first, and nothing is written if
+    /// one is not a valid gzip file
+    Concat {
+        /// The gzip file to write
+        output: PathBuf,
+
+        #[command(flatten)]
+        input: InputArgs,
+
+        /// Adds the files to the end of the output if it exists already, instead of refusing to
+        /// replace it
+        #[arg(short, long, action = clap::ArgAction::SetTrue)]
+        append: bool,
+
+        /// Replaces the output if it exists already
+        #[arg(short, long, conflicts_with = "append", action = clap::ArgAction::SetTrue)]
+        force: bool,
+    },
+
+    /// Joins the volumes written by --split-size back into the gzip files they were split from,
+    /// e.g. file.gz from file.gz.000, file.gz.001, and so on, for all volumes matching the given
+    /// patterns. Every volume is checked first, and the volumes are deleted once joined
+    Join {
+        #[command(flatten)]
+        input: InputArgs,
+
+        /// Whether to keep the volumes after joining them
+        #[arg(short, long, action = clap::ArgAction::SetTrue)]
+        keep: bool,
+
+        /// Replaces the joined file if it exists already
+        #[arg(short, long, action = clap::ArgAction::SetTrue)]
+        force: bool,
+    },
+
+    /// Restores the original files that the latest run with --trash or --trash-dir moved to the
+    /// trash, deleting the outputs they were replaced by
+    Undo {
+        /// Restores the files of every run in the log of trashed files, not just the latest
+        #[arg(long, action = clap::ArgAction::SetTrue)]
+        all: bool,
+
+        /// Only lists the files that would be restored, and where they are in the trash
+        #[arg(long, action = clap::ArgAction::SetTrue)]
+        list: bool,
+
+        /// Whether to keep the outputs that replaced the restored files
+        #[arg(long, action = clap::ArgAction::SetTrue)]
+        keep_outputs: bool,
+
+        /// Replaces files that exist again where a restored file goes
+        #[arg(short, long, action = clap::ArgAction::SetTrue)]
+        force: bool,
+
+        /// Whether to print each file as it is restored
+        #[arg(short, long, action = clap::ArgAction::SetTrue)]
+        verbose: bool,
+    },
+
+    /// Builds a seek index of every gzip file matching the given patterns, written next to it as
+    /// file.gz.gzi, so that the `range` subcommand can read parts of the decompressed data
+    /// without decompressing everything before them. BGZF files are indexed like bgzip -i does
+    Index {
+        #[command(flatten)]
+        input: InputArgs,
+
+        /// The amount of decompressed data between the points that decompression can start from
+        /// (e.g. 1M): a smaller span makes ranges quicker to read and the index larger
+        #[arg(long, value_name = "SIZE", value_parser = parse_size, default_value = "4M")]
+        span: u64,
+    },
+
+    /// Writes a range of the decompressed data of a gzip file to stdout, decompressing from the
+    /// last point of its index (built by the `index` subcommand) before the range
+    Range {
+        /// The indexed gzip file to read from
+        file: PathBuf,
+
+        /// The offset of the range in the decompressed data (e.g. 10G)
+        #[arg(long, value_name = "SIZE", value_parser = parse_size, default_value = "0")]
+        start: u64,
+
+        /// The length of the range (e.g. 1M), to the end of the data if not given
+        #[arg(long, value_name = "SIZE", value_parser = parse_size)]
+        length: Option<u64>,
+    },
+
+    /// Lists the compressed and uncompressed sizes, ratio, stored name, and modification time of
+    /// all gzip files matching the given patterns, without decompressing them
+    List {
+        #[command(flatten)]
+        input: InputArgs,
+    },
+
+    /// Prints every field of the headers of the gzip files matching the given patterns (MTIME,
+    /// FNAME, FCOMMENT, XFL, OS, and the subfields of FEXTRA), along with how many members they
+    /// have and the CRC32 and ISIZE of their trailers, which are checked by decompressing them.
+    /// Only the first member is shown in full unless --verbose is given
+    Info {
+        #[command(flatten)]
+        input: InputArgs,
+    },
+
+    /// Packs a directory tree into a single .tar.gz archive. The tar stream is compressed as it is
+    /// written, in parallel blocks across the threads, which saves space and inodes compared to
+    /// compressing many small files individually
+    #[command(alias = "tar")]
+    Archive {
+        #[command(flatten)]
+        args: ArchiveArgs,
+    },
+
+    /// Unpacks .tar.gz / .tgz archives. Entries are never written outside of the output
+    /// directory: names containing .. and paths through symlinks are skipped with a warning
+    Extract {
+        /// The archives to unpack
+        #[arg(required = true)]
+        archives: Vec<PathBuf>,
+
+        /// The directory to unpack the archives into (default: the current directory)
+        #[arg(short, long)]
+        output_dir: Option<PathBuf>,
+
+        /// Whether to print the name of each entry as it is extracted
+        #[arg(short, long, action = clap::ArgAction::SetTrue)]
+        verbose: bool,
+
+        /// Whether to replace existing files. By default, entries that already exist are skipped
+        /// with a warning.
+        #[arg(short, long, action = clap::ArgAction::SetTrue)]
+        force: bool,
+
+        /// Whether to leave the permissions and modification times of extracted files as
+        /// created, rather than restoring them from the archive
+        #[arg(long, action = clap::ArgAction::SetTrue)]
+        no_preserve: bool,
+    },
+
+    /// Watches for new files matching the given patterns and compresses each one using the Gzip
+    /// algorithm once it has stopped changing, until stopped with Ctrl-C. Files that already
+    /// exist when watching starts are left alone unless --existing is given
+    Watch {
+        #[command(flatten)]
+        batch: BatchArgs,
+
+        #[command(flatten)]
+        compress: CompressArgs,
+
+        /// The compression level, from 1 (fastest) to 9 (smallest output). Defaults to 6
+        #[arg(short, long, value_parser = clap::value_parser!(u32).range(1..=9))]
+        level: Option<u32>,
+
+        /// How often to look for new files, in seconds
+        #[arg(long, default_value_t = 2.0, value_parser = positive_seconds)]
+        interval: f64,
+
+        /// How long a new file must go without changing size or modification time before it is
+        /// compressed, in seconds
+        #[arg(long, default_value_t = 5.0, value_parser = non_negative_seconds)]
+        settle: f64,
+
+        /// Whether to also compress the files that already exist when watching starts
+        #[arg(long, action = clap::ArgAction::SetTrue)]
+        existing: bool,
+
+        /// Serves Prometheus metrics of the files processed at http://ADDRESS/metrics, e.g.
+        /// 127.0.0.1:9184
+        #[arg(long, value_name = "ADDRESS")]
+        metrics: Option<SocketAddr>,
+    },
+
+    /// Runs in the background, accepting gzip, unzip, lz4, unlz4, and recompress jobs submitted
+    /// with the submit subcommand over a Unix domain socket and running them on one shared thread
+    /// pool, until stopped with Ctrl-C
+    #[cfg(unix)]
+    Daemon {
+        /// The socket to listen on (default: super-gunzip.sock in $XDG_RUNTIME_DIR, which must be
+        /// set otherwise)
+        #[arg(short, long)]
+        socket: Option<PathBuf>,
+
+        /// The maximum number of jobs to run at once. Further jobs wait in submission order
+        #[arg(short = 'j', long, default_value_t = 1, value_parser = clap::value_parser!(u64).range(1..))]
+        max_jobs: u64,
+
+        /// Serves Prometheus metrics of the files processed by all jobs at
+        /// http://ADDRESS/metrics, e.g. 127.0.0.1:9184
+        #[arg(long, value_name = "ADDRESS")]
+        metrics: Option<SocketAddr>,
+    },
+
+    /// Submits a job to a running daemon, given as the arguments of a gzip, unzip, lz4, unlz4, or
+    /// recompress command, e.g. `submit gzip -r logs`. Prints the id of the job
+    #[cfg(unix)]
+    Submit {
+        /// The socket the daemon listens on (default: as for daemon)
+        #[arg(short, long)]
+        socket: Option<PathBuf>,
+
+        /// The subcommand and arguments of the job
+        #[arg(required = true, trailing_var_arg = true, allow_hyphen_values = true)]
+        args: Vec<String>,
+    },
+
+    /// Prints the state and progress of the jobs of a running daemon as JSON
+    #[cfg(unix)]
+    Jobs {
+        /// The socket the daemon listens on (default: as for daemon)
+        #[arg(short, long)]
+        socket: Option<PathBuf>,
+
+        /// The job to describe (default: every job)
+        id: Option<u64>,
+    },
+
+    /// Cancels a queued or running job of a running daemon. A running job abandons the files in
+    /// progress, removing their partial outputs, and deletes no more originals
+    #[cfg(unix)]
+    Cancel {
+        /// The socket the daemon listens on (default: as for daemon)
+        #[arg(short, long)]
+        socket: Option<PathBuf>,
+
+        /// The job to cancel
+        id: u64,
+    },
+
+    /// Packs files and directory trees into a single .zip archive of deflated entries, which
+    /// Windows and macOS can open without extra tools
+    Zip {
+        /// The files and directories to archive
+        #[arg(required = true)]
+        paths: Vec<PathBuf>,
+
+        /// The archive to write (default: the name of the single file or directory followed by
+        /// .zip, or archive.zip, in the current directory)
+        #[arg(short, long)]
+        output: Option<PathBuf>,
+
+        /// The compression level, from 1 (fastest) to 9 (smallest output). Defaults to 6
+        #[arg(short, long, value_parser = clap::value_parser!(u32).range(1..=9))]
+        level: Option<u32>,
+
+        /// Whether to print the name of each entry as it is added
+        #[arg(short, long, action = clap::ArgAction::SetTrue)]
+        verbose: bool,
+
+        /// Whether to overwrite the archive if it already exists
+        #[arg(short, long, action = clap::ArgAction::SetTrue)]
+        force: bool,
+    },
+
+    /// Unpacks .zip archives with stored or deflated entries, checking each against its CRC32.
+    /// Like extract, entries are never written outside of the output directory
+    UnzipArchive {
+        /// The archives to unpack
+        #[arg(required = true)]
+        archives: Vec<PathBuf>,
+
+        /// The directory to unpack the archives into (default: the current directory)
+        #[arg(short, long)]
+        output_dir: Option<PathBuf>,
+
+        /// Whether to print the name of each entry as it is extracted
+        #[arg(short, long, action = clap::ArgAction::SetTrue)]
+        verbose: bool,
+
+        /// Whether to replace existing files. By default, entries that already exist are skipped
+        /// with a warning.
+        #[arg(short, long, action = clap::ArgAction::SetTrue)]
+        force: bool,
+
+        /// Whether to leave the permissions and modification times of extracted files as
+        /// created, rather than restoring them from the archive
+        #[arg(long, action = clap::ArgAction::SetTrue)]
+        no_preserve: bool,
+    },
+
+    /// Prints a completion script for the shell, which completes the subcommands, options, and
+    /// their values, including the names of the profiles in the configuration file
+    Completions {
+        /// The shell to complete in
+        #[arg(value_enum)]
+        shell: Shell,
+    },
+
+    /// Prints the names of the profiles in the configuration file, one per line
+    Profiles,
+
+    /// Compresses a sample of the file with each codec at several levels and prints the ratio,
+    /// compression and decompression speed (on one thread), and memory of each, to help choose
+    /// the codec and --level before a large run
+    Bench {
+        /// The file to take the sample from
+        file: PathBuf,
+
+        /// How much of the start of the file to use as the sample, e.g. 16M
+        #[arg(long, value_name = "SIZE", value_parser = parse_size, default_value = "64M")]
+        sample: u64,
+    },
+
+    /// Projects what compressing the files matching the given patterns would save, per
+    /// directory, by compressing samples spread over each file, to tell whether a large run is
+    /// worth it before starting it. Files that compressing would skip are left out
+    Estimate {
+        #[command(flatten)]
+        input: InputArgs,
+
+        #[command(flatten)]
+        compressible: CompressibleArgs,
+
+        /// How much of each file to sample, in percent, e.g. 1% (at least 64 KiB of each file)
+        #[arg(long, value_name = "PERCENT", value_parser = parse_sample_percent, default_value_t = 1.0)]
+        sample: f64,
+
+        /// The compression level to project, from 1 (fastest) to 9 (smallest output)
+        #[arg(short, long, value_parser = clap::value_parser!(u32).range(1..=9), default_value_t = 6)]
+        level: u32,
+    },
+}
+
+/// The options of the `archive` subcommand
+#[derive(Args, Debug)]
+struct ArchiveArgs {
+    /// The directory (or file) to archive
+    root: PathBuf,
+
+    /// The archive to write, or - for stdout (default: the name of the directory followed by
+    /// .tar.gz, in the current directory)
+    #[arg(short, long)]
+    output: Option<PathBuf>,
+
+    /// The compression level, from 1 (fastest) to 9 (smallest output). Defaults to 6
+    #[arg(short, long, value_parser = clap::value_parser!(u32).range(1..=9))]
+    level: Option<u32>,
+
+    /// The size in KiB of the blocks compressed in parallel (default: 128 when using more
+    /// than one thread)
+    #[arg(short, long, value_parser = parse_block_size)]
+    block_size: Option<usize>,
+
+    /// The maximum number of threads to compress with, or auto for one per CPU. Use 1 to
+    /// compress on a single thread.
+    #[arg(short, long, default_value = "auto", value_parser = parse_num_threads)]
+    num_threads: usize,
+
+    /// The maximum number of threads doing the CPU-bound compression at once, or auto for one
+    /// per CPU
+    #[arg(long, default_value = "auto", value_parser = parse_num_threads)]
+    cpu_threads: usize,
+
+    /// Whether to print the name of each entry as it is added
+    #[arg(short, long, action = clap::ArgAction::SetTrue)]
+    verbose: bool,
+
+    /// Whether to overwrite the archive if it already exists
+    #[arg(short, long, action = clap::ArgAction::SetTrue)]
+    force: bool,
+}
+
+/// Parses a number of seconds that can be zero
+fn non_negative_seconds(value: &str) -> Result<f64, String> {
+    match value.parse::<f64>() {
+        Ok(seconds) if seconds.is_finite() && seconds >= 0.0 => Ok(seconds),
+        _ => Err(format!("{} is not a number of seconds", value)),
+    }
+}
+
+/// Parses a percentage from 0 up to 100, e.g. `5` or `5%`
+fn parse_percent(value: &str) -> Result<f64, String> {
+    match value.strip_suffix('%').unwrap_or(value).parse::<f64>() {
+        Ok(percent) if (0.0..100.0).contains(&percent) => Ok(percent),
+        Ok(_) => Err("must be at least 0 and less than 100".to_string()),
+        Err(_) => Err(format!("`{}` is not a percentage", value)),
+    }
+}
+
+/// Parses the percentage of a file to sample, more than 0 and up to 100, e.g. `1` or `1%`
+fn parse_sample_percent(value: &str) -> Result<f64, String> {
+    match value.strip_suffix('%').unwrap_or(value).parse::<f64>() {
+        Ok(percent) if percent > 0.0 && percent <= 100.0 => Ok(percent),
+        Ok(_) => Err("must be more than 0 and at most 100".to_string()),
+        Err(_) => Err(format!("`{}` is not a percentage", value)),
+    }
+}
+
+/// Parses a number of seconds that must be more than zero
+fn positive_seconds(value: &str) -> Result<f64, String> {
+    match non_negative_seconds(value)? {
+        0.0 => Err("must be more than zero".to_string()),
+        seconds => Ok(seconds),
+    }
+}
+
+/// Creates the settings for reading the given inputs, which do not write any files until the
+/// output options are filled in
+fn input_settings(codec: Codec, b_zip: bool, input: &InputArgs) -> Result<Settings, clap::Error> {
+    // Reading from stdin only makes sense on its own
+    let from_stdin = input.patterns.iter().any(|pattern| pattern == "-");
+    if from_stdin && input.patterns.len() > 1 {
+        return Err(SuperGunzip::command().error(
+            clap::error::ErrorKind::ArgumentConflict,
+            "- (stdin) cannot be combined with other patterns",
+        ));
+    }
+
+    // An empty suffix would make every file look compressed
+    let suffix = match &input.suffix {
+        Some(suffix) => suffix.trim_start_matches('.').to_string(),
+        None => codec.extension().to_string(),
+    };
+    if suffix.is_empty() {
+        return Err(SuperGunzip::command().error(
+            clap::error::ErrorKind::InvalidValue,
+            "the suffix cannot be empty",
+        ));
+    }
+
+    let mut settings = Settings::new(codec, b_zip);
+    settings.num_threads = input.num_threads.unwrap_or_else(available_threads);
+    settings.cpu_threads = input.cpu_threads;
+    settings.max_memory = input.max_memory;
+    settings.max_open_files = input
+        .max_open_files
+        .map(|max_open_files| max_open_files as usize);
+    settings.mmap = input.mmap;
+    settings.bwlimit = input.bwlimit;
+    settings.read_buffer = input.read_buffer as usize;
+    settings.write_buffer = input.write_buffer as usize;
+    settings.timings = input.timings;
+    settings.keep_original = true;
+    settings.verbosity = Verbosity::new(input.verbose, input.quiet);
+    settings.color = input.color;
+    settings.to_stdout = from_stdin;
+    settings.recursive = input.recursive;
+    settings.max_depth = input.max_depth;
+    settings.excludes = input.exclude.clone();
+    settings.respect_gitignore = input.respect_gitignore;
+    settings.min_size = input.min_size;
+    settings.max_size = input.max_size;
+    settings.older_than = input.older_than;
+    settings.newer_than = input.newer_than;
+    settings.symlinks = match (input.follow_symlinks, input.preserve_symlinks) {
+        (true, _) => SymlinkPolicy::Follow,
+        (_, true) => SymlinkPolicy::Preserve,
+        _ => SymlinkPolicy::Skip,
+    };
+    settings.format = input.format;
+    settings.log = match (input.log_level, input.log_format) {
+        (None, None) => None,
+        (level, format) => Some(Logger::new(
+            level.unwrap_or(LogLevel::Info),
+            format.unwrap_or(LogFormat::Text),
+        )),
+    };
+    if let Some(target) = &input.progress_json {
+        let writer = events::open(target).map_err(|e| {
+            SuperGunzip::command().error(
+                clap::error::ErrorKind::InvalidValue,
+                format!("cannot write progress events to {}: {}", target, e),
+            )
+        })?;
+        settings.progress_json = Some(Arc::new(EventStream::new(writer)));
+    }
+    settings.suffix = suffix;
+    Ok(settings)
+}
+
+/// Applies the options that decide which files are worth compressing to the settings
+fn compressible_settings(settings: &mut Settings, compressible: &CompressibleArgs) {
+    settings.force_compress = compressible.force_compress;
+    settings.incompressible = compressible.incompressible;
+}
+
+/// Applies the options of a subcommand that compresses files to the settings
+fn compress_settings(settings: &mut Settings, compress: &CompressArgs) {
+    compressible_settings(settings, &compress.compressible);
+    settings.min_savings = compress.min_savings;
+}
+
+/// The patterns to process of a compression or decompression subcommand: the source of --mirror,
+/// escaped so that it only matches itself, or those of [`input_patterns`]
+fn batch_patterns(
+    batch: &BatchArgs,
+    job_directory: Option<&Path>,
+) -> Result<Vec<OsString>, clap::Error> {
+    match batch.mirror.as_deref() {
+        Some([source, _]) => Ok(vec![glob::Pattern::escape(&source.to_string_lossy()).into()]),
+        _ => input_patterns(&batch.input, job_directory),
+    }
+}
+
+/// The patterns to process: the given ones followed by the paths listed in the --files-from
+/// file, escaped so that they only match themselves. The file is looked up in the directory of
+/// a daemon job if there is one, and cannot be stdin then.
+fn input_patterns(
+    input: &InputArgs,
+    job_directory: Option<&Path>,
+) -> Result<Vec<OsString>, clap::Error> {
+    let mut patterns = input.patterns.clone();
+    // Only the batch subcommands have --mirror, so clap cannot tell that the patterns may be
+    // left out for it
+    if patterns.is_empty() && input.files_from.is_none() {
+        return Err(SuperGunzip::command().error(
+            clap::error::ErrorKind::MissingRequiredArgument,
+            "the following required arguments were not provided:\n  <PATTERNS>...",
+        ));
+    }
+    let Some(files_from) = &input.files_from else {
+        return Ok(patterns);
+    };
+    let error = |message: String| {
+        SuperGunzip::command().error(clap::error::ErrorKind::InvalidValue, message)
+    };
+    let list = match (files_from.as_os_str() == "-", job_directory) {
+        (true, Some(_)) => {
+            return Err(error(
+                "jobs cannot read --files-from from stdin".to_string(),
+            ))
+        }
+        (true, None) => {
+            let mut list = Vec::new();
+            std::io::Read::read_to_end(&mut std::io::stdin().lock(), &mut list).map(|_| list)
+        }
+        (false, directory) => std::fs::read(directory.map_or_else(
+            || files_from.clone(),
+            |directory| directory.join(files_from),
+        )),
+    }
+    .map_err(|e| error(format!("cannot read {}: {}", files_from.display(), e)))?;
+
+    let separator = match input.null {
+        true => b'\0',
+        false => b'\n',
+    };
+    for path in list.split(|byte| *byte == separator) {
+        // Tolerate lists written on Windows
+        let path = match input.null {
+            true => path,
+            false => path.strip_suffix(b"\r").unwrap_or(path),
+        };
+        if path.is_empty() {
+            continue;
+        }
+        #[cfg(unix)]
+        let path =
+            <std::ffi::OsStr as std::os::unix::ffi::OsStrExt>::from_bytes(path).to_os_string();
+        #[cfg(not(unix))]
+        let path = OsString::from(String::from_utf8_lossy(path).into_owned());
+        // Paths that are not valid UTF-8 are taken as they are by discovery already
+        patterns.push(match path.to_str() {
+            Some(path) => OsString::from(glob::Pattern::escape(path)),
+            None => path,
+        });
+    }
+    Ok(patterns)
+}
+
+/// Reads the profile with the given name from the configuration file
+fn load_profile(name: &str) -> Result<Profile, clap::Error> {
+    let error = |message: String| {
+        SuperGunzip::command().error(clap::error::ErrorKind::InvalidValue, message)
+    };
+    let path = config::default_path()
+        .ok_or_else(|| error("the configuration file cannot be found without $HOME".to_string()))?;
+    config::load_profile(&path, name).map_err(error)
+}
+
+/// Creates the settings for a batch with the given codec and direction from the shared batch
+/// options, filling in the options that are not given from the profile, if any. The codec is
+/// always the given one: the codec of the profile only limits its level to that codec.
+fn batch_settings(codec: Codec, b_zip: bool, batch: &BatchArgs) -> Result<Settings, clap::Error> {
+    let profile = match &batch.profile {
+        Some(name) => load_profile(name)?,
+        None => Profile::default(),
+    };
+    let mut settings = input_settings(codec, b_zip, &batch.input)?;
+    let level = profile
+        .level
+        .filter(|_| profile.codec.is_none_or(|c| c == codec));
+    if let Some(level) = level {
+        if level > codec.max_level() {
+            return Err(SuperGunzip::command().error(
+                clap::error::ErrorKind::InvalidValue,
+                format!(
+                    "the level of the profile must be at most {} for {}",
+                    codec.max_level(),
+                    codec.name()
+                ),
+            ));
+        }
+        settings.level = Some(level);
+    }
+    if let (None, Some(threads)) = (batch.input.num_threads, profile.threads) {
+        settings.num_threads = threads;
+    }
+    for pattern in &profile.excludes {
+        let pattern = parse_pattern(pattern).map_err(|e| {
+            SuperGunzip::command().error(
+                clap::error::ErrorKind::InvalidValue,
+                format!(
+                    "invalid exclude pattern `{}` in the profile: {}",
+                    pattern, e
+                ),
+            )
+        })?;
+        settings.excludes.push(pattern);
+    }
+    // Never delete originals when acting as a filter
+    settings.keep_original = batch.keep_original
+        || profile.keep_original == Some(true)
+        || batch.stdout
+        || settings.to_stdout;
+    settings.to_stdout |= batch.stdout;
+    let trash = match (&batch.trash_dir, batch.trash) {
+        (Some(directory), _) => Some(Trash::directory(directory)),
+        (None, true) => Some(Trash::platform()),
+        (None, false) => None,
+    };
+    settings.trash = trash.transpose().map_err(|e| {
+        SuperGunzip::command().error(
+            clap::error::ErrorKind::InvalidValue,
+            format!("cannot use the trash: {}", e),
+        )
+    })?;
+    settings.shred = batch.shred;
+    settings.min_free = batch.min_free;
+    settings.engine = batch.engine;
+    if let Some([source, destination]) = batch.mirror.as_deref() {
+        settings.recursive = true;
+        settings.output_dir = Some(destination.clone());
+        settings.mirror_root = Some(source.clone());
+        if batch.output_dir.is_some() || batch.preserve_structure || batch.stdout {
+            return Err(SuperGunzip::command().error(
+                clap::error::ErrorKind::ArgumentConflict,
+                "--mirror cannot be combined with --output-dir, --preserve-structure, or --stdout",
+            ));
+        }
+        // The source tree is never changed
+        settings.keep_original = true;
+    } else {
+        settings.output_dir = batch.output_dir.clone();
+    }
+    settings.preserve_structure = batch.preserve_structure;
+    settings.dry_run = batch.dry_run;
+    settings.collision = match (batch.force, batch.rename) {
+        (true, _) => CollisionPolicy::Force,
+        (_, true) => CollisionPolicy::Rename,
+        _ => CollisionPolicy::Skip,
+    };
+    settings.skip_newer = batch.skip_newer;
+    settings.interactive = batch.interactive;
+    if batch.interactive
+        && (settings.to_stdout && !batch.stdout
+            || batch.input.files_from.as_deref() == Some(Path::new("-")))
+    {
+        return Err(SuperGunzip::command().error(
+            clap::error::ErrorKind::ArgumentConflict,
+            "--interactive reads its answers from stdin, which cannot also be read for input",
+        ));
+    }
+    settings.retries = batch.retries;
+    settings.preserve_metadata = !batch.no_preserve;
+    settings.journal = batch.resume.clone();
+    settings.report_csv = batch.report.clone();
+    settings.order = batch.order;
+    settings.verify = batch.verify;
+    settings.dedupe = batch.dedupe;
+    settings.sparse = !batch.no_sparse;
+    settings.max_errors = match batch.fail_fast {
+        true => Some(1),
+        false => batch.max_errors.map(|max_errors| max_errors as usize),
+    };
+    Ok(settings)
+}
+
+/// Checks that the volumes of `--split-size` fit the blocks the outputs are compressed in
+fn check_split_size(settings: &Settings) -> Result<(), clap::Error> {
+    let Some(split_size) = settings.split_size else {
+        return Ok(());
+    };
+    let error = |message: String| {
+        SuperGunzip::command().error(clap::error::ErrorKind::InvalidValue, message)
+    };
+    if split_size < split::MIN_SPLIT_SIZE {
+        return Err(error(format!(
+            "--split-size must be at least {}",
+            format_bytes(split::MIN_SPLIT_SIZE)
+        )));
+    }
+    if settings.to_stdout {
+        return Err(error(
+            "--split-size cannot be used when writing to stdout".to_string(),
+        ));
+    }
+    match settings.block_size {
+        Some(block_size) if block_size as u64 * 1024 * 2 > split_size => Err(error(format!(
+            "--block-size must be at most half of --split-size ({} KiB)",
+            split_size / 2 / 1024
+        ))),
+        _ => Ok(()),
+    }
+}
+
+/// Creates the settings for recompressing from one codec to another. The level of a profile is
+/// used for the codec recompressed to.
+fn recompress_settings(
+    from: Codec,
+    to: Codec,
+    level: Option<u32>,
+    block_size: Option<usize>,
+    batch: &BatchArgs,
+) -> Result<Settings, clap::Error> {
+    let error = |kind, message: String| SuperGunzip::command().error(kind, message);
+    if from == to {
+        return Err(error(
+            clap::error::ErrorKind::ArgumentConflict,
+            format!("the files are already compressed with {}", to.name()),
+        ));
+    }
+    if batch.verify {
+        return Err(error(
+            clap::error::ErrorKind::ArgumentConflict,
+            "--verify is not supported when recompressing".to_string(),
+        ));
+    }
+    if block_size.is_some() && to != Codec::Gzip {
+        return Err(error(
+            clap::error::ErrorKind::ArgumentConflict,
+            "--block-size only applies when recompressing to gzip".to_string(),
+        ));
+    }
+    let mut settings = batch_settings(to, false, batch)?;
+    settings.codec = from;
+    if batch.input.suffix.is_none() {
+        settings.suffix = from.extension().to_string();
+    }
+    settings.recompress_to = Some(to);
+    settings.level = level.or(settings.level);
+    if let Some(level) = settings.level.filter(|level| *level > to.max_level()) {
+        return Err(error(
+            clap::error::ErrorKind::InvalidValue,
+            format!(
+                "the level must be at most {} for {}, not {}",
+                to.max_level(),
+                to.name(),
+                level
+            ),
+        ));
+    }
+    settings.block_size = block_size;
+    Ok(settings)
+}
+
+/// Fails unless the program was built with the backend and it can produce what the settings ask
+/// for
+fn check_backend(backend: Backend, settings: &Settings) -> Result<(), clap::Error> {
+    let message = if !backend.is_available() {
+        format!(
+            "this build has no {} backend: rebuild with --features {}",
+            backend.name(),
+            backend.name()
+        )
+    } else if backend.whole_buffers() && settings.rsyncable {
+        format!(
+            "--rsyncable cannot be used with the {} backend, which only compresses whole buffers",
+            backend.name()
+        )
+    } else {
+        return Ok(());
+    };
+    Err(SuperGunzip::command().error(clap::error::ErrorKind::ArgumentConflict, message))
+}
+
+/// The time of the SOURCE_DATE_EPOCH environment variable of reproducible builds, if it is set
+fn source_date_epoch() -> Result<Option<u32>, clap::Error> {
+    let Some(epoch) = std::env::var_os("SOURCE_DATE_EPOCH") else {
+        return Ok(None);
+    };
+    let epoch = epoch.to_string_lossy();
+    match epoch.trim().parse::<u64>() {
+        Ok(epoch) => Ok(Some(epoch.min(u32::MAX as u64) as u32)),
+        Err(_) => Err(SuperGunzip::command().error(
+            clap::error::ErrorKind::InvalidValue,
+            format!(
+                "SOURCE_DATE_EPOCH must be a number of seconds, not `{}`",
+                epoch
+            ),
+        )),
+    }
+}
+
+/// Creates the settings and patterns of a compression or decompression subcommand, or returns
+/// `None` for the other subcommands. The directory of a daemon job is where its --files-from
+/// file is read from.
+fn batch_job(
+    commands: Commands,
+    job_directory: Option<&Path>,
+) -> Option<Result<(Settings, Vec<OsString>), clap::Error>> {
+    let (settings, batch) = match commands {
+        Commands::Gzip {
+            batch,
+            compress,
+            level,
+            level_digits,
+            block_size,
+            no_name,
+            rsyncable,
+            split_size,
+            adaptive,
+            bgzf,
+            batch_small,
+            bundle_files,
+            reproducible,
+            comment,
+            extra,
+            backend,
+        } => {
+            let settings = batch_settings(Codec::Gzip, true, &batch).and_then(|mut settings| {
+                compress_settings(&mut settings, &compress);
+                settings.level = level.or(level_digits.level()).or(settings.level);
+                settings.block_size = block_size;
+                settings.store_name = !no_name;
+                settings.rsyncable = rsyncable;
+                settings.split_size = split_size;
+                settings.adaptive = adaptive;
+                settings.bgzf = bgzf;
+                settings.batch_small = batch_small;
+                settings.bundle_files = bundle_files;
+                if reproducible {
+                    settings.reproducible = true;
+                    settings.source_date_epoch = source_date_epoch()?;
+                }
+                // The length of the extra field is stored in 16 bits
+                if gzip_header::extra_field(&extra).len() > u16::MAX as usize {
+                    return Err(SuperGunzip::command().error(
+                        clap::error::ErrorKind::InvalidValue,
+                        "the --extra subfields must add up to less than 64 KiB",
+                    ));
+                }
+                settings.comment = comment;
+                settings.extra = extra;
+                check_backend(backend, &settings)?;
+                settings.backend = backend;
+                check_split_size(&settings)?;
+                Ok(settings)
+            });
+            (settings, batch)
+        }
+        Commands::Unzip {
+            batch,
+            restore_name,
+        } => {
+            let settings = batch_settings(Codec::Gzip, false, &batch).map(|mut settings| {
+                settings.restore_name = restore_name;
+                settings
+            });
+            (settings, batch)
+        }
+        Commands::Lz4 {
+            batch,
+            compress,
+            level,
+            level_digits,
+        } => {
+            let settings = batch_settings(Codec::Lz4, true, &batch).map(|mut settings| {
+                compress_settings(&mut settings, &compress);
+                settings.level = level.or(level_digits.level()).or(settings.level);
+                settings
+            });
+            (settings, batch)
+        }
+        Commands::Unlz4 { batch } => (batch_settings(Codec::Lz4, false, &batch), batch),
+        Commands::Recompress {
+            batch,
+            from,
+            to,
+            level,
+            block_size,
+        } => {
+            let settings = recompress_settings(from, to, level, block_size, &batch);
+            (settings, batch)
+        }
+        _ => return None,
+    };
+    Some(settings.and_then(|settings| Ok((settings, batch_patterns(&batch, job_directory)?))))
+}
+
+/// Parses the arguments of a job submitted to the daemon like those of a batch subcommand,
+/// resolving its relative paths against the working directory of the submitter
+#[cfg(unix)]
+fn parse_job(directory: &Path, args: &[String]) -> Result<(Settings, Vec<OsString>), String> {
+    // Keep clap's error on a single line, without its prefix or usage
+    let message = |e: clap::Error| {
+        let rendered = e.to_string();
+        let lines: Vec<&str> = rendered
+            .lines()
+            .take_while(|line| !line.is_empty())
+            .map(str::trim)
+            .collect();
+        lines.join(" ").trim_start_matches("error: ").to_string()
+    };
+    // A descriptor or relative path would be the daemon's, not the submitter's
+    if args
+        .iter()
+        .any(|arg| arg == "--progress-json" || arg.starts_with("--progress-json="))
+    {
+        return Err("jobs cannot write progress events; see --metrics of the daemon".to_string());
+    }
+    let command = SuperGunzip::try_parse_from(
+        std::iter::once("super-gunzip").chain(args.iter().map(String::as_str)),
+    )
+    .map_err(message)?;
+    let (mut settings, patterns) = batch_job(command.commands, Some(directory))
+        .ok_or("only gzip, unzip, lz4, unlz4, and recompress jobs can be submitted")?
+        .map_err(message)?;
+    if settings.to_stdout {
+        return Err("jobs cannot read from stdin or write to stdout".to_string());
+    }
+    if settings.interactive {
+        return Err("jobs cannot be interactive".to_string());
+    }
+    settings.output_dir = settings
+        .output_dir
+        .map(|output_dir| directory.join(output_dir));
+    settings.journal = settings.journal.map(|journal| directory.join(journal));
+    settings.mirror_root = settings
+        .mirror_root
+        .map(|mirror_root| directory.join(mirror_root));
+    settings.report_csv = settings
+        .report_csv
+        .map(|report_csv| directory.join(report_csv));
+    let prefix = glob::Pattern::escape(&directory.to_string_lossy());
+    let patterns = patterns
+        .into_iter()
+        .map(|pattern| {
+            let is_url = pattern.to_str().is_some_and(crate::http::is_url);
+            match Path::new(&pattern).is_absolute() || is_url {
+                true => pattern,
+                false => {
+                    let mut joined = OsString::from(format!("{}/", prefix));
+                    joined.push(pattern);
+                    joined
+                }
+            }
+        })
+        .collect();
+    Ok((settings, patterns))
+}
+
+/// Tests the integrity of the matched gzip files without writing anything
+async fn run_test(input: InputArgs) -> Result<(), SuperGzipError> {
+    let mut settings = input_settings(Codec::Gzip, false, &input).unwrap_or_else(|e| e.exit());
+    settings.test_only = true;
+    settings.to_stdout = false;
+    let patterns = input_patterns(&input, None).unwrap_or_else(|e| e.exit());
+    run_batch(settings, patterns).await.map(|_| ())
+}
+
+/// Decompresses the matched files (or stdin) to stdout in order
+async fn run_cat(input: InputArgs, header: bool) -> Result<(), SuperGzipError> {
+    let mut settings = input_settings(Codec::Gzip, false, &input).unwrap_or_else(|e| e.exit());
+    // Messages go to stderr, since stdout carries the data
+    settings.to_stdout = true;
+    let mut stdout = tokio::io::stdout();
+    if input.patterns == ["-"] {
+        let result = cat::decompress_stream(tokio::io::stdin(), &mut stdout, settings.cpu_pool());
+        return match result.await {
+            Err(SuperGzipError::IO(e)) if e.kind() == std::io::ErrorKind::BrokenPipe => Ok(()),
+            result => result.map(|_| ()),
+        };
+    }
+
+    let mut errors: Vec<SuperGzipError> = vec![];
+    let patterns = input_patterns(&input, None).unwrap_or_else(|e| e.exit());
+    let paths = discover(&patterns, &settings, &mut errors).await;
+    let count = paths.len();
+    errors.extend(cat::cat(paths, header, &mut stdout, &settings).await?);
+    let succeeded = count.saturating_sub(errors.len());
+    summarize_errors(&settings, errors, succeeded)
+}
+
+/// Searches the matched files (or stdin) for lines matching the regular expression, in parallel,
+/// printing the matches of each file in the order the files were matched
+async fn run_grep(regex: &str, input: InputArgs, ignore_case: bool) -> Result<(), SuperGzipError> {
+    let regex = Regex::new(regex, ignore_case).unwrap_or_else(|e| {
+        SuperGunzip::command()
+            .error(
+                clap::error::ErrorKind::InvalidValue,
+                format!("invalid regular expression: {}", e),
+            )
+            .exit()
+    });
+    let regex = Arc::new(regex);
+    let mut settings = input_settings(Codec::Gzip, false, &input).unwrap_or_else(|e| e.exit());
+    // Messages go to stderr, since stdout carries the matches
+    settings.to_stdout = true;
+    let settings = Arc::new(settings);
+    let mut stdout = std::io::BufWriter::new(std::io::stdout().lock());
+    let print = |stdout: &mut std::io::BufWriter<_>, name: &str, matches: &[grep::Match]| {
+        for found in matches {
+            writeln!(stdout, "{}:{}:{}", name, found.line_number, found.line)?;
+        }
+        stdout.flush()
+    };
+    let ignore_broken_pipe = |result: std::io::Result<()>| match result {
+        Err(e) if e.kind() == std::io::ErrorKind::BrokenPipe => Ok(()),
+        result => result,
+    };
+
+    if input.patterns == ["-"] {
+        let matches = grep::search_stream(tokio::io::stdin(), regex, settings.cpu_pool()).await?;
+        ignore_broken_pipe(print(&mut stdout, "(standard input)", &matches))?;
+        return Ok(());
+    }
+
+    let mut errors: Vec<SuperGzipError> = vec![];
+    let patterns = input_patterns(&input, None).unwrap_or_else(|e| e.exit());
+    let paths = discover(&patterns, &settings, &mut errors).await;
+    let semaphore = Arc::new(Semaphore::new(settings.num_threads));
+    let mut handles = Vec::new();
+    for path in paths {
+        let semaphore = Arc::clone(&semaphore);
+        let settings = Arc::clone(&settings);
+        let regex = Arc::clone(&regex);
+        handles.push(tokio::spawn(async move {
+            // Directories matched by the patterns are left out
+            let is_file = tokio::fs::metadata(&path).await.is_ok_and(|m| m.is_file());
+            if crate::http::url(&path).is_none() && !is_file {
+                return None;
+            }
+            let _permit = semaphore.acquire_owned().await.expect("Failed to acquire permit from semaphore. This is a bug in the program. Please report it.");
+            let result = grep::search(&path, regex, &settings).await;
+            Some((path, result))
+        }));
+    }
+
+    let mut searched = 0;
+    for handle in handles {
+        match handle.await {
+            Ok(Some((path, Ok(matches)))) => {
+                searched += 1;
+                let printed = print(&mut stdout, &path.to_string_lossy(), &matches);
+                if let Err(e) = printed {
+                    // Stop once the reader of the output is gone, e.g. head has read enough
+                    return ignore_broken_pipe(Err(e)).map_err(SuperGzipError::IO);
+                }
+            }
+            Ok(Some((path, Err(e)))) => {
+                let e = e.for_file(&path, Operation::Search);
+                settings.report(&e.to_string());
+                errors.push(e);
+            }
+            Ok(None) => {}
+            Err(join_error) => errors.push(join_error.into()),
+        }
+    }
+    summarize_errors(&settings, errors, searched)
+}
+
+/// Joins the matched gzip files into the output, after checking all of them
+async fn run_concat(
+    output: &Path,
+    input: InputArgs,
+    append: bool,
+    force: bool,
+) -> Result<(), SuperGzipError> {
+    let mut settings = input_settings(Codec::Gzip, false, &input).unwrap_or_else(|e| e.exit());
+    settings.to_stdout = false;
+    if force {
+        settings.collision = CollisionPolicy::Force;
+    }
+    if input.patterns == ["-"] {
+        SuperGunzip::command()
+            .error(
+                clap::error::ErrorKind::ArgumentConflict,
+                "concat cannot read from stdin, since every input is checked before it is written",
+            )
+            .exit()
+    }
+
+    let mut errors: Vec<SuperGzipError> = vec![];
+    let patterns = input_patterns(&input, None).unwrap_or_else(|e| e.exit());
+    let paths = discover(&patterns, &settings, &mut errors).await;
+    let (members, check_errors) = concat::check(paths, output, &settings).await;
+    errors.extend(check_errors);
+    if !errors.is_empty() {
+        settings.report(&format!("Nothing written to {}.", output.to_string_lossy()));
+        return summarize_errors(&settings, errors, 0);
+    }
+    if members.is_empty() {
+        return Err(SuperGzipError::IO(std::io::Error::new(
+            std::io::ErrorKind::NotFound,
+            "no gzip files to join",
+        )));
+    }
+
+    let written = concat::join(&members, output, append, &settings).await?;
+    if settings.verbose() {
+        let verb = if append { "Appended" } else { "Joined" };
+        settings.report(&format!(
+            "{} {} files ({}) to {}",
+            verb,
+            members.len(),
+            format_bytes(written),
+            output.to_string_lossy()
+        ));
+    }
+    Ok(())
+}
+
+/// Joins the volumes of each output that the matched volumes belong to, after checking them
+async fn run_join(input: InputArgs, keep: bool, force: bool) -> Result<(), SuperGzipError> {
+    let mut settings = input_settings(Codec::Gzip, false, &input).unwrap_or_else(|e| e.exit());
+    settings.to_stdout = false;
+    if force {
+        settings.collision = CollisionPolicy::Force;
+    }
+    let mut errors: Vec<SuperGzipError> = vec![];
+    let patterns = input_patterns(&input, None).unwrap_or_else(|e| e.exit());
+    let paths = discover(&patterns, &settings, &mut errors).await;
+
+    // Matching several volumes of the same output joins it once
+    let mut outputs: Vec<PathBuf> = Vec::new();
+    for path in paths {
+        match split::volume_base(&path) {
+            Some(output) if !outputs.contains(&output) => outputs.push(output),
+            Some(_) => {}
+            None => {
+                let e = SuperGzipError::IO(std::io::Error::new(
+                    std::io::ErrorKind::InvalidInput,
+                    "not a volume, whose name ends in a number like .000",
+                ));
+                let e = e.for_file(&path, Operation::Join);
+                settings.report(&e.to_string());
+                errors.push(e);
+            }
+        }
+    }
+
+    let mut joined = 0;
+    for output in outputs {
+        match split::join(&output, keep, &settings).await {
+            Ok((volumes, written)) => {
+                joined += 1;
+                if settings.verbose() {
+                    settings.report(&format!(
+                        "Joined {} volumes ({}) into {}",
+                        volumes,
+                        format_bytes(written),
+                        output.to_string_lossy()
+                    ));
+                }
+            }
+            Err(volume_errors) => errors.extend(volume_errors),
+        }
+    }
+    summarize_errors(&settings, errors, joined)
+}
+
+/// Restores the trashed files of the latest run (or every run), and removes them from the log of
+/// trashed files. Files that cannot be restored stay in the log.
+async fn run_undo(
+    all: bool,
+    list: bool,
+    keep_outputs: bool,
+    force: bool,
+    verbose: bool,
+) -> Result<(), SuperGzipError> {
+    let mut settings = Settings::new(Codec::Gzip, false);
+    settings.verbosity = Verbosity::new(verbose as u8, false);
+    let Some(log_path) = trash::log_path() else {
+        return Err(SuperGzipError::IO(std::io::Error::new(
+            std::io::ErrorKind::NotFound,
+            "the log of trashed files cannot be found without $HOME",
+        )));
+    };
+    let entries = trash::read_log(&log_path)?;
+    let latest = entries.last().map(|entry| entry.run.clone());
+    let (restored, kept): (Vec<_>, Vec<_>) = entries
+        .into_iter()
+        .partition(|entry| all || Some(&entry.run) == latest.as_ref());
+    if restored.is_empty() {
+        settings.report("Nothing to undo: no files were moved to the trash");
+        return Ok(());
+    }
+    if list {
+        for entry in &restored {
+            println!(
+                "{}\t{}\t{}",
+                gzip_header::format_mtime(entry.time.min(u32::MAX as u64) as u32),
+                entry.original.to_string_lossy(),
+                entry.trashed.to_string_lossy()
+            );
+        }
+        return Ok(());
+    }
+
+    // Put the latest files back first, so that a file trashed twice ends up as it was first
+    let mut errors = Vec::new();
+    let mut remaining = kept;
+    let mut count = 0;
+    for entry in restored.into_iter().rev() {
+        let restoring = entry.clone();
+        let result =
+            tokio::task::spawn_blocking(move || restoring.restore(force, keep_outputs)).await?;
+        match result {
+            Ok(()) => {
+                count += 1;
+                if settings.verbose() {
+                    settings.report(&format!("Restored {}", entry.original.to_string_lossy()));
+                }
+            }
+            Err(e) => {
+                let message = format!("cannot restore {}: {}", entry.original.to_string_lossy(), e);
+                errors.push(SuperGzipError::IO(std::io::Error::new(e.kind(), message)));
+                remaining.push(entry);
+            }
+        }
+    }
+    remaining.sort_by_key(|entry| entry.time);
+    trash::write_log(&log_path, &remaining)?;
+    settings.report(&format!("Restored {} files", count));
+    summarize_errors(&settings, errors, count)
+}
+
+/// Builds the seek index of each matched gzip file, in parallel on the CPU pool
+async fn run_index(input: InputArgs, span: u64) -> Result<(), SuperGzipError> {
+    let mut settings = input_settings(Codec::Gzip, false, &input).unwrap_or_else(|e| e.exit());
+    settings.to_stdout = false;
+    if input.patterns == ["-"] {
+        SuperGunzip::command()
+            .error(
+                clap::error::ErrorKind::ArgumentConflict,
+                "index cannot read from stdin, since the index is written next to the file",
+            )
+            .exit()
+    }
+    let mut errors: Vec<SuperGzipError> = vec![];
+    let patterns = input_patterns(&input, None).unwrap_or_else(|e| e.exit());
+    let paths = discover(&patterns, &settings, &mut errors).await;
+
+    // Start all files before waiting for any, so that they are indexed in parallel
+    let mut handles = Vec::new();
+    for path in paths {
+        if let Some(reason) = skip_reason(&path, &settings).await {
+            if settings.verbose() && reason != SkipReason::NotAFile {
+                settings.report(&format!("Skipping {}", path.to_string_lossy()));
+            }
+            continue;
+        }
+        let indexed = path.clone();
+        let handle = settings
+            .cpu_pool()
+            .spawn(move || index::index_file(&indexed, span.max(1)))
+            .await;
+        handles.push((path, handle));
+    }
+
+    let mut indexed = 0;
+    for (path, handle) in handles {
+        match handle
+            .await
+            .unwrap_or_else(|join_error| Err(join_error.into()))
+        {
+            Ok(points) => {
+                indexed += 1;
+                if settings.verbose() {
+                    settings.report(&format!(
+                        "Indexed {} ({} points)",
+                        path.to_string_lossy(),
+                        points
+                    ));
+                }
+            }
+            Err(e) => {
+                settings.report(&e.to_string());
+                errors.push(e);
+            }
+        }
+    }
+    summarize_errors(&settings, errors, indexed)
+}
+
+/// Writes a range of the decompressed data of the indexed gzip file to stdout
+async fn run_range(file: PathBuf, start: u64, length: Option<u64>) -> Result<(), SuperGzipError> {
+    let index = match SeekIndex::load(&file) {
+        Ok(index) => index,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound && file.is_file() => {
+            return Err(SuperGzipError::IO(std::io::Error::new(
+                std::io::ErrorKind::NotFound,
+                format!(
+                    "{} has no index, which `super-gunzip index` builds",
+                    file.to_string_lossy()
+                ),
+            )));
+        }
+        Err(e) => return Err(SuperGzipError::IO(e)),
+    };
+    let extracted = tokio::task::spawn_blocking(move || {
+        let mut stdout = std::io::BufWriter::new(std::io::stdout().lock());
+        index.extract(&file, start, length, &mut stdout)?;
+        stdout.flush()
+    })
+    .await?;
+    match extracted {
+        Err(e) if e.kind() == std::io::ErrorKind::BrokenPipe => Ok(()),
+        Err(e) => Err(Codec::Gzip
+            .header_error(&e)
+            .unwrap_or(SuperGzipError::IO(e))),
+        Ok(()) => Ok(()),
+    }
+}
+
+/// Prints every header field of the matched gzip files, inspecting them in parallel
+async fn run_info(input: InputArgs) -> Result<(), SuperGzipError> {
+    let mut settings = input_settings(Codec::Gzip, false, &input).unwrap_or_else(|e| e.exit());
+    settings.to_stdout = false;
+    let mut errors: Vec<SuperGzipError> = vec![];
+
+    // Start all files before waiting for any, so that they are inspected in parallel
+    let mut handles = Vec::new();
+    if input.patterns == ["-"] {
+        let handle = settings
+            .cpu_pool()
+            .spawn(|| info::inspect(std::io::stdin().lock()))
+            .await;
+        handles.push((PathBuf::from("-"), handle));
+    } else {
+        let patterns = input_patterns(&input, None).unwrap_or_else(|e| e.exit());
+        let paths = discover(&patterns, &settings, &mut errors).await;
+        for path in paths {
+            if let Some(reason) = skip_reason(&path, &settings).await {
+                if settings.verbose() && reason != SkipReason::NotAFile {
+                    settings.report(&format!("Skipping {}", path.to_string_lossy()));
+                }
+                continue;
+            }
+            let inspected = path.clone();
+            let handle = settings
+                .cpu_pool()
+                .spawn(move || {
+                    info::inspect(std::io::BufReader::new(std::fs::File::open(&inspected)?))
+                })
+                .await;
+            handles.push((path, handle));
+        }
+    }
+
+    // Print the files in the order they were matched
+    let mut inspected = Vec::new();
+    for (path, handle) in handles {
+        match handle
+            .await
+            .unwrap_or_else(|join_error| Err(join_error.into()))
+        {
+            Ok(inspection) => inspected.push((path, inspection)),
+            Err(e) => {
+                let e = Codec::Gzip
+                    .header_error(&e)
+                    .unwrap_or(SuperGzipError::IO(e));
+                let e = e.for_file(&path, Operation::Inspect);
+                settings.report(&e.to_string());
+                errors.push(e);
+            }
+        }
+    }
+
+    let lines = inspected
+        .iter()
+        .map(|(path, inspection)| info::render_json(path, inspection));
+    match settings.format {
+        OutputFormat::Json => {
+            settings.emit(&format!("[{}]", lines.collect::<Vec<_>>().join(",")));
+        }
+        OutputFormat::Ndjson => lines.for_each(|line| settings.emit(&line)),
+        OutputFormat::Text => {
+            for (path, inspection) in &inspected {
+                let text = info::render_text(path, inspection, settings.verbose());
+                settings.emit(text.trim_end());
+            }
+        }
+    }
+    summarize_errors(&settings, errors, inspected.len())
+}
+
+/// Reads the metadata of a single matched gzip file for `list`
+async fn list_file(path: &Path) -> Result<GzipInfo, SuperGzipError> {
+    GzipInfo::read(path).await.map_err(|e| {
+        Codec::Gzip
+            .header_error(&e)
+            .unwrap_or(SuperGzipError::IO(e))
+    })
+}
+
+/// Prints the metadata stored in the matched gzip files, reading them in parallel
+async fn run_list(input: InputArgs) -> Result<(), SuperGzipError> {
+    let mut settings = input_settings(Codec::Gzip, false, &input).unwrap_or_else(|e| e.exit());
+    settings.to_stdout = false;
+    let settings = Arc::new(settings);
+    let mut errors: Vec<SuperGzipError> = vec![];
+
+    let mut listed = Vec::new();
+    if input.patterns == ["-"] {
+        let mut data = Vec::new();
+        tokio::io::stdin().read_to_end(&mut data).await?;
+        match GzipInfo::from_bytes(&data) {
+            Ok(info) => listed.push((PathBuf::from("-"), info)),
+            Err(e) => errors.push(
+                Codec::Gzip
+                    .header_error(&e)
+                    .unwrap_or(SuperGzipError::IO(e)),
+            ),
+        }
+    } else {
+        let semaphmore = Arc::new(Semaphore::new(settings.num_threads));
+        let patterns = input_patterns(&input, None).unwrap_or_else(|e| e.exit());
+        let paths = discover(&patterns, &settings, &mut errors).await;
+        let mut handles = Vec::new();
+        for path in paths {
+            let resource_lock = Arc::clone(&semaphmore);
+            let settings = Arc::clone(&settings);
+            handles.push(tokio::spawn(async move {
+                if let Some(reason) = skip_reason(&path, &settings).await {
+                    if settings.verbose() && reason != SkipReason::NotAFile {
+                        settings.report(&format!("Skipping {}", path.to_string_lossy()));
+                    }
+                    return None;
+                }
+                let _permit = resource_lock.acquire_owned().await.expect("Failed to acquire permit from semaphore. This is a bug in the program. Please report it.");
+                Some((path.clone(), list_file(&path).await))
+            }));
+        }
+
+        // Print the files in the order they were matched
+        for handle in handles {
+            match handle.await {
+                Ok(Some((path, Ok(info)))) => listed.push((path, info)),
+                Ok(Some((path, Err(e)))) => {
+                    let e = e.for_file(&path, Operation::List);
+                    settings.report(&e.to_string());
+                    errors.push(e);
+                }
+                Ok(None) => {}
+                Err(join_error) => errors.push(join_error.into()),
+            }
+        }
+    }
+
+    let lines = listed
+        .iter()
+        .map(|(path, info)| report::list_json(path, info));
+    match settings.format {
+        OutputFormat::Json => {
+            settings.emit(&format!("[{}]", lines.collect::<Vec<_>>().join(",")));
+        }
+        OutputFormat::Ndjson => lines.for_each(|line| settings.emit(&line)),
+        OutputFormat::Text => {
+            let row =
+                |compressed: u64, uncompressed: u64, modified: &str, name: &str, file: &str| {
+                    let ratio = report::ratio(uncompressed, compressed)
+                        .map_or_else(|| "-".to_string(), |ratio| format!("{:.1}%", ratio * 100.0));
+                    format!(
+                        "{:>14} {:>14} {:>7}  {:<19}  {:<20}  {}",
+                        compressed, uncompressed, ratio, modified, name, file
+                    )
+                };
+            settings.emit(&format!(
+                "{:>14} {:>14} {:>7}  {:<19}  {:<20}  {}",
+                "compressed", "uncompressed", "ratio", "modified", "name", "file"
+            ));
+            for (path, info) in &listed {
+                let modified = match info.mtime {
+                    0 => "-".to_string(),
+                    mtime => gzip_header::format_mtime(mtime),
+                };
+                let name = info.name.as_ref().map_or_else(
+                    || "-".to_string(),
+                    |name| String::from_utf8_lossy(name).into_owned(),
+                );
+                settings.emit(&row(
+                    info.compressed_size,
+                    info.uncompressed_size,
+                    &modified,
+                    &name,
+                    &path.to_string_lossy(),
+                ));
+                // The tags of a file, if it has any, go on lines of their own below it
+                if let Some(comment) = &info.comment {
+                    settings.emit(&format!("  comment: {}", String::from_utf8_lossy(comment)));
+                }
+                if let Some(extra) = &info.extra {
+                    settings.emit(&format!("  extra: {}", gzip_header::format_extra(extra)));
+                }
+            }
+            if listed.len() > 1 {
+                settings.emit(&row(
+                    listed.iter().map(|(_, info)| info.compressed_size).sum(),
+                    listed.iter().map(|(_, info)| info.uncompressed_size).sum(),
+                    "",
+                    "",
+                    "(totals)",
+                ));
+            }
+        }
+    }
+    summarize_errors(&settings, errors, listed.len())
+}
+
+/// Packs a directory tree into a compressed tar archive
+async fn run_archive(args: ArchiveArgs) -> Result<(), SuperGzipError> {
+    let start = Instant::now();
+    let to_stdout = args.output.as_deref() == Some(Path::new("-"));
+    let output = match args.output {
+        Some(output) => output,
+        None => tokio::fs::canonicalize(&args.root)
+            .await
+            .ok()
+            .and_then(|root| archive::default_archive_name(&root))
+            .unwrap_or_else(|| PathBuf::from("archive.tar.gz")),
+    };
+
+    let mut settings = Settings::new(Codec::Gzip, true);
+    settings.level = args.level;
+    settings.num_threads = args.num_threads;
+    settings.cpu_threads = args.cpu_threads;
+    settings.block_size = match args.block_size {
+        None if settings.num_threads > 1 => Some(archive::DEFAULT_BLOCK_SIZE),
+        block_size => block_size,
+    };
+    settings.verbosity = Verbosity::new(args.verbose as u8, false);
+    settings.to_stdout = to_stdout;
+    if args.force {
+        settings.collision = CollisionPolicy::Force;
+    }
+    let settings = Arc::new(settings);
+
+    let summary = if to_stdout {
+        let mut stdout = tokio::io::stdout();
+        let summary =
+            archive::write_archive(&args.root, &mut stdout, Vec::new(), &settings).await?;
+        stdout.flush().await?;
+        summary
+    } else {
+        archive::archive_to_file(&args.root, &output, &settings)
+            .await
+            .map_err(|e| e.for_file(&output, Operation::Write))?
+    };
+    settings.report(&format!(
+        "Archived {} entries into {}: {} -> {} ({}) at {:.1} MB/s",
+        summary.entries,
+        if to_stdout {
+            "stdout".into()
+        } else {
+            output.to_string_lossy()
+        },
+        format_bytes(summary.input_size),
+        format_bytes(summary.output_size),
+        format_ratio(report::ratio(summary.input_size, summary.output_size)),
+        Totals {
+            files: summary.entries,
+            input_size: summary.input_size,
+            output_size: summary.output_size
+        }
+        .throughput(start.elapsed())
+    ));
+    Ok(())
+}
+
+/// Packs files and directory trees into a ZIP archive
+async fn run_zip(
+    paths: Vec<PathBuf>,
+    output: Option<PathBuf>,
+    settings: Settings,
+) -> Result<(), SuperGzipError> {
+    let start = Instant::now();
+    let output = match (output, paths.as_slice()) {
+        (Some(output), _) => output,
+        (None, [root]) => tokio::fs::canonicalize(root)
+            .await
+            .ok()
+            .and_then(|root| root.file_name().map(|name| name.to_owned()))
+            .map(|mut name| {
+                name.push(".zip");
+                PathBuf::from(name)
+            })
+            .unwrap_or_else(|| PathBuf::from("archive.zip")),
+        (None, _) => PathBuf::from("archive.zip"),
+    };
+    let settings = Arc::new(settings);
+    let summary = archive::zip_to_file(&paths, &output, &settings)
+        .await
+        .map_err(|e| e.for_file(&output, Operation::Write))?;
+    settings.report(&format!(
+        "Archived {} entries into {}: {} -> {} ({}) at {:.1} MB/s",
+        summary.entries,
+        output.to_string_lossy(),
+        format_bytes(summary.input_size),
+        format_bytes(summary.output_size),
+        format_ratio(report::ratio(summary.input_size, summary.output_size)),
+        Totals {
+            files: summary.entries,
+            input_size: summary.input_size,
+            output_size: summary.output_size
+        }
+        .throughput(start.elapsed())
+    ));
+    Ok(())
+}
+
+/// Unpacks each archive (ZIP archives when `zip` is set, compressed tar archives otherwise) into
+/// the output directory, one after the other
+async fn run_extract(
+    archives: Vec<PathBuf>,
+    output_dir: PathBuf,
+    settings: Settings,
+    zip: bool,
+) -> Result<(), SuperGzipError> {
+    let settings = Arc::new(settings);
+    let mut errors = Vec::new();
+    let mut extracted = 0;
+    for path in archives {
+        let start = Instant::now();
+        let result = if zip {
+            archive::extract_zip(&path, &output_dir, &settings).await
+        } else {
+            match tokio::fs::File::open(&path).await {
+                Ok(file) => archive::extract_archive(file, &output_dir, &settings).await,
+                Err(e) => Err(e.into()),
+            }
+        };
+        match result {
+            Ok(summary) => {
+                extracted += 1;
+                let input_size = tokio::fs::metadata(&path)
+                    .await
+                    .map_or(0, |metadata| metadata.len());
+                settings.report(&format!(
+                    "Extracted {} entries from {}{}: {} -> {} at {:.1} MB/s",
+                    summary.entries,
+                    path.to_string_lossy(),
+                    match summary.skipped {
+                        0 => String::new(),
+                        skipped => format!(" ({} skipped)", skipped),
+                    },
+                    format_bytes(input_size),
+                    format_bytes(summary.output_size),
+                    Totals {
+                        files: summary.entries,
+                        input_size,
+                        output_size: summary.output_size
+                    }
+                    .throughput(start.elapsed())
+                ));
+            }
+            Err(e) => {
+                let e = e.for_file(&path, Operation::Extract);
+                settings.report(&e.to_string());
+                errors.push(e);
+            }
+        }
+    }
+    summarize_errors(&settings, errors, extracted)
+}
+
+/// Sends a request to the daemon and prints the payload of its response
+#[cfg(unix)]
+fn send_request(socket: Option<PathBuf>, request: &str) -> Result<(), SuperGzipError> {
+    let socket = match socket {
+        Some(socket) => socket,
+        None => daemon::default_socket()?,
+    };
+    let response = daemon::request(&socket, request).map_err(|e| {
+        std::io::Error::new(
+            e.kind(),
+            format!("cannot reach the daemon on {}: {}", socket.display(), e),
+        )
+    })?;
+    match response {
+        Ok(payload) => {
+            println!("{}", payload);
+            Ok(())
+        }
+        Err(message) => Err(std::io::Error::other(message).into()),
+    }
+}
+
+/// Runs the command line tool on the arguments of the process
+#[tokio::main]
+pub async fn main() -> ExitCode {
+    match run(SuperGunzip::parse()).await {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            // The errors of the files have been reported already
+            if !matches!(e, SuperGzipError::Failed { .. }) {
+                eprintln!("Error: {}", e);
+            }
+            ExitCode::from(e.exit_code())
+        }
+    }
+}
+
+/// Measures each codec at several levels on a sample of the file and prints the results
+async fn run_bench(file: &Path, sample_size: u64) -> Result<(), SuperGzipError> {
+    let sample = bench::read_sample(file, sample_size).await?;
+    println!(
+        "Sample: {} of {}",
+        format_bytes(sample.len() as u64),
+        file.display()
+    );
+    println!();
+    println!(
+        "{:<6} {:>6} {:>7} {:>14} {:>14} {:>10}",
+        "codec", "level", "ratio", "compress", "decompress", "memory"
+    );
+    let mut has_default = false;
+    for codec in Codec::ALL.into_iter().filter(|codec| codec.is_available()) {
+        for &level in bench::levels(codec) {
+            let measurement = bench::measure(&sample, codec, level).await?;
+            let level = match measurement.default_level {
+                true => format!("{}*", level),
+                false => level.to_string(),
+            };
+            has_default |= measurement.default_level;
+            let ratio = measurement
+                .ratio()
+                .map_or_else(|| "-".to_string(), |ratio| format!("{:.1}%", ratio * 100.0));
+            println!(
+                "{:<6} {:>6} {:>7} {:>9.1} MB/s {:>9.1} MB/s {:>10}",
+                codec.name(),
+                level,
+                ratio,
+                measurement.compress_speed() / 1e6,
+                measurement.decompress_speed() / 1e6,
+                format_bytes(measurement.memory)
+            );
+        }
+    }
+    if has_default {
+        println!();
+        println!("* the level used when --level is not given");
+    }
+    Ok(())
+}
+
+/// Projects the savings of compressing the matched files from samples of them, per directory
+async fn run_estimate(
+    input: InputArgs,
+    compressible: CompressibleArgs,
+    percent: f64,
+    level: u32,
+) -> Result<(), SuperGzipError> {
+    let mut settings = input_settings(Codec::Gzip, true, &input).unwrap_or_else(|e| e.exit());
+    compressible_settings(&mut settings, &compressible);
+    settings.to_stdout = false;
+    if input.patterns == ["-"] {
+        SuperGunzip::command()
+            .error(
+                clap::error::ErrorKind::ArgumentConflict,
+                "estimate cannot read from stdin, since it samples files throughout",
+            )
+            .exit()
+    }
+    let mut errors: Vec<SuperGzipError> = vec![];
+    let patterns = input_patterns(&input, None).unwrap_or_else(|e| e.exit());
+    let paths = discover(&patterns, &settings, &mut errors).await;
+
+    // Start all files before waiting for any, so that they are sampled in parallel
+    let mut handles = Vec::new();
+    for path in paths {
+        if let Some(reason) = skip_reason(&path, &settings).await {
+            if settings.verbose() && reason != SkipReason::NotAFile {
+                settings.report(&format!(
+                    "Skipping {}: {}",
+                    path.to_string_lossy(),
+                    reason.describe()
+                ));
+            }
+            continue;
+        }
+        let sampled = path.clone();
+        let handle = settings
+            .cpu_pool()
+            .spawn(move || estimate::project_file(&sampled, percent, level))
+            .await;
+        handles.push((path, handle));
+    }
+
+    let mut projected = Vec::new();
+    for (path, handle) in handles {
+        match handle
+            .await
+            .unwrap_or_else(|join_error| Err(join_error.into()))
+        {
+            Ok(projection) => projected.push((path, projection)),
+            Err(e) => {
+                let e = SuperGzipError::IO(e);
+                let e = e.for_file(&path, Operation::Estimate);
+                settings.report(&e.to_string());
+                errors.push(e);
+            }
+        }
+    }
+
+    let directories = estimate::by_directory(
+        projected
+            .iter()
+            .map(|(path, projection)| (path.as_path(), *projection)),
+    );
+    let mut total = estimate::Projection::default();
+    directories
+        .values()
+        .for_each(|projection| total.add(projection));
+    match settings.format {
+        OutputFormat::Json => {
+            let rows: Vec<String> = directories
+                .iter()
+                .map(|(directory, projection)| estimate::to_json(Some(directory), projection))
+                .collect();
+            settings.emit(&format!(
+                "{{\"total\":{},\"directories\":[{}]}}",
+                estimate::to_json(None, &total),
+                rows.join(",")
+            ));
+        }
+        OutputFormat::Ndjson => {
+            for (directory, projection) in &directories {
+                settings.emit(&estimate::to_json(Some(directory), projection));
+            }
+        }
+        OutputFormat::Text => settings.emit(&estimate::render_text(&directories, &total)),
+    }
+    summarize_errors(&settings, errors, projected.len())
+}
+
+/// Runs the subcommand
+async fn run(args: SuperGunzip) -> Result<(), SuperGzipError> {
+    match args.commands {
+        Commands::Gzip { .. }
+        | Commands::Unzip { .. }
+        | Commands::Lz4 { .. }
+        | Commands::Unlz4 { .. }
+        | Commands::Recompress { .. } => {
+            let (settings, patterns) = batch_job(args.commands, None)
+                .expect("a batch subcommand")
+                .unwrap_or_else(|e| e.exit());
+            interrupt::install();
+            engine::run(settings, patterns).await.map(|_| ())
+        }
+        Commands::Test { input } => run_test(input).await,
+        Commands::Cat { input, header } => run_cat(input, header).await,
+        Commands::Grep {
+            regex,
+            input,
+            ignore_case,
+        } => run_grep(&regex, input, ignore_case).await,
+        Commands::Concat {
+            output,
+            input,
+            append,
+            force,
+        } => run_concat(&output, input, append, force).await,
+        Commands::Join { input, keep, force } => run_join(input, keep, force).await,
+        Commands::Undo {
+            all,
+            list,
+            keep_outputs,
+            force,
+            verbose,
+        } => run_undo(all, list, keep_outputs, force, verbose).await,
+        Commands::Index { input, span } => run_index(input, span).await,
+        Commands::Range {
+            file,
+            start,
+            length,
+        } => run_range(file, start, length).await,
+        Commands::List { input } => run_list(input).await,
+        Commands::Info { input } => run_info(input).await,
+        Commands::Archive { args } => run_archive(args).await,
+        Commands::Extract {
+            archives,
+            output_dir,
+            verbose,
+            force,
+            no_preserve,
+        } => {
+            let mut settings = Settings::new(Codec::Gzip, false);
+            settings.verbosity = Verbosity::new(verbose as u8, false);
+            settings.preserve_metadata = !no_preserve;
+            if force {
+                settings.collision = CollisionPolicy::Force;
+            }
+            let output_dir = output_dir.unwrap_or_else(|| PathBuf::from("."));
+            run_extract(archives, output_dir, settings, false).await
+        }
+        Commands::Watch {
+            batch,
+            compress,
+            level,
+            interval,
+            settle,
+            existing,
+            metrics,
+        } => {
+            if batch.input.patterns == ["-"] {
+                SuperGunzip::command()
+                    .error(
+                        clap::error::ErrorKind::InvalidValue,
+                        "- (stdin) cannot be watched",
+                    )
+                    .exit();
+            }
+            let mut settings =
+                batch_settings(Codec::Gzip, true, &batch).unwrap_or_else(|e| e.exit());
+            compress_settings(&mut settings, &compress);
+            settings.level = level.or(settings.level);
+            let options = WatchOptions {
+                interval: Duration::from_secs_f64(interval),
+                settle: Duration::from_secs_f64(settle),
+                existing,
+                metrics,
+            };
+            let patterns = batch_patterns(&batch, None).unwrap_or_else(|e| e.exit());
+            interrupt::install();
+            watch(settings, patterns, options).await
+        }
+        #[cfg(unix)]
+        Commands::Daemon {
+            socket,
+            max_jobs,
+            metrics,
+        } => {
+            let socket = match socket {
+                Some(socket) => socket,
+                None => daemon::default_socket()?,
+            };
+            daemon::serve(&socket, max_jobs as usize, metrics, Box::new(parse_job)).await
+        }
+        #[cfg(unix)]
+        Commands::Submit { socket, args } => {
+            // Tabs separate the arguments in the request
+            if args.iter().any(|arg| arg.contains(['\t', '\n'])) {
+                SuperGunzip::command()
+                    .error(
+                        clap::error::ErrorKind::InvalidValue,
+                        "job arguments cannot contain tabs or newlines",
+                    )
+                    .exit();
+            }
+            let directory = std::env::current_dir()?;
+            let request = format!(
+                "submit\t{}\t{}",
+                directory.to_string_lossy(),
+                args.join("\t")
+            );
+            send_request(socket, &request)
+        }
+        #[cfg(unix)]
+        Commands::Jobs { socket, id } => match id {
+            Some(id) => send_request(socket, &format!("status\t{}", id)),
+            None => send_request(socket, "status"),
+        },
+        #[cfg(unix)]
+        Commands::Cancel { socket, id } => send_request(socket, &format!("cancel\t{}", id)),
+        Commands::Zip {
+            paths,
+            output,
+            level,
+            verbose,
+            force,
+        } => {
+            let mut settings = Settings::new(Codec::Gzip, true);
+            settings.level = level;
+            settings.verbosity = Verbosity::new(verbose as u8, false);
+            if force {
+                settings.collision = CollisionPolicy::Force;
+            }
+            run_zip(paths, output, settings).await
+        }
+        Commands::UnzipArchive {
+            archives,
+            output_dir,
+            verbose,
+            force,
+            no_preserve,
+        } => {
+            let mut settings = Settings::new(Codec::Gzip, false);
+            settings.verbosity = Verbosity::new(verbose as u8, false);
+            settings.preserve_metadata = !no_preserve;
+            if force {
+                settings.collision = CollisionPolicy::Force;
+            }
+            let output_dir = output_dir.unwrap_or_else(|| PathBuf::from("."));
+            run_extract(archives, output_dir, settings, true).await
+        }
+        Commands::Completions { shell } => {
+            let script =
+                completions::generate(shell, &SuperGunzip::command(), &[("profile", "profiles")]);
+            print!("{}", script);
+            Ok(())
+        }
+        Commands::Bench { file, sample } => run_bench(&file, sample).await,
+        Commands::Estimate {
+            input,
+            compressible,
+            sample,
+            level,
+        } => run_estimate(input, compressible, sample, level).await,
+        Commands::Profiles => {
+            if let Some(path) = config::default_path() {
+                for name in config::profile_names(&path) {
+                    println!("{}", name);
+                }
+            }
+            Ok(())
+        }
+    }
+}
@@ -0,0 +1,1020 @@
+//! The compression formats and the streaming (de)compression of a single input
+
+use crate::adaptive::AdaptiveLevel;
+use crate::backend::{self, Backend};
+use crate::bgzf;
+use crate::entropy;
+use crate::error::SuperGzipError;
+use crate::features::Feature;
+use crate::gzip_header::{self, GzipOrigin, Trailer, TrailerMismatch};
+#[cfg(feature = "lz4")]
+use crate::lz4;
+use crate::magic::Format;
+use crate::settings::{Incompressible, Settings};
+use crate::timings::{self, Stage};
+use flate2::bufread::DeflateDecoder;
+use flate2::{Compression as BlockCompression, CrcWriter, GzBuilder};
+use std::io::{BufRead, BufReader, Read, Seek, Write};
+use std::path::Path;
+use std::sync::Arc;
+use std::time::Instant;
+use tokio::io::{
+    AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, Error as TokioIOError,
+    Result as TokioIOResult,
+};
+use tokio::sync::{mpsc, Semaphore};
+use tokio::task::JoinHandle;
+
+/// The size of the chunks passed between the async I/O and the threads doing the CPU work
+const CHUNK_SIZE: usize = 64 * 1024;
+
+/// The compression formats that files can be processed with
+#[derive(clap::ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Codec {
+    #[value(alias = "gz")]
+    Gzip,
+    Lz4,
+}
+
+impl Codec {
+    /// The error to report when decompression failed because the data does not start with a
+    /// valid header for this codec, as opposed to having corrupt contents
+    pub fn header_error(self, e: &TokioIOError) -> Option<SuperGzipError> {
+        if !matches!(
+            e.kind(),
+            std::io::ErrorKind::InvalidData | std::io::ErrorKind::InvalidInput
+        ) {
+            return None;
+        }
+        let message = e.to_string();
+        match self {
+            Codec::Gzip if message.eq_ignore_ascii_case("invalid gzip header") => {
+                Some(SuperGzipError::InvalidGzipHeader)
+            }
+            Codec::Lz4 if message.ends_with("bad magic number") => {
+                Some(SuperGzipError::InvalidLz4Frame)
+            }
+            _ => None,
+        }
+    }
+
+    /// The format that files compressed with this codec are recognized by
+    pub fn format(self) -> Format {
+        match self {
+            Codec::Gzip => Format::Gzip,
+            Codec::Lz4 => Format::Lz4,
+        }
+    }
+
+    /// Extensions (without the leading dot) that stand for the codec's extension following
+    /// another one, along with that other one, e.g. `tgz` for `tar.gz`
+    pub fn aliases(self) -> &'static [(&'static str, &'static str)] {
+        match self {
+            Codec::Gzip => &[("tgz", "tar"), ("taz", "tar"), ("svgz", "svg")],
+            Codec::Lz4 => &[],
+        }
+    }
+
+    /// The file extension (without the leading dot) of files compressed with this codec
+    pub fn extension(self) -> &'static str {
+        match self {
+            Codec::Gzip => "gz",
+            Codec::Lz4 => "lz4",
+        }
+    }
+
+    /// Every codec, in the order they are listed
+    pub const ALL: [Codec; 2] = [Codec::Gzip, Codec::Lz4];
+
+    /// The name of the codec, as used in configuration files
+    pub fn name(self) -> &'static str {
+        match self {
+            Codec::Gzip => "gzip",
+            Codec::Lz4 => "lz4",
+        }
+    }
+
+    /// The codec with the given name or extension, if there is one
+    pub fn from_name(name: &str) -> Option<Codec> {
+        Codec::ALL
+            .into_iter()
+            .find(|codec| name == codec.name() || name == codec.extension())
+    }
+
+    /// The highest compression level of the codec, where 1 is the lowest
+    pub fn max_level(self) -> u32 {
+        match self {
+            Codec::Gzip => 9,
+            Codec::Lz4 => 12,
+        }
+    }
+
+    /// The compression level of the codec when none is given: the fastest one for LZ4, which is
+    /// what it is chosen for
+    pub fn default_level(self) -> u32 {
+        match self {
+            Codec::Gzip => 6,
+            Codec::Lz4 => 1,
+        }
+    }
+
+    /// The cargo feature that the codec is built with, if it is optional
+    pub fn feature(self) -> Option<Feature> {
+        match self {
+            Codec::Gzip => None,
+            Codec::Lz4 => Some(Feature::Lz4),
+        }
+    }
+
+    /// Whether the program was built with the codec
+    pub fn is_available(self) -> bool {
+        self.feature().is_none_or(Feature::is_enabled)
+    }
+}
+
+/// The threads that CPU-bound (de)compression runs on, kept off the async runtime so that a slow,
+/// high-level compression does not hold up the file I/O of every other file
+#[derive(Clone, Debug)]
+pub struct CpuPool {
+    slots: Arc<Semaphore>,
+}
+
+impl CpuPool {
+    /// Creates a pool that runs at most this many functions at once
+    pub fn new(threads: usize) -> CpuPool {
+        CpuPool {
+            slots: Arc::new(Semaphore::new(threads.max(1))),
+        }
+    }
+
+    /// Waits for a free thread, then runs the function on it. The wait and the run count
+    /// towards the timings of the file being processed, if they are recorded.
+    pub async fn spawn<F, T>(&self, function: F) -> JoinHandle<T>
+    where
+        F: FnOnce() -> T + Send + 'static,
+        T: Send + 'static,
+    {
+        let start = Instant::now();
+        let permit = Arc::clone(&self.slots)
+            .acquire_owned()
+            .await
+            .expect("the CPU pool is never closed");
+        timings::record(Stage::Queue, start.elapsed());
+        let recorder = timings::current();
+        tokio::task::spawn_blocking(move || {
+            let _permit = permit;
+            timings::on_thread(recorder, function)
+        })
+    }
+}
+
+/// Reads the chunks sent from the async side, on a CPU thread
+struct ChunkReader {
+    chunks: mpsc::Receiver<Vec<u8>>,
+    current: Vec<u8>,
+    position: usize,
+}
+
+impl Read for ChunkReader {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        while self.position == self.current.len() {
+            let start = Instant::now();
+            let chunk = self.chunks.blocking_recv();
+            timings::idle(start.elapsed());
+            match chunk {
+                Some(chunk) => {
+                    self.current = chunk;
+                    self.position = 0;
+                }
+                None => return Ok(0),
+            }
+        }
+        let read = buf.len().min(self.current.len() - self.position);
+        buf[..read].copy_from_slice(&self.current[self.position..self.position + read]);
+        self.position += read;
+        Ok(read)
+    }
+}
+
+/// Collects what is written on a CPU thread into chunks sent to the async side
+struct ChunkWriter {
+    chunks: mpsc::Sender<Vec<u8>>,
+    buffer: Vec<u8>,
+}
+
+impl Write for ChunkWriter {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.buffer.extend_from_slice(buf);
+        if self.buffer.len() >= CHUNK_SIZE {
+            self.flush()?;
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        if self.buffer.is_empty() {
+            return Ok(());
+        }
+        let chunk = std::mem::replace(&mut self.buffer, Vec::with_capacity(CHUNK_SIZE));
+        let start = Instant::now();
+        let sent = self.chunks.blocking_send(chunk);
+        timings::idle(start.elapsed());
+        sent.map_err(|_| TokioIOError::from(std::io::ErrorKind::BrokenPipe))
+    }
+}
+
+/// Runs a synchronous codec on the CPU pool, streaming the data of the reader into it and its
+/// output into the writer while both are still being produced. Returns the number of bytes
+/// written.
+async fn on_pool<R, W, F>(
+    mut reader: R,
+    writer: &mut W,
+    pool: &CpuPool,
+    codec: F,
+) -> TokioIOResult<u64>
+where
+    R: AsyncRead + Unpin,
+    W: AsyncWrite + Unpin,
+    F: FnOnce(&mut ChunkReader, &mut ChunkWriter) -> std::io::Result<()> + Send + 'static,
+{
+    let (input_sender, input) = mpsc::channel(4);
+    let (output_sender, mut output) = mpsc::channel(4);
+    let task = pool
+        .spawn(move || {
+            let mut input = ChunkReader {
+                chunks: input,
+                current: Vec::new(),
+                position: 0,
+            };
+            let mut output = ChunkWriter {
+                chunks: output_sender,
+                buffer: Vec::with_capacity(CHUNK_SIZE),
+            };
+            codec(&mut input, &mut output)?;
+            output.flush()
+        })
+        .await;
+
+    // Dropping the channels when either side fails also stops the codec
+    let feed = async move {
+        loop {
+            let mut chunk = vec![0; CHUNK_SIZE];
+            let read = reader.read(&mut chunk).await?;
+            chunk.truncate(read);
+            // The codec only stops reading early when it fails, which its result reports
+            if read == 0 || input_sender.send(chunk).await.is_err() {
+                return Ok::<(), TokioIOError>(());
+            }
+        }
+    };
+    let drain = async move {
+        let mut written = 0;
+        while let Some(chunk) = output.recv().await {
+            writer.write_all(&chunk).await?;
+            written += chunk.len() as u64;
+        }
+        Ok(written)
+    };
+    let ((), written) = tokio::try_join!(feed, drain)?;
+    task.await??;
+    Ok(written)
+}
+
+fn gzip_block(
+    block: &[u8],
+    level: Option<u32>,
+    origin: Option<GzipOrigin>,
+    backend: Backend,
+) -> std::io::Result<Vec<u8>> {
+    // Each block becomes a complete, independent gzip member
+    let mut member = backend::gzip_member(backend, block, level)?;
+    if let Some(origin) = origin {
+        let header = origin.header(&member);
+        member.splice(..10, header);
+    }
+    Ok(member)
+}
+
+/// Compresses the input block by block in three overlapping stages, so that the disks and the
+/// CPU stay busy at the same time: a reader fills blocks and hands each to the CPU pool, the
+/// pool compresses them, and a writer drains the finished members in order. The stages are
+/// connected by a bounded channel of in-flight blocks.
+async fn gzip_blocks<R, W>(
+    mut reader: R,
+    writer: &mut W,
+    block_size: usize,
+    level: Option<u32>,
+    mut origin: Option<GzipOrigin>,
+    settings: &Settings,
+) -> Result<u64, SuperGzipError>
+where
+    R: AsyncRead + Unpin,
+    W: AsyncWrite + Unpin,
+{
+    // Empty blocks would never get through the input, leaving a single empty member
+    if block_size == 0 {
+        return Err(SuperGzipError::IO(TokioIOError::new(
+            std::io::ErrorKind::InvalidInput,
+            "the block size must be at least 1 KiB",
+        )));
+    }
+    let pool = settings.cpu_pool();
+    let backend = settings.backend;
+    // BGZF blocks have a fixed header, which leaves no room for the original name
+    let bgzf = settings.writes_bgzf();
+    if bgzf {
+        origin = None;
+    }
+    // Stored blocks are not compressed at any level, so there is nothing to adapt
+    let adaptive = settings.adaptive_level().filter(|_| level != Some(0));
+    // At most two blocks per worker are kept in flight, counting the one the reader is filling,
+    // so that memory usage stays bounded for huge files
+    let max_in_flight = settings.num_threads.max(1) * 2;
+    let (sender, mut receiver) = mpsc::channel(max_in_flight - 1);
+
+    // Dropping the channel when the writer fails also stops the reader
+    let read = async move {
+        let mut first = true;
+        loop {
+            let start = Instant::now();
+            let mut block = Vec::with_capacity(block_size);
+            (&mut reader)
+                .take(block_size as u64)
+                .read_to_end(&mut block)
+                .await?;
+            let read_wait = start.elapsed();
+
+            // An empty input still needs a single (empty) member to be a valid gzip file, which
+            // the end of a BGZF file already is
+            if block.is_empty() && (!first || bgzf) {
+                return Ok::<(), SuperGzipError>(());
+            }
+            first = false;
+            let is_last = block.len() < block_size;
+            // Only the first member carries the original name and modification time
+            let origin = origin.take();
+            let level = adaptive.map(AdaptiveLevel::level).or(level);
+            let handle = pool
+                .spawn(move || match bgzf {
+                    true => bgzf::block(&block, level, backend),
+                    false => gzip_block(&block, level, origin, backend),
+                })
+                .await;
+            if sender.send((handle, read_wait)).await.is_err() || is_last {
+                return Ok(());
+            }
+        }
+    };
+    let write = async move {
+        let mut written = 0;
+        while let Some((handle, read_wait)) = receiver.recv().await {
+            let start = Instant::now();
+            let member = handle.await??;
+            let cpu_wait = start.elapsed();
+            let start = Instant::now();
+            writer.write_all(&member).await?;
+            written += member.len() as u64;
+            if let Some(adaptive) = adaptive {
+                adaptive.observe(read_wait + start.elapsed(), cpu_wait);
+            }
+        }
+        if bgzf {
+            writer.write_all(&bgzf::EOF).await?;
+            written += bgzf::EOF.len() as u64;
+        }
+        Ok::<u64, SuperGzipError>(written)
+    };
+    let ((), written) = tokio::try_join!(read, write)?;
+
+    Ok(written)
+}
+
+/// The number of bytes the rolling sum of `--rsyncable` covers (a power of two), which is also
+/// the average distance between the points where the compressed stream is flushed
+const RSYNC_WINDOW: usize = 4096;
+
+/// Copies the input into the encoder, flushing it wherever the sum of the last `RSYNC_WINDOW`
+/// bytes is a multiple of the window, like gzip --rsyncable. Since those points only depend on
+/// the nearby contents, a change to the input only changes the output up to the next point after
+/// it, letting rsync transfer the rest as unchanged.
+fn copy_rsyncable(input: &mut impl Read, encoder: &mut impl Write) -> TokioIOResult<()> {
+    let mut window = [0u8; RSYNC_WINDOW];
+    let mut position = 0;
+    let mut sum: usize = 0;
+    let mut seen = 0;
+    let mut buffer = vec![0; CHUNK_SIZE];
+    loop {
+        let read = match input.read(&mut buffer) {
+            Ok(0) => return Ok(()),
+            Ok(read) => read,
+            Err(e) if e.kind() == std::io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e),
+        };
+        let mut start = 0;
+        for (i, &byte) in buffer[..read].iter().enumerate() {
+            sum = sum + byte as usize - window[position] as usize;
+            window[position] = byte;
+            position = (position + 1) % RSYNC_WINDOW;
+            seen += 1;
+            if seen >= RSYNC_WINDOW && sum & (RSYNC_WINDOW - 1) == 0 {
+                encoder.write_all(&buffer[start..=i])?;
+                encoder.flush()?;
+                start = i + 1;
+            }
+        }
+        encoder.write_all(&buffer[start..read])?;
+    }
+}
+
+/// Compresses the input into a single gzip member with a streaming backend, framing its deflate
+/// stream like flate2's encoder does
+fn gzip_stream(
+    backend: Backend,
+    input: &mut impl Read,
+    output: &mut impl Write,
+    level: Option<u32>,
+    origin: Option<GzipOrigin>,
+    rsyncable: bool,
+) -> std::io::Result<()> {
+    let minimal = backend::minimal_header(level);
+    let header = match origin {
+        Some(origin) => origin.header(&minimal),
+        None => minimal.to_vec(),
+    };
+    output.write_all(&header)?;
+    let mut encoder = CrcWriter::new(backend::deflate_writer(backend, &mut *output, level)?);
+    if rsyncable {
+        copy_rsyncable(input, &mut encoder)?;
+    } else {
+        std::io::copy(input, &mut encoder)?;
+    }
+    let trailer = Trailer::of(encoder.crc());
+    encoder.into_inner().finish()?;
+    output.write_all(&trailer.crc.to_le_bytes())?;
+    output.write_all(&trailer.size.to_le_bytes())
+}
+
+/// Compresses the input into a single gzip member, storing the origin in its header. Blocks.
+fn gzip_member(
+    backend: Backend,
+    input: &mut impl Read,
+    output: &mut impl Write,
+    level: Option<u32>,
+    origin: Option<GzipOrigin>,
+    rsyncable: bool,
+) -> std::io::Result<()> {
+    // flate2's encoder only compresses with miniz
+    if backend != Backend::Miniz {
+        return gzip_stream(backend, input, output, level, origin, rsyncable);
+    }
+    // Store the original name, modification time, and tags in the header
+    let mut builder = GzBuilder::new();
+    if let Some(origin) = origin {
+        builder = builder.mtime(origin.mtime);
+        if !origin.name.is_empty() {
+            builder = builder.filename(origin.name);
+        }
+        if !origin.comment.is_empty() {
+            builder = builder.comment(origin.comment);
+        }
+        if !origin.extra.is_empty() {
+            builder = builder.extra(origin.extra);
+        }
+    }
+    let compression = level.map_or(BlockCompression::default(), BlockCompression::new);
+    let mut encoder = builder.write(output, compression);
+    if rsyncable {
+        copy_rsyncable(input, &mut encoder)?;
+    } else {
+        std::io::copy(input, &mut encoder)?;
+    }
+    encoder.finish().map(|_| ())
+}
+
+async fn gzip<R, W>(
+    reader: R,
+    writer: &mut W,
+    pool: &CpuPool,
+    level: Option<u32>,
+    origin: Option<GzipOrigin>,
+    rsyncable: bool,
+    backend: Backend,
+) -> TokioIOResult<u64>
+where
+    R: AsyncRead + Unpin,
+    W: AsyncWrite + Unpin,
+{
+    on_pool(reader, writer, pool, move |input, output| {
+        gzip_member(backend, input, output, level, origin, rsyncable)
+    })
+    .await
+}
+
+/// Decompresses every gzip member of the input in turn, ignoring the zeros that may pad its
+/// end. Blocks.
+fn unzip_all(input: impl Read, output: &mut impl Write) -> TokioIOResult<()> {
+    // Files written in block mode (or by pigz, or by concatenating .gz files) contain several
+    // gzip members, all of which must be decompressed in turn
+    let mut input = BufReader::with_capacity(CHUNK_SIZE, input);
+    unzip_members(&mut input, output, |_| Ok(()))?;
+    skip_padding(&mut input)
+}
+
+async fn unzip<R, W>(reader: R, writer: &mut W, pool: &CpuPool) -> TokioIOResult<u64>
+where
+    R: AsyncRead + Unpin,
+    W: AsyncWrite + Unpin,
+{
+    on_pool(reader, writer, pool, |input, output| {
+        unzip_all(input, output)
+    })
+    .await
+}
+
+/// Decompresses the gzip members at the start of the input in turn, stopping at the end of the
+/// input or at the first byte that cannot start another member. `member_done` is called with the
+/// input after each member.
+fn unzip_members<I: BufRead>(
+    input: &mut I,
+    output: &mut impl Write,
+    mut member_done: impl FnMut(&mut I) -> TokioIOResult<()>,
+) -> TokioIOResult<()> {
+    loop {
+        match unzip_member(input, output) {
+            Ok(_) => member_done(input)?,
+            Err(e) if e.kind() == std::io::ErrorKind::UnexpectedEof => {
+                return Err(TokioIOError::new(
+                    std::io::ErrorKind::UnexpectedEof,
+                    "unexpected end of file",
+                ))
+            }
+            Err(e) => return Err(e),
+        }
+        match input.fill_buf()?.first() {
+            Some(&byte) if byte == gzip_header::MAGIC[0] => {}
+            _ => return Ok(()),
+        }
+    }
+}
+
+/// Checks the gzip file at the path by decompressing it without writing anything, like `test`,
+/// and returns the offset of the end of each of its members. The last one is the length of the
+/// members, which leaves out any zeros padding the end of the file. Blocks, so it is meant to run
+/// on the CPU pool.
+pub fn gzip_member_ends(path: &Path) -> TokioIOResult<Vec<u64>> {
+    let mut input = BufReader::with_capacity(CHUNK_SIZE, std::fs::File::open(path)?);
+    let mut ends = Vec::new();
+    unzip_members(&mut input, &mut std::io::sink(), |input| {
+        ends.push(input.stream_position()?);
+        Ok(())
+    })?;
+    skip_padding(&mut input)?;
+    Ok(ends)
+}
+
+/// Decompresses a single gzip member, checking the decompressed data against its trailer
+fn unzip_member(input: &mut impl BufRead, output: &mut impl Write) -> TokioIOResult<()> {
+    gzip_header::skip_header(input)?;
+    let mut decoder = DeflateDecoder::new(input);
+    let mut output = CrcWriter::new(output);
+    std::io::copy(&mut decoder, &mut output)?;
+    let expected = Trailer::read(decoder.into_inner())?;
+    let actual = Trailer::of(output.crc());
+    if expected != actual {
+        return Err(TokioIOError::new(
+            std::io::ErrorKind::InvalidData,
+            TrailerMismatch { expected, actual },
+        ));
+    }
+    Ok(())
+}
+
+/// Skips the zeros that may pad the end of a gzip file (e.g. one written to tape), which gzip
+/// ignores too, failing if anything else follows the last member
+fn skip_padding(input: &mut impl BufRead) -> TokioIOResult<()> {
+    loop {
+        let buffer = input.fill_buf()?;
+        if buffer.is_empty() {
+            return Ok(());
+        }
+        if buffer.iter().any(|&byte| byte != 0) {
+            return Err(TokioIOError::new(
+                std::io::ErrorKind::InvalidData,
+                "trailing garbage after the last gzip member",
+            ));
+        }
+        let len = buffer.len();
+        input.consume(len);
+    }
+}
+
+/// Compresses the data into a single LZ4 frame. Blocks.
+#[cfg(feature = "lz4")]
+fn lz4_frame(data: &[u8], level: u32) -> TokioIOResult<Vec<u8>> {
+    Ok(lz4::compress(data, level))
+}
+
+#[cfg(not(feature = "lz4"))]
+fn lz4_frame(_data: &[u8], _level: u32) -> TokioIOResult<Vec<u8>> {
+    Err(Feature::Lz4.unavailable())
+}
+
+/// Decompresses every LZ4 frame of the data. Blocks.
+#[cfg(feature = "lz4")]
+fn lz4_frames(data: &[u8]) -> TokioIOResult<Vec<u8>> {
+    lz4::decompress(data)
+}
+
+#[cfg(not(feature = "lz4"))]
+fn lz4_frames(_data: &[u8]) -> TokioIOResult<Vec<u8>> {
+    Err(Feature::Lz4.unavailable())
+}
+
+/// Compresses the data into a single LZ4 frame on the pool
+async fn lz4_compress(pool: &CpuPool, data: Vec<u8>, level: u32) -> TokioIOResult<Vec<u8>> {
+    pool.spawn(move || lz4_frame(&data, level)).await.await?
+}
+
+/// Decompresses every LZ4 frame of the data on the pool
+async fn lz4_decompress(pool: &CpuPool, data: Vec<u8>) -> TokioIOResult<Vec<u8>> {
+    pool.spawn(move || lz4_frames(&data)).await.await?
+}
+
+async fn lz4<R, W>(
+    mut reader: R,
+    writer: &mut W,
+    pool: &CpuPool,
+    level: Option<u32>,
+) -> TokioIOResult<u64>
+where
+    R: AsyncRead + Unpin,
+    W: AsyncWrite + Unpin,
+{
+    // Read the original data into the buffer and compress it into a single LZ4 frame
+    let mut buffer = Vec::new();
+    reader.read_to_end(&mut buffer).await?;
+    let level = level.unwrap_or(Codec::Lz4.default_level());
+    let compressed = lz4_compress(pool, buffer, level).await?;
+
+    // Write the compressed data to the writer
+    writer.write_all(&compressed).await?;
+
+    Ok(compressed.len() as u64)
+}
+
+async fn unlz4<R, W>(mut reader: R, writer: &mut W, pool: &CpuPool) -> TokioIOResult<u64>
+where
+    R: AsyncRead + Unpin,
+    W: AsyncWrite + Unpin,
+{
+    // Read the compressed data into the buffer and decompress all of its frames
+    let mut buffer = Vec::new();
+    reader.read_to_end(&mut buffer).await?;
+    let decompressed = lz4_decompress(pool, buffer).await?;
+
+    // Write the decompressed data to the writer
+    writer.write_all(&decompressed).await?;
+
+    Ok(decompressed.len() as u64)
+}
+
+/// Decompresses the data of the reader with the codec into the writer, regardless of the
+/// direction of the settings (e.g. to check an output that was just compressed). Returns the
+/// number of bytes written.
+pub async fn decompress<R, W>(
+    codec: Codec,
+    reader: R,
+    writer: &mut W,
+    pool: &CpuPool,
+) -> TokioIOResult<u64>
+where
+    R: AsyncRead + Unpin,
+    W: AsyncWrite + Unpin,
+{
+    match codec {
+        Codec::Gzip => unzip(reader, writer, pool).await,
+        Codec::Lz4 => unlz4(reader, writer, pool).await,
+    }
+}
+
+/// Compresses everything from the reader into the writer with the codec, at the level (or the
+/// codec's default) and in the mode of the settings, storing the origin in gzip headers. Returns
+/// the number of bytes written.
+async fn compress<R, W>(
+    codec: Codec,
+    reader: R,
+    writer: &mut W,
+    origin: Option<GzipOrigin>,
+    level: Option<u32>,
+    settings: &Settings,
+) -> Result<u64, SuperGzipError>
+where
+    R: AsyncRead + Unpin,
+    W: AsyncWrite + Unpin,
+{
+    let pool = settings.cpu_pool();
+    let result = match (codec, settings.gzip_block_size()) {
+        (Codec::Gzip, _) if settings.writes_bgzf() => {
+            return gzip_blocks(reader, writer, bgzf::BLOCK_SIZE, level, origin, settings).await;
+        }
+        (Codec::Gzip, Some(block_size)) => {
+            return gzip_blocks(reader, writer, block_size * 1024, level, origin, settings).await;
+        }
+        (Codec::Gzip, None) => {
+            let rsyncable = settings.rsyncable;
+            gzip(
+                reader,
+                writer,
+                pool,
+                level,
+                origin,
+                rsyncable,
+                settings.backend,
+            )
+            .await
+        }
+        (Codec::Lz4, _) => lz4(reader, writer, pool, level).await,
+    };
+    Ok(result?)
+}
+
+/// What reproducible outputs store in their gzip header: the time of `SOURCE_DATE_EPOCH`, if
+/// it is set, and no name
+fn reproducible_origin(settings: &Settings) -> Option<GzipOrigin> {
+    settings.source_date_epoch.map(GzipOrigin::timestamp)
+}
+
+/// Adds the comment and extra field of the settings to the origin to store in gzip headers
+pub(crate) fn tag_origin(origin: &mut GzipOrigin, settings: &Settings) {
+    origin.comment = settings.comment.clone().unwrap_or_default().into_bytes();
+    origin.extra = gzip_header::extra_field(&settings.extra);
+}
+
+/// The origin with the comment and extra field of the settings, which are stored without a name
+/// or time if there is no origin
+fn tagged_origin(origin: Option<GzipOrigin>, settings: &Settings) -> Option<GzipOrigin> {
+    if settings.comment.is_none() && settings.extra.is_empty() {
+        return origin;
+    }
+    let mut origin = origin.unwrap_or_else(|| GzipOrigin::timestamp(0));
+    tag_origin(&mut origin, settings);
+    Some(origin)
+}
+
+/// The name and modification time to store in the gzip header of a recompressed file: those
+/// stored in its own gzip header if it has them, or else those of the file without its extension
+async fn recompressed_origin(source: &Path, settings: &Settings) -> TokioIOResult<GzipOrigin> {
+    let mut origin = GzipOrigin::of(source).await?;
+    origin.name = GzipOrigin::name_bytes(source.file_stem().unwrap_or_default());
+    if settings.codec == Codec::Gzip {
+        if let Ok(info) = gzip_header::GzipInfo::read(source).await {
+            if let Some(name) = info.name {
+                origin.name = name;
+            }
+            if info.mtime != 0 {
+                origin.mtime = info.mtime;
+            }
+        }
+    }
+    Ok(origin)
+}
+
+/// Counts the bytes written through it
+#[cfg(feature = "sync-engine")]
+struct Counted<W> {
+    inner: W,
+    written: u64,
+}
+
+#[cfg(feature = "sync-engine")]
+impl<W: Write> Write for Counted<W> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let written = self.inner.write(buf)?;
+        self.written += written as u64;
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// Compresses or decompresses everything from the input into the output according to the
+/// settings, like [`transcode`] but on the calling thread, for the sync engine. Only whole-stream
+/// compression and decompression are supported: blocks, BGZF, and recompressing are left to
+/// [`transcode`]. Returns the number of bytes written. Blocks.
+#[cfg(feature = "sync-engine")]
+pub(crate) fn transcode_blocking(
+    input: &mut impl Read,
+    output: &mut impl Write,
+    source: Option<&Path>,
+    settings: &Settings,
+) -> Result<u64, SuperGzipError> {
+    let mut output = Counted {
+        inner: output,
+        written: 0,
+    };
+    if settings.recompress_to.is_some() || settings.gzip_block_size().is_some() {
+        return Err(SuperGzipError::IO(TokioIOError::new(
+            std::io::ErrorKind::Unsupported,
+            "blocks and recompressing are only supported by the async engine",
+        )));
+    }
+    if !settings.b_zip {
+        let result = match settings.codec {
+            Codec::Gzip => unzip_all(input, &mut output),
+            Codec::Lz4 => {
+                let mut data = Vec::new();
+                input.read_to_end(&mut data)?;
+                lz4_frames(&data).and_then(|decompressed| output.write_all(&decompressed))
+            }
+        };
+        result.map_err(|e| {
+            settings
+                .codec
+                .header_error(&e)
+                .unwrap_or(SuperGzipError::IO(e))
+        })?;
+        return Ok(output.written);
+    }
+
+    let origin = match source {
+        _ if settings.reproducible => reproducible_origin(settings),
+        Some(source) if settings.store_name => Some(GzipOrigin::from_metadata(
+            source,
+            &std::fs::metadata(source)?,
+        )?),
+        _ => None,
+    };
+    let origin = tagged_origin(origin, settings);
+    let mut level = settings.level;
+    if let (true, Some(source)) = (stores_incompressible(settings), source) {
+        if entropy::is_incompressible(source)? {
+            level = Some(0);
+        }
+    }
+    match settings.codec {
+        Codec::Gzip => gzip_member(
+            settings.backend,
+            input,
+            &mut output,
+            level,
+            origin,
+            settings.rsyncable,
+        )?,
+        Codec::Lz4 => {
+            let mut data = Vec::new();
+            input.read_to_end(&mut data)?;
+            let level = level.unwrap_or(Codec::Lz4.default_level());
+            output.write_all(&lz4_frame(&data, level)?)?;
+        }
+    }
+    Ok(output.written)
+}
+
+/// Whether files whose samples barely compress are stored in the gzip file as they are, with
+/// --incompressible store. Their outputs are a little larger than the files.
+pub(crate) fn stores_incompressible(settings: &Settings) -> bool {
+    settings.incompressible == Incompressible::Store && settings.codec == Codec::Gzip
+}
+
+/// Samples the file on the CPU pool to tell whether compressing it would save next to nothing
+pub(crate) async fn sampled_incompressible(
+    source: &Path,
+    settings: &Settings,
+) -> Result<bool, SuperGzipError> {
+    let source = source.to_path_buf();
+    let sampled = settings
+        .cpu_pool()
+        .spawn(move || entropy::is_incompressible(&source))
+        .await
+        .await??;
+    Ok(sampled)
+}
+
+/// Compresses or decompresses everything from the reader into the writer according to the
+/// settings, returning the number of bytes written. The source is the file being read, if any,
+/// whose name and modification time are stored in gzip headers.
+///
+/// When recompressing, the data is decompressed with the codec of the settings and compressed
+/// again with the other codec as it streams through, without an intermediate file.
+pub async fn transcode<R, W>(
+    reader: R,
+    writer: &mut W,
+    source: Option<&Path>,
+    settings: &Settings,
+) -> Result<u64, SuperGzipError>
+where
+    R: AsyncRead + Unpin,
+    W: AsyncWrite + Unpin,
+{
+    // Name the codec of data that does not start like it should, rather than calling it corrupt
+    let decompress_error = |e: TokioIOError| {
+        settings
+            .codec
+            .header_error(&e)
+            .unwrap_or(SuperGzipError::IO(e))
+    };
+    let pool = settings.cpu_pool();
+    match (settings.b_zip, settings.recompress_to) {
+        (true, _) => {
+            let origin = match source {
+                _ if settings.reproducible => reproducible_origin(settings),
+                Some(source) if settings.store_name => Some(GzipOrigin::of(source).await?),
+                _ => None,
+            };
+            let origin = tagged_origin(origin, settings);
+            // Contents that barely compress are stored in the gzip file as they are when asked
+            // to, which takes next to no CPU time
+            let mut level = settings.level;
+            if let (true, Some(source)) = (stores_incompressible(settings), source) {
+                if sampled_incompressible(source, settings).await? {
+                    level = Some(0);
+                }
+            }
+            compress(settings.codec, reader, writer, origin, level, settings).await
+        }
+        (false, None) => decompress(settings.codec, reader, writer, pool)
+            .await
+            .map_err(decompress_error),
+        (false, Some(target)) => {
+            let origin = match source {
+                _ if settings.reproducible => reproducible_origin(settings),
+                Some(source) if settings.store_name && target == Codec::Gzip => {
+                    Some(recompressed_origin(source, settings).await?)
+                }
+                _ => None,
+            };
+            let origin = tagged_origin(origin, settings);
+            // LZ4 decompresses whole files at once, so take its output before starting the
+            // compression, which would otherwise hold a CPU thread that the decompression needs
+            if settings.codec == Codec::Lz4 {
+                let mut decompressed = Vec::new();
+                decompress(settings.codec, reader, &mut decompressed, pool)
+                    .await
+                    .map_err(decompress_error)?;
+                let decompressed = decompressed.as_slice();
+                return compress(
+                    target,
+                    decompressed,
+                    writer,
+                    origin,
+                    settings.level,
+                    settings,
+                )
+                .await;
+            }
+            let (mut decompressed, compressed) = tokio::io::duplex(CHUNK_SIZE);
+            let decompress = async move {
+                let result = decompress(settings.codec, reader, &mut decompressed, pool).await;
+                // Closing the pipe ends the input of the compression
+                drop(decompressed);
+                result.map_err(decompress_error)
+            };
+            let (_, written) = tokio::try_join!(
+                decompress,
+                compress(target, compressed, writer, origin, settings.level, settings)
+            )?;
+            Ok(written)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn gzipped(data: &[u8]) -> Vec<u8> {
+        let mut encoder = GzBuilder::new().write(Vec::new(), BlockCompression::default());
+        encoder.write_all(data).unwrap();
+        encoder.finish().unwrap()
+    }
+
+    #[test]
+    fn concatenated_members_decompress_in_turn() {
+        let (a, b) = (
+            b"the first file\n".repeat(1000),
+            b"the second file\n".repeat(1000),
+        );
+        let input = [gzipped(&a), gzipped(&b)].concat();
+        let mut output = Vec::new();
+        unzip_all(&input[..], &mut output).unwrap();
+        assert_eq!(output, [a, b].concat());
+    }
+
+    #[test]
+    fn zero_padding_after_the_last_member_is_ignored() {
+        let (a, b) = (b"the first file\n".to_vec(), b"the second file\n".to_vec());
+        let input = [gzipped(&a), gzipped(&b), vec![0; 10_000]].concat();
+        let mut output = Vec::new();
+        unzip_all(&input[..], &mut output).unwrap();
+        assert_eq!(output, [a, b].concat());
+    }
+
+    #[test]
+    fn garbage_after_the_padding_is_refused() {
+        let input = [gzipped(b"data"), vec![0; 100], b"garbage".to_vec()].concat();
+        let error = unzip_all(&input[..], &mut Vec::new()).unwrap_err();
+        assert_eq!(error.kind(), std::io::ErrorKind::InvalidData);
+    }
+}
@@ -0,0 +1,74 @@
+//! Coloring the text printed on a terminal, for `--color`.
+//!
+//! Statuses are green when a file went fine, yellow when it was skipped or kept, and red when it
+//! failed. With `--color auto`, colors are only used when the output goes to a terminal and
+//! `NO_COLOR` is not set (see <https://no-color.org>).
+
+use clap::ValueEnum;
+use std::io::IsTerminal;
+
+/// When to color the output
+#[derive(ValueEnum, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ColorChoice {
+    /// Only when the output goes to a terminal, and `NO_COLOR` is not set
+    #[default]
+    Auto,
+    Always,
+    Never,
+}
+
+impl ColorChoice {
+    /// Whether to color the text printed to stdout, or to stderr if `stderr`
+    pub fn enabled(&self, stderr: bool) -> bool {
+        match self {
+            ColorChoice::Always => true,
+            ColorChoice::Never => false,
+            ColorChoice::Auto => {
+                let terminal = match stderr {
+                    true => std::io::stderr().is_terminal(),
+                    false => std::io::stdout().is_terminal(),
+                };
+                terminal
+                    && std::env::var_os("NO_COLOR").is_none_or(|value| value.is_empty())
+                    && std::env::var_os("TERM").is_none_or(|term| term != "dumb")
+            }
+        }
+    }
+}
+
+/// How a file or message turned out, which picks its color
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Status {
+    Ok,
+    Skipped,
+    Failed,
+}
+
+impl Status {
+    fn code(&self) -> &'static str {
+        match self {
+            Status::Ok => "32",
+            Status::Skipped => "33",
+            Status::Failed => "31",
+        }
+    }
+}
+
+/// The text in the color of the status if `enabled`, and as is otherwise
+pub fn paint(text: &str, status: Status, enabled: bool) -> String {
+    match enabled {
+        true => format!("\x1b[{}m{}\x1b[0m", status.code(), text),
+        false => text.to_string(),
+    }
+}
+
+/// Colors the `Warning:` or `Error:` that a message starts with, if any
+pub fn paint_prefix(message: &str, enabled: bool) -> String {
+    let prefixes = [("Warning:", Status::Skipped), ("Error:", Status::Failed)];
+    for (prefix, status) in prefixes {
+        if let Some(rest) = message.strip_prefix(prefix) {
+            return format!("{}{}", paint(prefix, status, enabled), rest);
+        }
+    }
+    message.to_string()
+}
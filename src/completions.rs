@@ -0,0 +1,546 @@
+//! Shell completion scripts, generated from the definition of the command line so that they
+//! always offer the current subcommands, options, and values.
+//!
+//! Values that depend on the machine, like the names of profiles, are not written into the
+//! scripts: they run the program itself to list them when completing.
+
+use clap::{ArgAction, Command, ValueEnum};
+use std::fmt::Write;
+
+/// A shell that completion scripts can be generated for
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Shell {
+    Bash,
+    Zsh,
+    Fish,
+    Powershell,
+}
+
+/// What the value of an option or positional argument is completed with
+enum Values {
+    Files,
+    List(Vec<String>),
+    /// The lines printed by the program when run with these arguments
+    Program(String),
+}
+
+struct Opt {
+    long: Option<String>,
+    short: Option<char>,
+    /// The values the option takes, if it takes any
+    values: Option<Values>,
+    repeatable: bool,
+    help: String,
+}
+
+impl Opt {
+    /// The names of the option, with their dashes
+    fn names(&self) -> Vec<String> {
+        let short = self.short.map(|short| format!("-{}", short));
+        let long = self.long.as_ref().map(|long| format!("--{}", long));
+        short.into_iter().chain(long).collect()
+    }
+}
+
+struct Positional {
+    name: String,
+    values: Values,
+    multiple: bool,
+}
+
+struct Subcommand {
+    names: Vec<String>,
+    help: String,
+    options: Vec<Opt>,
+    positionals: Vec<Positional>,
+}
+
+/// The first sentence of a help text, which is what fits next to a completion
+fn summary(help: &str) -> String {
+    let help = help.trim();
+    let end = help
+        .match_indices(". ")
+        .find(|(index, _)| help[index + 2..].starts_with(char::is_uppercase))
+        .map_or(help.len(), |(index, _)| index);
+    help[..end].trim_end_matches('.').replace('\n', " ")
+}
+
+/// How the values of the argument are completed, if it takes any
+fn values(arg: &clap::Arg, program: &str, dynamic: &[(&str, &str)]) -> Option<Values> {
+    if !arg.get_action().takes_values() {
+        return None;
+    }
+    let long = arg.get_long().unwrap_or_default();
+    if let Some((_, args)) = dynamic.iter().find(|(option, _)| *option == long) {
+        return Some(Values::Program(format!("{} {}", program, args)));
+    }
+    let possible: Vec<String> = arg
+        .get_possible_values()
+        .iter()
+        .filter(|value| !value.is_hide_set())
+        .map(|value| value.get_name().to_string())
+        .collect();
+    match possible.is_empty() {
+        true => Some(Values::Files),
+        false => Some(Values::List(possible)),
+    }
+}
+
+/// The options and positional arguments of the command
+fn arguments(
+    command: &Command,
+    program: &str,
+    dynamic: &[(&str, &str)],
+) -> (Vec<Opt>, Vec<Positional>) {
+    let mut options = Vec::new();
+    let mut positionals = Vec::new();
+    for arg in command.get_arguments().filter(|arg| !arg.is_hide_set()) {
+        if arg.is_positional() {
+            positionals.push(Positional {
+                name: arg.get_id().to_string().to_uppercase(),
+                values: values(arg, program, dynamic).unwrap_or(Values::Files),
+                multiple: arg
+                    .get_num_args()
+                    .is_some_and(|range| range.max_values() > 1),
+            });
+            continue;
+        }
+        options.push(Opt {
+            long: arg.get_long().map(str::to_string),
+            short: arg.get_short(),
+            values: values(arg, program, dynamic),
+            repeatable: matches!(arg.get_action(), ArgAction::Append | ArgAction::Count),
+            help: arg
+                .get_help()
+                .map_or_else(String::new, |help| summary(&help.to_string())),
+        });
+    }
+    (options, positionals)
+}
+
+/// The completions of a program with subcommands, read from its command line definition
+struct Completions {
+    program: String,
+    options: Vec<Opt>,
+    subcommands: Vec<Subcommand>,
+}
+
+impl Completions {
+    fn new(command: &Command, dynamic: &[(&str, &str)]) -> Completions {
+        let mut command = command.clone();
+        command.build();
+        let program = command.get_name().to_string();
+        let (options, _) = arguments(&command, &program, dynamic);
+        let subcommands = command
+            .get_subcommands()
+            .filter(|subcommand| !subcommand.is_hide_set())
+            .map(|subcommand| {
+                let (options, positionals) = arguments(subcommand, &program, dynamic);
+                Subcommand {
+                    names: std::iter::once(subcommand.get_name())
+                        .chain(subcommand.get_visible_aliases())
+                        .map(str::to_string)
+                        .collect(),
+                    help: subcommand
+                        .get_about()
+                        .map_or_else(String::new, |about| summary(&about.to_string())),
+                    options,
+                    positionals,
+                }
+            })
+            .collect();
+        Completions {
+            program,
+            options,
+            subcommands,
+        }
+    }
+
+    /// The name of the shell function that completes the program
+    fn function(&self) -> String {
+        format!(
+            "_{}",
+            self.program.replace(|c: char| !c.is_alphanumeric(), "_")
+        )
+    }
+
+    fn bash(&self) -> String {
+        let words =
+            |options: &[Opt]| -> Vec<String> { options.iter().flat_map(Opt::names).collect() };
+        let compgen = |values: &Values| match values {
+            Values::Files => "COMPREPLY=($(compgen -f -- \"$cur\"))".to_string(),
+            Values::List(list) => {
+                format!(
+                    "COMPREPLY=($(compgen -W \"{}\" -- \"$cur\"))",
+                    list.join(" ")
+                )
+            }
+            // One value per line, which may contain spaces
+            Values::Program(args) => format!(
+                "local IFS=$'\\n'; COMPREPLY=($(compgen -W \"$({} 2>/dev/null)\" -- \"$cur\"))",
+                args
+            ),
+        };
+
+        let mut script = String::new();
+        let _ = writeln!(script, "{}() {{", self.function());
+        script.push_str(concat!(
+            "    local cur=\"${COMP_WORDS[COMP_CWORD]}\" prev=\"${COMP_WORDS[COMP_CWORD-1]}\"\n",
+            "    local command=\"\" i\n",
+            "    for ((i = 1; i < COMP_CWORD; i++)); do\n",
+            "        if [[ \"${COMP_WORDS[i]}\" != -* ]]; then\n",
+            "            command=\"${COMP_WORDS[i]}\"\n",
+            "            break\n",
+            "        fi\n",
+            "    done\n",
+            "    case \"$command\" in\n",
+            "        \"\")\n",
+        ));
+        let top: Vec<String> = self
+            .subcommands
+            .iter()
+            .flat_map(|subcommand| subcommand.names.clone())
+            .chain(words(&self.options))
+            .collect();
+        let _ = writeln!(
+            script,
+            "            COMPREPLY=($(compgen -W \"{}\" -- \"$cur\"))",
+            top.join(" ")
+        );
+        script.push_str("            ;;\n");
+        for subcommand in &self.subcommands {
+            let _ = writeln!(script, "        {})", subcommand.names.join("|"));
+            // The options completed alike share an arm
+            let mut arms: Vec<(String, Vec<String>)> = Vec::new();
+            for option in &subcommand.options {
+                if let Some(values) = &option.values {
+                    let completion = compgen(values);
+                    match arms
+                        .iter_mut()
+                        .find(|(existing, _)| *existing == completion)
+                    {
+                        Some((_, names)) => names.extend(option.names()),
+                        None => arms.push((completion, option.names())),
+                    }
+                }
+            }
+            script.push_str("            case \"$prev\" in\n");
+            for (completion, names) in arms {
+                let _ = writeln!(script, "                {})", names.join("|"));
+                let _ = writeln!(script, "                    {}", completion);
+                script.push_str("                    return\n");
+                script.push_str("                    ;;\n");
+            }
+            script.push_str("            esac\n");
+            script.push_str("            if [[ \"$cur\" == -* ]]; then\n");
+            let _ = writeln!(
+                script,
+                "                COMPREPLY=($(compgen -W \"{}\" -- \"$cur\"))",
+                words(&subcommand.options).join(" ")
+            );
+            script.push_str("            else\n");
+            let positional = subcommand
+                .positionals
+                .first()
+                .map_or(&Values::Files, |positional| &positional.values);
+            let _ = writeln!(script, "                {}", compgen(positional));
+            script.push_str("            fi\n");
+            script.push_str("            ;;\n");
+        }
+        script.push_str("    esac\n}\n\n");
+        let _ = writeln!(
+            script,
+            "complete -o filenames -o bashdefault -F {} {}",
+            self.function(),
+            self.program
+        );
+        script
+    }
+
+    fn zsh(&self) -> String {
+        // Descriptions go inside single-quoted brackets
+        let escape = |text: &str| {
+            text.replace('\'', "'\\''")
+                .replace('[', "\\[")
+                .replace(']', "\\]")
+                .replace(':', "\\:")
+        };
+        let action = |values: &Values| match values {
+            Values::Files => "_files".to_string(),
+            Values::List(list) => format!("({})", list.join(" ")),
+            Values::Program(args) => {
+                format!("{{compadd -- ${{(f)\"$({} 2>/dev/null)\"}}}}", args)
+            }
+        };
+        let spec = |option: &Opt| {
+            let repeat = if option.repeatable { "'*'" } else { "" };
+            let names: Vec<String> = option
+                .names()
+                .into_iter()
+                .map(|name| match (&option.values, name.starts_with("--")) {
+                    (None, _) => name,
+                    (Some(_), true) => format!("{}=", name),
+                    (Some(_), false) => format!("{}+", name),
+                })
+                .collect();
+            let names = match names.as_slice() {
+                [name] => name.clone(),
+                names => format!("{{{}}}", names.join(",")),
+            };
+            let mut spec = format!("{}{}'[{}]", repeat, names, escape(&option.help));
+            if let Some(values) = &option.values {
+                let _ = write!(spec, ":VALUE:{}", action(values));
+            }
+            spec.push('\'');
+            spec
+        };
+
+        let function = self.function();
+        let mut script = format!("#compdef {}\n\n{}() {{\n", self.program, function);
+        script.push_str("    local curcontext=\"$curcontext\" state line\n");
+        script.push_str("    _arguments -C \\\n");
+        for option in &self.options {
+            let _ = writeln!(script, "        {} \\", spec(option));
+        }
+        script.push_str("        '1: :->command' \\\n");
+        script.push_str("        '*:: :->args'\n");
+        script.push_str("    case $state in\n");
+        script.push_str("        command)\n");
+        script.push_str("            local -a commands\n");
+        script.push_str("            commands=(\n");
+        for subcommand in &self.subcommands {
+            for name in &subcommand.names {
+                let _ = writeln!(
+                    script,
+                    "                '{}:{}'",
+                    name,
+                    escape(&subcommand.help)
+                );
+            }
+        }
+        script.push_str("            )\n");
+        script.push_str("            _describe -t commands 'command' commands\n");
+        script.push_str("            ;;\n");
+        script.push_str("        args)\n");
+        script.push_str("            case $line[1] in\n");
+        for subcommand in &self.subcommands {
+            let _ = writeln!(script, "                {})", subcommand.names.join("|"));
+            script.push_str("                    _arguments");
+            for option in &subcommand.options {
+                let _ = write!(script, " \\\n                        {}", spec(option));
+            }
+            for positional in &subcommand.positionals {
+                let _ = write!(
+                    script,
+                    " \\\n                        '{}:{}:{}'",
+                    if positional.multiple { "*" } else { "" },
+                    positional.name,
+                    action(&positional.values)
+                );
+            }
+            script.push_str("\n                    ;;\n");
+        }
+        script.push_str("            esac\n");
+        script.push_str("            ;;\n");
+        script.push_str("    esac\n}\n\n");
+        let _ = writeln!(script, "{} \"$@\"", function);
+        script
+    }
+
+    fn fish(&self) -> String {
+        let escape = |text: &str| text.replace('\\', "\\\\").replace('\'', "\\'");
+        let line = |condition: &str, option: &Opt| {
+            let mut line = format!("complete -c {} -n '{}'", self.program, condition);
+            if let Some(short) = option.short {
+                let _ = write!(line, " -s {}", short);
+            }
+            if let Some(long) = &option.long {
+                let _ = write!(line, " -l {}", long);
+            }
+            match &option.values {
+                None => {}
+                Some(Values::Files) => line.push_str(" -r"),
+                Some(Values::List(list)) => {
+                    let _ = write!(line, " -x -a '{}'", list.join(" "));
+                }
+                Some(Values::Program(args)) => {
+                    let _ = write!(line, " -x -a '({} 2>/dev/null)'", args);
+                }
+            }
+            let _ = write!(line, " -d '{}'", escape(&option.help));
+            line
+        };
+
+        let mut script = String::new();
+        let _ = writeln!(
+            script,
+            "complete -c {} -n '__fish_use_subcommand' -f",
+            self.program
+        );
+        for option in &self.options {
+            let _ = writeln!(script, "{}", line("__fish_use_subcommand", option));
+        }
+        for subcommand in &self.subcommands {
+            for name in &subcommand.names {
+                let _ = writeln!(
+                    script,
+                    "complete -c {} -n '__fish_use_subcommand' -a {} -d '{}'",
+                    self.program,
+                    name,
+                    escape(&subcommand.help)
+                );
+            }
+        }
+        for subcommand in &self.subcommands {
+            let condition = format!("__fish_seen_subcommand_from {}", subcommand.names.join(" "));
+            for option in &subcommand.options {
+                let _ = writeln!(script, "{}", line(&condition, option));
+            }
+            match subcommand
+                .positionals
+                .first()
+                .map(|positional| &positional.values)
+            {
+                Some(Values::List(list)) => {
+                    let _ = writeln!(
+                        script,
+                        "complete -c {} -n '{}' -f -a '{}'",
+                        self.program,
+                        condition,
+                        list.join(" ")
+                    );
+                }
+                Some(Values::Program(args)) => {
+                    let _ = writeln!(
+                        script,
+                        "complete -c {} -n '{}' -f -a '({} 2>/dev/null)'",
+                        self.program, condition, args
+                    );
+                }
+                Some(Values::Files) => {}
+                None => {
+                    let _ = writeln!(script, "complete -c {} -n '{}' -f", self.program, condition);
+                }
+            }
+        }
+        script
+    }
+
+    fn powershell(&self) -> String {
+        let quote = |text: &str| format!("'{}'", text.replace('\'', "''"));
+        let list = |words: &[String]| {
+            words
+                .iter()
+                .map(|word| quote(word))
+                .collect::<Vec<_>>()
+                .join(", ")
+        };
+        let block = |body: String| match body.is_empty() {
+            true => "{ }".to_string(),
+            false => format!("{{ {} }}", body),
+        };
+        // An empty result falls back to completing file names
+        let candidates = |values: &Values| match values {
+            Values::Files => block(String::new()),
+            Values::List(values) => block(list(values)),
+            Values::Program(args) => block(format!("& {} 2>$null", args)),
+        };
+
+        let mut script = format!(
+            "Register-ArgumentCompleter -Native -CommandName {} -ScriptBlock {{\n",
+            quote(&self.program)
+        );
+        script.push_str(concat!(
+            "    param($wordToComplete, $commandAst, $cursorPosition)\n",
+            "    $words = @($commandAst.CommandElements |\n",
+            "        Where-Object { $_.Extent.EndOffset -le $cursorPosition } |\n",
+            "        ForEach-Object { $_.ToString() })\n",
+            "    if ($wordToComplete) {\n",
+            "        $words = @($words | Select-Object -SkipLast 1)\n",
+            "    }\n",
+            "    $command = $words | Select-Object -Skip 1 |\n",
+            "        Where-Object { -not $_.StartsWith('-') } | Select-Object -First 1\n",
+            "    $previous = $words[-1]\n",
+            "    $candidates = if (-not $command) {\n",
+        ));
+        let top: Vec<String> = self
+            .subcommands
+            .iter()
+            .flat_map(|subcommand| subcommand.names.clone())
+            .chain(self.options.iter().flat_map(Opt::names))
+            .collect();
+        let _ = writeln!(script, "        {}", list(&top));
+        script.push_str("    } else {\n");
+        script.push_str("        switch ($command) {\n");
+        for subcommand in &self.subcommands {
+            let patterns: Vec<String> = subcommand.names.iter().map(|name| quote(name)).collect();
+            let _ = writeln!(
+                script,
+                "            {{ $_ -in {} }} {{",
+                patterns.join(", ")
+            );
+            let mut keyword = "if";
+            let mut takes_files = Vec::new();
+            for option in &subcommand.options {
+                match &option.values {
+                    None => {}
+                    Some(Values::Files) => takes_files.extend(option.names()),
+                    Some(values) => {
+                        let _ = writeln!(
+                            script,
+                            "                {} ($previous -in {}) {}",
+                            keyword,
+                            list(&option.names()),
+                            candidates(values)
+                        );
+                        keyword = "elseif";
+                    }
+                }
+            }
+            if !takes_files.is_empty() {
+                let _ = writeln!(
+                    script,
+                    "                {} ($previous -in {}) {{ }}",
+                    keyword,
+                    list(&takes_files)
+                );
+                keyword = "elseif";
+            }
+            let options: Vec<String> = subcommand.options.iter().flat_map(Opt::names).collect();
+            let _ = writeln!(
+                script,
+                "                {} ($wordToComplete.StartsWith('-')) {}",
+                keyword,
+                block(list(&options))
+            );
+            let positional = subcommand
+                .positionals
+                .first()
+                .map_or(&Values::Files, |positional| &positional.values);
+            let _ = writeln!(script, "                else {}", candidates(positional));
+            script.push_str("            }\n");
+        }
+        script.push_str("        }\n");
+        script.push_str("    }\n");
+        script.push_str(concat!(
+            "    $candidates | Where-Object { $_ -like \"$wordToComplete*\" } | ForEach-Object {\n",
+            "        [System.Management.Automation.CompletionResult]::new($_, $_, 'ParameterValue', $_)\n",
+            "    }\n",
+            "}\n",
+        ));
+        script
+    }
+}
+
+/// Generates the completion script of the command for the shell. The options named in `dynamic`
+/// are completed with the lines the program prints when run with the arguments given for them,
+/// e.g. `("profile", "profiles")`.
+pub fn generate(shell: Shell, command: &Command, dynamic: &[(&str, &str)]) -> String {
+    let completions = Completions::new(command, dynamic);
+    match shell {
+        Shell::Bash => completions.bash(),
+        Shell::Zsh => completions.zsh(),
+        Shell::Fish => completions.fish(),
+        Shell::Powershell => completions.powershell(),
+    }
+}
@@ -0,0 +1,334 @@
+//! A builder for embedding batch (de)compression in other programs
+
+use crate::archive::MAX_BLOCK_SIZE;
+use crate::cancel::CancellationToken;
+use crate::codec::Codec;
+use crate::engine::{self, Engine};
+use crate::error::SuperGzipError;
+use crate::report::FileReport;
+use crate::settings::{CollisionPolicy, Incompressible, Settings, SymlinkPolicy};
+use std::ffi::OsString;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+
+/// Compresses or decompresses every file matching a set of paths and glob patterns in parallel.
+///
+/// Unlike the command line tool, it prints nothing and keeps the original files by default.
+/// Progress can be followed through the [`on_file_start`](Compressor::on_file_start) and
+/// [`on_file_done`](Compressor::on_file_done) hooks.
+#[derive(Debug)]
+pub struct Compressor {
+    settings: Settings,
+}
+
+impl Compressor {
+    /// Creates a compressor for the given codec with its default level, processing one file at a
+    /// time (see [`concurrency`](Compressor::concurrency))
+    pub fn new(codec: Codec) -> Compressor {
+        let mut settings = Settings::new(codec, true);
+        settings.keep_original = true;
+        settings.quiet = true;
+        Compressor { settings }
+    }
+
+    /// Sets the compression level: 1 to 9 for gzip and 1 to 12 for LZ4. Runs that compress with
+    /// a level out of range fail with [`ErrorKind::InvalidInput`](std::io::ErrorKind::InvalidInput).
+    pub fn level(mut self, level: u32) -> Compressor {
+        self.settings.level = Some(level);
+        self
+    }
+
+    /// Splits gzip inputs into blocks of this many KiB that are compressed in parallel. Runs that
+    /// compress with a block size of 0 or over 1 GiB fail with
+    /// [`ErrorKind::InvalidInput`](std::io::ErrorKind::InvalidInput).
+    pub fn block_size(mut self, block_size: usize) -> Compressor {
+        self.settings.block_size = Some(block_size);
+        self
+    }
+
+    /// Splits gzip outputs into numbered volumes of at most this many bytes, each of which is a
+    /// valid gzip file, e.g. `file.gz.000` and `file.gz.001` instead of `file.gz`
+    pub fn split_size(mut self, split_size: u64) -> Compressor {
+        self.settings.split_size = Some(split_size.max(crate::split::MIN_SPLIT_SIZE));
+        self
+    }
+
+    /// Writes gzip outputs in the BGZF format, with a `.gzi` index of their blocks next to them
+    pub fn bgzf(mut self, bgzf: bool) -> Compressor {
+        self.settings.bgzf = bgzf;
+        self
+    }
+
+    /// Sets the maximum number of files processed at once
+    pub fn concurrency(mut self, num_threads: usize) -> Compressor {
+        self.settings.num_threads = num_threads.max(1);
+        self
+    }
+
+    /// Sets whether the original files are kept after processing (default: true)
+    pub fn keep_original(mut self, keep_original: bool) -> Compressor {
+        self.settings.keep_original = keep_original;
+        self
+    }
+
+    /// Walks matched directories, at most `max_depth` levels deep if given
+    pub fn recursive(mut self, max_depth: Option<usize>) -> Compressor {
+        self.settings.recursive = true;
+        self.settings.max_depth = max_depth;
+        self
+    }
+
+    /// Skips files smaller than `min_size` or larger than `max_size` bytes, where given
+    pub fn size_range(mut self, min_size: Option<u64>, max_size: Option<u64>) -> Compressor {
+        self.settings.min_size = min_size;
+        self.settings.max_size = max_size;
+        self
+    }
+
+    /// Skips files modified more recently than `older_than` ago, or longer than `newer_than` ago,
+    /// where given
+    pub fn age_range(
+        mut self,
+        older_than: Option<Duration>,
+        newer_than: Option<Duration>,
+    ) -> Compressor {
+        self.settings.older_than = older_than;
+        self.settings.newer_than = newer_than;
+        self
+    }
+
+    /// Compresses files whose contents are already compressed instead of skipping them
+    pub fn force_compress(mut self, force_compress: bool) -> Compressor {
+        self.settings.force_compress = force_compress;
+        self
+    }
+
+    /// Sets what to do with files whose samples barely compress (default: compress them anyway)
+    pub fn incompressible(mut self, incompressible: Incompressible) -> Compressor {
+        self.settings.incompressible = incompressible;
+        self
+    }
+
+    /// Sets what to do with symbolic links to files (default: skip them)
+    pub fn symlinks(mut self, symlinks: SymlinkPolicy) -> Compressor {
+        self.settings.symlinks = symlinks;
+        self
+    }
+
+    /// Writes the outputs into this directory instead of next to the inputs
+    pub fn output_dir(mut self, output_dir: impl Into<PathBuf>) -> Compressor {
+        self.settings.output_dir = Some(output_dir.into());
+        self
+    }
+
+    /// Records completed files in the journal at this path, skipping the files an earlier run
+    /// already completed
+    pub fn resume(mut self, journal: impl Into<PathBuf>) -> Compressor {
+        self.settings.journal = Some(journal.into());
+        self
+    }
+
+    /// Decompresses each output again and compares it with the original before keeping it
+    pub fn verify(mut self, verify: bool) -> Compressor {
+        self.settings.verify = verify;
+        self
+    }
+
+    /// Tries a file again up to this many times after a transient I/O error, with exponential
+    /// backoff, before it fails
+    pub fn retries(mut self, retries: usize) -> Compressor {
+        self.settings.retries = retries;
+        self
+    }
+
+    /// Overwrites existing outputs instead of skipping their inputs
+    pub fn force(mut self, force: bool) -> Compressor {
+        self.settings.collision = match force {
+            true => CollisionPolicy::Force,
+            false => CollisionPolicy::Skip,
+        };
+        self
+    }
+
+    /// Sets the engine that runs the batch (default: the async one). The sync engine is only
+    /// built with the `sync-engine` cargo feature, and supports fewer options.
+    pub fn engine(mut self, engine: Engine) -> Compressor {
+        self.settings.engine = engine;
+        self
+    }
+
+    /// Lets the run be cancelled by cancelling the token, or a clone of it, from another task or
+    /// thread. Cancelling abandons the files in progress and removes their partial outputs, and
+    /// no original is deleted afterwards; the run then fails with
+    /// [`SuperGzipError::Interrupted`].
+    pub fn cancellation(mut self, token: CancellationToken) -> Compressor {
+        self.settings.cancellation = token;
+        self
+    }
+
+    /// Calls the hook whenever a file starts being processed
+    pub fn on_file_start(mut self, hook: impl Fn(&Path) + Send + Sync + 'static) -> Compressor {
+        self.settings.hooks.on_file_start = Some(Arc::new(hook));
+        self
+    }
+
+    /// Calls the hook whenever a file is done, whether it was processed, skipped, or failed
+    pub fn on_file_done(
+        mut self,
+        hook: impl Fn(&FileReport) + Send + Sync + 'static,
+    ) -> Compressor {
+        self.settings.hooks.on_file_done = Some(Arc::new(hook));
+        self
+    }
+
+    /// Checks the level and block size of a run that compresses with the codec, since the
+    /// builder takes them as they are
+    fn check(&self, codec: Codec) -> Result<(), SuperGzipError> {
+        let invalid = |message: String| {
+            Err(SuperGzipError::IO(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                message,
+            )))
+        };
+        if let Some(level) = self.settings.level {
+            if !(1..=codec.max_level()).contains(&level) {
+                return invalid(format!(
+                    "the level must be from 1 to {} for {}, not {}",
+                    codec.max_level(),
+                    codec.name(),
+                    level
+                ));
+            }
+        }
+        if let Some(block_size) = self.settings.block_size {
+            if !(1..=MAX_BLOCK_SIZE).contains(&block_size) {
+                return invalid(format!(
+                    "the block size must be from 1 to {} KiB, not {}",
+                    MAX_BLOCK_SIZE, block_size
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    /// Compresses every file matching the patterns, returning a report per discovered path
+    pub async fn compress<I, S>(mut self, patterns: I) -> Result<Vec<FileReport>, SuperGzipError>
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<OsString>,
+    {
+        self.check(self.settings.codec)?;
+        self.settings.b_zip = true;
+        engine::run(
+            self.settings,
+            patterns.into_iter().map(Into::into).collect(),
+        )
+        .await
+    }
+
+    /// Decompresses every file matching the patterns, returning a report per discovered path
+    pub async fn decompress<I, S>(mut self, patterns: I) -> Result<Vec<FileReport>, SuperGzipError>
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<OsString>,
+    {
+        self.settings.b_zip = false;
+        engine::run(
+            self.settings,
+            patterns.into_iter().map(Into::into).collect(),
+        )
+        .await
+    }
+
+    /// Recompresses every file matching the patterns from the codec of the compressor to the
+    /// given one, at the level of the compressor, returning a report per discovered path
+    pub async fn recompress<I, S>(
+        mut self,
+        to: Codec,
+        patterns: I,
+    ) -> Result<Vec<FileReport>, SuperGzipError>
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<OsString>,
+    {
+        self.check(to)?;
+        self.settings.b_zip = false;
+        self.settings.recompress_to = Some(to);
+        engine::run(
+            self.settings,
+            patterns.into_iter().map(Into::into).collect(),
+        )
+        .await
+    }
+
+    /// Compresses every file matching the patterns like [`compress`](Compressor::compress),
+    /// blocking until it is done. With the sync engine, no async runtime is involved; the async
+    /// engine is given a runtime of its own, so this must not be called from async code.
+    pub fn compress_blocking<I, S>(mut self, patterns: I) -> Result<Vec<FileReport>, SuperGzipError>
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<OsString>,
+    {
+        self.check(self.settings.codec)?;
+        self.settings.b_zip = true;
+        engine::run_blocking(
+            self.settings,
+            patterns.into_iter().map(Into::into).collect(),
+        )
+    }
+
+    /// Decompresses every file matching the patterns like
+    /// [`decompress`](Compressor::decompress), blocking until it is done, like
+    /// [`compress_blocking`](Compressor::compress_blocking)
+    pub fn decompress_blocking<I, S>(
+        mut self,
+        patterns: I,
+    ) -> Result<Vec<FileReport>, SuperGzipError>
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<OsString>,
+    {
+        self.settings.b_zip = false;
+        engine::run_blocking(
+            self.settings,
+            patterns.into_iter().map(Into::into).collect(),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn invalid_input(result: Result<Vec<FileReport>, SuperGzipError>) -> bool {
+        matches!(result, Err(SuperGzipError::IO(e)) if e.kind() == std::io::ErrorKind::InvalidInput)
+    }
+
+    /// Levels and block sizes out of range fail the run instead of panicking in it
+    #[tokio::test]
+    async fn out_of_range_settings_fail_the_run() {
+        let none: [&str; 0] = [];
+        for level in [0, 10, 42] {
+            let result = Compressor::new(Codec::Gzip)
+                .level(level)
+                .compress(none)
+                .await;
+            assert!(invalid_input(result), "level {}", level);
+        }
+        let result = Compressor::new(Codec::Lz4).level(12).compress(none).await;
+        assert!(result.is_ok());
+        let result = Compressor::new(Codec::Lz4)
+            .level(12)
+            .recompress(Codec::Gzip, none)
+            .await;
+        assert!(invalid_input(result));
+        for block_size in [0, MAX_BLOCK_SIZE + 1] {
+            let result = Compressor::new(Codec::Gzip)
+                .block_size(block_size)
+                .compress(none)
+                .await;
+            assert!(invalid_input(result), "block size {}", block_size);
+        }
+    }
+}
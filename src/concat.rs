@@ -0,0 +1,178 @@
+//! Joining gzip files into one without recompressing them. A gzip file may be made of several
+//! members that decompress to the consecutive parts of its data, so copying the members of the
+//! inputs one after the other makes a valid file that decompresses to all of their data in turn.
+//!
+//! Every input is checked before anything is written, since a corrupt member would make the
+//! rest of the joined file unreadable. Zeros padding the end of an input are left out for the
+//! same reason, as they are only allowed after the last member.
+
+use crate::batch::{move_into_place, temp_path};
+use crate::codec::{self, Codec};
+use crate::error::{Operation, SuperGzipError};
+use crate::magic::{self, Format};
+use crate::settings::{CollisionPolicy, Settings};
+use std::path::{Path, PathBuf};
+use tokio::io::{AsyncReadExt, AsyncSeekExt, AsyncWrite, AsyncWriteExt};
+use tokio::task::JoinHandle;
+
+/// A gzip file that was checked and can be joined
+#[derive(Clone, Debug)]
+pub struct Member {
+    pub path: PathBuf,
+    /// The length of the gzip members at the start of the file, without any padding after them
+    pub length: u64,
+}
+
+/// Starts checking that the file is a valid gzip file on the CPU pool, once a thread is free
+async fn start_check(
+    path: &Path,
+    settings: &Settings,
+) -> Result<JoinHandle<std::io::Result<Vec<u64>>>, SuperGzipError> {
+    match magic::sniff(path).await? {
+        Some(Format::Gzip) => {}
+        Some(format) => return Err(SuperGzipError::WrongFormat(format, Codec::Gzip)),
+        None => return Err(SuperGzipError::InvalidGzipHeader),
+    }
+    let path = path.to_path_buf();
+    Ok(settings
+        .cpu_pool()
+        .spawn(move || codec::gzip_member_ends(&path))
+        .await)
+}
+
+/// Waits for the check of the file to finish, returning the length of its members
+async fn finish_check(
+    check: JoinHandle<std::io::Result<Vec<u64>>>,
+    path: &Path,
+) -> Result<u64, SuperGzipError> {
+    match check.await? {
+        Ok(ends) => Ok(ends.last().copied().unwrap_or(0)),
+        Err(e) => Err(Codec::Gzip
+            .header_error(&e)
+            .unwrap_or(SuperGzipError::IO(e))
+            .for_file(path, Operation::Check)),
+    }
+}
+
+/// Checks that the file is a valid gzip file, returning the length of its members
+async fn check_file(path: &Path, settings: &Settings) -> Result<u64, SuperGzipError> {
+    finish_check(start_check(path, settings).await?, path).await
+}
+
+/// Whether the two paths are the same file, e.g. through different relative paths or links
+fn same_file(a: &Path, b: &Path) -> bool {
+    match (std::fs::canonicalize(a), std::fs::canonicalize(b)) {
+        (Ok(a), Ok(b)) => a == b,
+        _ => false,
+    }
+}
+
+/// Checks the files to join in parallel on the CPU pool, returning the ones that can be joined
+/// in order along with the errors of the others, which name the file
+pub async fn check(
+    paths: Vec<PathBuf>,
+    output: &Path,
+    settings: &Settings,
+) -> (Vec<Member>, Vec<SuperGzipError>) {
+    // Start all checks before waiting for any, so that they run in parallel
+    let mut checks = Vec::new();
+    for path in paths {
+        // Directories matched by the patterns are left out, as when decompressing
+        if std::fs::metadata(&path).is_ok_and(|m| m.is_dir()) {
+            continue;
+        }
+        let check = match same_file(&path, output) {
+            true => Err(SuperGzipError::IO(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                "is the output as well",
+            ))),
+            false => start_check(&path, settings).await,
+        };
+        checks.push((path, check));
+    }
+    let mut members = Vec::new();
+    let mut errors = Vec::new();
+    for (path, check) in checks {
+        let length = match check {
+            Ok(check) => finish_check(check, &path).await,
+            Err(e) => Err(e),
+        };
+        match (path, length) {
+            (path, Ok(length)) => members.push(Member { path, length }),
+            (path, Err(e)) => {
+                let e = e.for_file(&path, Operation::Check);
+                settings.report(&e.to_string());
+                errors.push(e);
+            }
+        }
+    }
+    (members, errors)
+}
+
+/// Copies the members of the checked files to the writer in order, returning the number of bytes
+/// written
+async fn copy_members<W>(members: &[Member], writer: &mut W) -> Result<u64, SuperGzipError>
+where
+    W: AsyncWrite + Unpin,
+{
+    let mut written = 0;
+    for member in members {
+        let file = tokio::fs::File::open(&member.path).await?;
+        written += tokio::io::copy(&mut file.take(member.length), writer).await?;
+    }
+    writer.flush().await?;
+    Ok(written)
+}
+
+/// Writes the members of the checked files to the output in order, returning the number of bytes
+/// written. With `append`, they are added to the end of the output if it exists, after checking
+/// that it is a valid gzip file itself and dropping any padding at its end. Otherwise the output
+/// is written to a temporary file that replaces it once complete, which it only does if
+/// `--force` is given.
+pub async fn join(
+    members: &[Member],
+    output: &Path,
+    append: bool,
+    settings: &Settings,
+) -> Result<u64, SuperGzipError> {
+    let exists = tokio::fs::symlink_metadata(output).await.is_ok();
+    if append && exists {
+        let length = match tokio::fs::metadata(output).await?.len() {
+            0 => 0,
+            _ => check_file(output, settings).await?,
+        };
+        let mut file = tokio::fs::OpenOptions::new()
+            .write(true)
+            .open(output)
+            .await?;
+        file.set_len(length).await?;
+        file.seek(std::io::SeekFrom::End(0)).await?;
+        let written = copy_members(members, &mut file).await?;
+        file.sync_all().await?;
+        return Ok(written);
+    }
+
+    if exists && settings.collision != CollisionPolicy::Force {
+        return Err(SuperGzipError::IO(std::io::Error::new(
+            std::io::ErrorKind::AlreadyExists,
+            format!("{} already exists", output.to_string_lossy()),
+        )));
+    }
+    let temp_path = temp_path(output);
+    let write = async {
+        let mut file = tokio::fs::File::create(&temp_path).await?;
+        let written = copy_members(members, &mut file).await?;
+        file.sync_all().await?;
+        Ok::<_, SuperGzipError>(written)
+    };
+    match write.await {
+        Ok(written) => {
+            move_into_place(&temp_path, output).await?;
+            Ok(written)
+        }
+        Err(e) => {
+            let _ = tokio::fs::remove_file(&temp_path).await;
+            Err(e)
+        }
+    }
+}
@@ -0,0 +1,476 @@
+//! Named profiles of settings in a configuration file, so that recurring jobs can be run with
+//! `--profile <NAME>` instead of repeating their options.
+//!
+//! The file is `supergzip/config.toml` in `$XDG_CONFIG_HOME` (by default `~/.config`), in the
+//! subset of TOML that profiles need:
+//!
+//! ```toml
+//! [profile.nightly-logs]
+//! codec = "gzip"
+//! level = 9
+//! threads = 4
+//! excludes = ["*.tmp", "current.log"]
+//! keep_original = true
+//! ```
+
+use crate::codec::Codec;
+use std::path::{Path, PathBuf};
+
+/// The settings of a profile, each of which is only used where the command line does not give it
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Profile {
+    /// The codec the level is for: the level is not used by the subcommands of other codecs
+    pub codec: Option<Codec>,
+    pub level: Option<u32>,
+    pub threads: Option<usize>,
+    /// Glob patterns of the files and directories to leave out
+    pub excludes: Vec<String>,
+    pub keep_original: Option<bool>,
+}
+
+/// The path of the configuration file, if the home directory is known
+pub fn default_path() -> Option<PathBuf> {
+    let config_home = std::env::var_os("XDG_CONFIG_HOME")
+        .filter(|dir| !dir.is_empty())
+        .map(PathBuf::from)
+        .or_else(|| std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".config")))?;
+    Some(config_home.join("supergzip").join("config.toml"))
+}
+
+/// The error of an array that goes on past the end of the text, which continues on the next line
+const UNTERMINATED_ARRAY: &str = "unterminated array";
+
+/// A value of a key in the configuration file
+#[derive(Debug)]
+enum Value {
+    String(String),
+    Integer(i64),
+    Boolean(bool),
+    Array(Vec<Value>),
+}
+
+impl Value {
+    fn kind(&self) -> &'static str {
+        match self {
+            Value::String(_) => "a string",
+            Value::Integer(_) => "an integer",
+            Value::Boolean(_) => "a boolean",
+            Value::Array(_) => "an array",
+        }
+    }
+}
+
+/// Parses a basic string starting at the opening quote, returning it and the rest of the line
+fn parse_string(text: &str) -> Result<(String, &str), String> {
+    let mut value = String::new();
+    let mut chars = text[1..].char_indices();
+    while let Some((index, c)) = chars.next() {
+        match c {
+            '"' => return Ok((value, &text[index + 2..])),
+            '\\' => match chars.next().map(|(_, c)| c) {
+                Some('"') => value.push('"'),
+                Some('\\') => value.push('\\'),
+                Some('n') => value.push('\n'),
+                Some('t') => value.push('\t'),
+                _ => return Err("unsupported escape sequence".to_string()),
+            },
+            c => value.push(c),
+        }
+    }
+    Err("unterminated string".to_string())
+}
+
+/// Parses the value at the start of the text, returning it and the rest of the line
+fn parse_value(text: &str) -> Result<(Value, &str), String> {
+    let text = text.trim_start();
+    if text.starts_with('"') {
+        let (value, rest) = parse_string(text)?;
+        return Ok((Value::String(value), rest));
+    }
+    if let Some(mut rest) = text.strip_prefix('[') {
+        let mut values = Vec::new();
+        // Whether a value was just read, so that a comma or the end of the array must come next
+        let mut after_value = false;
+        loop {
+            rest = rest.trim_start();
+            if let Some(comment) = rest.strip_prefix('#') {
+                rest = comment.find('\n').map_or("", |end| &comment[end..]);
+                continue;
+            }
+            if rest.is_empty() {
+                return Err(UNTERMINATED_ARRAY.to_string());
+            }
+            if let Some(after) = rest.strip_prefix(']') {
+                return Ok((Value::Array(values), after));
+            }
+            if after_value {
+                rest = rest.strip_prefix(',').ok_or("expected , or ] in array")?;
+                after_value = false;
+                continue;
+            }
+            let (value, after) = parse_value(rest)?;
+            values.push(value);
+            rest = after;
+            after_value = true;
+        }
+    }
+    let end = text
+        .find(|c: char| c == ',' || c == ']' || c == '#' || c.is_whitespace())
+        .unwrap_or(text.len());
+    let (token, rest) = text.split_at(end);
+    let value = match token {
+        "true" => Value::Boolean(true),
+        "false" => Value::Boolean(false),
+        token => Value::Integer(
+            token
+                .replace('_', "")
+                .parse()
+                .map_err(|_| format!("`{}` is not a supported value", token))?,
+        ),
+    };
+    Ok((value, rest))
+}
+
+/// Reads the name of a `[profile.<name>]` table header, or `None` for other tables
+fn profile_header(line: &str) -> Result<Option<String>, String> {
+    let inner = line
+        .strip_prefix('[')
+        .and_then(|line| line.strip_suffix(']'))
+        .ok_or("malformed table header")?
+        .trim();
+    let Some(name) = inner.strip_prefix("profile.") else {
+        return Ok(None);
+    };
+    if name.starts_with('"') {
+        let (name, rest) = parse_string(name)?;
+        return match rest.trim().is_empty() {
+            true => Ok(Some(name)),
+            false => Err("malformed table header".to_string()),
+        };
+    }
+    Ok(Some(name.trim().to_string()))
+}
+
+/// Sets a key of a profile from its value
+fn set_key(profile: &mut Profile, key: &str, value: Value) -> Result<(), String> {
+    let wrong_type = |expected: &str| format!("{} must be {}, not {}", key, expected, value.kind());
+    match (key, &value) {
+        ("codec", Value::String(name)) => {
+            profile.codec =
+                Some(Codec::from_name(name).ok_or_else(|| format!("unknown codec `{}`", name))?);
+        }
+        ("level", Value::Integer(level)) => {
+            profile.level = Some(
+                u32::try_from(*level)
+                    .ok()
+                    .filter(|level| *level >= 1)
+                    .ok_or("level must be at least 1")?,
+            );
+        }
+        ("threads", Value::Integer(threads)) => {
+            profile.threads = Some(
+                usize::try_from(*threads)
+                    .ok()
+                    .filter(|threads| *threads >= 1)
+                    .ok_or("threads must be at least 1")?,
+            );
+        }
+        ("threads", Value::String(threads)) if threads == "auto" => {
+            profile.threads = Some(crate::settings::available_threads());
+        }
+        ("excludes", Value::Array(patterns)) => {
+            profile.excludes = patterns
+                .iter()
+                .map(|pattern| match pattern {
+                    Value::String(pattern) => Ok(pattern.clone()),
+                    _ => Err(wrong_type("an array of strings")),
+                })
+                .collect::<Result<_, _>>()?;
+        }
+        ("keep_original", Value::Boolean(keep_original)) => {
+            profile.keep_original = Some(*keep_original);
+        }
+        ("codec", _) => return Err(wrong_type("a string")),
+        ("level", _) => return Err(wrong_type("an integer")),
+        ("threads", _) => return Err(wrong_type("an integer or \"auto\"")),
+        ("excludes", _) => return Err(wrong_type("an array of strings")),
+        ("keep_original", _) => return Err(wrong_type("a boolean")),
+        _ => return Err(format!("unknown key `{}`", key)),
+    }
+    Ok(())
+}
+
+/// Parses every profile of a configuration file, in the order they are defined
+pub fn parse(text: &str) -> Result<Vec<(String, Profile)>, String> {
+    let mut profiles: Vec<(String, Profile)> = Vec::new();
+    // Keys outside of profile tables are ignored, so that the file can hold other settings
+    let mut in_profile = false;
+    let mut lines = text.lines().enumerate();
+    while let Some((number, line)) = lines.next() {
+        let error = |message: String| format!("line {}: {}", number + 1, message);
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        if line.starts_with('[') {
+            let header = line.split(" #").next().unwrap_or(line).trim();
+            in_profile = match profile_header(header).map_err(error)? {
+                Some(name) if profiles.iter().any(|(existing, _)| *existing == name) => {
+                    return Err(error(format!("profile `{}` is defined twice", name)));
+                }
+                Some(name) => {
+                    profiles.push((name, Profile::default()));
+                    true
+                }
+                None => false,
+            };
+            continue;
+        }
+        let (key, value) = line
+            .split_once('=')
+            .ok_or_else(|| error("expected key = value".to_string()))?;
+
+        // Arrays may span several lines
+        let mut value = value.to_string();
+        let parsed = loop {
+            match parse_value(&value) {
+                Err(e) if e == UNTERMINATED_ARRAY => match lines.next() {
+                    Some((_, next)) => {
+                        value.push('\n');
+                        value.push_str(next);
+                    }
+                    None => break Err(e),
+                },
+                parsed => break parsed.map(|(value, rest)| (value, rest.trim().to_string())),
+            }
+        };
+        if !in_profile {
+            continue;
+        }
+        let (value, rest) = parsed.map_err(error)?;
+        if !rest.is_empty() && !rest.starts_with('#') {
+            return Err(error(format!("unexpected `{}` after the value", rest)));
+        }
+        let (_, profile) = profiles.last_mut().expect("inside a profile table");
+        set_key(profile, key.trim(), value).map_err(error)?;
+    }
+    Ok(profiles)
+}
+
+/// Reads the profile with the given name from the configuration file at the path
+pub fn load_profile(path: &Path, name: &str) -> Result<Profile, String> {
+    let text = std::fs::read_to_string(path)
+        .map_err(|e| format!("cannot read {}: {}", path.display(), e))?;
+    let profiles = parse(&text).map_err(|e| format!("{}: {}", path.display(), e))?;
+    let names: Vec<_> = profiles.iter().map(|(name, _)| name.clone()).collect();
+    profiles
+        .into_iter()
+        .find(|(candidate, _)| candidate == name)
+        .map(|(_, profile)| profile)
+        .ok_or_else(|| match names.is_empty() {
+            true => format!("{} defines no profiles", path.display()),
+            false => format!(
+                "no profile named `{}` in {} (available: {})",
+                name,
+                path.display(),
+                names.join(", ")
+            ),
+        })
+}
+
+/// The names of the profiles in the configuration file at the path, or none if it cannot be read
+pub fn profile_names(path: &Path) -> Vec<String> {
+    let Ok(text) = std::fs::read_to_string(path) else {
+        return Vec::new();
+    };
+    parse(&text)
+        .map(|profiles| profiles.into_iter().map(|(name, _)| name).collect())
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Parses a file with a single profile, panicking on errors
+    fn parse_one(text: &str) -> Profile {
+        let mut profiles = parse(text).unwrap();
+        assert_eq!(profiles.len(), 1);
+        profiles.remove(0).1
+    }
+
+    /// The error of parsing a file, which must fail
+    fn parse_error(text: &str) -> String {
+        parse(text).unwrap_err()
+    }
+
+    #[test]
+    fn the_documented_example_parses() {
+        let profile = parse_one(
+            "[profile.nightly-logs]\n\
+             codec = \"gzip\"\n\
+             level = 9\n\
+             threads = 4\n\
+             excludes = [\"*.tmp\", \"current.log\"]\n\
+             keep_original = true\n",
+        );
+        assert_eq!(
+            profile,
+            Profile {
+                codec: Some(Codec::Gzip),
+                level: Some(9),
+                threads: Some(4),
+                excludes: vec!["*.tmp".to_string(), "current.log".to_string()],
+                keep_original: Some(true),
+            }
+        );
+    }
+
+    #[test]
+    fn strings_and_names_can_be_quoted() {
+        let profiles = parse(
+            "[profile.\"with spaces.and dots\"]\n\
+             excludes = [\"a \\\"quoted\\\" name\", \"back\\\\slash\", \"tab\\there\", \"# not a comment\"]\n",
+        )
+        .unwrap();
+        assert_eq!(profiles[0].0, "with spaces.and dots");
+        assert_eq!(
+            profiles[0].1.excludes,
+            [
+                "a \"quoted\" name",
+                "back\\slash",
+                "tab\there",
+                "# not a comment"
+            ]
+        );
+
+        assert!(parse_error("[profile.p]\nexcludes = [\"\\q\"]\n").contains("escape sequence"));
+        assert!(parse_error("[profile.p]\ncodec = \"gzip\n").contains("unterminated string"));
+        assert!(parse_error("[profile.\"p\" x]\n").contains("malformed table header"));
+    }
+
+    #[test]
+    fn comments_and_blank_lines_are_ignored() {
+        let profile = parse_one(
+            "# The profiles of the backups\n\
+             \n\
+             [profile.p] # nightly\n\
+             \tlevel = 1_0 # an integer with a separator\n\
+             excludes = [ # one per line\n\
+             \t\"*.tmp\", # temporary files\n\
+             \n\
+             \t\"*.bak\" # backups, without a trailing comma\n\
+             ]\n",
+        );
+        assert_eq!(profile.level, Some(10));
+        assert_eq!(profile.excludes, ["*.tmp", "*.bak"]);
+        let profile = parse_one("[profile.p]\nexcludes = [\"a\"\n, \"b\"]\n");
+        assert_eq!(profile.excludes, ["a", "b"]);
+        assert!(parse_error("[profile.p]\nexcludes = [\"a\"").contains(UNTERMINATED_ARRAY));
+        assert!(parse_error("[profile.p]\nlevel = 9 10\n").contains("unexpected `10`"));
+    }
+
+    #[test]
+    fn unknown_keys_are_refused_in_profiles_only() {
+        let error = parse_error("[profile.p]\nlevel = 9\ncompression = \"max\"\n");
+        assert_eq!(error, "line 3: unknown key `compression`");
+
+        // Other tables and the keys before the first table may hold anything
+        let profiles = parse(
+            "editor = \"vi\"\n\
+             [ui]\n\
+             colors = [1, 2]\n\
+             [profile.p]\n\
+             level = 3\n\
+             [other]\n\
+             level = \"not a profile\"\n",
+        )
+        .unwrap();
+        assert_eq!(profiles.len(), 1);
+        assert_eq!(profiles[0].1.level, Some(3));
+    }
+
+    #[test]
+    fn bad_values_are_refused() {
+        for (line, message) in [
+            ("codec = \"brotli\"", "unknown codec `brotli`"),
+            ("codec = 1", "codec must be a string, not an integer"),
+            ("level = 0", "level must be at least 1"),
+            ("level = -3", "level must be at least 1"),
+            ("level = \"9\"", "level must be an integer, not a string"),
+            ("level = nine", "`nine` is not a supported value"),
+            ("threads = 0", "threads must be at least 1"),
+            (
+                "threads = \"all\"",
+                "threads must be an integer or \"auto\"",
+            ),
+            (
+                "excludes = \"*.tmp\"",
+                "excludes must be an array of strings",
+            ),
+            (
+                "excludes = [\"*.tmp\", 1]",
+                "excludes must be an array of strings",
+            ),
+            ("excludes = [\"a\" \"b\"]", "expected , or ] in array"),
+            (
+                "keep_original = 1",
+                "keep_original must be a boolean, not an integer",
+            ),
+            ("keep_original", "expected key = value"),
+        ] {
+            let error = parse_error(&format!("[profile.p]\n{}\n", line));
+            assert!(
+                error.starts_with("line 2: ") && error.contains(message),
+                "{}: {}",
+                line,
+                error
+            );
+        }
+        assert!(parse_one("[profile.p]\nthreads = \"auto\"\n")
+            .threads
+            .is_some());
+    }
+
+    #[test]
+    fn profiles_are_separate_sections() {
+        let profiles = parse(
+            "[profile.fast]\n\
+             codec = \"lz4\"\n\
+             level = 1\n\
+             [ profile.small ]\n\
+             level = 9\n",
+        )
+        .unwrap();
+        let names: Vec<_> = profiles.iter().map(|(name, _)| name.as_str()).collect();
+        assert_eq!(names, ["fast", "small"]);
+        assert_eq!(profiles[0].1.codec, Some(Codec::Lz4));
+        assert_eq!(profiles[1].1.codec, None);
+        assert_eq!(profiles[1].1.level, Some(9));
+
+        let error = parse_error("[profile.p]\n[profile.q]\n[profile.p]\n");
+        assert_eq!(error, "line 3: profile `p` is defined twice");
+        assert!(parse_error("[profile.p\n").contains("malformed table header"));
+    }
+
+    #[test]
+    fn profiles_are_loaded_by_name() {
+        let path =
+            std::env::temp_dir().join(format!("super-gunzip-config-{}.toml", std::process::id()));
+        std::fs::write(&path, "[profile.a]\nlevel = 2\n[profile.b]\nlevel = 5\n").unwrap();
+        assert_eq!(load_profile(&path, "b").unwrap().level, Some(5));
+        assert_eq!(profile_names(&path), ["a", "b"]);
+        let error = load_profile(&path, "c").unwrap_err();
+        assert!(error.ends_with("(available: a, b)"), "{}", error);
+
+        std::fs::write(&path, "# nothing yet\n").unwrap();
+        assert!(load_profile(&path, "a")
+            .unwrap_err()
+            .ends_with("defines no profiles"));
+        std::fs::remove_file(&path).unwrap();
+        assert!(load_profile(&path, "a")
+            .unwrap_err()
+            .starts_with("cannot read"));
+        assert!(profile_names(&path).is_empty());
+    }
+}
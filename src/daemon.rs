@@ -0,0 +1,349 @@
+//! A long-running server that runs batches submitted over a Unix domain socket on one shared
+//! runtime, so that many scheduled jobs do not each start their own process and thread pool.
+//!
+//! Clients send one request per line and receive one response per line, either `ok <payload>` or
+//! `error <message>`:
+//!
+//! - `submit<TAB><directory><TAB><arg><TAB><arg>...` queues a batch described by the arguments
+//!   of a batch subcommand, e.g. `gzip`, `-r`, `logs`, with relative paths resolved against the
+//!   given working directory, and responds with the id of the new job
+//! - `status` responds with a JSON array describing every job
+//! - `status<TAB><id>` responds with a JSON object describing a single job
+//! - `cancel<TAB><id>` cancels a queued or running job, responding with its id. A queued job never
+//!   starts, and a running one abandons the files in progress, removing their partial outputs,
+//!   and deletes no more originals.
+
+use crate::cancel::CancellationToken;
+use crate::engine;
+use crate::error::SuperGzipError;
+use crate::metrics::{self, Metrics};
+use crate::report::{json_optional_string, json_string, FileStatus};
+use crate::settings::Settings;
+use std::ffi::OsString;
+use std::io::{BufRead, BufReader, Error, ErrorKind, Result, Write};
+use std::net::SocketAddr;
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use tokio::runtime::Handle;
+use tokio::sync::Semaphore;
+
+/// Turns the working directory and arguments of a submitted job into its settings and patterns,
+/// or an error message
+pub type ParseJob = dyn Fn(&Path, &[String]) -> std::result::Result<(Settings, Vec<OsString>), String>
+    + Send
+    + Sync;
+
+/// The default socket path: `super-gunzip.sock` in `$XDG_RUNTIME_DIR`, which only its user can
+/// enter. There is no default without it, since in a shared directory like /tmp other users
+/// could get at the socket, or take its name first.
+pub fn default_socket() -> Result<PathBuf> {
+    match std::env::var_os("XDG_RUNTIME_DIR") {
+        Some(directory) if !directory.is_empty() => {
+            Ok(PathBuf::from(directory).join("super-gunzip.sock"))
+        }
+        _ => Err(Error::new(
+            ErrorKind::NotFound,
+            "$XDG_RUNTIME_DIR is not set, so there is no private directory for the socket: give \
+             one with --socket",
+        )),
+    }
+}
+
+/// Where a job is in its life
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum JobState {
+    Queued,
+    Running,
+    Finished,
+    Failed(String),
+    Cancelled,
+}
+
+/// A submitted job and its progress so far
+#[derive(Debug)]
+pub struct Job {
+    pub id: u64,
+    pub directory: PathBuf,
+    pub args: Vec<String>,
+    pub state: JobState,
+    pub files_processed: usize,
+    pub files_skipped: usize,
+    pub files_failed: usize,
+    pub input_size: u64,
+    pub output_size: u64,
+    /// Cancels the job, whether it is queued or running
+    pub cancellation: CancellationToken,
+}
+
+impl Job {
+    /// Renders the job as a single-line JSON object
+    pub fn to_json(&self) -> String {
+        let (state, error) = match &self.state {
+            JobState::Queued => ("queued", None),
+            JobState::Running => ("running", None),
+            JobState::Finished => ("finished", None),
+            JobState::Failed(error) => ("failed", Some(error.as_str())),
+            JobState::Cancelled => ("cancelled", None),
+        };
+        let args: Vec<String> = self.args.iter().map(|arg| json_string(arg)).collect();
+        format!(
+            "{{\"id\":{},\"directory\":{},\"args\":[{}],\"state\":\"{}\",\"error\":{},\"processed\":{},\"skipped\":{},\"failed\":{},\"input_size\":{},\"output_size\":{}}}",
+            self.id,
+            json_string(&self.directory.to_string_lossy()),
+            args.join(","),
+            state,
+            json_optional_string(error),
+            self.files_processed,
+            self.files_skipped,
+            self.files_failed,
+            self.input_size,
+            self.output_size
+        )
+    }
+}
+
+/// The state shared by every connection to the daemon
+struct Daemon {
+    jobs: Mutex<Vec<Job>>,
+    runtime: Handle,
+    /// Limits how many jobs run at once, queueing the rest
+    slots: Arc<Semaphore>,
+    parse: Box<ParseJob>,
+    metrics: Option<Arc<Metrics>>,
+}
+
+impl Daemon {
+    fn update(&self, id: u64, change: impl FnOnce(&mut Job)) {
+        let mut jobs = self.jobs.lock().unwrap();
+        if let Some(job) = jobs.iter_mut().find(|job| job.id == id) {
+            change(job);
+        }
+        self.count_queued(&jobs);
+    }
+
+    /// Records the number of jobs waiting to run in the metrics, if they are served
+    fn count_queued(&self, jobs: &[Job]) {
+        if let Some(metrics) = &self.metrics {
+            let queued = jobs.iter().filter(|job| job.state == JobState::Queued);
+            metrics.set_queue_depth(queued.count());
+        }
+    }
+
+    /// Queues a job on the runtime, returning its id
+    fn submit(
+        self: &Arc<Self>,
+        directory: PathBuf,
+        args: Vec<String>,
+    ) -> std::result::Result<u64, String> {
+        if !directory.is_absolute() {
+            return Err("the working directory must be absolute".to_string());
+        }
+        let (mut settings, patterns) = (self.parse)(&directory, &args)?;
+        let description = args.join(" ");
+        let id = {
+            let mut jobs = self.jobs.lock().unwrap();
+            let id = jobs.last().map_or(1, |job| job.id + 1);
+            jobs.push(Job {
+                id,
+                directory,
+                args,
+                state: JobState::Queued,
+                files_processed: 0,
+                files_skipped: 0,
+                files_failed: 0,
+                input_size: 0,
+                output_size: 0,
+                cancellation: settings.cancellation.clone(),
+            });
+            self.count_queued(&jobs);
+            id
+        };
+        println!("Job {} submitted: {}", id, description);
+
+        // Record the progress of the job as its files are done instead of printing it
+        settings.quiet = true;
+        let daemon = Arc::clone(self);
+        settings.hooks.on_file_done = Some(Arc::new(move |report| {
+            daemon.update(id, |job| match report.status {
+                FileStatus::Processed => {
+                    job.files_processed += 1;
+                    job.input_size += report.input_size;
+                    job.output_size += report.output_size;
+                }
+                FileStatus::Skipped(_) => job.files_skipped += 1,
+                FileStatus::Failed(_) => job.files_failed += 1,
+            })
+        }));
+        if let Some(metrics) = &self.metrics {
+            metrics.attach(&mut settings.hooks);
+        }
+
+        let daemon = Arc::clone(self);
+        self.runtime.spawn(async move {
+            let _slot = daemon.slots.acquire().await;
+            let cancellation = settings.cancellation.clone();
+            let state = match cancellation.is_cancelled() {
+                true => JobState::Cancelled,
+                false => {
+                    daemon.update(id, |job| job.state = JobState::Running);
+                    match engine::run(settings, patterns).await {
+                        Ok(_) => JobState::Finished,
+                        Err(_) if cancellation.is_cancelled() => JobState::Cancelled,
+                        Err(e) => JobState::Failed(e.to_string()),
+                    }
+                }
+            };
+            match &state {
+                JobState::Failed(error) => println!("Job {} failed: {}", id, error),
+                JobState::Cancelled => println!("Job {} cancelled", id),
+                _ => println!("Job {} finished", id),
+            }
+            daemon.update(id, |job| job.state = state);
+        });
+        Ok(id)
+    }
+
+    /// Answers a single request line
+    fn respond(self: &Arc<Self>, request: &str) -> String {
+        let mut parts = request.split('\t');
+        let response = match (parts.next(), parts.next()) {
+            (Some("submit"), Some(directory)) => {
+                let args = parts.map(String::from).collect();
+                self.submit(PathBuf::from(directory), args)
+                    .map(|id| id.to_string())
+            }
+            (Some("status"), None) => {
+                let jobs = self.jobs.lock().unwrap();
+                let jobs: Vec<String> = jobs.iter().map(Job::to_json).collect();
+                Ok(format!("[{}]", jobs.join(",")))
+            }
+            (Some("status"), Some(id)) => {
+                let jobs = self.jobs.lock().unwrap();
+                id.parse::<u64>()
+                    .ok()
+                    .and_then(|id| jobs.iter().find(|job| job.id == id))
+                    .map(Job::to_json)
+                    .ok_or_else(|| format!("there is no job {}", id))
+            }
+            (Some("cancel"), Some(id)) => {
+                let jobs = self.jobs.lock().unwrap();
+                let job = id
+                    .parse::<u64>()
+                    .ok()
+                    .and_then(|id| jobs.iter().find(|job| job.id == id));
+                match job {
+                    Some(job) if matches!(job.state, JobState::Queued | JobState::Running) => {
+                        job.cancellation.cancel();
+                        Ok(job.id.to_string())
+                    }
+                    Some(job) => Err(format!("job {} is no longer running", job.id)),
+                    None => Err(format!("there is no job {}", id)),
+                }
+            }
+            _ => Err(format!("unknown request: {}", request)),
+        };
+        match response {
+            Ok(payload) => format!("ok {}", payload),
+            Err(message) => format!("error {}", message.replace('\n', " ")),
+        }
+    }
+
+    fn handle_connection(self: &Arc<Self>, stream: UnixStream) -> Result<()> {
+        let mut writer = stream.try_clone()?;
+        for line in BufReader::new(stream).lines() {
+            let line = line?;
+            writeln!(writer, "{}", self.respond(line.trim_end_matches('\r')))?;
+        }
+        Ok(())
+    }
+}
+
+/// Binds the socket, replacing a stale socket left behind by a daemon that is no longer running.
+/// Only the owner can connect, since jobs run with the daemon's permissions.
+fn bind(socket: &Path) -> Result<UnixListener> {
+    if std::fs::symlink_metadata(socket).is_ok() {
+        if UnixStream::connect(socket).is_ok() {
+            return Err(Error::new(
+                ErrorKind::AddrInUse,
+                format!("a daemon is already listening on {}", socket.display()),
+            ));
+        }
+        std::fs::remove_file(socket)?;
+    }
+    // Create the socket without permissions for anyone else, rather than taking them away
+    // afterwards, which would leave a moment for other users to connect
+    // SAFETY: umask only swaps the file mode mask of the process
+    let umask = unsafe { libc::umask(0o077) };
+    let listener = UnixListener::bind(socket);
+    // SAFETY: as above
+    unsafe { libc::umask(umask) };
+    let listener = listener?;
+    use std::os::unix::fs::PermissionsExt;
+    std::fs::set_permissions(socket, std::fs::Permissions::from_mode(0o600))?;
+    Ok(listener)
+}
+
+/// Serves job submissions on the socket until the process is stopped, running at most
+/// `max_jobs` jobs at once and queueing the rest in submission order. The metrics of the jobs
+/// are served over HTTP at `http://<metrics>/metrics` if an address is given.
+pub async fn serve(
+    socket: &Path,
+    max_jobs: usize,
+    metrics: Option<SocketAddr>,
+    parse: Box<ParseJob>,
+) -> std::result::Result<(), SuperGzipError> {
+    let listener = bind(socket)?;
+    println!("Listening on {}", socket.display());
+    let metrics = match metrics {
+        Some(address) => {
+            let metrics = Arc::new(Metrics::default());
+            metrics::serve(Arc::clone(&metrics), address)?;
+            println!("Serving metrics on http://{}/metrics", address);
+            Some(metrics)
+        }
+        None => None,
+    };
+    let daemon = Arc::new(Daemon {
+        jobs: Mutex::new(Vec::new()),
+        runtime: Handle::current(),
+        slots: Arc::new(Semaphore::new(max_jobs)),
+        parse,
+        metrics,
+    });
+
+    // The standard library's blocking socket is served from its own threads
+    tokio::task::spawn_blocking(move || {
+        for stream in listener.incoming() {
+            match stream {
+                Ok(stream) => {
+                    let daemon = Arc::clone(&daemon);
+                    std::thread::spawn(move || daemon.handle_connection(stream));
+                }
+                Err(e) => eprintln!("Warning: failed to accept a connection: {}", e),
+            }
+        }
+    })
+    .await?;
+    Ok(())
+}
+
+/// Sends a single request to the daemon listening on the socket, returning the payload of its
+/// response or the error message it responded with
+pub fn request(socket: &Path, request: &str) -> Result<std::result::Result<String, String>> {
+    let mut stream = UnixStream::connect(socket)?;
+    writeln!(stream, "{}", request)?;
+    let mut response = String::new();
+    BufReader::new(stream).read_line(&mut response)?;
+    let response = response.trim_end();
+    if let Some(payload) = response.strip_prefix("ok ") {
+        Ok(Ok(payload.to_string()))
+    } else if let Some(message) = response.strip_prefix("error ") {
+        Ok(Err(message.to_string()))
+    } else {
+        Err(Error::new(
+            ErrorKind::InvalidData,
+            format!("unexpected response from the daemon: {}", response),
+        ))
+    }
+}
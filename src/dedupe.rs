@@ -0,0 +1,156 @@
+//! Finding the files of a run that are hard links to the same file or have the same contents, so
+//! that their output is only produced once and reused for the others
+
+use crate::gzip_header::{self, GzipOrigin};
+use crate::journal::{self, Fingerprint};
+use std::collections::HashMap;
+use std::io::{BufRead, BufReader as StdBufReader, ErrorKind, Write};
+use std::path::{Path, PathBuf};
+use tokio::fs::File as AsyncFile;
+use tokio::io::{AsyncBufReadExt, BufReader};
+use tokio::sync::watch;
+
+/// Announces where the first file with some contents put its output once it is done
+pub type OutputSender = watch::Sender<Option<PathBuf>>;
+/// Waits for the output of the first file with some contents
+pub type OutputReceiver = watch::Receiver<Option<PathBuf>>;
+
+/// An earlier file of the run that a file is the same as
+pub struct Earlier {
+    pub output: OutputReceiver,
+    /// Whether both are hard links to the same file, rather than files with the same contents
+    pub same_file: bool,
+}
+
+/// The device and inode number of a file with several hard links, which identify it
+#[cfg(unix)]
+async fn link_key(path: &Path) -> Option<(u64, u64)> {
+    use std::os::unix::fs::MetadataExt;
+    let metadata = tokio::fs::metadata(path).await.ok()?;
+    (metadata.nlink() > 1).then(|| (metadata.dev(), metadata.ino()))
+}
+
+#[cfg(not(unix))]
+async fn link_key(_path: &Path) -> Option<(u64, u64)> {
+    None
+}
+
+/// The files of a run registered so far, by the file they link to and by the fingerprint of
+/// their contents
+#[derive(Default)]
+pub struct Dedupe {
+    links: HashMap<(u64, u64), OutputReceiver>,
+    seen: HashMap<Fingerprint, Vec<(PathBuf, OutputReceiver)>>,
+}
+
+impl Dedupe {
+    /// Registers a file that is about to be processed, also comparing its contents with the
+    /// earlier files if `by_contents` is set. Returns the sender to announce its output with if
+    /// it is the first of its kind, or the earlier file that it is the same as otherwise.
+    pub async fn register(
+        &mut self,
+        path: &Path,
+        by_contents: bool,
+    ) -> (Option<OutputSender>, Option<Earlier>) {
+        let link = link_key(path).await;
+        if let Some(output) = link.and_then(|link| self.links.get(&link)) {
+            let output = output.clone();
+            return (
+                None,
+                Some(Earlier {
+                    output,
+                    same_file: true,
+                }),
+            );
+        }
+
+        let fingerprint = match by_contents {
+            true => journal::fingerprint(path).await.ok(),
+            false => None,
+        };
+        if let Some(fingerprint) = fingerprint {
+            // Fingerprints can collide, so the contents are compared before they count as the
+            // same
+            for (first, output) in self.seen.get(&fingerprint).into_iter().flatten() {
+                if same_contents(first, path).await.unwrap_or(false) {
+                    let output = output.clone();
+                    return (
+                        None,
+                        Some(Earlier {
+                            output,
+                            same_file: false,
+                        }),
+                    );
+                }
+            }
+        }
+
+        if link.is_none() && fingerprint.is_none() {
+            return (None, None);
+        }
+        let (sender, receiver) = watch::channel(None);
+        if let Some(link) = link {
+            self.links.insert(link, receiver.clone());
+        }
+        if let Some(fingerprint) = fingerprint {
+            let candidates = self.seen.entry(fingerprint).or_default();
+            candidates.push((path.to_path_buf(), receiver));
+        }
+        (Some(sender), None)
+    }
+}
+
+/// Waits for the earlier file to be done, returning its output if it produced one
+pub async fn first_output(mut receiver: OutputReceiver) -> Option<PathBuf> {
+    while receiver.borrow().is_none() {
+        // The sender is dropped without an output when the first file fails or is skipped
+        if receiver.changed().await.is_err() {
+            break;
+        }
+    }
+    let output = receiver.borrow().clone();
+    output
+}
+
+/// Whether the files at the two paths have the same contents
+async fn same_contents(a: &Path, b: &Path) -> std::io::Result<bool> {
+    let mut a = BufReader::new(AsyncFile::open(a).await?);
+    let mut b = BufReader::new(AsyncFile::open(b).await?);
+    loop {
+        let chunk_a = a.fill_buf().await?;
+        let chunk_b = b.fill_buf().await?;
+        let len = chunk_a.len().min(chunk_b.len());
+        if len == 0 {
+            return Ok(chunk_a.len() == chunk_b.len());
+        }
+        if chunk_a[..len] != chunk_b[..len] {
+            return Ok(false);
+        }
+        a.consume(len);
+        b.consume(len);
+    }
+}
+
+/// Copies a gzip file written by this program to the target, storing the given name and
+/// modification time in its header instead of the ones of the file it was compressed from
+pub fn copy_with_origin(
+    compressed: &Path,
+    target: &Path,
+    origin: &GzipOrigin,
+) -> std::io::Result<()> {
+    let mut input = StdBufReader::new(std::fs::File::open(compressed)?);
+    let mut minimal = [0; 10];
+    let start = input.fill_buf()?;
+    if start.len() < 10 {
+        return Err(ErrorKind::UnexpectedEof.into());
+    }
+    minimal.copy_from_slice(&start[..10]);
+    gzip_header::skip_header(&mut input)?;
+
+    // Only the name and tags are ever stored in the headers written here, and they are replaced
+    minimal[3] = 0;
+    let mut output = std::fs::File::create(target)?;
+    output.write_all(&origin.header(&minimal))?;
+    std::io::copy(&mut input, &mut output)?;
+    output.flush()
+}
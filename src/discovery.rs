@@ -0,0 +1,465 @@
+//! Finding the files to process from paths and glob patterns
+
+use crate::bundle;
+use crate::entropy;
+use crate::error::{Operation, SuperGzipError};
+use crate::http;
+use crate::magic;
+use crate::settings::{Incompressible, Order, Settings, SymlinkPolicy};
+use std::collections::HashSet;
+use std::ffi::{OsStr, OsString};
+use std::fs::Metadata;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use tokio::fs::metadata as async_metadata;
+use tokio::io::Result as TokioIOResult;
+use tokio::sync::mpsc::{self, Receiver};
+use tokio::task::JoinHandle;
+
+/// Why a file is left out by the size and age filters of the settings, if it is
+pub(crate) fn filter(metadata: &Metadata, settings: &Settings) -> Option<SkipReason> {
+    let size = metadata.len();
+    if settings.min_size.is_some_and(|min_size| size < min_size) {
+        return Some(SkipReason::TooSmall);
+    } else if settings.max_size.is_some_and(|max_size| size > max_size) {
+        return Some(SkipReason::TooLarge);
+    }
+    if settings.older_than.is_none() && settings.newer_than.is_none() {
+        return None;
+    }
+    let Ok(modified) = metadata.modified() else {
+        return None;
+    };
+    // Files modified in the future count as brand new
+    let age = modified.elapsed().unwrap_or_default();
+    if settings
+        .older_than
+        .is_some_and(|older_than| age < older_than)
+    {
+        Some(SkipReason::TooNew)
+    } else if settings
+        .newer_than
+        .is_some_and(|newer_than| age > newer_than)
+    {
+        Some(SkipReason::TooOld)
+    } else {
+        None
+    }
+}
+
+/// Why a discovered path is not processed
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SkipReason {
+    NotAFile,
+    AlreadyCompressed,
+    NotCompressed,
+    OutputExists,
+    OutputCollision,
+    AlreadyDone,
+    Interrupted,
+    UpToDate,
+    TooSmall,
+    TooLarge,
+    TooNew,
+    TooOld,
+    CompressedContent,
+    Incompressible,
+    Symlink,
+    Aborted,
+    Declined,
+    BundleManifest,
+    NoSavings,
+    OutputIsInput,
+}
+
+impl SkipReason {
+    pub fn describe(self) -> &'static str {
+        match self {
+            SkipReason::NotAFile => "not a file",
+            SkipReason::AlreadyCompressed => "already compressed",
+            SkipReason::NotCompressed => "not compressed",
+            SkipReason::OutputExists => "output already exists",
+            SkipReason::OutputCollision => "output collides with another input",
+            SkipReason::AlreadyDone => "already done according to the journal",
+            SkipReason::Interrupted => "the run was interrupted before it started",
+            SkipReason::UpToDate => "output is up to date",
+            SkipReason::TooSmall => "smaller than --min-size",
+            SkipReason::TooLarge => "larger than --max-size",
+            SkipReason::TooNew => "modified more recently than --older-than",
+            SkipReason::TooOld => "modified longer ago than --newer-than",
+            SkipReason::CompressedContent => "content is already compressed",
+            SkipReason::Incompressible => "content barely compresses",
+            SkipReason::Symlink => "a symbolic link",
+            SkipReason::Aborted => "the run was aborted after too many errors",
+            SkipReason::Declined => "overwriting its output was declined",
+            SkipReason::BundleManifest => "the manifest of a bundle",
+            SkipReason::NoSavings => {
+                "not compressed, since compressing it saved too little (see --min-savings)"
+            }
+            SkipReason::OutputIsInput => "its output would replace the file itself",
+        }
+    }
+}
+
+/// The file name without a `.suffix` at its end, if it has one and something is left. Names
+/// that are not valid UTF-8 are handled byte for byte rather than mangled.
+#[cfg(unix)]
+fn strip_name_suffix(name: &OsStr, suffix: &str) -> Option<OsString> {
+    use std::os::unix::ffi::OsStrExt;
+    let stem = name
+        .as_bytes()
+        .strip_suffix(suffix.as_bytes())?
+        .strip_suffix(b".")?;
+    (!stem.is_empty()).then(|| OsStr::from_bytes(stem).to_os_string())
+}
+
+#[cfg(not(unix))]
+fn strip_name_suffix(name: &OsStr, suffix: &str) -> Option<OsString> {
+    let stem = name.to_str()?.strip_suffix(suffix)?.strip_suffix('.')?;
+    (!stem.is_empty()).then(|| OsString::from(stem))
+}
+
+/// The file name of the path once decompressed, if it has the compressed suffix (e.g. `a.txt` for
+/// `a.txt.gz`) or one of its aliases (e.g. `a.tar` for `a.tgz`)
+pub fn decompressed_name(path: &Path, settings: &Settings) -> Option<OsString> {
+    let file_name = path.file_name()?;
+    if let Some(stem) = strip_name_suffix(file_name, &settings.suffix) {
+        return Some(stem);
+    }
+    settings
+        .codec
+        .aliases()
+        .iter()
+        .find_map(|(alias, extension)| {
+            let mut name = strip_name_suffix(file_name, alias)?;
+            name.push(format!(".{}", extension));
+            Some(name)
+        })
+}
+
+/// Whether the path is a symbolic link, rather than the file it points to
+pub async fn is_symlink(path: &Path) -> bool {
+    tokio::fs::symlink_metadata(path)
+        .await
+        .is_ok_and(|metadata| metadata.file_type().is_symlink())
+}
+
+/// Decides whether a discovered path should be processed, returning why it is skipped otherwise
+pub async fn skip_reason(path: &Path, settings: &Settings) -> Option<SkipReason> {
+    // URLs are judged by the name their download is saved under, since their size is unknown
+    let local_name;
+    let (path, metadata) = match http::url(path) {
+        Some(url) => {
+            local_name = PathBuf::from(http::file_name(url));
+            (local_name.as_path(), None)
+        }
+        None => match async_metadata(path).await {
+            Ok(metadata) if metadata.is_file() => (path, Some(metadata)),
+            _ => return Some(SkipReason::NotAFile),
+        },
+    };
+    if metadata.is_some() && settings.symlinks == SymlinkPolicy::Skip && is_symlink(path).await {
+        return Some(SkipReason::Symlink);
+    }
+
+    // Skip processing if the file is already compressed with the codec and we're compressing
+    // or if the file is not compressed with the codec and we're decompressing
+    let is_compressed = decompressed_name(path, settings).is_some();
+    if settings.b_zip && is_compressed {
+        return Some(SkipReason::AlreadyCompressed);
+    }
+    if settings.b_zip && bundle::is_manifest(path).await {
+        return Some(SkipReason::BundleManifest);
+    }
+    // Files are judged by their contents when their name does not tell, so that mis-named files
+    // still get decompressed
+    let Some(metadata) = metadata else {
+        return (!settings.b_zip && !is_compressed).then_some(SkipReason::NotCompressed);
+    };
+    if !settings.b_zip && !is_compressed {
+        let format = magic::sniff(path).await.ok().flatten();
+        if format != Some(settings.codec.format()) {
+            return Some(SkipReason::NotCompressed);
+        }
+    }
+    if let Some(reason) = filter(&metadata, settings) {
+        return Some(reason);
+    }
+
+    // Compressing already compressed contents only wastes time, whatever the file is named.
+    // Unreadable files are left for processing to report.
+    if settings.b_zip && !settings.force_compress {
+        if let Ok(Some(_)) = magic::sniff(path).await {
+            return Some(SkipReason::CompressedContent);
+        }
+    }
+    // Contents that barely compress can be left alone too, judging by a few samples
+    if settings.b_zip && settings.incompressible == Incompressible::Skip {
+        let path = path.to_path_buf();
+        let sampled = tokio::task::spawn_blocking(move || entropy::is_incompressible(&path)).await;
+        if let Ok(Ok(true)) = sampled {
+            return Some(SkipReason::Incompressible);
+        }
+    }
+    None
+}
+
+/// Whether the path is left out by the exclude patterns of the settings, which are matched
+/// against both its file name and the whole path, or because git ignores it
+pub fn is_excluded(path: &Path, settings: &Settings) -> bool {
+    let excluded = settings.excludes.iter().any(|pattern| {
+        pattern.matches_path(path)
+            || path
+                .file_name()
+                .is_some_and(|name| pattern.matches_path(Path::new(name)))
+    });
+    excluded
+        || settings.gitignore().is_some_and(|gitignore| {
+            http::url(path).is_none() && gitignore.is_ignored(path, path.is_dir())
+        })
+}
+
+/// The number of directories listed in parallel ahead of the walk
+const PREFETCH_DIRS: usize = 16;
+
+/// The number of discovered paths that can wait to be picked up before discovery pauses
+pub const DISCOVERY_BUFFER: usize = 1024;
+
+/// A discovered path, or an error that occurred while looking for paths
+pub type Discovered = Result<PathBuf, SuperGzipError>;
+
+/// The non-directory entries and the subdirectories of a directory, each in name order
+pub(crate) type Listing = (Vec<PathBuf>, Vec<PathBuf>);
+
+pub(crate) fn list_dir(dir: &Path) -> TokioIOResult<Listing> {
+    let mut files = Vec::new();
+    let mut dirs = Vec::new();
+    for entry in std::fs::read_dir(dir)? {
+        let entry = entry?;
+        match entry.file_type()?.is_dir() {
+            true => dirs.push(entry.path()),
+            false => files.push(entry.path()),
+        }
+    }
+    files.sort();
+    dirs.sort();
+    Ok((files, dirs))
+}
+
+/// A directory waiting to be walked, which may already be being listed
+struct PendingDir {
+    path: PathBuf,
+    depth: usize,
+    listing: Option<JoinHandle<TokioIOResult<Listing>>>,
+}
+
+impl PendingDir {
+    fn new(path: PathBuf, depth: usize) -> PendingDir {
+        PendingDir {
+            path,
+            depth,
+            listing: None,
+        }
+    }
+
+    /// Starts listing the directory in the background, if it has not been started yet
+    fn prefetch(&mut self) {
+        if self.listing.is_none() {
+            let path = self.path.clone();
+            self.listing = Some(tokio::task::spawn_blocking(move || list_dir(&path)));
+        }
+    }
+
+    async fn list(mut self) -> Result<Listing, SuperGzipError> {
+        self.prefetch();
+        let listing = self.listing.expect("the listing was just started");
+        match listing.await? {
+            Ok(listing) => Ok(listing),
+            Err(e) => Err(SuperGzipError::IO(e).for_file(&self.path, Operation::Read)),
+        }
+    }
+}
+
+/// Whether the pattern names a path as it is, rather than matching paths with wildcards
+fn is_literal(pattern: &OsStr) -> bool {
+    pattern
+        .to_str()
+        .is_none_or(|pattern| !pattern.contains(['*', '?', '[']))
+}
+
+/// The error for a pattern that matched nothing, if it names a path as it is: a missing input
+/// the user named fails the run, while a wildcard that matches nothing is no error. Blocks.
+pub(crate) fn missing_input(pattern: &OsStr) -> Option<SuperGzipError> {
+    if !is_literal(pattern) {
+        return None;
+    }
+    let error = std::fs::symlink_metadata(pattern).err()?;
+    Some(SuperGzipError::IO(error).for_file(Path::new(pattern), Operation::Read))
+}
+
+/// Where discovered paths go, each only once and only if it is not excluded. Sending waits
+/// while the channel is full, and fails once its receiver is gone.
+struct Sink<'a> {
+    sender: mpsc::Sender<Discovered>,
+    settings: &'a Settings,
+    seen: HashSet<PathBuf>,
+}
+
+impl Sink<'_> {
+    async fn path(&mut self, path: PathBuf) -> Result<(), ()> {
+        if is_excluded(&path, self.settings) || !self.seen.insert(path.clone()) {
+            return Ok(());
+        }
+        self.sender.send(Ok(path)).await.map_err(|_| ())
+    }
+
+    async fn error(&mut self, error: SuperGzipError) -> Result<(), ()> {
+        self.sender.send(Err(error)).await.map_err(|_| ())
+    }
+}
+
+/// Walks a directory tree depth-first in name order, sending every non-directory entry to the
+/// sink. The directories walked next are listed in parallel ahead of time. Symlinks to
+/// directories are not followed, so cyclic trees cannot loop forever, excluded directories are
+/// not entered, and directories that cannot be read are sent as errors.
+async fn walk_dir(root: PathBuf, sink: &mut Sink<'_>) -> Result<(), ()> {
+    let settings = sink.settings;
+    let mut stack = vec![PendingDir::new(root, 1)];
+    loop {
+        for pending in stack.iter_mut().rev().take(PREFETCH_DIRS) {
+            pending.prefetch();
+        }
+        let Some(dir) = stack.pop() else {
+            return Ok(());
+        };
+        let depth = dir.depth;
+        let (files, dirs) = match dir.list().await {
+            Ok(listing) => listing,
+            Err(e) => {
+                sink.error(e).await?;
+                continue;
+            }
+        };
+        for file in files {
+            sink.path(file).await?;
+        }
+
+        // Push subdirectories in reverse so they are popped in name order
+        if settings.max_depth.is_none_or(|max_depth| depth < max_depth) {
+            stack.extend(
+                dirs.into_iter()
+                    .rev()
+                    .filter(|dir| !is_excluded(dir, settings))
+                    .map(|dir| PendingDir::new(dir, depth + 1)),
+            );
+        }
+    }
+}
+
+/// Sends the paths the patterns expand to, in order, stopping early if the receiver is gone
+async fn walk_patterns(
+    patterns: &[OsString],
+    settings: &Settings,
+    sender: mpsc::Sender<Discovered>,
+) -> Result<(), ()> {
+    let mut sink = Sink {
+        sender,
+        settings,
+        seen: HashSet::new(),
+    };
+    for pattern in patterns {
+        let matches: Box<dyn Iterator<Item = PathBuf> + Send> = match pattern.to_str() {
+            // URLs are downloaded as they are rather than matched against the file system
+            Some(pattern) if http::is_url(pattern) => {
+                Box::new(std::iter::once(PathBuf::from(pattern)))
+            }
+            Some(pattern) => Box::new(
+                glob::glob(pattern)
+                    .expect("Invalid glob pattern provided. Please check your input.")
+                    .flatten(),
+            ),
+            // Patterns that are not valid UTF-8 cannot be globs, so they name a path as it is
+            None if tokio::fs::symlink_metadata(pattern).await.is_ok() => {
+                Box::new(std::iter::once(PathBuf::from(pattern)))
+            }
+            None => Box::new(std::iter::empty()),
+        };
+        let mut matched = false;
+        for path in matches {
+            matched = true;
+            if settings.recursive
+                && http::url(&path).is_none()
+                && !is_excluded(&path, settings)
+                && async_metadata(&path).await.is_ok_and(|m| m.is_dir())
+            {
+                walk_dir(path, &mut sink).await?;
+            } else {
+                sink.path(path).await?;
+            }
+        }
+        if let (false, Some(e)) = (matched, missing_input(pattern)) {
+            sink.error(e).await?;
+        }
+    }
+    Ok(())
+}
+
+/// Expands the glob patterns into the paths to process, walking any matched directories when
+/// recursing. HTTP(S) URLs are passed through as they are. Paths matched by several patterns
+/// are only returned once. Directories that cannot be read, and paths named as they are that do
+/// not exist, are recorded as errors.
+pub async fn discover(
+    patterns: &[OsString],
+    settings: &Settings,
+    errors: &mut Vec<SuperGzipError>,
+) -> Vec<PathBuf> {
+    let (sender, mut receiver) = mpsc::channel(DISCOVERY_BUFFER);
+    let mut paths = Vec::new();
+    let collect = async {
+        while let Some(discovered) = receiver.recv().await {
+            match discovered {
+                Ok(path) => paths.push(path),
+                Err(e) => errors.push(e),
+            }
+        }
+    };
+    let _ = tokio::join!(walk_patterns(patterns, settings, sender), collect);
+    paths
+}
+
+/// Sorts the discovered paths into the order. Paths without metadata, such as URLs, count as
+/// empty and as modified at the Unix epoch. Blocks.
+pub fn sort_paths(mut paths: Vec<PathBuf>, order: Order) -> Vec<PathBuf> {
+    if order == Order::Name {
+        paths.sort();
+        return paths;
+    }
+    let mut keyed: Vec<(u64, std::time::SystemTime, PathBuf)> = paths
+        .into_iter()
+        .map(|path| {
+            let metadata = std::fs::metadata(&path).ok();
+            let size = metadata.as_ref().map_or(0, Metadata::len);
+            let modified = metadata
+                .and_then(|metadata| metadata.modified().ok())
+                .unwrap_or(std::time::UNIX_EPOCH);
+            (size, modified, path)
+        })
+        .collect();
+    // The sorts are stable, so ties keep the order of discovery
+    match order {
+        Order::LargestFirst => keyed.sort_by_key(|(size, ..)| std::cmp::Reverse(*size)),
+        Order::SmallestFirst => keyed.sort_by_key(|(size, ..)| *size),
+        Order::Mtime => keyed.sort_by_key(|(_, modified, _)| *modified),
+        Order::Name => unreachable!(),
+    }
+    keyed.into_iter().map(|(.., path)| path).collect()
+}
+
+/// Like [`discover`], but sends the paths (and errors) through a channel as they are found, so
+/// that processing them can start right away. Discovery pauses while [`DISCOVERY_BUFFER`] paths
+/// are waiting to be received, so the paths of huge trees are never all held in memory.
+pub fn discover_stream(patterns: Vec<OsString>, settings: Arc<Settings>) -> Receiver<Discovered> {
+    let (sender, receiver) = mpsc::channel(DISCOVERY_BUFFER);
+    tokio::spawn(async move { walk_patterns(&patterns, &settings, sender).await });
+    receiver
+}
@@ -0,0 +1,77 @@
+//! The engines that run a batch, which the library and the command line tool pick between with
+//! [`Settings::engine`].
+//!
+//! The async engine runs on tokio and supports every option. The sync engine, only built with the
+//! `sync-engine` cargo feature, processes the files on plain threads with blocking I/O, for
+//! programs that would rather not run an async runtime and to compare the two.
+
+use crate::batch::run_batch;
+use crate::error::SuperGzipError;
+use crate::features::Feature;
+use crate::report::FileReport;
+use crate::settings::Settings;
+use clap::ValueEnum;
+use std::ffi::OsString;
+
+/// The engine that runs a batch
+#[derive(ValueEnum, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Engine {
+    /// The tokio runtime, which supports every option
+    #[default]
+    Async,
+    /// A pool of threads with blocking I/O, built with the sync-engine feature
+    Sync,
+}
+
+impl Engine {
+    /// The cargo feature that the engine is built with, if it is optional
+    pub fn feature(self) -> Option<Feature> {
+        match self {
+            Engine::Async => None,
+            Engine::Sync => Some(Feature::SyncEngine),
+        }
+    }
+}
+
+/// Runs the batch on the engine of the settings, like [`run_batch`]. The sync engine runs on a
+/// blocking thread of the runtime.
+pub async fn run(
+    settings: Settings,
+    patterns: Vec<OsString>,
+) -> Result<Vec<FileReport>, SuperGzipError> {
+    match settings.engine {
+        Engine::Async => run_batch(settings, patterns).await,
+        Engine::Sync => tokio::task::spawn_blocking(move || run_sync(settings, patterns)).await?,
+    }
+}
+
+/// Runs the batch on the engine of the settings, blocking until it is done. The async engine is
+/// given a runtime of its own, so this must not be called from async code.
+pub fn run_blocking(
+    settings: Settings,
+    patterns: Vec<OsString>,
+) -> Result<Vec<FileReport>, SuperGzipError> {
+    match settings.engine {
+        Engine::Async => tokio::runtime::Builder::new_multi_thread()
+            .enable_all()
+            .build()?
+            .block_on(run_batch(settings, patterns)),
+        Engine::Sync => run_sync(settings, patterns),
+    }
+}
+
+#[cfg(feature = "sync-engine")]
+fn run_sync(
+    settings: Settings,
+    patterns: Vec<OsString>,
+) -> Result<Vec<FileReport>, SuperGzipError> {
+    crate::sync_engine::run(&settings, &patterns)
+}
+
+#[cfg(not(feature = "sync-engine"))]
+fn run_sync(
+    _settings: Settings,
+    _patterns: Vec<OsString>,
+) -> Result<Vec<FileReport>, SuperGzipError> {
+    Err(Feature::SyncEngine.unavailable().into())
+}
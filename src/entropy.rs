@@ -0,0 +1,86 @@
+//! Estimating how well a file compresses from a few samples of it, so that files whose contents
+//! are already compressed or encrypted, which their first bytes do not always tell, can be
+//! skipped or stored without spending the time to compress them in full.
+//!
+//! The samples are compressed at the fastest level rather than judged by their byte entropy,
+//! which would miss the repeated strings that deflate finds in e.g. text with many distinct bytes.
+
+use flate2::write::DeflateEncoder;
+use flate2::Compression;
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::path::Path;
+
+/// The number of samples taken of a file, spread evenly from its start to its end
+pub const SAMPLES: u64 = 4;
+
+/// The size of each sample
+pub const SAMPLE_SIZE: u64 = 64 * 1024;
+
+/// The compressed size, as a fraction of the sampled size, above which a file counts as
+/// incompressible, i.e. compressing it would save less than 5%
+pub const INCOMPRESSIBLE_RATIO: f64 = 0.95;
+
+/// How much of a file was sampled, and what it compressed to
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Sample {
+    pub size: u64,
+    pub compressed_size: u64,
+}
+
+impl Sample {
+    /// The compressed size of the samples as a fraction of their size, if anything was sampled
+    pub fn ratio(&self) -> Option<f64> {
+        crate::report::ratio(self.size, self.compressed_size)
+    }
+}
+
+/// Compresses the data at the level, returning its compressed size
+fn compressed_size(data: &[u8], level: Compression) -> std::io::Result<u64> {
+    let mut encoder = DeflateEncoder::new(Vec::new(), level);
+    encoder.write_all(data)?;
+    Ok(encoder.finish()?.len() as u64)
+}
+
+/// Reads `count` samples of `size` bytes spread evenly over the file, or all of it if it is not
+/// larger than the samples together, and compresses each at the fastest level. Blocks.
+pub fn sample(path: &Path, count: u64, size: u64) -> std::io::Result<Sample> {
+    sample_at_level(path, count, size, Compression::fast())
+}
+
+/// Like [`sample`], compressing the samples at the level instead. Blocks.
+pub fn sample_at_level(
+    path: &Path,
+    count: u64,
+    size: u64,
+    level: Compression,
+) -> std::io::Result<Sample> {
+    let mut file = std::fs::File::open(path)?;
+    let length = file.metadata()?.len();
+    let offsets: Vec<u64> = match length <= count * size {
+        true => (0..length.div_ceil(size.max(1)))
+            .map(|i| i * size)
+            .collect(),
+        false => (0..count)
+            .map(|i| i * (length - size) / (count - 1).max(1))
+            .collect(),
+    };
+    let mut sample = Sample::default();
+    let mut buffer = Vec::with_capacity(size as usize);
+    for offset in offsets {
+        buffer.clear();
+        file.seek(SeekFrom::Start(offset))?;
+        (&mut file).take(size).read_to_end(&mut buffer)?;
+        sample.size += buffer.len() as u64;
+        sample.compressed_size += compressed_size(&buffer, level)?;
+    }
+    Ok(sample)
+}
+
+/// Whether the samples of the file compress to more than `INCOMPRESSIBLE_RATIO` of their size.
+/// Empty files are not incompressible. Blocks.
+pub fn is_incompressible(path: &Path) -> std::io::Result<bool> {
+    let sample = sample(path, SAMPLES, SAMPLE_SIZE)?;
+    Ok(sample
+        .ratio()
+        .is_some_and(|ratio| ratio > INCOMPRESSIBLE_RATIO))
+}
@@ -0,0 +1,340 @@
+//! The error type shared by every operation
+
+use crate::codec::Codec;
+use crate::gzip_header::{Trailer, TrailerMismatch};
+use crate::magic::Format;
+use std::path::{Path, PathBuf};
+use tokio::io::Error as TokioIOError;
+use tokio::task::JoinError as TokioJoinError;
+
+/// What was being done to a file when an error occurred on it
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Operation {
+    Compress,
+    Decompress,
+    Recompress,
+    Test,
+    Bundle,
+    /// Deleting or trashing the original once its output is in place
+    Remove,
+    Read,
+    Write,
+    Index,
+    Check,
+    Search,
+    Join,
+    Inspect,
+    List,
+    Extract,
+    Estimate,
+}
+
+impl Operation {
+    /// The operation as a verb, e.g. `compress`
+    pub fn describe(&self) -> &'static str {
+        match self {
+            Operation::Compress => "compress",
+            Operation::Decompress => "decompress",
+            Operation::Recompress => "recompress",
+            Operation::Test => "test",
+            Operation::Bundle => "bundle",
+            Operation::Remove => "remove",
+            Operation::Read => "read",
+            Operation::Write => "write",
+            Operation::Index => "index",
+            Operation::Check => "check",
+            Operation::Search => "search",
+            Operation::Join => "join",
+            Operation::Inspect => "inspect",
+            Operation::List => "list",
+            Operation::Extract => "extract",
+            Operation::Estimate => "estimate",
+        }
+    }
+}
+
+/// The errors that can occur while processing files
+#[derive(Debug)]
+pub enum SuperGzipError {
+    IO(TokioIOError),
+    Threading(TokioJoinError),
+    InvalidGzipHeader,
+    InvalidLz4Frame,
+    /// The file is compressed in another format than the codec's, which it cannot decompress
+    WrongFormat(Format, Codec),
+    /// The decompressed data of a file does not match the checksum and size stored in its gzip
+    /// trailer
+    CorruptInput {
+        path: PathBuf,
+        expected: Trailer,
+        actual: Trailer,
+    },
+    /// The output written for a file did not decompress back to the original contents, so the
+    /// original was kept
+    VerifyFailed(PathBuf),
+    /// The run was interrupted by a signal
+    Interrupted,
+    /// An error that occurred on a file, naming the file and what was being done to it
+    File {
+        path: PathBuf,
+        operation: Operation,
+        source: Box<SuperGzipError>,
+    },
+    /// Some or all of the files of a run failed, with the errors that were reported for them
+    Failed {
+        errors: Vec<SuperGzipError>,
+        /// The number of files that were processed successfully anyway
+        succeeded: usize,
+    },
+}
+
+impl std::fmt::Display for SuperGzipError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SuperGzipError::IO(e) => write!(f, "{}", e),
+            SuperGzipError::Threading(e) => write!(f, "{}", e),
+            SuperGzipError::InvalidGzipHeader => write!(f, "Invalid gzip header"),
+            SuperGzipError::InvalidLz4Frame => write!(f, "Invalid LZ4 frame"),
+            SuperGzipError::WrongFormat(format, codec) => write!(
+                f,
+                "Compressed as {} rather than {}, which is not supported",
+                format.name(),
+                codec.format().name()
+            ),
+            SuperGzipError::CorruptInput {
+                path,
+                expected,
+                actual,
+            } => write!(
+                f,
+                "{} is corrupt: expected {}, got {}",
+                path.to_string_lossy(),
+                expected,
+                actual
+            ),
+            SuperGzipError::VerifyFailed(path) => write!(
+                f,
+                "The output of {} does not decompress back to the original, which was kept",
+                path.to_string_lossy()
+            ),
+            SuperGzipError::Interrupted => write!(f, "Interrupted"),
+            SuperGzipError::File {
+                path,
+                operation,
+                source,
+            } => write!(
+                f,
+                "Cannot {} {}: {}",
+                operation.describe(),
+                path.to_string_lossy(),
+                source
+            ),
+            SuperGzipError::Failed { errors, .. } => match errors.as_slice() {
+                [] => write!(f, "Failed"),
+                [error] => write!(f, "{}", error),
+                [first, rest @ ..] => write!(f, "{} (and {} more errors)", first, rest.len()),
+            },
+        }
+    }
+}
+
+impl std::error::Error for SuperGzipError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            SuperGzipError::IO(e) => Some(e),
+            SuperGzipError::Threading(e) => Some(e),
+            SuperGzipError::File { source, .. } => Some(source.as_ref()),
+            _ => None,
+        }
+    }
+}
+
+impl SuperGzipError {
+    /// The exit code of the program when it ends with this error: 1 when some files were
+    /// processed successfully anyway, and 3 when none were (usage errors exit with 2)
+    pub fn exit_code(&self) -> u8 {
+        match self {
+            SuperGzipError::Failed { succeeded, .. } if *succeeded > 0 => 1,
+            _ => 3,
+        }
+    }
+
+    /// The error itself, without the file it occurred on
+    pub fn inner(&self) -> &SuperGzipError {
+        match self {
+            SuperGzipError::File { source, .. } => source.inner(),
+            error => error,
+        }
+    }
+
+    /// The kind of the error, which the errors of a run are grouped by when they are summarized,
+    /// e.g. `Not found`
+    pub fn kind(&self) -> &'static str {
+        use std::io::ErrorKind;
+        match self.inner() {
+            SuperGzipError::IO(e) => match e.kind() {
+                ErrorKind::NotFound => "Not found",
+                ErrorKind::PermissionDenied | ErrorKind::ReadOnlyFilesystem => "Permission denied",
+                ErrorKind::AlreadyExists => "Already exists",
+                ErrorKind::StorageFull | ErrorKind::QuotaExceeded | ErrorKind::FileTooLarge => {
+                    "Out of space"
+                }
+                ErrorKind::InvalidData | ErrorKind::InvalidInput | ErrorKind::UnexpectedEof => {
+                    "Corrupt or invalid input"
+                }
+                _ if self.is_transient() => "Busy or unreachable",
+                _ => "Input/output errors",
+            },
+            SuperGzipError::CorruptInput { .. } => "Corrupt or invalid input",
+            SuperGzipError::InvalidGzipHeader
+            | SuperGzipError::InvalidLz4Frame
+            | SuperGzipError::WrongFormat(..) => "Not in the expected format",
+            SuperGzipError::VerifyFailed(_) => "Failed verification",
+            SuperGzipError::Threading(_) => "Internal errors",
+            SuperGzipError::Interrupted => "Interrupted",
+            SuperGzipError::Failed { .. } | SuperGzipError::File { .. } => "Failed",
+        }
+    }
+
+    /// Whether the error may go away if the file is tried again, like a busy file or a hiccup
+    /// of a network file system
+    pub fn is_transient(&self) -> bool {
+        use std::io::ErrorKind;
+        let SuperGzipError::IO(e) = self.inner() else {
+            return false;
+        };
+        #[cfg(unix)]
+        if let Some(libc::EAGAIN | libc::EBUSY | libc::ESTALE | libc::ETIMEDOUT | libc::EINTR) =
+            e.raw_os_error()
+        {
+            return true;
+        }
+        matches!(
+            e.kind(),
+            ErrorKind::WouldBlock
+                | ErrorKind::Interrupted
+                | ErrorKind::TimedOut
+                | ErrorKind::ResourceBusy
+                | ErrorKind::StaleNetworkFileHandle
+                | ErrorKind::ConnectionReset
+                | ErrorKind::ConnectionAborted
+        )
+    }
+
+    /// Whether the process ran out of file descriptors, which other files closing theirs fixes
+    pub fn is_too_many_open_files(&self) -> bool {
+        #[cfg(unix)]
+        if let SuperGzipError::IO(e) = self.inner() {
+            return matches!(e.raw_os_error(), Some(libc::EMFILE | libc::ENFILE));
+        }
+        false
+    }
+
+    /// Names the file that an error occurred on and what was being done to it, unless the
+    /// error names it already
+    pub fn for_file(self, path: &Path, operation: Operation) -> SuperGzipError {
+        // Errors that name their file already keep it
+        let this = match self {
+            SuperGzipError::IO(e) => SuperGzipError::from(e),
+            error => error,
+        };
+        let mismatch = match &this {
+            SuperGzipError::IO(e) => e
+                .get_ref()
+                .and_then(|inner| inner.downcast_ref::<TrailerMismatch>()),
+            _ => None,
+        };
+        if let Some(mismatch) = mismatch {
+            return SuperGzipError::CorruptInput {
+                path: path.to_path_buf(),
+                expected: mismatch.expected,
+                actual: mismatch.actual,
+            };
+        }
+        match this {
+            SuperGzipError::CorruptInput { .. }
+            | SuperGzipError::VerifyFailed(_)
+            | SuperGzipError::Interrupted
+            | SuperGzipError::Failed { .. }
+            | SuperGzipError::File { .. } => this,
+            source => SuperGzipError::File {
+                path: path.to_path_buf(),
+                operation,
+                source: Box::new(source),
+            },
+        }
+    }
+}
+
+/// An I/O error on a file, for the parts that work with plain I/O errors, like the tar and ZIP
+/// writers. It becomes a [`SuperGzipError::File`] when converted into a [`SuperGzipError`].
+#[derive(Debug)]
+struct FileError {
+    path: PathBuf,
+    operation: Operation,
+    source: TokioIOError,
+}
+
+impl std::fmt::Display for FileError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "Cannot {} {}: {}",
+            self.operation.describe(),
+            self.path.to_string_lossy(),
+            self.source
+        )
+    }
+}
+
+impl std::error::Error for FileError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        Some(&self.source)
+    }
+}
+
+/// Names the file that an I/O error occurred on and what was being done to it, keeping the kind
+/// of the error, e.g. `.map_err(file_error(path, Operation::Read))`
+pub(crate) fn file_error(
+    path: &Path,
+    operation: Operation,
+) -> impl FnOnce(TokioIOError) -> TokioIOError + '_ {
+    move |source| {
+        if source
+            .get_ref()
+            .is_some_and(|inner| inner.is::<FileError>())
+        {
+            return source;
+        }
+        let kind = source.kind();
+        let error = FileError {
+            path: path.to_path_buf(),
+            operation,
+            source,
+        };
+        TokioIOError::new(kind, error)
+    }
+}
+
+impl From<TokioIOError> for SuperGzipError {
+    fn from(src: TokioIOError) -> Self {
+        if !src.get_ref().is_some_and(|inner| inner.is::<FileError>()) {
+            return Self::IO(src);
+        }
+        let inner = src.into_inner().expect("the error was just checked");
+        let error = inner
+            .downcast::<FileError>()
+            .expect("the error was just checked");
+        Self::File {
+            path: error.path,
+            operation: error.operation,
+            source: Box::new(Self::IO(error.source)),
+        }
+    }
+}
+
+impl From<TokioJoinError> for SuperGzipError {
+    fn from(src: TokioJoinError) -> Self {
+        Self::Threading(src)
+    }
+}
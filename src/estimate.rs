@@ -0,0 +1,135 @@
+//! Forecasting what compressing a tree of files would save before starting a long run, by
+//! compressing samples of each file and projecting their ratio onto the whole file.
+//!
+//! The projections are summed per directory, since whether a run is worth it usually comes down
+//! to a few directories of logs or dumps among many of media that barely compress.
+
+use crate::entropy::{self, SAMPLE_SIZE};
+use crate::progress::format_bytes;
+use crate::report::{json_string, ratio};
+use flate2::Compression;
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+/// The projected outcome of compressing some files
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Projection {
+    pub files: u64,
+    pub size: u64,
+    /// How much of the files was sampled
+    pub sampled_size: u64,
+    /// What the files are projected to compress to
+    pub projected_size: u64,
+}
+
+impl Projection {
+    pub fn add(&mut self, other: &Projection) {
+        self.files += other.files;
+        self.size += other.size;
+        self.sampled_size += other.sampled_size;
+        self.projected_size += other.projected_size;
+    }
+
+    /// The projected size relative to the size of the files
+    pub fn ratio(&self) -> Option<f64> {
+        ratio(self.size, self.projected_size)
+    }
+
+    pub fn savings(&self) -> u64 {
+        self.size.saturating_sub(self.projected_size)
+    }
+}
+
+/// Compresses samples making up `percent` percent of the file (at least one sample) at the
+/// level, and projects the ratio of the samples onto the whole file. Blocks.
+pub fn project_file(path: &Path, percent: f64, level: u32) -> std::io::Result<Projection> {
+    let size = std::fs::metadata(path)?.len();
+    let count = (size as f64 * percent / 100.0 / SAMPLE_SIZE as f64).ceil() as u64;
+    let sample =
+        entropy::sample_at_level(path, count.max(1), SAMPLE_SIZE, Compression::new(level))?;
+    let projected_size = match sample.ratio() {
+        Some(ratio) => (size as f64 * ratio).round() as u64,
+        None => 0,
+    };
+    Ok(Projection {
+        files: 1,
+        size,
+        sampled_size: sample.size,
+        projected_size,
+    })
+}
+
+/// The projections of files summed per directory
+pub fn by_directory<'a>(
+    files: impl IntoIterator<Item = (&'a Path, Projection)>,
+) -> BTreeMap<PathBuf, Projection> {
+    let mut directories: BTreeMap<PathBuf, Projection> = BTreeMap::new();
+    for (path, projection) in files {
+        let directory = path.parent().unwrap_or(Path::new("")).to_path_buf();
+        directories.entry(directory).or_default().add(&projection);
+    }
+    directories
+}
+
+/// The name a directory is shown under, where the current directory is `.`
+fn directory_name(directory: &Path) -> String {
+    match directory.as_os_str().is_empty() {
+        true => ".".to_string(),
+        false => directory.to_string_lossy().into_owned(),
+    }
+}
+
+/// Renders the projections as a table with a row per directory, followed by the totals
+pub fn render_text(directories: &BTreeMap<PathBuf, Projection>, total: &Projection) -> String {
+    let row = |projection: &Projection, name: &str| {
+        let ratio = projection
+            .ratio()
+            .map_or_else(|| "-".to_string(), |ratio| format!("{:.1}%", ratio * 100.0));
+        format!(
+            "{:>9} {:>12} {:>12} {:>12} {:>7}  {}\n",
+            projection.files,
+            format_bytes(projection.size),
+            format_bytes(projection.projected_size),
+            format_bytes(projection.savings()),
+            ratio,
+            name
+        )
+    };
+    let mut text = format!(
+        "{:>9} {:>12} {:>12} {:>12} {:>7}  {}\n",
+        "files", "size", "projected", "savings", "ratio", "directory"
+    );
+    for (directory, projection) in directories {
+        text.push_str(&row(projection, &directory_name(directory)));
+    }
+    if directories.len() > 1 {
+        text.push_str(&row(total, "(totals)"));
+    }
+    text.push_str(&format!(
+        "\nSampled {} of {}",
+        format_bytes(total.sampled_size),
+        format_bytes(total.size)
+    ));
+    text
+}
+
+/// Renders a projection as a single-line JSON object, with the directory it is of, if any
+pub fn to_json(directory: Option<&Path>, projection: &Projection) -> String {
+    let directory = match directory {
+        Some(directory) => format!("\"directory\":{},", json_string(&directory_name(directory))),
+        None => String::new(),
+    };
+    let ratio = projection
+        .ratio()
+        .map_or_else(|| "null".to_string(), |ratio| ratio.to_string());
+    format!(
+        "{{{}\"files\":{},\"size\":{},\"sampled_size\":{},\"projected_size\":{},\"savings\":{},\"ratio\":{}}}",
+        directory,
+        projection.files,
+        projection.size,
+        projection.sampled_size,
+        projection.projected_size,
+        projection.savings(),
+        ratio
+    )
+}
@@ -0,0 +1,174 @@
+//! A stream of progress events for programs that wrap SuperGZip and draw their own progress, e.g.
+//! a GUI. Each event is a JSON object on its own line (NDJSON), written to a file descriptor the
+//! wrapper passed in or to a file or named pipe:
+//!
+//! - `{"event":"discovered","path":...}` when a file is found
+//! - `{"event":"started","path":...,"size":...}` when a file starts being read
+//! - `{"event":"progress","path":...,"bytes":...,"size":...,"percent":...}` as it is read
+//! - `{"event":"finished","path":...,"output_path":...,"input_size":...,"output_size":...,
+//!   "duration_secs":...}`, `{"event":"failed","path":...,"error":...}`, or
+//!   `{"event":"skipped","path":...,"reason":...}` once it is done
+//!
+//! The size and percentage are `null` when the size of a file is not known up front, such as for
+//! downloads whose server does not say.
+
+use crate::report::{json_optional_string, json_string, FileReport, FileStatus};
+use std::io::{Result, Write};
+use std::path::Path;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// The least time between two progress events of the same file
+const PROGRESS_INTERVAL: Duration = Duration::from_millis(200);
+
+/// Where the events of a run go
+pub struct EventStream {
+    writer: Mutex<Box<dyn Write + Send>>,
+}
+
+impl std::fmt::Debug for EventStream {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("EventStream").finish_non_exhaustive()
+    }
+}
+
+/// The progress of a file whose events are being written
+#[derive(Debug)]
+pub struct FileProgress {
+    path: String,
+    size: u64,
+    bytes: u64,
+    last_event: Option<Instant>,
+}
+
+/// Opens the target of `--progress-json`: an open file descriptor given by its number (on Unix),
+/// or else a file (appended to) or named pipe
+pub fn open(target: &str) -> Result<Box<dyn Write + Send>> {
+    #[cfg(unix)]
+    if let Ok(fd) = target.parse::<std::os::unix::io::RawFd>() {
+        // Taking over a descriptor that is not open would write to whatever gets its number later
+        if unsafe { libc::fcntl(fd, libc::F_GETFD) } == -1 {
+            return Err(std::io::Error::last_os_error());
+        }
+        let file = unsafe { <std::fs::File as std::os::unix::io::FromRawFd>::from_raw_fd(fd) };
+        return Ok(Box::new(file));
+    }
+    let file = std::fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(target)?;
+    Ok(Box::new(file))
+}
+
+impl EventStream {
+    pub fn new(writer: Box<dyn Write + Send>) -> EventStream {
+        EventStream {
+            writer: Mutex::new(writer),
+        }
+    }
+
+    /// Writes an event with the fields, which are already JSON. A wrapper that stopped reading
+    /// does not stop the run, so failed writes are ignored.
+    fn emit(&self, event: &str, path: &str, fields: &[(&str, String)]) {
+        let mut line = format!("{{\"event\":\"{}\",\"path\":{}", event, json_string(path));
+        for (name, value) in fields {
+            line.push_str(&format!(",\"{}\":{}", name, value));
+        }
+        line.push_str("}\n");
+        let mut writer = self.writer.lock().unwrap();
+        let _ = writer.write_all(line.as_bytes());
+        let _ = writer.flush();
+    }
+
+    /// Records that a file was found
+    pub fn discovered(&self, path: &Path) {
+        self.emit("discovered", &path.to_string_lossy(), &[]);
+    }
+
+    /// Records that a file of the size (or 0 if unknown) started being read, returning the
+    /// progress to report as it is read
+    pub fn started(&self, path: String, size: u64) -> FileProgress {
+        self.emit("started", &path, &[("size", optional_size(size))]);
+        FileProgress {
+            path,
+            size,
+            bytes: 0,
+            last_event: None,
+        }
+    }
+
+    /// Records that `bytes` more bytes of a file were read, writing a progress event unless one
+    /// was written very recently
+    pub fn advance(&self, file: &mut FileProgress, bytes: u64) {
+        // The event of the whole file being read is only written once
+        if file.size > 0 && file.bytes >= file.size {
+            return;
+        }
+        file.bytes += bytes;
+        let now = Instant::now();
+        let done = file.size > 0 && file.bytes >= file.size;
+        if !done
+            && file
+                .last_event
+                .is_some_and(|last_event| now - last_event < PROGRESS_INTERVAL)
+        {
+            return;
+        }
+        file.last_event = Some(now);
+        let percent = match file.size {
+            0 => "null".to_string(),
+            size => format!(
+                "{:.1}",
+                (file.bytes as f64 / size as f64 * 100.0).min(100.0)
+            ),
+        };
+        self.emit(
+            "progress",
+            &file.path,
+            &[
+                ("bytes", file.bytes.to_string()),
+                ("size", optional_size(file.size)),
+                ("percent", percent),
+            ],
+        );
+    }
+
+    /// Records the outcome of a file
+    pub fn file_done(&self, report: &FileReport) {
+        let path = report.path.to_string_lossy();
+        match &report.status {
+            FileStatus::Processed => {
+                let output_path = report
+                    .output_path
+                    .as_ref()
+                    .map(|output_path| output_path.to_string_lossy());
+                self.emit(
+                    "finished",
+                    &path,
+                    &[
+                        ("output_path", json_optional_string(output_path.as_deref())),
+                        ("input_size", report.input_size.to_string()),
+                        ("output_size", report.output_size.to_string()),
+                        (
+                            "duration_secs",
+                            format!("{:.3}", report.duration.as_secs_f64()),
+                        ),
+                    ],
+                );
+            }
+            FileStatus::Skipped(reason) => {
+                self.emit("skipped", &path, &[("reason", json_string(reason))]);
+            }
+            FileStatus::Failed(error) => {
+                self.emit("failed", &path, &[("error", json_string(error))]);
+            }
+        }
+    }
+}
+
+fn optional_size(size: u64) -> String {
+    match size {
+        0 => "null".to_string(),
+        size => size.to_string(),
+    }
+}
@@ -0,0 +1,68 @@
+//! The optional parts of the program, which are cargo features. Most of them are enabled by
+//! default, and a minimal build, e.g. a gzip-only static binary, leaves them out with
+//! `--no-default-features`; the sync engine is only built when asked for.
+
+use std::io::{Error, ErrorKind};
+
+/// An optional part of the program
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Feature {
+    /// The LZ4 codec
+    Lz4,
+    /// Downloading inputs given as `http://` URLs
+    Http,
+    /// The synchronous engine, which runs batches without an async runtime
+    SyncEngine,
+}
+
+impl Feature {
+    /// The name of the cargo feature
+    pub fn name(self) -> &'static str {
+        match self {
+            Feature::Lz4 => "lz4",
+            Feature::Http => "http",
+            Feature::SyncEngine => "sync-engine",
+        }
+    }
+
+    /// Whether the program was built with the feature
+    pub fn is_enabled(self) -> bool {
+        match self {
+            Feature::Lz4 => cfg!(feature = "lz4"),
+            Feature::Http => cfg!(feature = "http"),
+            Feature::SyncEngine => cfg!(feature = "sync-engine"),
+        }
+    }
+
+    /// The error of using the feature in a build without it
+    pub fn unavailable(self) -> Error {
+        missing_error(&[self])
+    }
+}
+
+/// Checks that the program was built with the features, failing with an error that lists the
+/// ones it was built without
+pub fn require(features: &[Feature]) -> std::io::Result<()> {
+    let mut missing: Vec<Feature> = Vec::new();
+    for &feature in features {
+        if !feature.is_enabled() && !missing.contains(&feature) {
+            missing.push(feature);
+        }
+    }
+    match missing.is_empty() {
+        true => Ok(()),
+        false => Err(missing_error(&missing)),
+    }
+}
+
+fn missing_error(missing: &[Feature]) -> Error {
+    let names: Vec<&str> = missing.iter().map(|feature| feature.name()).collect();
+    Error::new(
+        ErrorKind::Unsupported,
+        format!(
+            "this build has no {} support: rebuild with --features {}",
+            names.join(" or "),
+            names.join(",")
+        ),
+    )
+}
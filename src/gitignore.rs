@@ -0,0 +1,203 @@
+//! Leaving out the files that git ignores, so that compressing everything in a repository skips
+//! build artifacts and other ignored files and directories.
+//!
+//! The `.gitignore` files of the repository a path is in, from its root down to the directory
+//! of the path, are applied as git applies them: deeper files take precedence over the ones
+//! above, later patterns over earlier ones, `!` re-includes, a trailing `/` only matches
+//! directories, and nothing inside an ignored directory can be re-included. The repository's
+//! `.git/info/exclude` counts as a `.gitignore` at its root, and `.git` itself is always left out.
+//! Paths outside of repositories are never ignored.
+
+use glob::{MatchOptions, Pattern};
+use std::collections::HashMap;
+use std::path::{Component, Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+/// `*` and `?` do not match `/`, like in git
+const MATCH_OPTIONS: MatchOptions = MatchOptions {
+    case_sensitive: true,
+    require_literal_separator: true,
+    require_literal_leading_dot: false,
+};
+
+/// A line of a `.gitignore` file
+#[derive(Debug)]
+struct Rule {
+    pattern: Pattern,
+    /// Whether the rule re-includes what it matches (`!pattern`)
+    negated: bool,
+    /// Whether the rule only matches directories (`pattern/`)
+    dir_only: bool,
+    /// Whether the pattern is matched against the path relative to the directory of the
+    /// `.gitignore` file (it contains a `/`), rather than against the name of the entry
+    anchored: bool,
+}
+
+impl Rule {
+    fn parse(line: &str) -> Option<Rule> {
+        let line = line.trim_end();
+        if line.is_empty() || line.starts_with('#') {
+            return None;
+        }
+        let (negated, line) = match line.strip_prefix('!') {
+            Some(line) => (true, line),
+            None => (false, line.strip_prefix('\\').unwrap_or(line)),
+        };
+        let (dir_only, line) = match line.strip_suffix('/') {
+            Some(line) => (true, line),
+            None => (false, line),
+        };
+        let anchored = line.contains('/');
+        let line = line.strip_prefix('/').unwrap_or(line);
+        // A pattern ending in /** matches everything inside, but not the directory itself
+        let pattern = match line.strip_suffix("/**") {
+            Some(dir) => format!("{}/**/*", dir),
+            None => line.to_string(),
+        };
+        Some(Rule {
+            pattern: Pattern::new(&pattern).ok()?,
+            negated,
+            dir_only,
+            anchored,
+        })
+    }
+
+    /// Whether the rule matches the entry at `relative`, the path from the directory of its
+    /// `.gitignore` file
+    fn matches(&self, relative: &Path, is_dir: bool) -> bool {
+        if self.dir_only && !is_dir {
+            return false;
+        }
+        match self.anchored {
+            true => self.pattern.matches_path_with(relative, MATCH_OPTIONS),
+            false => relative.file_name().is_some_and(|name| {
+                self.pattern
+                    .matches_path_with(Path::new(name), MATCH_OPTIONS)
+            }),
+        }
+    }
+}
+
+/// What is known about a directory: the rules of its `.gitignore` and whether it is the root
+/// of a repository
+#[derive(Debug)]
+struct DirInfo {
+    rules: Vec<Rule>,
+    is_repository: bool,
+}
+
+fn read_rules(path: &Path) -> Vec<Rule> {
+    std::fs::read_to_string(path)
+        .map(|text| text.lines().filter_map(Rule::parse).collect())
+        .unwrap_or_default()
+}
+
+/// The path made absolute and without `.` and `..` components, without resolving symlinks
+fn normalize(path: &Path) -> Option<PathBuf> {
+    let absolute = match path.is_absolute() {
+        true => path.to_path_buf(),
+        false => std::env::current_dir().ok()?.join(path),
+    };
+    let mut normalized = PathBuf::new();
+    for component in absolute.components() {
+        match component {
+            Component::CurDir => {}
+            Component::ParentDir => {
+                normalized.pop();
+            }
+            component => normalized.push(component),
+        }
+    }
+    Some(normalized)
+}
+
+/// Decides which paths git ignores, reading each `.gitignore` file once
+#[derive(Debug, Default)]
+pub struct GitIgnore {
+    dirs: Mutex<HashMap<PathBuf, Arc<DirInfo>>>,
+}
+
+impl GitIgnore {
+    pub fn new() -> GitIgnore {
+        GitIgnore::default()
+    }
+
+    fn dir_info(&self, dir: &Path) -> Arc<DirInfo> {
+        if let Some(info) = self.dirs.lock().unwrap().get(dir) {
+            return Arc::clone(info);
+        }
+        let git_dir = dir.join(".git");
+        let is_repository = git_dir.exists();
+        let mut rules = Vec::new();
+        if is_repository {
+            rules.extend(read_rules(&git_dir.join("info").join("exclude")));
+        }
+        rules.extend(read_rules(&dir.join(".gitignore")));
+        let info = Arc::new(DirInfo {
+            rules,
+            is_repository,
+        });
+        self.dirs
+            .lock()
+            .unwrap()
+            .insert(dir.to_path_buf(), Arc::clone(&info));
+        info
+    }
+
+    /// Whether git ignores the path, which is a directory if `is_dir`
+    pub fn is_ignored(&self, path: &Path, is_dir: bool) -> bool {
+        let Some(path) = normalize(path) else {
+            return false;
+        };
+
+        // Find the root of the repository, along with the directories below it
+        let mut dirs = Vec::new();
+        let mut repository = None;
+        for dir in path.ancestors().skip(1) {
+            let info = self.dir_info(dir);
+            dirs.push((dir, info));
+            if dirs.last().is_some_and(|(_, info)| info.is_repository) {
+                repository = Some(dirs.len());
+                break;
+            }
+        }
+        let Some(depth) = repository else {
+            return false;
+        };
+        dirs.truncate(depth);
+        dirs.reverse();
+        let (root, _) = dirs[0];
+        let Ok(inside) = path.strip_prefix(root) else {
+            return false;
+        };
+        if inside
+            .components()
+            .any(|component| component.as_os_str() == ".git")
+        {
+            return true;
+        }
+
+        // Nothing inside an ignored directory can be re-included, so the path is ignored if it
+        // or any directory on the way to it is
+        let mut entry = root.to_path_buf();
+        let components: Vec<_> = inside.components().collect();
+        for (index, component) in components.iter().enumerate() {
+            entry.push(component);
+            let entry_is_dir = index + 1 < components.len() || is_dir;
+            // The rules of the deepest .gitignore that matches win, and within a file the last
+            let mut ignored = false;
+            for (dir, info) in &dirs[..=index] {
+                let relative = entry.strip_prefix(dir).unwrap_or(&entry);
+                for rule in &info.rules {
+                    if rule.matches(relative, entry_is_dir) {
+                        ignored = !rule.negated;
+                    }
+                }
+            }
+            if ignored {
+                return true;
+            }
+        }
+        false
+    }
+}
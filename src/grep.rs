@@ -0,0 +1,120 @@
+//! Searching the lines of compressed files for a regular expression, like zgrep, decompressing
+//! them as they are searched so that nothing is written to disk.
+
+use crate::cat;
+use crate::codec::CpuPool;
+use crate::error::SuperGzipError;
+use crate::regex::Regex;
+use crate::settings::Settings;
+use std::path::Path;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+use tokio::io::{AsyncRead, AsyncWrite};
+
+/// A line that matched
+#[derive(Clone, Debug)]
+pub struct Match {
+    /// The number of the line in the decompressed file, counting from 1
+    pub line_number: u64,
+    /// The line without its line break, with invalid UTF-8 replaced
+    pub line: String,
+}
+
+/// A writer that splits what is written to it into lines and keeps the ones that match
+struct LineMatcher {
+    regex: Arc<Regex>,
+    /// The start of a line whose end has not been written yet
+    partial: Vec<u8>,
+    line_number: u64,
+    matches: Vec<Match>,
+}
+
+impl LineMatcher {
+    fn new(regex: Arc<Regex>) -> LineMatcher {
+        LineMatcher {
+            regex,
+            partial: Vec::new(),
+            line_number: 0,
+            matches: Vec::new(),
+        }
+    }
+
+    fn line(&mut self, line: &[u8]) {
+        self.line_number += 1;
+        let line = String::from_utf8_lossy(line);
+        if self.regex.is_match(&line) {
+            self.matches.push(Match {
+                line_number: self.line_number,
+                line: line.into_owned(),
+            });
+        }
+    }
+
+    /// Matches the last line, if the data did not end with a line break
+    fn finish(mut self) -> Vec<Match> {
+        if !self.partial.is_empty() {
+            let partial = std::mem::take(&mut self.partial);
+            self.line(&partial);
+        }
+        self.matches
+    }
+}
+
+impl AsyncWrite for LineMatcher {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        _cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<std::io::Result<usize>> {
+        let this = self.get_mut();
+        let mut rest = buf;
+        while let Some(end) = rest.iter().position(|&byte| byte == b'\n') {
+            match this.partial.is_empty() {
+                true => this.line(&rest[..end]),
+                false => {
+                    let mut line = std::mem::take(&mut this.partial);
+                    line.extend_from_slice(&rest[..end]);
+                    this.line(&line);
+                }
+            }
+            rest = &rest[end + 1..];
+        }
+        this.partial.extend_from_slice(rest);
+        Poll::Ready(Ok(buf.len()))
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+}
+
+/// Decompresses the stream, whatever codec it is compressed with, and returns the lines that
+/// match the regular expression
+pub async fn search_stream<R>(
+    reader: R,
+    regex: Arc<Regex>,
+    pool: &CpuPool,
+) -> Result<Vec<Match>, SuperGzipError>
+where
+    R: AsyncRead + Unpin,
+{
+    let mut matcher = LineMatcher::new(regex);
+    cat::decompress_stream(reader, &mut matcher, pool).await?;
+    Ok(matcher.finish())
+}
+
+/// Like [`search_stream`], for the file (or download) at the path
+pub async fn search(
+    path: &Path,
+    regex: Arc<Regex>,
+    settings: &Settings,
+) -> Result<Vec<Match>, SuperGzipError> {
+    let mut matcher = LineMatcher::new(regex);
+    cat::decompress_path(path, &mut matcher, settings).await?;
+    Ok(matcher.finish())
+}
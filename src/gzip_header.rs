@@ -0,0 +1,459 @@
+//! Parsing of the gzip member header and trailer (RFC 1952), for reporting the metadata stored in
+//! gzip files without decompressing them and for checking decompressed data against them
+
+use flate2::Crc;
+use std::io::{BufRead, Error, ErrorKind, Result, SeekFrom};
+use std::path::Path;
+use tokio::fs::File as AsyncFile;
+use tokio::io::{AsyncReadExt, AsyncSeekExt};
+
+/// The bytes every gzip member starts with
+pub const MAGIC: [u8; 2] = [0x1f, 0x8b];
+const DEFLATE: u8 = 8;
+const FHCRC: u8 = 0x02;
+const FEXTRA: u8 = 0x04;
+const FNAME: u8 = 0x08;
+const FCOMMENT: u8 = 0x10;
+/// The flags that no version of the format defines
+const FRESERVED: u8 = 0xe0;
+/// The most bytes read from the start of a file when looking for the end of its header
+const MAX_HEADER_SIZE: u64 = 64 * 1024;
+
+/// The metadata of a gzip file, taken from the header of its first member and its final trailer
+#[derive(Debug)]
+pub struct GzipInfo {
+    pub compressed_size: u64,
+    /// The uncompressed size modulo 2^32 as stored in the trailer, which is only exact for
+    /// single-member files smaller than 4 GiB
+    pub uncompressed_size: u64,
+    /// The original file name, if one was stored
+    pub name: Option<Vec<u8>>,
+    /// The modification time of the original file in seconds since the Unix epoch, where 0 means
+    /// no time was stored
+    pub mtime: u32,
+    pub comment: Option<Vec<u8>>,
+    /// The extra field, see [`subfields`]
+    pub extra: Option<Vec<u8>>,
+}
+
+fn invalid(message: &str) -> Error {
+    Error::new(ErrorKind::InvalidData, message)
+}
+
+/// Reads a zero-terminated header field, returning it along with the position after it
+fn zero_terminated(data: &[u8], start: usize) -> Result<(&[u8], usize)> {
+    let rest = data
+        .get(start..)
+        .ok_or_else(|| invalid("Invalid gzip header"))?;
+    let end = rest
+        .iter()
+        .position(|&byte| byte == 0)
+        .ok_or_else(|| invalid("Invalid gzip header"))?;
+    Ok((&rest[..end], start + end + 1))
+}
+
+impl GzipInfo {
+    /// Parses the metadata from the start of a gzip file, its last four bytes, and its total size
+    pub fn parse(start: &[u8], trailer: [u8; 4], compressed_size: u64) -> Result<GzipInfo> {
+        if start.len() < 10 || start[..2] != MAGIC || start[2] != DEFLATE {
+            return Err(invalid("Invalid gzip header"));
+        }
+        let flags = start[3];
+        let mtime = u32::from_le_bytes([start[4], start[5], start[6], start[7]]);
+
+        let mut position = 10;
+        let mut extra = None;
+        if flags & FEXTRA != 0 {
+            let length = start
+                .get(position..position + 2)
+                .ok_or_else(|| invalid("Invalid gzip header"))?;
+            let end = position + 2 + u16::from_le_bytes([length[0], length[1]]) as usize;
+            // An extra field longer than the start that was read is left out
+            extra = start.get(position + 2..end).map(<[u8]>::to_vec);
+            position = end;
+        }
+        let mut name = None;
+        if flags & FNAME != 0 {
+            let (field, next) = zero_terminated(start, position)?;
+            name = Some(field.to_vec());
+            position = next;
+        }
+        let mut comment = None;
+        if flags & FCOMMENT != 0 {
+            let (field, next) = zero_terminated(start, position)?;
+            comment = Some(field.to_vec());
+            position = next;
+        }
+        if flags & FHCRC != 0 {
+            position += 2;
+        }
+        // The trailer follows at least an empty deflate block
+        if compressed_size < position as u64 + 8 {
+            return Err(invalid("Invalid gzip footer length"));
+        }
+
+        Ok(GzipInfo {
+            compressed_size,
+            uncompressed_size: u32::from_le_bytes(trailer) as u64,
+            name,
+            mtime,
+            comment,
+            extra,
+        })
+    }
+
+    /// Reads the metadata of the gzip file at the given path
+    pub async fn read(path: &Path) -> Result<GzipInfo> {
+        let mut file = AsyncFile::open(path).await?;
+        let compressed_size = file.metadata().await?.len();
+
+        let mut start = Vec::new();
+        (&mut file)
+            .take(MAX_HEADER_SIZE)
+            .read_to_end(&mut start)
+            .await?;
+        let mut trailer = [0; 4];
+        if compressed_size >= 4 {
+            file.seek(SeekFrom::End(-4)).await?;
+            file.read_exact(&mut trailer).await?;
+        }
+        GzipInfo::parse(&start, trailer, compressed_size)
+    }
+
+    /// Parses the metadata of a gzip file held entirely in memory
+    pub fn from_bytes(data: &[u8]) -> Result<GzipInfo> {
+        let mut trailer = [0; 4];
+        if data.len() >= 4 {
+            trailer.copy_from_slice(&data[data.len() - 4..]);
+        }
+        GzipInfo::parse(data, trailer, data.len() as u64)
+    }
+}
+
+/// Every field of the header of a gzip member
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Header {
+    pub flags: u8,
+    /// The modification time of the original file in seconds since the Unix epoch, or 0
+    pub mtime: u32,
+    /// The extra flags, which tell whether the fastest (4) or slowest (2) compression was used
+    pub xfl: u8,
+    /// The operating system the member was written on, see [`os_name`]
+    pub os: u8,
+    pub extra: Option<Vec<u8>>,
+    pub name: Option<Vec<u8>>,
+    pub comment: Option<Vec<u8>>,
+    /// The checksum of the header itself, if it has one (and it matched)
+    pub header_crc: Option<u16>,
+}
+
+impl Header {
+    /// Reads the header of a gzip member, leaving the input at the start of its compressed data
+    pub fn read(input: &mut impl BufRead) -> Result<Header> {
+        let mut bytes = vec![0; 10];
+        input.read_exact(&mut bytes)?;
+        if bytes[..2] != MAGIC || bytes[2] != DEFLATE || bytes[3] & FRESERVED != 0 {
+            return Err(invalid("Invalid gzip header"));
+        }
+        let mut header = Header {
+            flags: bytes[3],
+            mtime: u32::from_le_bytes([bytes[4], bytes[5], bytes[6], bytes[7]]),
+            xfl: bytes[8],
+            os: bytes[9],
+            ..Header::default()
+        };
+        if header.flags & FEXTRA != 0 {
+            let mut length = [0; 2];
+            input.read_exact(&mut length)?;
+            bytes.extend_from_slice(&length);
+            let start = bytes.len();
+            bytes.resize(start + u16::from_le_bytes(length) as usize, 0);
+            input.read_exact(&mut bytes[start..])?;
+            header.extra = Some(bytes[start..].to_vec());
+        }
+        for field in [FNAME, FCOMMENT] {
+            if header.flags & field != 0 {
+                let start = bytes.len();
+                input.read_until(0, &mut bytes)?;
+                if bytes.last() != Some(&0) {
+                    return Err(ErrorKind::UnexpectedEof.into());
+                }
+                let value = Some(bytes[start..bytes.len() - 1].to_vec());
+                match field {
+                    FNAME => header.name = value,
+                    _ => header.comment = value,
+                }
+            }
+        }
+        // The header checksum is the low 16 bits of the CRC32 of everything before it
+        if header.flags & FHCRC != 0 {
+            let mut checksum = [0; 2];
+            input.read_exact(&mut checksum)?;
+            let mut crc = Crc::new();
+            crc.update(&bytes);
+            if u16::from_le_bytes(checksum) != crc.sum() as u16 {
+                return Err(invalid("Invalid gzip header"));
+            }
+            header.header_crc = Some(u16::from_le_bytes(checksum));
+        }
+        Ok(header)
+    }
+}
+
+/// Reads the header of a gzip member, leaving the input at the start of its compressed data
+pub fn skip_header(input: &mut impl BufRead) -> Result<()> {
+    Header::read(input).map(|_| ())
+}
+
+/// The subfields of the extra field of a header, each a two-letter id and its data, or `None`
+/// if the extra field is not made of subfields
+pub fn subfields(extra: &[u8]) -> Option<Vec<([u8; 2], &[u8])>> {
+    let mut subfields = Vec::new();
+    let mut rest = extra;
+    while !rest.is_empty() {
+        if rest.len() < 4 {
+            return None;
+        }
+        let length = u16::from_le_bytes([rest[2], rest[3]]) as usize;
+        let data = rest.get(4..4 + length)?;
+        subfields.push(([rest[0], rest[1]], data));
+        rest = &rest[4 + length..];
+    }
+    Some(subfields)
+}
+
+/// Builds an extra field out of subfields, each a two-letter id and its data
+pub fn extra_field(subfields: &[([u8; 2], Vec<u8>)]) -> Vec<u8> {
+    let mut extra = Vec::new();
+    for (id, data) in subfields {
+        extra.extend_from_slice(id);
+        extra.extend_from_slice(&(data.len() as u16).to_le_bytes());
+        extra.extend_from_slice(data);
+    }
+    extra
+}
+
+/// The extra field as its subfields, e.g. `JB=nightly` or `BC=1b00`, with their data as text
+/// if it is printable and in hex otherwise, or as hex altogether if it is not made of subfields
+pub fn format_extra(extra: &[u8]) -> String {
+    let hex = |data: &[u8]| data.iter().map(|byte| format!("{:02x}", byte)).collect();
+    let printable = |data: &[u8]| {
+        !data.is_empty()
+            && data
+                .iter()
+                .all(|&byte| byte.is_ascii_graphic() || byte == b' ')
+    };
+    match subfields(extra) {
+        Some(subfields) => subfields
+            .iter()
+            .map(|(id, data)| match printable(data) {
+                true => format!(
+                    "{}={}",
+                    String::from_utf8_lossy(id),
+                    String::from_utf8_lossy(data)
+                ),
+                false => format!("{}={}", String::from_utf8_lossy(id), hex(data)),
+            })
+            .collect::<Vec<String>>()
+            .join(" "),
+        None => hex(extra),
+    }
+}
+
+/// The names of the flags that are set, e.g. `FNAME FEXTRA`
+pub fn flag_names(flags: u8) -> String {
+    let names: Vec<&str> = [
+        (0x01, "FTEXT"),
+        (FHCRC, "FHCRC"),
+        (FEXTRA, "FEXTRA"),
+        (FNAME, "FNAME"),
+        (FCOMMENT, "FCOMMENT"),
+    ]
+    .iter()
+    .filter(|(flag, _)| flags & flag != 0)
+    .map(|(_, name)| *name)
+    .collect();
+    names.join(" ")
+}
+
+/// The name of the operating system of a header, as listed in RFC 1952
+pub fn os_name(os: u8) -> &'static str {
+    match os {
+        0 => "FAT",
+        1 => "Amiga",
+        2 => "VMS",
+        3 => "Unix",
+        4 => "VM/CMS",
+        5 => "Atari TOS",
+        6 => "HPFS",
+        7 => "Macintosh",
+        8 => "Z-System",
+        9 => "CP/M",
+        10 => "TOPS-20",
+        11 => "NTFS",
+        12 => "QDOS",
+        13 => "Acorn RISCOS",
+        255 => "unknown",
+        _ => "undefined",
+    }
+}
+
+/// The CRC32 checksum and the size modulo 2^32 of the uncompressed data of a gzip member, as
+/// stored in the trailer that ends it
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Trailer {
+    pub crc: u32,
+    pub size: u32,
+}
+
+impl Trailer {
+    /// Reads the trailer that follows the compressed data of a gzip member
+    pub fn read(input: &mut impl BufRead) -> Result<Trailer> {
+        let mut trailer = [0; 8];
+        input.read_exact(&mut trailer)?;
+        Ok(Trailer {
+            crc: u32::from_le_bytes([trailer[0], trailer[1], trailer[2], trailer[3]]),
+            size: u32::from_le_bytes([trailer[4], trailer[5], trailer[6], trailer[7]]),
+        })
+    }
+
+    /// The trailer that the uncompressed data with this checksum should have
+    pub fn of(crc: &Crc) -> Trailer {
+        Trailer {
+            crc: crc.sum(),
+            size: crc.amount(),
+        }
+    }
+}
+
+impl std::fmt::Display for Trailer {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "CRC32 {:08x} and size {}", self.crc, self.size)
+    }
+}
+
+/// The error of decompressed data that does not match the trailer of its gzip member, which is
+/// carried inside an I/O error until the path of the file is known
+#[derive(Debug)]
+pub struct TrailerMismatch {
+    pub expected: Trailer,
+    pub actual: Trailer,
+}
+
+impl std::fmt::Display for TrailerMismatch {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "corrupt gzip data: expected {}, got {}",
+            self.expected, self.actual
+        )
+    }
+}
+
+impl std::error::Error for TrailerMismatch {}
+
+/// Formats seconds since the Unix epoch as a UTC date and time, e.g. `2023-04-01 12:30:00`
+pub fn format_mtime(mtime: u32) -> String {
+    let days = (mtime / 86400) as i64;
+    let seconds = mtime % 86400;
+
+    // Convert the day count to a civil date (Howard Hinnant's algorithm)
+    let z = days + 719468;
+    let era = z / 146097;
+    let day_of_era = z - era * 146097;
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let shifted_month = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * shifted_month + 2) / 5 + 1;
+    let month = if shifted_month < 10 {
+        shifted_month + 3
+    } else {
+        shifted_month - 9
+    };
+    let year = year_of_era + era * 400 + if month <= 2 { 1 } else { 0 };
+
+    format!(
+        "{:04}-{:02}-{:02} {:02}:{:02}:{:02}",
+        year,
+        month,
+        day,
+        seconds / 3600,
+        seconds / 60 % 60,
+        seconds % 60
+    )
+}
+
+/// The original file name and modification time to store in the header of a gzip file, which
+/// `gunzip -N` and `unzip --restore-name` use to restore the file, along with the comment and
+/// extra field it is tagged with
+#[derive(Clone, Debug)]
+pub struct GzipOrigin {
+    pub name: Vec<u8>,
+    pub mtime: u32,
+    pub comment: Vec<u8>,
+    /// The extra field, see [`extra_field`]
+    pub extra: Vec<u8>,
+}
+
+impl GzipOrigin {
+    /// Takes the file name and modification time of the file at the given path
+    pub async fn of(path: &Path) -> Result<GzipOrigin> {
+        GzipOrigin::from_metadata(path, &tokio::fs::metadata(path).await?)
+    }
+
+    /// Takes the file name of the path and the modification time of its metadata
+    pub fn from_metadata(path: &Path, metadata: &std::fs::Metadata) -> Result<GzipOrigin> {
+        let modified = metadata.modified()?;
+        let mtime = modified
+            .duration_since(std::time::UNIX_EPOCH)
+            .map_or(0, |duration| duration.as_secs().min(u32::MAX as u64) as u32);
+        let name = GzipOrigin::name_bytes(path.file_name().unwrap_or_default());
+        Ok(GzipOrigin {
+            name,
+            mtime,
+            comment: Vec::new(),
+            extra: Vec::new(),
+        })
+    }
+
+    /// The bytes a file name is stored as
+    pub fn name_bytes(name: &std::ffi::OsStr) -> Vec<u8> {
+        #[cfg(unix)]
+        let name = std::os::unix::ffi::OsStrExt::as_bytes(name).to_vec();
+        #[cfg(not(unix))]
+        let name = name.to_string_lossy().into_owned().into_bytes();
+        name
+    }
+
+    /// Only a modification time, for outputs that store no name
+    pub fn timestamp(mtime: u32) -> GzipOrigin {
+        GzipOrigin {
+            name: Vec::new(),
+            mtime,
+            comment: Vec::new(),
+            extra: Vec::new(),
+        }
+    }
+
+    /// Rewrites the minimal 10-byte header written by the encoders to also store the name,
+    /// comment, and extra field (those that are not empty) and modification time, keeping the
+    /// extra flags and operating system bytes
+    pub fn header(&self, minimal: &[u8]) -> Vec<u8> {
+        let mut header = minimal[..10].to_vec();
+        header[4..8].copy_from_slice(&self.mtime.to_le_bytes());
+        if !self.extra.is_empty() {
+            header[3] |= FEXTRA;
+            header.extend_from_slice(&(self.extra.len() as u16).to_le_bytes());
+            header.extend_from_slice(&self.extra);
+        }
+        for (flag, field) in [(FNAME, &self.name), (FCOMMENT, &self.comment)] {
+            if !field.is_empty() {
+                header[3] |= flag;
+                header.extend_from_slice(field);
+                header.push(0);
+            }
+        }
+        header
+    }
+}
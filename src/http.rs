@@ -0,0 +1,392 @@
+//! A minimal HTTP/1.1 client for downloading inputs given as `http://` URLs, which streams the
+//! response body into the codec as it arrives.
+//!
+//! Only plain HTTP is supported: HTTPS needs a TLS implementation, which this crate does not
+//! include.
+//!
+//! Downloading is the cargo feature `http`: builds without it still tell URLs apart from paths,
+//! but fail to download them.
+
+use std::io::Result;
+#[cfg(feature = "http")]
+use std::io::{BufRead, BufReader, Error, ErrorKind, Read};
+#[cfg(feature = "http")]
+use std::net::{TcpStream, ToSocketAddrs};
+use std::path::Path;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+#[cfg(feature = "http")]
+use std::time::Duration;
+use tokio::io::{AsyncRead, ReadBuf};
+use tokio::sync::mpsc;
+
+/// The most redirects followed before giving up
+#[cfg(feature = "http")]
+const MAX_REDIRECTS: usize = 5;
+/// How long to wait for a connection, and then for each read from it
+#[cfg(feature = "http")]
+const TIMEOUT: Duration = Duration::from_secs(30);
+/// The size of the chunks handed from the download thread to the codec
+#[cfg(feature = "http")]
+const CHUNK_SIZE: usize = 64 * 1024;
+
+/// Whether the pattern is an HTTP(S) URL rather than a path
+pub fn is_url(pattern: &str) -> bool {
+    let lowercase = pattern.to_ascii_lowercase();
+    lowercase.starts_with("http://") || lowercase.starts_with("https://")
+}
+
+/// The URL held by a discovered path, if it is one
+pub fn url(path: &Path) -> Option<&str> {
+    path.to_str().filter(|path| is_url(path))
+}
+
+/// Decodes `%XX` escapes, leaving invalid ones as they are
+fn percent_decode(value: &str) -> String {
+    let bytes = value.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let escaped = bytes
+            .get(i + 1..i + 3)
+            .and_then(|hex| std::str::from_utf8(hex).ok())
+            .and_then(|hex| u8::from_str_radix(hex, 16).ok());
+        match (bytes[i], escaped) {
+            (b'%', Some(byte)) => {
+                decoded.push(byte);
+                i += 3;
+            }
+            (byte, _) => {
+                decoded.push(byte);
+                i += 1;
+            }
+        }
+    }
+    String::from_utf8_lossy(&decoded).into_owned()
+}
+
+/// The name to save the download of a URL under: the last segment of its path, or `download`
+/// when that is empty or unusable as a file name
+pub fn file_name(url: &str) -> String {
+    let without_query = url.split(['?', '#']).next().unwrap_or_default();
+    let after_scheme = without_query
+        .split_once("://")
+        .map_or(without_query, |(_, rest)| rest);
+    let name = match after_scheme.split_once('/') {
+        Some((_, path)) => percent_decode(path.rsplit('/').next().unwrap_or_default()),
+        None => String::new(),
+    };
+    if name.is_empty() || name == "." || name == ".." || name.contains(['/', '\\', '\0']) {
+        "download".to_string()
+    } else {
+        name
+    }
+}
+
+/// The parts of an `http://` URL needed to request it
+#[cfg(feature = "http")]
+struct Target {
+    host: String,
+    /// The host and port as given, for the Host header
+    authority: String,
+    port: u16,
+    path: String,
+}
+
+#[cfg(feature = "http")]
+fn parse_url(url: &str) -> Result<Target> {
+    let invalid = || Error::new(ErrorKind::InvalidInput, format!("invalid URL: {}", url));
+    let (scheme, rest) = url.split_once("://").ok_or_else(invalid)?;
+    if scheme.eq_ignore_ascii_case("https") {
+        return Err(Error::new(
+            ErrorKind::Unsupported,
+            format!(
+                "{}: HTTPS is not supported, since no TLS implementation is included; download the file first or use http://",
+                url
+            ),
+        ));
+    } else if !scheme.eq_ignore_ascii_case("http") {
+        return Err(invalid());
+    }
+
+    let rest = rest.split('#').next().unwrap_or_default();
+    let (authority, path) = match rest.find(['/', '?']) {
+        Some(index) => (&rest[..index], &rest[index..]),
+        None => (rest, "/"),
+    };
+    let path = match path.starts_with('?') {
+        true => format!("/{}", path),
+        false => path.to_string(),
+    };
+    if authority.contains('@') {
+        return Err(Error::new(
+            ErrorKind::Unsupported,
+            format!("{}: credentials in URLs are not supported", url),
+        ));
+    }
+    // IPv6 addresses are enclosed in brackets to set them apart from the port
+    let (host, port) = match authority.strip_prefix('[') {
+        Some(rest) => {
+            let (host, after) = rest.split_once(']').ok_or_else(invalid)?;
+            (host, after.strip_prefix(':'))
+        }
+        None => match authority.rsplit_once(':') {
+            Some((host, port)) => (host, Some(port)),
+            None => (authority, None),
+        },
+    };
+    let port = port.map_or(Ok(80), |port| port.parse().map_err(|_| invalid()))?;
+    if host.is_empty() {
+        return Err(invalid());
+    }
+    Ok(Target {
+        host: host.to_string(),
+        authority: authority.to_string(),
+        port,
+        path,
+    })
+}
+
+/// Reads a body sent with chunked transfer encoding
+#[cfg(feature = "http")]
+struct ChunkedReader<R> {
+    inner: R,
+    remaining: u64,
+    done: bool,
+}
+
+#[cfg(feature = "http")]
+impl<R: BufRead> Read for ChunkedReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
+        if self.done || buf.is_empty() {
+            return Ok(0);
+        }
+        if self.remaining == 0 {
+            let mut line = String::new();
+            self.inner.read_line(&mut line)?;
+            let size = line.split(';').next().unwrap_or_default().trim();
+            let size = u64::from_str_radix(size, 16)
+                .map_err(|_| Error::new(ErrorKind::InvalidData, "invalid HTTP chunk size"))?;
+            if size == 0 {
+                // Skip any trailers up to the final empty line
+                self.done = true;
+                loop {
+                    line.clear();
+                    if self.inner.read_line(&mut line)? == 0 || line.trim().is_empty() {
+                        return Ok(0);
+                    }
+                }
+            }
+            self.remaining = size;
+        }
+
+        let max = buf
+            .len()
+            .min(self.remaining.try_into().unwrap_or(usize::MAX));
+        let read = self.inner.read(&mut buf[..max])?;
+        if read == 0 {
+            return Err(Error::new(
+                ErrorKind::UnexpectedEof,
+                "the download ended in the middle of a chunk",
+            ));
+        }
+        self.remaining -= read as u64;
+        if self.remaining == 0 {
+            let mut line_end = String::new();
+            self.inner.read_line(&mut line_end)?;
+        }
+        Ok(read)
+    }
+}
+
+/// The body of a successful response
+#[cfg(feature = "http")]
+struct Response {
+    body: Box<dyn Read + Send>,
+    length: Option<u64>,
+}
+
+/// Requests the URL, following redirects, until it responds with its contents
+#[cfg(feature = "http")]
+fn open(url: &str) -> Result<Response> {
+    let mut url = url.to_string();
+    for _ in 0..=MAX_REDIRECTS {
+        let target = parse_url(&url)?;
+        let address = (target.host.as_str(), target.port)
+            .to_socket_addrs()?
+            .next()
+            .ok_or_else(|| {
+                Error::new(
+                    ErrorKind::NotFound,
+                    format!("{} does not resolve", target.host),
+                )
+            })?;
+        let mut stream = TcpStream::connect_timeout(&address, TIMEOUT)?;
+        stream.set_read_timeout(Some(TIMEOUT))?;
+        std::io::Write::write_all(
+            &mut stream,
+            format!(
+                "GET {} HTTP/1.1\r\nHost: {}\r\nUser-Agent: super-gunzip/{}\r\nAccept-Encoding: identity\r\nConnection: close\r\n\r\n",
+                target.path,
+                target.authority,
+                env!("CARGO_PKG_VERSION")
+            )
+            .as_bytes(),
+        )?;
+
+        let mut reader = BufReader::new(stream);
+        let mut status_line = String::new();
+        reader.read_line(&mut status_line)?;
+        let status: u16 = status_line
+            .split_whitespace()
+            .nth(1)
+            .and_then(|status| status.parse().ok())
+            .ok_or_else(|| Error::new(ErrorKind::InvalidData, "invalid HTTP response"))?;
+        let (mut length, mut chunked, mut location) = (None, false, None);
+        loop {
+            let mut line = String::new();
+            if reader.read_line(&mut line)? == 0 || line.trim().is_empty() {
+                break;
+            }
+            let Some((name, value)) = line.split_once(':') else {
+                continue;
+            };
+            let value = value.trim();
+            match name.trim().to_ascii_lowercase().as_str() {
+                "content-length" => length = value.parse().ok(),
+                "transfer-encoding" => chunked = value.to_ascii_lowercase().contains("chunked"),
+                "location" => location = Some(value.to_string()),
+                _ => {}
+            }
+        }
+
+        match (status, location) {
+            (200..=299, _) => {
+                let body: Box<dyn Read + Send> = match (chunked, length) {
+                    (true, _) => Box::new(ChunkedReader {
+                        inner: reader,
+                        remaining: 0,
+                        done: false,
+                    }),
+                    (false, Some(length)) => Box::new(reader.take(length)),
+                    (false, None) => Box::new(reader),
+                };
+                return Ok(Response {
+                    body,
+                    length: length.filter(|_| !chunked),
+                });
+            }
+            (301 | 302 | 303 | 307 | 308, Some(location)) => {
+                url = if is_url(&location) {
+                    location
+                } else if location.starts_with('/') {
+                    format!("http://{}{}", target.authority, location)
+                } else {
+                    let directory = target.path.rsplit_once('/').map_or("", |(dir, _)| dir);
+                    format!("http://{}{}/{}", target.authority, directory, location)
+                };
+            }
+            _ => {
+                return Err(Error::other(format!(
+                    "{} responded with {}",
+                    url,
+                    status_line.trim()
+                )))
+            }
+        }
+    }
+    Err(Error::other(format!("{} redirected too many times", url)))
+}
+
+/// A download in progress, read as its body arrives
+pub struct Download {
+    chunks: mpsc::Receiver<Result<Vec<u8>>>,
+    current: Vec<u8>,
+    position: usize,
+    /// The size of the body, if the server sent it
+    pub length: Option<u64>,
+}
+
+impl AsyncRead for Download {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<Result<()>> {
+        loop {
+            if self.position < self.current.len() {
+                let end = self.current.len().min(self.position + buf.remaining());
+                buf.put_slice(&self.current[self.position..end]);
+                self.position = end;
+                return Poll::Ready(Ok(()));
+            }
+            match self.chunks.poll_recv(cx) {
+                Poll::Ready(Some(Ok(chunk))) => {
+                    self.current = chunk;
+                    self.position = 0;
+                }
+                Poll::Ready(Some(Err(e))) => return Poll::Ready(Err(e)),
+                Poll::Ready(None) => return Poll::Ready(Ok(())),
+                Poll::Pending => return Poll::Pending,
+            }
+        }
+    }
+}
+
+/// Starts downloading the URL, returning once the server has responded successfully. The body
+/// is read on a blocking thread, and a download that ends early is an error rather than a
+/// truncated input.
+#[cfg(feature = "http")]
+pub async fn get(url: &str) -> Result<Download> {
+    let url = url.to_string();
+    let (sender, chunks) = mpsc::channel(4);
+    let (length_sender, length) = tokio::sync::oneshot::channel();
+    tokio::task::spawn_blocking(move || {
+        let mut response = match open(&url) {
+            Ok(response) => response,
+            Err(e) => {
+                let _ = length_sender.send(Err(e));
+                return;
+            }
+        };
+        let _ = length_sender.send(Ok(response.length));
+
+        let mut received = 0;
+        loop {
+            let mut chunk = vec![0; CHUNK_SIZE];
+            let result = match response.body.read(&mut chunk) {
+                Ok(0) if response.length.is_some_and(|length| received < length) => Err(
+                    Error::new(ErrorKind::UnexpectedEof, format!("{} ended early", url)),
+                ),
+                Ok(0) => return,
+                Ok(read) => {
+                    received += read as u64;
+                    chunk.truncate(read);
+                    Ok(chunk)
+                }
+                Err(e) if e.kind() == ErrorKind::Interrupted => continue,
+                Err(e) => Err(e),
+            };
+            let failed = result.is_err();
+            // Stop downloading once the reader is gone
+            if sender.blocking_send(result).is_err() || failed {
+                return;
+            }
+        }
+    });
+
+    let length = length
+        .await
+        .map_err(|_| Error::other("the download stopped unexpectedly"))??;
+    Ok(Download {
+        chunks,
+        current: Vec::new(),
+        position: 0,
+        length,
+    })
+}
+
+#[cfg(not(feature = "http"))]
+pub async fn get(_url: &str) -> Result<Download> {
+    Err(crate::features::Feature::Http.unavailable())
+}
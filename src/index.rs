@@ -0,0 +1,611 @@
+//! Seek indexes of gzip files, for reading a range of the decompressed data of a large file
+//! without decompressing everything before it, like zlib's zran example.
+//!
+//! A gzip file can only be decompressed from the start of a member or, given the last 32 KiB of
+//! data before it, from the start of a deflate block. The index records such points every few
+//! MiB of decompressed data, along with the data before the ones inside members, so that a range
+//! is read by decompressing from the last point before it. BGZF files are indexed in the format
+//! of `bgzip -i` instead, as every one of their blocks is a member that starts afresh.
+
+use crate::bgzf;
+use crate::codec::Codec;
+use crate::error::{Operation, SuperGzipError};
+use crate::gzip_header::{self, Trailer, TrailerMismatch};
+use crate::inflate::{BitReader, Inflater, WINDOW_SIZE};
+use flate2::read::DeflateDecoder;
+use flate2::write::DeflateEncoder;
+use flate2::{Compression, Crc};
+use std::fs::File;
+use std::io::{
+    BufRead, BufReader, BufWriter, Error, ErrorKind, Read, Result, Seek, SeekFrom, Write,
+};
+use std::path::{Path, PathBuf};
+
+/// The bytes indexes of this format start with, which the indexes of bgzip do not
+const MAGIC: [u8; 8] = *b"SGZINDX1";
+
+fn invalid(message: &str) -> Error {
+    Error::new(ErrorKind::InvalidData, message)
+}
+
+/// The path of the index of a gzip file, e.g. `file.gz.gzi`
+pub fn index_path(path: &Path) -> PathBuf {
+    let mut index_path = path.as_os_str().to_owned();
+    index_path.push(".gzi");
+    index_path.into()
+}
+
+/// Writes the index of the gzip file at the path next to it, replacing any earlier index of it
+/// once the new one is complete. Blocks.
+pub(crate) fn save(
+    path: &Path,
+    write: impl FnOnce(&mut BufWriter<File>) -> Result<()>,
+) -> Result<()> {
+    let index_path = index_path(path);
+    let temp_path = crate::batch::temp_path(&index_path);
+    let written = (|| {
+        let mut file = BufWriter::new(File::create(&temp_path)?);
+        write(&mut file)?;
+        file.into_inner()?.sync_all()?;
+        std::fs::rename(&temp_path, &index_path)
+    })();
+    if written.is_err() {
+        let _ = std::fs::remove_file(&temp_path);
+    }
+    written
+}
+
+/// A point that decompression can start from
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Checkpoint {
+    /// The position in the compressed file, in bits
+    pub position: u64,
+    /// The offset in the decompressed data
+    pub offset: u64,
+    /// The data decompressed before the point, which a point inside a member needs, or `None`
+    /// for the start of a member
+    pub window: Option<Vec<u8>>,
+}
+
+fn write_index(path: &Path, span: u64) -> Result<usize> {
+    if let Ok(blocks) = bgzf::Index::scan(path) {
+        blocks.save(path)?;
+        return Ok(blocks.entries.len());
+    }
+    let index = SeekIndex::build(path, span)?;
+    index.save(path)?;
+    Ok(index.checkpoints.len())
+}
+
+/// Indexes the gzip file at the path and writes the index next to it, reading only the headers
+/// of the blocks of a BGZF file and writing its index in the format of bgzip. Returns the number
+/// of points in the index, or an error that names the file if it is corrupt. Blocks.
+pub fn index_file(path: &Path, span: u64) -> std::result::Result<usize, SuperGzipError> {
+    write_index(path, span).map_err(|e| {
+        Codec::Gzip
+            .header_error(&e)
+            .unwrap_or(SuperGzipError::IO(e))
+            .for_file(path, Operation::Index)
+    })
+}
+
+/// The points of a gzip file that decompression can start from
+#[derive(Clone, Debug, Default)]
+pub struct SeekIndex {
+    /// The size of the compressed file when it was indexed, if known
+    pub compressed_size: Option<u64>,
+    /// The size of the decompressed data, if known
+    pub size: Option<u64>,
+    pub checkpoints: Vec<Checkpoint>,
+}
+
+fn read_u64(input: &mut impl Read) -> Result<u64> {
+    let mut value = [0; 8];
+    input.read_exact(&mut value)?;
+    Ok(u64::from_le_bytes(value))
+}
+
+/// Reads the header of the gzip member at the input, returning a decompressor of its data
+fn start_member<R: BufRead + Seek>(mut input: R) -> Result<Inflater<R>> {
+    gzip_header::skip_header(&mut input)?;
+    let start = input.stream_position()?;
+    Ok(Inflater::new(BitReader::new(input, start), &[]))
+}
+
+/// Reads to the end of the gzip member whose compressed data starts at the input, returning the
+/// input after its trailer, which must match the data
+fn finish_member<R: BufRead>(inflater: Inflater<R>, crc: &Crc) -> Result<R> {
+    let mut input = inflater.into_input();
+    input.align();
+    let mut trailer = [0; 8];
+    input.read_exact(&mut trailer)?;
+    let expected = Trailer::read(&mut &trailer[..])?;
+    let actual = Trailer::of(crc);
+    if expected != actual {
+        return Err(Error::new(
+            ErrorKind::InvalidData,
+            TrailerMismatch { expected, actual },
+        ));
+    }
+    Ok(input.into_inner())
+}
+
+/// Whether another gzip member follows, as opposed to the end of the file or the zeros that may
+/// pad it
+pub(crate) fn has_member(input: &mut impl BufRead) -> Result<bool> {
+    loop {
+        let buffer = input.fill_buf()?;
+        match buffer.iter().position(|&byte| byte != 0) {
+            Some(0) if buffer.len() < 2 || buffer[..2] == gzip_header::MAGIC => return Ok(true),
+            Some(_) => return Err(invalid("trailing garbage after the last gzip member")),
+            None if buffer.is_empty() => return Ok(false),
+            None => {
+                let length = buffer.len();
+                input.consume(length);
+            }
+        }
+    }
+}
+
+impl SeekIndex {
+    /// Decompresses the whole gzip file at the path, checking it and recording a point to start
+    /// from about every `span` bytes of decompressed data. Blocks.
+    pub fn build(path: &Path, span: u64) -> Result<SeekIndex> {
+        let file = File::open(path)?;
+        let mut index = SeekIndex {
+            compressed_size: Some(file.metadata()?.len()),
+            ..SeekIndex::default()
+        };
+        let mut input = BufReader::new(file);
+        let mut offset = 0;
+        let mut last = 0;
+        while index.checkpoints.is_empty() || has_member(&mut input)? {
+            if index.checkpoints.is_empty() || offset - last >= span {
+                index.checkpoints.push(Checkpoint {
+                    position: input.stream_position()? * 8,
+                    offset,
+                    window: None,
+                });
+                last = offset;
+            }
+            let mut inflater = start_member(input)?;
+            let mut crc = Crc::new();
+            loop {
+                let is_last = inflater.block()?;
+                let data = inflater.take();
+                crc.update(&data);
+                offset += data.len() as u64;
+                if is_last {
+                    break;
+                }
+                if offset - last >= span {
+                    index.checkpoints.push(Checkpoint {
+                        position: inflater.position(),
+                        offset,
+                        window: Some(inflater.window().to_vec()),
+                    });
+                    last = offset;
+                }
+            }
+            input = finish_member(inflater, &crc)?;
+        }
+        index.size = Some(offset);
+        Ok(index)
+    }
+
+    /// Writes the index: the magic bytes, the compressed and decompressed sizes, and the number of
+    /// points, followed by the position and offset of each point and the length of its window
+    /// (compressed with deflate) and the window itself, or `u32::MAX` for the start of a member,
+    /// all as little-endian integers
+    pub fn write(&self, writer: &mut impl Write) -> Result<()> {
+        writer.write_all(&MAGIC)?;
+        for value in [
+            self.compressed_size.unwrap_or(u64::MAX),
+            self.size.unwrap_or(u64::MAX),
+            self.checkpoints.len() as u64,
+        ] {
+            writer.write_all(&value.to_le_bytes())?;
+        }
+        for checkpoint in &self.checkpoints {
+            writer.write_all(&checkpoint.position.to_le_bytes())?;
+            writer.write_all(&checkpoint.offset.to_le_bytes())?;
+            match &checkpoint.window {
+                Some(window) => {
+                    let mut encoder = DeflateEncoder::new(Vec::new(), Compression::fast());
+                    encoder.write_all(window)?;
+                    let window = encoder.finish()?;
+                    writer.write_all(&(window.len() as u32).to_le_bytes())?;
+                    writer.write_all(&window)?;
+                }
+                None => writer.write_all(&u32::MAX.to_le_bytes())?,
+            }
+        }
+        Ok(())
+    }
+
+    /// Writes the index next to the gzip file at the path. Blocks.
+    pub fn save(&self, path: &Path) -> Result<()> {
+        save(path, |writer| self.write(writer))
+    }
+
+    /// Reads an index in the format written by `write` or in that of `bgzip -i`
+    pub fn read(reader: &mut impl Read) -> Result<SeekIndex> {
+        let mut data = Vec::new();
+        reader.read_to_end(&mut data)?;
+        let mut data = data.as_slice();
+        if !data.starts_with(&MAGIC) {
+            // The index of bgzip lists the blocks after the first one
+            let mut blocks = bgzf::Index::default();
+            blocks.entries.push(bgzf::Entry {
+                compressed: 0,
+                uncompressed: 0,
+            });
+            for _ in 0..read_u64(&mut data)? {
+                blocks.entries.push(bgzf::Entry {
+                    compressed: read_u64(&mut data)?,
+                    uncompressed: read_u64(&mut data)?,
+                });
+            }
+            return SeekIndex::from(blocks).checked(data);
+        }
+
+        data = &data[MAGIC.len()..];
+        let known = |value: u64| (value != u64::MAX).then_some(value);
+        let compressed_size = known(read_u64(&mut data)?);
+        let size = known(read_u64(&mut data)?);
+        let mut checkpoints = Vec::new();
+        for _ in 0..read_u64(&mut data)? {
+            let position = read_u64(&mut data)?;
+            let offset = read_u64(&mut data)?;
+            let mut length = [0; 4];
+            data.read_exact(&mut length)?;
+            let window = match u32::from_le_bytes(length) {
+                u32::MAX => None,
+                length => {
+                    let (compressed, rest) = data
+                        .split_at_checked(length as usize)
+                        .ok_or_else(|| invalid("truncated index"))?;
+                    data = rest;
+                    let mut window = Vec::new();
+                    DeflateDecoder::new(compressed).read_to_end(&mut window)?;
+                    Some(window)
+                }
+            };
+            checkpoints.push(Checkpoint {
+                position,
+                offset,
+                window,
+            });
+        }
+        SeekIndex {
+            compressed_size,
+            size,
+            checkpoints,
+        }
+        .checked(data)
+    }
+
+    /// Fails unless the index was read to its end, and its points are in order and inside the
+    /// file, so that a damaged index is not used to decompress from the wrong place
+    fn checked(self, rest: &[u8]) -> Result<SeekIndex> {
+        let corrupt = || invalid("corrupt index");
+        let first = self.checkpoints.first().ok_or_else(corrupt)?;
+        if !rest.is_empty() || first.offset != 0 || first.window.is_some() {
+            return Err(corrupt());
+        }
+        let in_order = self
+            .checkpoints
+            .windows(2)
+            .all(|pair| pair[0].position < pair[1].position && pair[0].offset <= pair[1].offset);
+        let inside = self.checkpoints.iter().all(|checkpoint| {
+            let window = checkpoint.window.as_ref().map_or(0, Vec::len);
+            window <= WINDOW_SIZE
+                && window as u64 <= checkpoint.offset
+                && self.size.is_none_or(|size| checkpoint.offset <= size)
+                && self
+                    .compressed_size
+                    .is_none_or(|size| checkpoint.position < size * 8)
+        });
+        match in_order && inside {
+            true => Ok(self),
+            false => Err(corrupt()),
+        }
+    }
+
+    /// Reads the index next to the gzip file at the path, checking that it is intact and that
+    /// the file has not changed size since it was indexed. Blocks.
+    pub fn load(path: &Path) -> Result<SeekIndex> {
+        let index_path = index_path(path);
+        let read = SeekIndex::read(&mut BufReader::new(File::open(&index_path)?));
+        let index = read.map_err(|e| match e.kind() {
+            ErrorKind::InvalidData | ErrorKind::InvalidInput | ErrorKind::UnexpectedEof => {
+                invalid(&format!(
+                    "the index of {} is corrupt, so it must be built again",
+                    path.to_string_lossy()
+                ))
+            }
+            _ => e,
+        })?;
+        if index
+            .compressed_size
+            .is_some_and(|size| std::fs::metadata(path).is_ok_and(|m| m.len() != size))
+        {
+            return Err(invalid(&format!(
+                "the index of {} is out of date, since the file changed",
+                path.to_string_lossy()
+            )));
+        }
+        Ok(index)
+    }
+
+    /// Writes `length` bytes (or everything) of the decompressed data of the gzip file at the
+    /// path from the offset into the writer, decompressing from the last point of the index
+    /// before it. Returns the number of bytes written, which is less than asked for if the data
+    /// ends first. Blocks.
+    pub fn extract(
+        &self,
+        path: &Path,
+        start: u64,
+        length: Option<u64>,
+        writer: &mut impl Write,
+    ) -> Result<u64> {
+        let end = length.map_or(u64::MAX, |length| start.saturating_add(length));
+        let point = self
+            .checkpoints
+            .partition_point(|point| point.offset <= start);
+        let Some(checkpoint) = self.checkpoints.get(point.saturating_sub(1)) else {
+            return Ok(0);
+        };
+
+        let mut file = File::open(path)?;
+        file.seek(SeekFrom::Start(checkpoint.position / 8))?;
+        let mut input = BufReader::new(file);
+        let mut inflater = match &checkpoint.window {
+            Some(window) => {
+                let mut bits = BitReader::new(input, checkpoint.position / 8);
+                bits.bits((checkpoint.position % 8) as u32)?;
+                Inflater::new(bits, window)
+            }
+            None if has_member(&mut input)? => start_member(input)?,
+            None => return Ok(0),
+        };
+        let mut offset = checkpoint.offset;
+        let mut written = 0;
+        loop {
+            let is_last = inflater.block()?;
+            let data = inflater.take();
+            let from = start.saturating_sub(offset).min(data.len() as u64) as usize;
+            let to = end.saturating_sub(offset).min(data.len() as u64) as usize;
+            writer.write_all(&data[from..to])?;
+            written += (to - from) as u64;
+            offset += data.len() as u64;
+            if offset >= end {
+                return Ok(written);
+            }
+            if !is_last {
+                continue;
+            }
+            // The trailers are not checked, since the members are not decompressed from their
+            // start
+            let mut bits = inflater.into_input();
+            bits.align();
+            bits.read_exact(&mut [0; 8])?;
+            input = bits.into_inner();
+            if !has_member(&mut input)? {
+                return Ok(written);
+            }
+            inflater = start_member(input)?;
+        }
+    }
+}
+
+impl From<bgzf::Index> for SeekIndex {
+    /// Every block of a BGZF file is a member to start from
+    fn from(blocks: bgzf::Index) -> SeekIndex {
+        SeekIndex {
+            compressed_size: None,
+            size: None,
+            checkpoints: blocks
+                .entries
+                .into_iter()
+                .map(|entry| Checkpoint {
+                    position: entry.compressed * 8,
+                    offset: entry.uncompressed,
+                    window: None,
+                })
+                .collect(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use flate2::write::GzEncoder;
+
+    /// Data that takes several deflate blocks to compress
+    fn sample(len: usize, seed: u32) -> Vec<u8> {
+        let mut state = seed;
+        let mut data = Vec::with_capacity(len);
+        while data.len() < len {
+            state = state.wrapping_mul(1_103_515_245).wrapping_add(12_345);
+            match state >> 29 {
+                0 => data.extend_from_slice(b"a phrase that repeats, "),
+                _ => data.extend(format!("{} ", state >> 8).bytes()),
+            }
+        }
+        data.truncate(len);
+        data
+    }
+
+    /// Writes a gzip file of several members, one of them empty, followed by zeros that pad it,
+    /// returning its path and decompressed data
+    fn gzip_file(name: &str) -> (PathBuf, Vec<u8>) {
+        let path = std::env::temp_dir().join(format!(
+            "super-gunzip-index-{}-{}.gz",
+            name,
+            std::process::id()
+        ));
+        let mut file = Vec::new();
+        let mut data = Vec::new();
+        for (member, level) in [
+            (sample(300_000, 1), 6),
+            (Vec::new(), 6),
+            (sample(200_000, 2), 1),
+        ] {
+            let mut encoder = GzEncoder::new(Vec::new(), Compression::new(level));
+            encoder.write_all(&member).unwrap();
+            file.extend(encoder.finish().unwrap());
+            data.extend(member);
+        }
+        file.extend([0; 100]);
+        std::fs::write(&path, file).unwrap();
+        (path, data)
+    }
+
+    fn extract(index: &SeekIndex, path: &Path, start: u64, length: Option<u64>) -> Vec<u8> {
+        let mut out = Vec::new();
+        let written = index.extract(path, start, length, &mut out).unwrap();
+        assert_eq!(written, out.len() as u64);
+        out
+    }
+
+    /// The written form of an index
+    fn written(index: &SeekIndex) -> Vec<u8> {
+        let mut out = Vec::new();
+        index.write(&mut out).unwrap();
+        out
+    }
+
+    #[test]
+    fn ranges_match_the_whole_data() {
+        let (path, data) = gzip_file("ranges");
+        let index = SeekIndex::build(&path, 64 * 1024).unwrap();
+        assert_eq!(index.size, Some(data.len() as u64));
+        assert_eq!(index.checkpoints[0].window, None);
+        let windows = index
+            .checkpoints
+            .iter()
+            .filter(|point| point.window.is_some());
+        assert!(windows.count() >= 2);
+
+        assert_eq!(extract(&index, &path, 0, None), data);
+        for checkpoint in &index.checkpoints {
+            let offset = checkpoint.offset as usize;
+            for start in [offset.saturating_sub(1), offset, offset + 1] {
+                let end = (start + 40_000).min(data.len());
+                let range = extract(&index, &path, start as u64, Some(40_000));
+                assert_eq!(range, data[start..end], "{} bytes in", start);
+            }
+        }
+        let end = data.len() as u64;
+        assert_eq!(
+            extract(&index, &path, end - 5, None),
+            data[data.len() - 5..]
+        );
+        assert!(extract(&index, &path, end, Some(10)).is_empty());
+        assert!(extract(&index, &path, end + 10, None).is_empty());
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn indexes_round_trip() {
+        let (path, data) = gzip_file("round-trip");
+        let index = SeekIndex::build(&path, 100_000).unwrap();
+        index.save(&path).unwrap();
+        let loaded = SeekIndex::load(&path).unwrap();
+        assert_eq!(loaded.checkpoints, index.checkpoints);
+        assert_eq!(loaded.size, index.size);
+        assert_eq!(loaded.compressed_size, index.compressed_size);
+        assert_eq!(
+            extract(&loaded, &path, 123_456, Some(10)),
+            data[123_456..123_466]
+        );
+
+        // A file that changed since it was indexed is not read with the old index
+        let mut file = std::fs::OpenOptions::new()
+            .append(true)
+            .open(&path)
+            .unwrap();
+        file.write_all(&[0]).unwrap();
+        let error = SeekIndex::load(&path).unwrap_err().to_string();
+        assert!(
+            error.ends_with("is out of date, since the file changed"),
+            "{}",
+            error
+        );
+        std::fs::remove_file(index_path(&path)).unwrap();
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn corrupt_indexes_are_rejected() {
+        let (path, _) = gzip_file("corrupt");
+        let index = SeekIndex::build(&path, 64 * 1024).unwrap();
+        let good = written(&index);
+        assert!(SeekIndex::read(&mut &good[..]).is_ok());
+
+        for len in (0..good.len()).step_by(101) {
+            assert!(SeekIndex::read(&mut &good[..len]).is_err(), "{} bytes", len);
+        }
+        let mut trailing = good.clone();
+        trailing.push(0);
+        let mut damaged = vec![trailing];
+        let mut change = |change: &dyn Fn(&mut SeekIndex)| {
+            let mut index = index.clone();
+            change(&mut index);
+            damaged.push(written(&index));
+        };
+        change(&|index| index.checkpoints.clear());
+        change(&|index| index.checkpoints.swap(1, 2));
+        change(&|index| index.checkpoints[0].offset = 1);
+        change(&|index| index.checkpoints[0].window = Some(vec![0]));
+        change(&|index| index.checkpoints[1].window = Some(vec![0; WINDOW_SIZE + 1]));
+        change(&|index| index.checkpoints[1].position = index.checkpoints[0].position);
+        change(&|index| index.size = Some(index.checkpoints[1].offset - 1));
+        change(&|index| index.compressed_size = Some(1));
+        for (number, damaged) in damaged.iter().enumerate() {
+            let error = SeekIndex::read(&mut &damaged[..]).unwrap_err();
+            assert_eq!(error.to_string(), "corrupt index", "change {}", number);
+        }
+
+        // The points of a bgzip index must be in order too
+        let blocks = |pairs: &[(u64, u64)]| {
+            let mut out = (pairs.len() as u64).to_le_bytes().to_vec();
+            for (compressed, uncompressed) in pairs {
+                out.extend(compressed.to_le_bytes());
+                out.extend(uncompressed.to_le_bytes());
+            }
+            out
+        };
+        let bgzip = SeekIndex::read(&mut &blocks(&[(100, 65_280), (180, 65_280)])[..]).unwrap();
+        assert_eq!(bgzip.checkpoints.len(), 3);
+        assert!(SeekIndex::read(&mut &blocks(&[(100, 65_280), (90, 130_560)])[..]).is_err());
+
+        // Loading names the file whose index is damaged
+        std::fs::write(index_path(&path), &good[..good.len() - 1]).unwrap();
+        let error = SeekIndex::load(&path).unwrap_err().to_string();
+        assert!(error.contains("is corrupt"), "{}", error);
+        std::fs::remove_file(index_path(&path)).unwrap();
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn corrupt_files_fail_to_index() {
+        let (path, _) = gzip_file("damaged");
+        let mut file = std::fs::read(&path).unwrap();
+        let trailer = file.len() - 100 - 8;
+        file[trailer] ^= 1;
+        std::fs::write(&path, &file).unwrap();
+        assert!(SeekIndex::build(&path, 64 * 1024).is_err());
+        file[trailer] ^= 1;
+        file.extend(b"garbage");
+        std::fs::write(&path, &file).unwrap();
+        let error = SeekIndex::build(&path, 64 * 1024).unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "trailing garbage after the last gzip member"
+        );
+        std::fs::remove_file(&path).unwrap();
+    }
+}
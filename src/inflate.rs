@@ -0,0 +1,529 @@
+//! A deflate decoder (RFC 1951) that stops between the blocks of a stream and can start again at
+//! any of those points given the data before it, which the decoders of flate2 cannot. Seeking
+//! into the middle of a gzip member by its index takes both, like zlib's zran example.
+
+use std::io::{BufRead, Error, ErrorKind, Result};
+
+/// The most data a back-reference reaches back to, which a decoder starting in the middle of a
+/// stream needs to be given
+pub const WINDOW_SIZE: usize = 32 * 1024;
+
+/// The length of the codes that are decoded with a single table lookup
+const FAST_BITS: u32 = 10;
+
+/// The length of the longest code
+const MAX_BITS: usize = 15;
+
+/// The base length and number of extra bits of each length symbol from 257 on
+const LENGTH_BASE: [u16; 29] = [
+    3, 4, 5, 6, 7, 8, 9, 10, 11, 13, 15, 17, 19, 23, 27, 31, 35, 43, 51, 59, 67, 83, 99, 115, 131,
+    163, 195, 227, 258,
+];
+const LENGTH_EXTRA: [u8; 29] = [
+    0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 2, 2, 3, 3, 3, 3, 4, 4, 4, 4, 5, 5, 5, 5, 0,
+];
+
+/// The base distance and number of extra bits of each distance symbol
+const DISTANCE_BASE: [u16; 30] = [
+    1, 2, 3, 4, 5, 7, 9, 13, 17, 25, 33, 49, 65, 97, 129, 193, 257, 385, 513, 769, 1025, 1537,
+    2049, 3073, 4097, 6145, 8193, 12289, 16385, 24577,
+];
+const DISTANCE_EXTRA: [u8; 30] = [
+    0, 0, 0, 0, 1, 1, 2, 2, 3, 3, 4, 4, 5, 5, 6, 6, 7, 7, 8, 8, 9, 9, 10, 10, 11, 11, 12, 12, 13,
+    13,
+];
+
+/// The order the lengths of the code length codes are stored in
+const CODE_LENGTH_ORDER: [usize; 19] = [
+    16, 17, 18, 0, 8, 7, 9, 6, 10, 5, 11, 4, 12, 3, 13, 2, 14, 1, 15,
+];
+
+fn invalid(message: &str) -> Error {
+    Error::new(ErrorKind::InvalidData, message)
+}
+
+/// Reads the input bit by bit, least significant bit first, keeping track of its position
+#[derive(Debug)]
+pub struct BitReader<R> {
+    input: R,
+    bits: u64,
+    count: u32,
+    /// The offset in the file of the next byte of the input
+    offset: u64,
+}
+
+impl<R: BufRead> BitReader<R> {
+    /// Reads the input, which is at the given offset in the file
+    pub fn new(input: R, offset: u64) -> BitReader<R> {
+        BitReader {
+            input,
+            bits: 0,
+            count: 0,
+            offset,
+        }
+    }
+
+    /// The position of the next bit to read, in bits from the start of the file
+    pub fn position(&self) -> u64 {
+        self.offset * 8 - self.count as u64
+    }
+
+    /// Takes the next byte of the input, returning false at its end
+    fn pull(&mut self) -> Result<bool> {
+        let byte = match self.input.fill_buf()?.first() {
+            Some(&byte) => byte,
+            None => return Ok(false),
+        };
+        self.input.consume(1);
+        self.bits |= (byte as u64) << self.count;
+        self.count += 8;
+        self.offset += 1;
+        Ok(true)
+    }
+
+    /// Reads the next `count` bits (at most 32)
+    pub fn bits(&mut self, count: u32) -> Result<u32> {
+        while self.count < count {
+            if !self.pull()? {
+                return Err(ErrorKind::UnexpectedEof.into());
+            }
+        }
+        let value = (self.bits & ((1 << count) - 1)) as u32;
+        self.bits >>= count;
+        self.count -= count;
+        Ok(value)
+    }
+
+    /// Skips to the start of the next byte
+    pub fn align(&mut self) {
+        let partial = self.count % 8;
+        self.bits >>= partial;
+        self.count -= partial;
+    }
+
+    /// Reads whole bytes, after aligning to a byte
+    pub fn read_exact(&mut self, buffer: &mut [u8]) -> Result<()> {
+        for byte in buffer {
+            *byte = self.bits(8)? as u8;
+        }
+        Ok(())
+    }
+
+    /// Gives the input back, which must be at a byte boundary with no bytes read ahead
+    pub fn into_inner(self) -> R {
+        debug_assert_eq!(self.count, 0);
+        self.input
+    }
+}
+
+/// A canonical Huffman code, decoded through a table for short codes and bit by bit otherwise
+#[derive(Debug)]
+struct Huffman {
+    /// The number of codes of each length
+    counts: [u16; MAX_BITS + 1],
+    /// The symbols ordered by the length of their codes, then by value
+    symbols: Vec<u16>,
+    /// The symbol and code length for every value of the next `FAST_BITS` bits that starts with
+    /// a code that short, as `symbol << 4 | length`, or 0
+    fast: Vec<u16>,
+}
+
+impl Huffman {
+    /// Builds the code from the code length of each symbol, where 0 means the symbol is unused
+    fn new(lengths: &[u8]) -> Result<Huffman> {
+        let mut counts = [0u16; MAX_BITS + 1];
+        for &length in lengths {
+            counts[length as usize] += 1;
+        }
+        counts[0] = 0;
+        // A set of lengths that more codes than there are would need is invalid, while one that
+        // leaves codes unused is allowed (e.g. a single distance code)
+        let mut left: i32 = 1;
+        for &count in &counts[1..] {
+            left = (left << 1) - count as i32;
+            if left < 0 {
+                return Err(invalid("invalid Huffman code lengths"));
+            }
+        }
+
+        let mut offsets = [0u16; MAX_BITS + 2];
+        for length in 1..=MAX_BITS {
+            offsets[length + 1] = offsets[length] + counts[length];
+        }
+        let mut symbols = vec![0; offsets[MAX_BITS + 1] as usize];
+        let mut fast = vec![0; 1 << FAST_BITS];
+        let mut next_code = [0u32; MAX_BITS + 1];
+        let mut code = 0;
+        for length in 1..=MAX_BITS {
+            code = (code + counts[length - 1] as u32) << 1;
+            next_code[length] = code;
+        }
+        for (symbol, &length) in lengths.iter().enumerate() {
+            let length = length as usize;
+            if length == 0 {
+                continue;
+            }
+            symbols[offsets[length] as usize] = symbol as u16;
+            offsets[length] += 1;
+            let code = next_code[length];
+            next_code[length] += 1;
+            if length as u32 <= FAST_BITS {
+                // Codes are stored from their most significant bit, while bits are read from the
+                // least significant one
+                let reversed = code.reverse_bits() >> (32 - length);
+                for index in (reversed as usize..fast.len()).step_by(1 << length) {
+                    fast[index] = (symbol as u16) << 4 | length as u16;
+                }
+            }
+        }
+        Ok(Huffman {
+            counts,
+            symbols,
+            fast,
+        })
+    }
+
+    /// Reads the next symbol
+    fn decode<R: BufRead>(&self, input: &mut BitReader<R>) -> Result<u16> {
+        while input.count < MAX_BITS as u32 && input.pull()? {}
+        let entry = self.fast[(input.bits & ((1 << FAST_BITS) - 1)) as usize];
+        let length = (entry & 0xf) as u32;
+        if entry != 0 && length <= input.count {
+            input.bits >>= length;
+            input.count -= length;
+            return Ok(entry >> 4);
+        }
+
+        // Longer codes are matched against the first code of each length in turn, like puff
+        let (mut code, mut first, mut index) = (0i32, 0i32, 0i32);
+        for length in 1..=MAX_BITS {
+            code |= input.bits(1)? as i32;
+            let count = self.counts[length] as i32;
+            if code - first < count {
+                return Ok(self.symbols[(index + code - first) as usize]);
+            }
+            index += count;
+            first = (first + count) << 1;
+            code <<= 1;
+        }
+        Err(invalid("invalid Huffman code"))
+    }
+}
+
+/// Decompresses a deflate stream block by block
+#[derive(Debug)]
+pub struct Inflater<R> {
+    input: BitReader<R>,
+    /// The data decompressed but not taken yet, after (up to) the window before it
+    output: Vec<u8>,
+    /// Where the data not taken yet starts in `output`
+    taken: usize,
+}
+
+impl<R: BufRead> Inflater<R> {
+    /// Decompresses the stream from the position of the input, where the window is the data
+    /// decompressed before that point (or nothing at the start of the stream)
+    pub fn new(input: BitReader<R>, window: &[u8]) -> Inflater<R> {
+        Inflater {
+            input,
+            output: window.to_vec(),
+            taken: window.len(),
+        }
+    }
+
+    /// The position of the next block in the file, in bits
+    pub fn position(&self) -> u64 {
+        self.input.position()
+    }
+
+    /// The last `WINDOW_SIZE` bytes decompressed, which decompressing from the current position
+    /// needs
+    pub fn window(&self) -> &[u8] {
+        &self.output[self.output.len().saturating_sub(WINDOW_SIZE)..]
+    }
+
+    /// Takes the data decompressed since it was last taken
+    pub fn take(&mut self) -> Vec<u8> {
+        let data = self.output[self.taken..].to_vec();
+        let start = self.output.len().saturating_sub(WINDOW_SIZE);
+        self.output.drain(..start);
+        self.taken = self.output.len();
+        data
+    }
+
+    /// Gives the input back, positioned after the last block that was decompressed
+    pub fn into_input(self) -> BitReader<R> {
+        self.input
+    }
+
+    /// Decompresses the next block, returning whether it was the last one of the stream
+    pub fn block(&mut self) -> Result<bool> {
+        let last = self.input.bits(1)? == 1;
+        match self.input.bits(2)? {
+            0 => self.stored()?,
+            1 => {
+                let mut lengths = [8u8; 288];
+                lengths[144..256].fill(9);
+                lengths[256..280].fill(7);
+                self.codes(&Huffman::new(&lengths)?, &Huffman::new(&[5; 30])?)?
+            }
+            2 => {
+                let (literals, distances) = self.dynamic_codes()?;
+                self.codes(&literals, &distances)?
+            }
+            _ => return Err(invalid("invalid deflate block type")),
+        }
+        Ok(last)
+    }
+
+    fn stored(&mut self) -> Result<()> {
+        self.input.align();
+        let length = self.input.bits(16)?;
+        if self.input.bits(16)? != !length & 0xffff {
+            return Err(invalid("invalid stored block length"));
+        }
+        let start = self.output.len();
+        self.output.resize(start + length as usize, 0);
+        self.input.read_exact(&mut self.output[start..])
+    }
+
+    /// Reads the literal/length and distance codes of a block with dynamic codes
+    fn dynamic_codes(&mut self) -> Result<(Huffman, Huffman)> {
+        let literals = self.input.bits(5)? as usize + 257;
+        let distances = self.input.bits(5)? as usize + 1;
+        let code_lengths = self.input.bits(4)? as usize + 4;
+        let mut lengths = [0u8; 19];
+        for &symbol in &CODE_LENGTH_ORDER[..code_lengths] {
+            lengths[symbol] = self.input.bits(3)? as u8;
+        }
+        let code_length_code = Huffman::new(&lengths)?;
+
+        let mut lengths = vec![0u8; literals + distances];
+        let mut index = 0;
+        while index < lengths.len() {
+            let symbol = code_length_code.decode(&mut self.input)?;
+            let (length, repeat) = match symbol {
+                0..=15 => (symbol as u8, 1),
+                16 if index > 0 => (lengths[index - 1], 3 + self.input.bits(2)?),
+                17 => (0, 3 + self.input.bits(3)?),
+                18 => (0, 11 + self.input.bits(7)?),
+                _ => return Err(invalid("invalid code lengths")),
+            };
+            let end = index + repeat as usize;
+            if end > lengths.len() {
+                return Err(invalid("too many code lengths"));
+            }
+            lengths[index..end].fill(length);
+            index = end;
+        }
+        if lengths[256] == 0 {
+            return Err(invalid("no end of block code"));
+        }
+        Ok((
+            Huffman::new(&lengths[..literals])?,
+            Huffman::new(&lengths[literals..])?,
+        ))
+    }
+
+    /// Decompresses the literals and back-references of a block with Huffman codes
+    fn codes(&mut self, literals: &Huffman, distances: &Huffman) -> Result<()> {
+        loop {
+            let symbol = literals.decode(&mut self.input)? as usize;
+            if symbol < 256 {
+                self.output.push(symbol as u8);
+                continue;
+            }
+            if symbol == 256 {
+                return Ok(());
+            }
+            let symbol = symbol - 257;
+            if symbol >= LENGTH_BASE.len() {
+                return Err(invalid("invalid length code"));
+            }
+            let length = LENGTH_BASE[symbol] as usize
+                + self.input.bits(LENGTH_EXTRA[symbol] as u32)? as usize;
+            let symbol = distances.decode(&mut self.input)? as usize;
+            if symbol >= DISTANCE_BASE.len() {
+                return Err(invalid("invalid distance code"));
+            }
+            let distance = DISTANCE_BASE[symbol] as usize
+                + self.input.bits(DISTANCE_EXTRA[symbol] as u32)? as usize;
+            if distance > self.output.len() {
+                return Err(invalid("invalid distance too far back"));
+            }
+            let start = self.output.len() - distance;
+            if distance >= length {
+                self.output.extend_from_within(start..start + length);
+            } else {
+                // The copy overlaps the data it produces, repeating the last `distance` bytes
+                for index in start..start + length {
+                    self.output.push(self.output[index]);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use flate2::write::DeflateEncoder;
+    use flate2::Compression;
+    use std::io::Write;
+
+    fn deflate(data: &[u8], level: u32) -> Vec<u8> {
+        let mut encoder = DeflateEncoder::new(Vec::new(), Compression::new(level));
+        encoder.write_all(data).unwrap();
+        encoder.finish().unwrap()
+    }
+
+    /// Decompresses a whole deflate stream, returning the data and the number of blocks
+    fn inflate(stream: &[u8]) -> Result<(Vec<u8>, usize)> {
+        let mut inflater = Inflater::new(BitReader::new(stream, 0), &[]);
+        let (mut data, mut blocks) = (Vec::new(), 0);
+        loop {
+            let is_last = inflater.block()?;
+            data.extend(inflater.take());
+            blocks += 1;
+            if is_last {
+                return Ok((data, blocks));
+            }
+        }
+    }
+
+    /// Text with enough repetition for long matches, and enough variety for dynamic codes
+    fn sample(len: usize) -> Vec<u8> {
+        let mut state = 7u32;
+        let mut data = Vec::with_capacity(len);
+        while data.len() < len {
+            state = state.wrapping_mul(1_103_515_245).wrapping_add(12_345);
+            match state >> 29 {
+                0 => data.extend_from_slice(b"a phrase that repeats, "),
+                _ => data.extend(format!("{} ", state >> 8).bytes()),
+            }
+        }
+        data.truncate(len);
+        data
+    }
+
+    #[test]
+    fn fixed_codes_are_decoded() {
+        // The raw deflate streams of zlib for "a", ten "a", and "abc" five times, which copy from
+        // a distance shorter than the copy
+        assert_eq!(inflate(&[0x4b, 0x04, 0x00]).unwrap().0, b"a");
+        assert_eq!(
+            inflate(&[0x4b, 0x4c, 0x84, 0x01, 0x00]).unwrap().0,
+            [b'a'; 10]
+        );
+        let abc = inflate(&[0x4b, 0x4c, 0x4a, 0x4e, 0x44, 0x42, 0x00])
+            .unwrap()
+            .0;
+        assert_eq!(abc, b"abc".repeat(5));
+    }
+
+    #[test]
+    fn every_level_is_decoded() {
+        let data = sample(300_000);
+        for level in [0, 1, 6, 9] {
+            let (inflated, blocks) = inflate(&deflate(&data, level)).unwrap();
+            assert_eq!(inflated, data, "level {}", level);
+            assert!(blocks > 1, "level {} {}", level, blocks);
+        }
+        assert_eq!(inflate(&deflate(b"", 6)).unwrap().0, b"");
+    }
+
+    #[test]
+    fn decompression_resumes_from_a_block_with_its_window() {
+        let data = sample(300_000);
+        let stream = deflate(&data, 6);
+        let mut inflater = Inflater::new(BitReader::new(&stream[..], 0), &[]);
+        let mut done = Vec::new();
+        let mut points = Vec::new();
+        loop {
+            let is_last = inflater.block().unwrap();
+            done.extend(inflater.take());
+            if is_last {
+                break;
+            }
+            assert!(inflater.window().len() <= WINDOW_SIZE);
+            assert!(done.ends_with(inflater.window()));
+            points.push((inflater.position(), done.len(), inflater.window().to_vec()));
+        }
+        assert!(points.len() > 1);
+
+        for (position, offset, window) in points {
+            let mut input = BitReader::new(&stream[(position / 8) as usize..], position / 8);
+            input.bits((position % 8) as u32).unwrap();
+            let mut inflater = Inflater::new(input, &window);
+            let mut rest = Vec::new();
+            while !inflater.block().unwrap() {
+                rest.extend(inflater.take());
+            }
+            rest.extend(inflater.take());
+            assert_eq!(rest, data[offset..]);
+
+            // Without the window, the references to the data before the point are refused
+            let mut input = BitReader::new(&stream[(position / 8) as usize..], position / 8);
+            input.bits((position % 8) as u32).unwrap();
+            let mut inflater = Inflater::new(input, &[]);
+            let error = std::iter::from_fn(|| Some(inflater.block()))
+                .find_map(|result| result.err())
+                .unwrap();
+            assert_eq!(error.to_string(), "invalid distance too far back");
+        }
+    }
+
+    #[test]
+    fn corrupt_streams_are_errors() {
+        let message = |stream: &[u8]| inflate(stream).unwrap_err().to_string();
+        // A last block of the reserved type 3
+        assert_eq!(message(&[0x07]), "invalid deflate block type");
+        // A stored block whose length does not match its complement
+        assert_eq!(
+            message(&[0x01, 0x05, 0x00, 0x00, 0x00]),
+            "invalid stored block length"
+        );
+        // A copy from before the start of the data
+        assert_eq!(
+            message(&[0x4b, 0x04, 0x42, 0x00]),
+            "invalid distance too far back"
+        );
+
+        let stream = deflate(&sample(4000), 6);
+        for len in 0..stream.len() {
+            let error = inflate(&stream[..len]).unwrap_err();
+            assert_eq!(error.kind(), ErrorKind::UnexpectedEof, "{} bytes", len);
+        }
+        // No damage to a single bit panics
+        for pos in 0..stream.len() {
+            for bit in [0x01, 0x08, 0x40] {
+                let mut corrupt = stream.clone();
+                corrupt[pos] ^= bit;
+                let _ = inflate(&corrupt);
+            }
+        }
+    }
+
+    #[test]
+    fn bits_are_read_least_significant_first() {
+        let mut input = BitReader::new(&[0b1010_1101, 0xff, 0x12, 0x34][..], 100);
+        assert_eq!(input.position(), 800);
+        assert_eq!(input.bits(3).unwrap(), 0b101);
+        assert_eq!(input.bits(2).unwrap(), 0b01);
+        assert_eq!(input.position(), 805);
+        input.align();
+        assert_eq!(input.position(), 808);
+        let mut bytes = [0; 3];
+        input.read_exact(&mut bytes).unwrap();
+        assert_eq!(bytes, [0xff, 0x12, 0x34]);
+        assert_eq!(input.bits(1).unwrap_err().kind(), ErrorKind::UnexpectedEof);
+    }
+
+    #[test]
+    fn oversubscribed_codes_are_refused() {
+        assert!(Huffman::new(&[1, 1, 1]).is_err());
+        // A code that leaves some codes unused, like a single distance code, is allowed
+        assert!(Huffman::new(&[1]).is_ok());
+        assert!(Huffman::new(&[0; 30]).is_ok());
+    }
+}
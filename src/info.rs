@@ -0,0 +1,218 @@
+//! Inspecting every field of the headers and trailers of the members of a gzip file, for
+//! debugging the gzip files that other tools wrote.
+
+use crate::gzip_header::{self, Header, Trailer, TrailerMismatch};
+use crate::index::has_member;
+use crate::progress::format_bytes;
+use crate::report::{json_optional_string, json_string};
+use flate2::bufread::DeflateDecoder;
+use flate2::{Crc, CrcWriter};
+use std::io::{BufRead, Error, ErrorKind, Read, Result};
+use std::path::Path;
+
+/// A member of a gzip file
+#[derive(Debug)]
+pub struct Member {
+    /// Where the member starts in the file
+    pub offset: u64,
+    pub compressed_size: u64,
+    pub header: Header,
+    /// The checksum and size of the data of the member, as stored in its trailer
+    pub trailer: Trailer,
+}
+
+/// The members of a gzip file and the data they hold together
+#[derive(Debug)]
+pub struct Inspection {
+    pub members: Vec<Member>,
+    /// The size of the decompressed data
+    pub size: u64,
+    /// The CRC32 checksum of the decompressed data
+    pub crc: u32,
+}
+
+/// A reader that counts the bytes consumed from it
+struct Counted<R> {
+    inner: R,
+    count: u64,
+}
+
+impl<R: BufRead> Read for Counted<R> {
+    fn read(&mut self, buffer: &mut [u8]) -> Result<usize> {
+        let read = self.inner.read(buffer)?;
+        self.count += read as u64;
+        Ok(read)
+    }
+}
+
+impl<R: BufRead> BufRead for Counted<R> {
+    fn fill_buf(&mut self) -> Result<&[u8]> {
+        self.inner.fill_buf()
+    }
+
+    fn consume(&mut self, amount: usize) {
+        self.count += amount as u64;
+        self.inner.consume(amount);
+    }
+}
+
+/// Decompresses every member of the gzip data, checking it against its trailer, and collects
+/// their headers and trailers. Blocks.
+pub fn inspect(input: impl BufRead) -> Result<Inspection> {
+    let mut input = Counted {
+        inner: input,
+        count: 0,
+    };
+    let mut inspection = Inspection {
+        members: Vec::new(),
+        size: 0,
+        crc: 0,
+    };
+    let mut crc = Crc::new();
+    while inspection.members.is_empty() || has_member(&mut input)? {
+        let offset = input.count;
+        let header = Header::read(&mut input)?;
+        let mut decoder = DeflateDecoder::new(&mut input);
+        let mut data = CrcWriter::new(std::io::sink());
+        inspection.size += std::io::copy(&mut decoder, &mut data)?;
+        let trailer = Trailer::read(decoder.into_inner())?;
+        let actual = Trailer::of(data.crc());
+        if trailer != actual {
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                TrailerMismatch {
+                    expected: trailer,
+                    actual,
+                },
+            ));
+        }
+        crc.combine(data.crc());
+        inspection.members.push(Member {
+            offset,
+            compressed_size: input.count - offset,
+            header,
+            trailer,
+        });
+    }
+    inspection.crc = crc.sum();
+    Ok(inspection)
+}
+
+/// Renders the inspection of the file at the path as text: the totals, followed by every field
+/// of the first member, or of every member if `all_members`
+pub fn render_text(path: &Path, inspection: &Inspection, all_members: bool) -> String {
+    let members = inspection.members.len();
+    let mut text = format!(
+        "{}: {} member{}, {} -> {}, CRC32 {:08x}\n",
+        path.to_string_lossy(),
+        members,
+        if members == 1 { "" } else { "s" },
+        format_bytes(inspection.members.iter().map(|m| m.compressed_size).sum()),
+        format_bytes(inspection.size),
+        inspection.crc
+    );
+    let shown = if all_members { members } else { 1 };
+    for (number, member) in inspection.members.iter().take(shown).enumerate() {
+        let header = &member.header;
+        let optional = |value: &Option<Vec<u8>>| {
+            value.as_ref().map_or_else(
+                || "-".to_string(),
+                |value| String::from_utf8_lossy(value).into_owned(),
+            )
+        };
+        let mtime = match header.mtime {
+            0 => "0 (none)".to_string(),
+            mtime => format!("{} ({})", mtime, gzip_header::format_mtime(mtime)),
+        };
+        let fields = [
+            (
+                "flags",
+                format!(
+                    "{:#04x} ({})",
+                    header.flags,
+                    gzip_header::flag_names(header.flags)
+                ),
+            ),
+            ("MTIME", mtime),
+            ("XFL", header.xfl.to_string()),
+            (
+                "OS",
+                format!("{} ({})", header.os, gzip_header::os_name(header.os)),
+            ),
+            ("FNAME", optional(&header.name)),
+            ("FCOMMENT", optional(&header.comment)),
+            (
+                "EXTRA",
+                header
+                    .extra
+                    .as_deref()
+                    .map_or_else(|| "-".to_string(), gzip_header::format_extra),
+            ),
+            (
+                "HCRC",
+                header
+                    .header_crc
+                    .map_or_else(|| "-".to_string(), |crc| format!("{:04x}", crc)),
+            ),
+            ("CRC32", format!("{:08x}", member.trailer.crc)),
+            ("ISIZE", member.trailer.size.to_string()),
+        ];
+        text.push_str(&format!(
+            "  member {} at offset {} ({} bytes)\n",
+            number + 1,
+            member.offset,
+            member.compressed_size
+        ));
+        for (name, value) in fields {
+            text.push_str(&format!("    {:<9} {}\n", name, value));
+        }
+    }
+    if shown < members {
+        text.push_str(&format!(
+            "  ... and {} more member{} (--verbose shows them)\n",
+            members - shown,
+            if members - shown == 1 { "" } else { "s" }
+        ));
+    }
+    text
+}
+
+/// Renders the inspection of the file at the path as a single-line JSON object
+pub fn render_json(path: &Path, inspection: &Inspection) -> String {
+    let members: Vec<String> = inspection
+        .members
+        .iter()
+        .map(|member| {
+            let header = &member.header;
+            let text = |value: &Option<Vec<u8>>| {
+                let value = value.as_ref().map(|value| String::from_utf8_lossy(value));
+                json_optional_string(value.as_deref())
+            };
+            let extra = header.extra.as_deref().map(gzip_header::format_extra);
+            format!(
+                "{{\"offset\":{},\"compressed_size\":{},\"flags\":{},\"mtime\":{},\"xfl\":{},\"os\":{},\"name\":{},\"comment\":{},\"extra\":{},\"header_crc\":{},\"crc32\":\"{:08x}\",\"isize\":{}}}",
+                member.offset,
+                member.compressed_size,
+                header.flags,
+                header.mtime,
+                header.xfl,
+                header.os,
+                text(&header.name),
+                text(&header.comment),
+                json_optional_string(extra.as_deref()),
+                header
+                    .header_crc
+                    .map_or_else(|| "null".to_string(), |crc| crc.to_string()),
+                member.trailer.crc,
+                member.trailer.size
+            )
+        })
+        .collect();
+    format!(
+        "{{\"path\":{},\"size\":{},\"crc32\":\"{:08x}\",\"members\":[{}]}}",
+        json_string(&path.to_string_lossy()),
+        inspection.size,
+        inspection.crc,
+        members.join(",")
+    )
+}
@@ -0,0 +1,52 @@
+//! Graceful handling of Ctrl-C (SIGINT) and SIGTERM while files are being processed.
+//!
+//! The first signal stops new files from being started while the files in progress finish, the
+//! second cancels the files in progress, removing their partial outputs and keeping their
+//! originals, and the third terminates the process immediately. Nothing changes until
+//! [`install`] is called, so programs embedding the library keep their own signal handling.
+
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Duration;
+
+/// How often waiting tasks check whether a signal arrived, since a signal handler can only set a
+/// flag
+const POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+/// The number of signals received since the handler was installed
+static SIGNALS: AtomicUsize = AtomicUsize::new(0);
+
+#[cfg(unix)]
+extern "C" fn handle_signal(signal: libc::c_int) {
+    // Only async-signal-safe calls are allowed here
+    if SIGNALS.fetch_add(1, Ordering::SeqCst) >= 1 {
+        unsafe { libc::signal(signal, libc::SIG_DFL) };
+    }
+}
+
+/// Installs the handler for SIGINT and SIGTERM. Without it, signals terminate the process as
+/// usual. Only supported on Unix; elsewhere this does nothing.
+pub fn install() {
+    #[cfg(unix)]
+    unsafe {
+        let handler = handle_signal as extern "C" fn(libc::c_int) as libc::sighandler_t;
+        libc::signal(libc::SIGINT, handler);
+        libc::signal(libc::SIGTERM, handler);
+    }
+}
+
+/// Whether new files should no longer be started
+pub fn stopping() -> bool {
+    SIGNALS.load(Ordering::SeqCst) >= 1
+}
+
+/// Whether the files in progress should be abandoned
+pub fn cancelled() -> bool {
+    SIGNALS.load(Ordering::SeqCst) >= 2
+}
+
+/// Waits until new files should no longer be started
+pub async fn stop_requested() {
+    while !stopping() {
+        tokio::time::sleep(POLL_INTERVAL).await;
+    }
+}
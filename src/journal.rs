@@ -0,0 +1,427 @@
+//! A journal of the files a run has completed, so that an interrupted run can be resumed without
+//! processing (or deleting) its files again.
+//!
+//! The journal is a text file with one line per completed file: the CRC32 checksum of its
+//! contents in hex, its size, and its absolute path, separated by tabs. Lines are appended and
+//! flushed as files complete, so the journal survives the run being killed.
+
+use flate2::Crc;
+use std::collections::HashMap;
+use std::hash::{DefaultHasher, Hasher};
+use std::io::{Result, Write};
+use std::path::{Path, PathBuf};
+use std::pin::Pin;
+use std::sync::Mutex;
+use std::task::{Context, Poll};
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+
+/// The checksum and size of a file's contents
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct Fingerprint {
+    pub crc: u32,
+    pub size: u64,
+}
+
+/// A reader that computes the fingerprint of everything read through it
+pub struct FingerprintReader<R> {
+    inner: R,
+    crc: Crc,
+    size: u64,
+    digest: Option<DefaultHasher>,
+}
+
+impl<R> FingerprintReader<R> {
+    pub fn new(inner: R) -> FingerprintReader<R> {
+        FingerprintReader {
+            inner,
+            crc: Crc::new(),
+            size: 0,
+            digest: None,
+        }
+    }
+
+    /// A reader that also computes the [`digest`](FingerprintReader::digest) of the data
+    pub fn digesting(inner: R) -> FingerprintReader<R> {
+        FingerprintReader {
+            digest: Some(DefaultHasher::new()),
+            ..FingerprintReader::new(inner)
+        }
+    }
+
+    /// The fingerprint of the data read so far
+    pub fn fingerprint(&self) -> Fingerprint {
+        Fingerprint {
+            crc: self.crc.sum(),
+            size: self.size,
+        }
+    }
+
+    /// A 64-bit SipHash of the data read so far, independent of the CRC32 that gzip stores, if
+    /// the reader was made with [`digesting`](FingerprintReader::digesting). It is only stable
+    /// within one build, so it is not recorded in the journal.
+    pub fn digest(&self) -> Option<u64> {
+        self.digest.as_ref().map(Hasher::finish)
+    }
+}
+
+impl<R: AsyncRead + Unpin> AsyncRead for FingerprintReader<R> {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<Result<()>> {
+        let before = buf.filled().len();
+        let poll = Pin::new(&mut self.inner).poll_read(cx, buf);
+        if let Poll::Ready(Ok(())) = poll {
+            let read = &buf.filled()[before..];
+            self.size += read.len() as u64;
+            self.crc.update(read);
+            if let Some(digest) = &mut self.digest {
+                digest.write(read);
+            }
+        }
+        poll
+    }
+}
+
+/// A writer that computes the fingerprint of everything written through it
+pub struct FingerprintWriter<W> {
+    inner: W,
+    crc: Crc,
+    size: u64,
+    digest: Option<DefaultHasher>,
+}
+
+impl<W> FingerprintWriter<W> {
+    pub fn new(inner: W) -> FingerprintWriter<W> {
+        FingerprintWriter {
+            inner,
+            crc: Crc::new(),
+            size: 0,
+            digest: None,
+        }
+    }
+
+    /// A writer that also computes the [`digest`](FingerprintWriter::digest) of the data
+    pub fn digesting(inner: W) -> FingerprintWriter<W> {
+        FingerprintWriter {
+            digest: Some(DefaultHasher::new()),
+            ..FingerprintWriter::new(inner)
+        }
+    }
+
+    /// The fingerprint of the data written so far
+    pub fn fingerprint(&self) -> Fingerprint {
+        Fingerprint {
+            crc: self.crc.sum(),
+            size: self.size,
+        }
+    }
+
+    /// The same digest as [`FingerprintReader::digest`], of the data written so far
+    pub fn digest(&self) -> Option<u64> {
+        self.digest.as_ref().map(Hasher::finish)
+    }
+}
+
+impl<W: AsyncWrite + Unpin> AsyncWrite for FingerprintWriter<W> {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<Result<usize>> {
+        let poll = Pin::new(&mut self.inner).poll_write(cx, buf);
+        if let Poll::Ready(Ok(written)) = poll {
+            self.size += written as u64;
+            self.crc.update(&buf[..written]);
+            if let Some(digest) = &mut self.digest {
+                digest.write(&buf[..written]);
+            }
+        }
+        poll
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<()>> {
+        Pin::new(&mut self.inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<()>> {
+        Pin::new(&mut self.inner).poll_shutdown(cx)
+    }
+}
+
+/// Computes the fingerprint of the file at the path
+pub async fn fingerprint(path: &Path) -> Result<Fingerprint> {
+    let mut reader = FingerprintReader::new(tokio::fs::File::open(path).await?);
+    tokio::io::copy(&mut reader, &mut tokio::io::sink()).await?;
+    Ok(reader.fingerprint())
+}
+
+#[cfg(unix)]
+pub(crate) fn path_to_bytes(path: &Path) -> Vec<u8> {
+    std::os::unix::ffi::OsStrExt::as_bytes(path.as_os_str()).to_vec()
+}
+
+#[cfg(not(unix))]
+pub(crate) fn path_to_bytes(path: &Path) -> Vec<u8> {
+    path.to_string_lossy().into_owned().into_bytes()
+}
+
+#[cfg(unix)]
+pub(crate) fn path_from_bytes(bytes: &[u8]) -> PathBuf {
+    PathBuf::from(<std::ffi::OsStr as std::os::unix::ffi::OsStrExt>::from_bytes(bytes))
+}
+
+#[cfg(not(unix))]
+pub(crate) fn path_from_bytes(bytes: &[u8]) -> PathBuf {
+    PathBuf::from(String::from_utf8_lossy(bytes).into_owned())
+}
+
+/// Parses a journal line, ignoring lines that are incomplete because the run was killed while
+/// writing them
+fn parse_line(line: &[u8]) -> Option<(PathBuf, Fingerprint)> {
+    let mut fields = line.splitn(3, |&byte| byte == b'\t');
+    let crc = u32::from_str_radix(std::str::from_utf8(fields.next()?).ok()?, 16).ok()?;
+    let size = std::str::from_utf8(fields.next()?).ok()?.parse().ok()?;
+    let path = fields.next().filter(|path| !path.is_empty())?;
+    Some((path_from_bytes(path), Fingerprint { crc, size }))
+}
+
+/// The files completed by earlier runs, along with the file to record newly completed ones in
+#[derive(Debug)]
+pub struct Journal {
+    completed: HashMap<PathBuf, Fingerprint>,
+    /// Where completed files are recorded, unless the journal was only loaded
+    file: Option<Mutex<std::fs::File>>,
+}
+
+impl Journal {
+    /// Loads the files completed according to the journal at the path, without recording
+    /// anything in it (e.g. for a dry run). A missing journal has no completed files.
+    pub async fn load(path: &Path) -> Result<Journal> {
+        let completed = match tokio::fs::read(path).await {
+            Ok(contents) => Journal::parse(&contents).0,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => HashMap::new(),
+            Err(e) => return Err(e),
+        };
+        Ok(Journal {
+            completed,
+            file: None,
+        })
+    }
+
+    /// Parses the contents of a journal, also returning whether it ends with a partial line
+    fn parse(contents: &[u8]) -> (HashMap<PathBuf, Fingerprint>, bool) {
+        // Only complete lines count, since the last one may have been cut short
+        let end = contents
+            .iter()
+            .rposition(|&byte| byte == b'\n')
+            .map_or(0, |end| end + 1);
+        let completed = contents[..end]
+            .split(|&byte| byte == b'\n')
+            .filter_map(parse_line)
+            .collect();
+        (completed, end != contents.len())
+    }
+
+    /// Opens the journal at the path for recording, creating it if it does not exist yet
+    pub async fn open(path: &Path) -> Result<Journal> {
+        let (completed, partial_line) = match tokio::fs::read(path).await {
+            Ok(contents) => Journal::parse(&contents),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => (HashMap::new(), false),
+            Err(e) => return Err(e),
+        };
+        let mut file = std::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)?;
+        if partial_line {
+            file.write_all(b"\n")?;
+        }
+        Ok(Journal {
+            completed,
+            file: Some(Mutex::new(file)),
+        })
+    }
+
+    /// The key a file is recorded under, which does not depend on the working directory
+    fn key(path: &Path) -> PathBuf {
+        std::path::absolute(path).unwrap_or_else(|_| path.to_path_buf())
+    }
+
+    /// Whether an earlier run completed the file at the path, and it has not changed since
+    pub async fn is_completed(&self, path: &Path) -> bool {
+        let Some(recorded) = self.completed.get(&Journal::key(path)) else {
+            return false;
+        };
+        // Only read files whose size still matches
+        match tokio::fs::metadata(path).await {
+            Ok(metadata) if metadata.len() == recorded.size => {}
+            _ => return false,
+        }
+        fingerprint(path)
+            .await
+            .is_ok_and(|fingerprint| fingerprint == *recorded)
+    }
+
+    /// Records that the file at the path was completed, with the fingerprint of its contents.
+    /// Paths containing newlines are not recorded, since they cannot be read back.
+    pub fn record(&self, path: &Path, fingerprint: Fingerprint) -> Result<()> {
+        let path = path_to_bytes(&Journal::key(path));
+        let Some(file) = self.file.as_ref().filter(|_| !path.contains(&b'\n')) else {
+            return Ok(());
+        };
+        let mut line = format!("{:08x}\t{}\t", fingerprint.crc, fingerprint.size).into_bytes();
+        line.extend_from_slice(&path);
+        line.push(b'\n');
+        let mut file = file.lock().unwrap();
+        file.write_all(&line)?;
+        file.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!(
+            "super-gunzip-journal-{}-{}",
+            name,
+            std::process::id()
+        ));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn parse_skips_corrupt_and_partial_lines() {
+        let contents = b"0000002a\t5\t/data/a.txt\n\
+            not hex\t5\t/data/b.txt\n\
+            0000002a\tfive\t/data/c.txt\n\
+            0000002a\t5\n\
+            0000002a\t5\t\n\
+            \n\
+            0000002b\t6\t/data/tab\tin name.txt\n\
+            0000002c\t7\t/data/cut sh";
+        let (completed, partial_line) = Journal::parse(contents);
+        assert!(partial_line);
+        assert_eq!(completed.len(), 2);
+        assert_eq!(
+            completed[Path::new("/data/a.txt")],
+            Fingerprint { crc: 42, size: 5 }
+        );
+        assert_eq!(
+            completed[Path::new("/data/tab\tin name.txt")],
+            Fingerprint { crc: 43, size: 6 }
+        );
+
+        let (completed, partial_line) = Journal::parse(b"");
+        assert!(completed.is_empty());
+        assert!(!partial_line);
+    }
+
+    /// A run killed while writing a line leaves it partial, and the next run must not append its
+    /// first record to it
+    #[tokio::test]
+    async fn open_resumes_after_a_partial_line() {
+        let dir = temp_dir("resume");
+        let file = dir.join("a.txt");
+        std::fs::write(&file, b"hello").unwrap();
+        let recorded = fingerprint(&file).await.unwrap();
+        let journal_path = dir.join("journal");
+        std::fs::write(&journal_path, b"0000002a\t5\t/data/cut sh").unwrap();
+
+        let journal = Journal::open(&journal_path).await.unwrap();
+        assert!(!journal.is_completed(&file).await);
+        journal.record(&file, recorded).unwrap();
+        drop(journal);
+
+        let journal = Journal::open(&journal_path).await.unwrap();
+        assert!(journal.is_completed(&file).await);
+        let contents = std::fs::read(&journal_path).unwrap();
+        assert!(contents.starts_with(b"0000002a\t5\t/data/cut sh\n"));
+        assert!(contents.ends_with(b"a.txt\n"));
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn changed_or_missing_files_are_not_completed() {
+        let dir = temp_dir("stale");
+        let file = dir.join("a.txt");
+        std::fs::write(&file, b"hello").unwrap();
+        let journal_path = dir.join("journal");
+        let journal = Journal::open(&journal_path).await.unwrap();
+        journal
+            .record(&file, fingerprint(&file).await.unwrap())
+            .unwrap();
+        drop(journal);
+
+        let journal = Journal::load(&journal_path).await.unwrap();
+        assert!(journal.is_completed(&file).await);
+
+        // Same size, different contents
+        std::fs::write(&file, b"jello").unwrap();
+        assert!(!journal.is_completed(&file).await);
+        std::fs::write(&file, b"hello!").unwrap();
+        assert!(!journal.is_completed(&file).await);
+        std::fs::remove_file(&file).unwrap();
+        assert!(!journal.is_completed(&file).await);
+        assert!(!journal.is_completed(&dir.join("b.txt")).await);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn load_does_not_write_the_journal() {
+        let dir = temp_dir("load");
+        let journal_path = dir.join("journal");
+        let journal = Journal::load(&journal_path).await.unwrap();
+        journal
+            .record(&dir.join("a.txt"), Fingerprint { crc: 1, size: 1 })
+            .unwrap();
+        assert!(!journal_path.exists());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn paths_with_newlines_are_not_recorded() {
+        let dir = temp_dir("newline");
+        let journal_path = dir.join("journal");
+        let journal = Journal::open(&journal_path).await.unwrap();
+        journal
+            .record(&dir.join("a\nb.txt"), Fingerprint { crc: 1, size: 1 })
+            .unwrap();
+        assert!(std::fs::read(&journal_path).unwrap().is_empty());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    /// `--verify` compares digests, since the CRC32 and size that gzip stores can collide
+    #[tokio::test]
+    async fn digests_tell_crc32_collisions_apart() {
+        let data = "a line of text\n".repeat(100).into_bytes();
+        let mut collision = data.clone();
+        for (byte, flip) in collision[100..]
+            .iter_mut()
+            .zip([0x41, 0x06, 0x71, 0xdb, 0x01])
+        {
+            *byte ^= flip;
+        }
+
+        let mut reader = FingerprintReader::digesting(&data[..]);
+        let mut writer = FingerprintWriter::digesting(Vec::new());
+        tokio::io::copy(&mut reader, &mut writer).await.unwrap();
+        assert_eq!(writer.fingerprint(), reader.fingerprint());
+        assert_eq!(writer.digest(), reader.digest());
+        assert_eq!(reader.fingerprint().size, data.len() as u64);
+
+        let mut other = FingerprintReader::digesting(&collision[..]);
+        tokio::io::copy(&mut other, &mut tokio::io::sink())
+            .await
+            .unwrap();
+        assert_eq!(other.fingerprint(), reader.fingerprint());
+        assert_ne!(other.digest(), reader.digest());
+        assert_eq!(FingerprintReader::new(&data[..]).digest(), None);
+    }
+}
@@ -0,0 +1,97 @@
+//! Batch compression and decompression of many files at once, spread across threads.
+//!
+//! The [`Compressor`] builder is the easiest way to embed SuperGZip in another program:
+//!
+//! ```no_run
+//! use super_gunzip::{Codec, Compressor};
+//!
+//! # async fn example() -> Result<(), super_gunzip::SuperGzipError> {
+//! let reports = Compressor::new(Codec::Gzip)
+//!     .level(9)
+//!     .concurrency(8)
+//!     .keep_original(true)
+//!     .on_file_done(|report| println!("{}", report.path.display()))
+//!     .compress(["logs/*.log"])
+//!     .await?;
+//! println!("Compressed {} files", reports.len());
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! The modules that make up the command line tool are internal to the crate.
+
+pub(crate) mod adaptive;
+pub(crate) mod archive;
+pub(crate) mod backend;
+pub(crate) mod batch;
+pub(crate) mod bench;
+pub(crate) mod bgzf;
+pub(crate) mod bundle;
+pub(crate) mod cancel;
+pub(crate) mod cat;
+mod cli;
+pub(crate) mod codec;
+pub(crate) mod color;
+pub(crate) mod completions;
+mod compressor;
+pub(crate) mod concat;
+pub(crate) mod config;
+#[cfg(unix)]
+pub(crate) mod daemon;
+pub(crate) mod dedupe;
+pub(crate) mod discovery;
+pub(crate) mod engine;
+pub(crate) mod entropy;
+mod error;
+pub(crate) mod estimate;
+pub(crate) mod events;
+pub(crate) mod features;
+pub(crate) mod gitignore;
+pub(crate) mod grep;
+pub(crate) mod gzip_header;
+pub(crate) mod http;
+pub(crate) mod index;
+pub(crate) mod inflate;
+pub(crate) mod info;
+pub(crate) mod interrupt;
+pub(crate) mod journal;
+pub(crate) mod log;
+#[cfg(feature = "lz4")]
+pub(crate) mod lz4;
+pub(crate) mod magic;
+pub(crate) mod memory;
+pub(crate) mod metrics;
+#[cfg(unix)]
+pub(crate) mod mmap;
+pub(crate) mod progress;
+pub(crate) mod prompt;
+pub(crate) mod regex;
+pub(crate) mod report;
+pub(crate) mod settings;
+pub(crate) mod shred;
+pub(crate) mod space;
+pub(crate) mod sparse;
+pub(crate) mod split;
+#[cfg(feature = "sync-engine")]
+pub(crate) mod sync_engine;
+pub(crate) mod tar;
+pub(crate) mod throttle;
+pub(crate) mod timings;
+pub(crate) mod trash;
+pub(crate) mod watch;
+pub(crate) mod zip;
+
+pub use cancel::CancellationToken;
+pub use codec::Codec;
+pub use compressor::Compressor;
+pub use engine::Engine;
+pub use error::{Operation, SuperGzipError};
+pub use gzip_header::Trailer;
+pub use magic::Format;
+pub use report::{FileReport, FileStatus};
+pub use settings::{Incompressible, SymlinkPolicy};
+pub use timings::Timings;
+
+/// The entry point of the `super-gunzip` binary, which is not part of the library's API
+#[doc(hidden)]
+pub use cli::main as run_cli;
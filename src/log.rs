@@ -0,0 +1,217 @@
+//! Structured logging of what runs do, for ingesting the activity of runs started from cron or
+//! other schedulers into a log pipeline. Each event is a line on stderr, as text or JSON, and
+//! belongs to the span of its run and, for the events about a file, to the span of that file.
+
+use crate::gzip_header::format_mtime;
+use crate::report::{json_string, FileReport, FileStatus};
+use clap::ValueEnum;
+use std::path::Path;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// How much is logged, from only errors to everything
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum LogLevel {
+    Error,
+    Warn,
+    Info,
+    Debug,
+    Trace,
+}
+
+impl LogLevel {
+    fn name(self) -> &'static str {
+        match self {
+            LogLevel::Error => "ERROR",
+            LogLevel::Warn => "WARN",
+            LogLevel::Info => "INFO",
+            LogLevel::Debug => "DEBUG",
+            LogLevel::Trace => "TRACE",
+        }
+    }
+}
+
+/// How log events are written
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LogFormat {
+    /// `<time> <LEVEL> run{id=<id>}:file{path=<path>}: <message> <field>=<value>...`
+    Text,
+    /// One JSON object per event, with the time, level, run, path, message, and fields as keys
+    Json,
+}
+
+/// The value of a field of a log event
+pub enum Value {
+    Str(String),
+    Int(u64),
+    Float(f64),
+}
+
+impl From<&str> for Value {
+    fn from(value: &str) -> Value {
+        Value::Str(value.to_string())
+    }
+}
+
+impl From<String> for Value {
+    fn from(value: String) -> Value {
+        Value::Str(value)
+    }
+}
+
+impl From<u64> for Value {
+    fn from(value: u64) -> Value {
+        Value::Int(value)
+    }
+}
+
+impl From<usize> for Value {
+    fn from(value: usize) -> Value {
+        Value::Int(value as u64)
+    }
+}
+
+impl From<Duration> for Value {
+    fn from(value: Duration) -> Value {
+        Value::Float(value.as_secs_f64())
+    }
+}
+
+impl Value {
+    fn to_json(&self) -> String {
+        match self {
+            Value::Str(value) => json_string(value),
+            Value::Int(value) => value.to_string(),
+            Value::Float(value) => format!("{:.6}", value),
+        }
+    }
+
+    /// Renders the value for a text event, quoting strings that would be ambiguous unquoted
+    fn to_text(&self) -> String {
+        match self {
+            Value::Str(value) if value.is_empty() || value.contains([' ', '"', '=']) => {
+                json_string(value)
+            }
+            Value::Str(value) => value.clone(),
+            Value::Int(value) => value.to_string(),
+            Value::Float(value) => format!("{:.6}", value),
+        }
+    }
+}
+
+/// The current time in UTC with milliseconds, e.g. `2023-04-01T12:30:00.123Z`
+fn timestamp() -> String {
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default();
+    let seconds = now.as_secs().min(u32::MAX as u64) as u32;
+    format!(
+        "{}.{:03}Z",
+        format_mtime(seconds).replace(' ', "T"),
+        now.subsec_millis()
+    )
+}
+
+/// Writes the log events of runs at or above a level
+#[derive(Clone, Copy, Debug)]
+pub struct Logger {
+    level: LogLevel,
+    format: LogFormat,
+}
+
+impl Logger {
+    pub fn new(level: LogLevel, format: LogFormat) -> Logger {
+        Logger { level, format }
+    }
+
+    /// Whether events of the level are logged
+    pub fn enabled(&self, level: LogLevel) -> bool {
+        level <= self.level
+    }
+
+    /// Logs an event of the run, about the file at the path if given
+    pub fn event(
+        &self,
+        level: LogLevel,
+        run: &str,
+        path: Option<&Path>,
+        message: &str,
+        fields: &[(&str, Value)],
+    ) {
+        if !self.enabled(level) {
+            return;
+        }
+        let path = path.map(|path| path.to_string_lossy());
+        let line = match self.format {
+            LogFormat::Text => {
+                let mut line = format!("{} {:>5} run{{id={}}}", timestamp(), level.name(), run);
+                if let Some(path) = &path {
+                    line.push_str(&format!(":file{{path={}}}", path));
+                }
+                line.push_str(&format!(": {}", message));
+                for (name, value) in fields {
+                    line.push_str(&format!(" {}={}", name, value.to_text()));
+                }
+                line
+            }
+            LogFormat::Json => {
+                let mut line = format!(
+                    "{{\"timestamp\":\"{}\",\"level\":\"{}\",\"run\":{}",
+                    timestamp(),
+                    level.name(),
+                    json_string(run)
+                );
+                if let Some(path) = &path {
+                    line.push_str(&format!(",\"path\":{}", json_string(path)));
+                }
+                line.push_str(&format!(",\"message\":{}", json_string(message)));
+                for (name, value) in fields {
+                    line.push_str(&format!(",{}:{}", json_string(name), value.to_json()));
+                }
+                line.push('}');
+                line
+            }
+        };
+        eprintln!("{}", line);
+    }
+
+    /// Logs the outcome of a file: failures as errors, processed files as info, and skipped
+    /// files as debug events
+    pub fn file_done(&self, run: &str, report: &FileReport) {
+        let (level, message, mut fields): (_, _, Vec<(&str, Value)>) = match &report.status {
+            FileStatus::Processed => (LogLevel::Info, "processed", Vec::new()),
+            FileStatus::Skipped(reason) => (
+                LogLevel::Debug,
+                "skipped",
+                vec![("reason", (*reason).into())],
+            ),
+            FileStatus::Failed(error) => (
+                LogLevel::Error,
+                "failed",
+                vec![("error", error.as_str().into())],
+            ),
+        };
+        if let Some(output_path) = &report.output_path {
+            fields.push((
+                "output_path",
+                output_path.to_string_lossy().into_owned().into(),
+            ));
+        }
+        if !matches!(report.status, FileStatus::Skipped(_)) {
+            fields.push(("input_size", report.input_size.into()));
+            fields.push(("output_size", report.output_size.into()));
+            fields.push(("duration_secs", report.duration.into()));
+        }
+        self.event(level, run, Some(&report.path), message, &fields);
+    }
+}
+
+/// A new identifier for a run, unique among the runs of every process on the machine at a time
+pub fn run_id() -> String {
+    static NEXT: AtomicU64 = AtomicU64::new(1);
+    format!(
+        "{}-{}",
+        std::process::id(),
+        NEXT.fetch_add(1, Ordering::Relaxed)
+    )
+}
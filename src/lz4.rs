@@ -0,0 +1,594 @@
+//! A small, dependency-free implementation of the LZ4 frame format.
+//!
+//! See <https://github.com/lz4/lz4/blob/dev/doc/lz4_Frame_format.md> and
+//! <https://github.com/lz4/lz4/blob/dev/doc/lz4_Block_format.md> for the specifications.
+//! Frames written here use independent 4 MiB blocks and a content checksum, which every
+//! LZ4 implementation (including the `lz4` command line tool) can read.
+
+use std::io::{Error, ErrorKind, Result};
+
+const MAGIC: u32 = 0x184D_2204;
+const SKIPPABLE_MAGIC_MASK: u32 = 0xFFFF_FFF0;
+const SKIPPABLE_MAGIC: u32 = 0x184D_2A50;
+
+const FLG_VERSION: u8 = 0b0100_0000;
+const FLG_BLOCK_INDEPENDENCE: u8 = 0b0010_0000;
+const FLG_BLOCK_CHECKSUM: u8 = 0b0001_0000;
+const FLG_CONTENT_SIZE: u8 = 0b0000_1000;
+const FLG_CONTENT_CHECKSUM: u8 = 0b0000_0100;
+const FLG_DICT_ID: u8 = 0b0000_0001;
+
+const BLOCK_MAX_SIZE_ID: u8 = 7;
+const BLOCK_MAX_SIZE: usize = 4 * 1024 * 1024;
+const UNCOMPRESSED_BLOCK: u32 = 0x8000_0000;
+
+const MIN_MATCH: usize = 4;
+const LAST_LITERALS: usize = 5;
+const MF_LIMIT: usize = 12;
+const MAX_OFFSET: usize = 65535;
+const HASH_LOG: u32 = 16;
+
+/// The slowest compression level, which produces the smallest output
+const MAX_LEVEL: u32 = 12;
+
+const PRIME32_1: u32 = 2654435761;
+const PRIME32_2: u32 = 2246822519;
+const PRIME32_3: u32 = 3266489917;
+const PRIME32_4: u32 = 668265263;
+const PRIME32_5: u32 = 374761393;
+
+fn invalid(message: &str) -> Error {
+    Error::new(
+        ErrorKind::InvalidData,
+        format!("invalid LZ4 frame: {}", message),
+    )
+}
+
+fn read_u32(data: &[u8], pos: usize) -> u32 {
+    u32::from_le_bytes([data[pos], data[pos + 1], data[pos + 2], data[pos + 3]])
+}
+
+fn xxh32_round(acc: u32, input: u32) -> u32 {
+    acc.wrapping_add(input.wrapping_mul(PRIME32_2))
+        .rotate_left(13)
+        .wrapping_mul(PRIME32_1)
+}
+
+/// Computes the 32-bit xxHash of `input`, as used for LZ4 header, block, and content checksums.
+fn xxh32(input: &[u8], seed: u32) -> u32 {
+    let len = input.len();
+    let mut pos = 0;
+    let mut hash = if len >= 16 {
+        let mut v1 = seed.wrapping_add(PRIME32_1).wrapping_add(PRIME32_2);
+        let mut v2 = seed.wrapping_add(PRIME32_2);
+        let mut v3 = seed;
+        let mut v4 = seed.wrapping_sub(PRIME32_1);
+        while pos + 16 <= len {
+            v1 = xxh32_round(v1, read_u32(input, pos));
+            v2 = xxh32_round(v2, read_u32(input, pos + 4));
+            v3 = xxh32_round(v3, read_u32(input, pos + 8));
+            v4 = xxh32_round(v4, read_u32(input, pos + 12));
+            pos += 16;
+        }
+        v1.rotate_left(1)
+            .wrapping_add(v2.rotate_left(7))
+            .wrapping_add(v3.rotate_left(12))
+            .wrapping_add(v4.rotate_left(18))
+    } else {
+        seed.wrapping_add(PRIME32_5)
+    };
+    hash = hash.wrapping_add(len as u32);
+    while pos + 4 <= len {
+        hash = hash
+            .wrapping_add(read_u32(input, pos).wrapping_mul(PRIME32_3))
+            .rotate_left(17)
+            .wrapping_mul(PRIME32_4);
+        pos += 4;
+    }
+    while pos < len {
+        hash = hash
+            .wrapping_add((input[pos] as u32).wrapping_mul(PRIME32_5))
+            .rotate_left(11)
+            .wrapping_mul(PRIME32_1);
+        pos += 1;
+    }
+    hash ^= hash >> 15;
+    hash = hash.wrapping_mul(PRIME32_2);
+    hash ^= hash >> 13;
+    hash = hash.wrapping_mul(PRIME32_3);
+    hash ^= hash >> 16;
+    hash
+}
+
+fn write_length(out: &mut Vec<u8>, mut length: usize) {
+    while length >= 255 {
+        out.push(255);
+        length -= 255;
+    }
+    out.push(length as u8);
+}
+
+fn write_sequence(out: &mut Vec<u8>, literals: &[u8], offset: usize, match_len: usize) {
+    let literal_nibble = literals.len().min(15) as u8;
+    let match_nibble = (match_len - MIN_MATCH).min(15) as u8;
+    out.push((literal_nibble << 4) | match_nibble);
+    if literals.len() >= 15 {
+        write_length(out, literals.len() - 15);
+    }
+    out.extend_from_slice(literals);
+    out.extend_from_slice(&(offset as u16).to_le_bytes());
+    if match_len - MIN_MATCH >= 15 {
+        write_length(out, match_len - MIN_MATCH - 15);
+    }
+}
+
+fn write_last_literals(out: &mut Vec<u8>, literals: &[u8]) {
+    out.push((literals.len().min(15) as u8) << 4);
+    if literals.len() >= 15 {
+        write_length(out, literals.len() - 15);
+    }
+    out.extend_from_slice(literals);
+}
+
+/// Compresses a single block. Level 1 uses a greedy single-probe hash table like LZ4's fast mode
+/// and skips ahead through incompressible data, while higher levels remember every position in a
+/// hash chain and try up to `2^(level - 1)` earlier candidates to find the longest match.
+fn compress_block(src: &[u8], out: &mut Vec<u8>, level: u32) {
+    let max_attempts = 1usize << (level.clamp(1, MAX_LEVEL) - 1);
+    let mut table = vec![u32::MAX; 1 << HASH_LOG];
+    let mut chain = if max_attempts > 1 {
+        vec![u32::MAX; MAX_OFFSET + 1]
+    } else {
+        Vec::new()
+    };
+    let hash = |sequence: u32| (sequence.wrapping_mul(PRIME32_1) >> (32 - HASH_LOG)) as usize;
+    let mut anchor = 0;
+    let mut pos = 0;
+
+    // The last match must start at least MF_LIMIT bytes before the end of the block
+    if src.len() > MF_LIMIT {
+        let match_limit = src.len() - MF_LIMIT;
+        let end_limit = src.len() - LAST_LITERALS;
+        let match_len = |candidate: usize, pos: usize| {
+            let mut match_end = pos + MIN_MATCH;
+            while match_end < end_limit && src[match_end] == src[candidate + match_end - pos] {
+                match_end += 1;
+            }
+            match_end - pos
+        };
+        let insert = |table: &mut Vec<u32>, chain: &mut Vec<u32>, pos: usize| {
+            let slot = hash(read_u32(src, pos));
+            let previous = table[slot];
+            table[slot] = pos as u32;
+            if !chain.is_empty() {
+                chain[pos & MAX_OFFSET] = previous;
+            }
+            previous as usize
+        };
+        while pos < match_limit {
+            let sequence = read_u32(src, pos);
+            let mut candidate = insert(&mut table, &mut chain, pos);
+
+            // Walk the candidates from nearest to furthest, keeping the longest match
+            let mut best: Option<(usize, usize)> = None;
+            for _ in 0..max_attempts {
+                if candidate >= pos || pos - candidate > MAX_OFFSET {
+                    break;
+                }
+                if read_u32(src, candidate) == sequence {
+                    let len = match_len(candidate, pos);
+                    if best.is_none_or(|(_, best_len)| len > best_len) {
+                        best = Some((candidate, len));
+                    }
+                }
+                if chain.is_empty() {
+                    break;
+                }
+                let next = chain[candidate & MAX_OFFSET] as usize;
+                if next >= candidate {
+                    break;
+                }
+                candidate = next;
+            }
+            let Some((candidate, len)) = best else {
+                // Skip faster through data that does not compress well in fast mode
+                pos += if chain.is_empty() {
+                    1 + ((pos - anchor) >> 6)
+                } else {
+                    1
+                };
+                continue;
+            };
+
+            // Extend the match backwards over pending literals
+            let (mut start, mut candidate_start) = (pos, candidate);
+            while start > anchor
+                && candidate_start > 0
+                && src[start - 1] == src[candidate_start - 1]
+            {
+                start -= 1;
+                candidate_start -= 1;
+            }
+            let match_end = pos + len;
+
+            write_sequence(
+                out,
+                &src[anchor..start],
+                start - candidate_start,
+                match_end - start,
+            );
+
+            // Remember the positions covered by the match so later data can refer to them
+            if chain.is_empty() {
+                if match_end - 2 < match_limit {
+                    insert(&mut table, &mut chain, match_end - 2);
+                }
+            } else {
+                for covered in pos + 1..match_end.min(match_limit) {
+                    insert(&mut table, &mut chain, covered);
+                }
+            }
+            pos = match_end;
+            anchor = pos;
+        }
+    }
+    write_last_literals(out, &src[anchor..]);
+}
+
+fn decompress_block(src: &[u8], out: &mut Vec<u8>) -> Result<()> {
+    let mut pos = 0;
+    let read_length = |pos: &mut usize, mut length: usize| -> Result<usize> {
+        loop {
+            let byte = *src.get(*pos).ok_or_else(|| invalid("truncated length"))?;
+            *pos += 1;
+            length += byte as usize;
+            if byte != 255 {
+                return Ok(length);
+            }
+        }
+    };
+    loop {
+        let token = *src.get(pos).ok_or_else(|| invalid("truncated sequence"))?;
+        pos += 1;
+
+        // Copy the literals
+        let mut literal_len = (token >> 4) as usize;
+        if literal_len == 15 {
+            literal_len = read_length(&mut pos, literal_len)?;
+        }
+        let literals = src
+            .get(pos..pos + literal_len)
+            .ok_or_else(|| invalid("literals overrun the block"))?;
+        out.extend_from_slice(literals);
+        pos += literal_len;
+
+        // The last sequence of a block only contains literals
+        if pos == src.len() {
+            return Ok(());
+        }
+
+        // Copy the match, which may overlap the bytes it produces
+        let offset = src
+            .get(pos..pos + 2)
+            .map(|bytes| u16::from_le_bytes([bytes[0], bytes[1]]) as usize)
+            .ok_or_else(|| invalid("truncated match offset"))?;
+        pos += 2;
+        if offset == 0 || offset > out.len() {
+            return Err(invalid("match offset out of range"));
+        }
+        let mut match_len = (token & 0x0F) as usize;
+        if match_len == 15 {
+            match_len = read_length(&mut pos, match_len)?;
+        }
+        match_len += MIN_MATCH;
+        let start = out.len() - offset;
+        if offset >= match_len {
+            out.extend_from_within(start..start + match_len);
+        } else {
+            for i in 0..match_len {
+                out.push(out[start + i]);
+            }
+        }
+    }
+}
+
+/// Compresses `data` into a single LZ4 frame at the given level (1 to [`MAX_LEVEL`]).
+pub fn compress(data: &[u8], level: u32) -> Vec<u8> {
+    let mut out = Vec::with_capacity(data.len() / 2 + 64);
+    out.extend_from_slice(&MAGIC.to_le_bytes());
+    let descriptor = [
+        FLG_VERSION | FLG_BLOCK_INDEPENDENCE | FLG_CONTENT_CHECKSUM,
+        BLOCK_MAX_SIZE_ID << 4,
+    ];
+    out.extend_from_slice(&descriptor);
+    out.push((xxh32(&descriptor, 0) >> 8) as u8);
+
+    let mut block = Vec::new();
+    for chunk in data.chunks(BLOCK_MAX_SIZE) {
+        block.clear();
+        compress_block(chunk, &mut block, level);
+
+        // Store the block uncompressed if compression did not help
+        if block.len() < chunk.len() {
+            out.extend_from_slice(&(block.len() as u32).to_le_bytes());
+            out.extend_from_slice(&block);
+        } else {
+            out.extend_from_slice(&(chunk.len() as u32 | UNCOMPRESSED_BLOCK).to_le_bytes());
+            out.extend_from_slice(chunk);
+        }
+    }
+    out.extend_from_slice(&0u32.to_le_bytes());
+    out.extend_from_slice(&xxh32(data, 0).to_le_bytes());
+    out
+}
+
+/// Decompresses every LZ4 frame in `data`, skipping over skippable frames.
+pub fn decompress(data: &[u8]) -> Result<Vec<u8>> {
+    let mut out = Vec::new();
+    let mut pos = 0;
+    let take = |pos: &mut usize, len: usize| -> Result<&[u8]> {
+        let bytes = data
+            .get(*pos..*pos + len)
+            .ok_or_else(|| invalid("unexpected end of input"))?;
+        *pos += len;
+        Ok(bytes)
+    };
+    loop {
+        let magic = read_u32(take(&mut pos, 4)?, 0);
+        if magic & SKIPPABLE_MAGIC_MASK == SKIPPABLE_MAGIC {
+            let len = read_u32(take(&mut pos, 4)?, 0) as usize;
+            take(&mut pos, len)?;
+        } else if magic != MAGIC {
+            return Err(invalid("bad magic number"));
+        } else {
+            // Parse and validate the frame descriptor
+            let descriptor_start = pos;
+            let flags = take(&mut pos, 2)?[0];
+            if flags & 0b1100_0000 != FLG_VERSION {
+                return Err(invalid("unsupported version"));
+            }
+            if flags & FLG_DICT_ID != 0 {
+                return Err(invalid("dictionaries are not supported"));
+            }
+            let content_size = if flags & FLG_CONTENT_SIZE != 0 {
+                let bytes = take(&mut pos, 8)?;
+                Some(u64::from_le_bytes(bytes.try_into().unwrap()))
+            } else {
+                None
+            };
+            let header_checksum = take(&mut pos, 1)?[0];
+            if header_checksum != (xxh32(&data[descriptor_start..pos - 1], 0) >> 8) as u8 {
+                return Err(invalid("header checksum mismatch"));
+            }
+
+            // Decode the blocks straight into the output so that linked blocks can reference
+            // data from previous blocks
+            let frame_start = out.len();
+            loop {
+                let block_header = read_u32(take(&mut pos, 4)?, 0);
+                if block_header == 0 {
+                    break;
+                }
+                let block_len = (block_header & !UNCOMPRESSED_BLOCK) as usize;
+                let block = take(&mut pos, block_len)?;
+                if flags & FLG_BLOCK_CHECKSUM != 0 {
+                    let checksum = read_u32(take(&mut pos, 4)?, 0);
+                    if checksum != xxh32(block, 0) {
+                        return Err(invalid("block checksum mismatch"));
+                    }
+                }
+                if block_header & UNCOMPRESSED_BLOCK != 0 {
+                    out.extend_from_slice(block);
+                } else {
+                    decompress_block(block, &mut out)?;
+                }
+            }
+
+            let frame = &out[frame_start..];
+            if content_size.is_some_and(|size| size != frame.len() as u64) {
+                return Err(invalid("content size mismatch"));
+            }
+            if flags & FLG_CONTENT_CHECKSUM != 0 {
+                let checksum = read_u32(take(&mut pos, 4)?, 0);
+                if checksum != xxh32(frame, 0) {
+                    return Err(invalid("content checksum mismatch"));
+                }
+            }
+        }
+        if pos == data.len() {
+            return Ok(out);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Text that compresses well, with runs of pseudo-random bytes that do not
+    fn sample(len: usize) -> Vec<u8> {
+        let mut state = 0x2545_F491_u32;
+        let mut data = Vec::with_capacity(len);
+        while data.len() < len {
+            state ^= state << 13;
+            state ^= state >> 17;
+            state ^= state << 5;
+            if state.is_multiple_of(4) {
+                data.extend_from_slice(&state.to_le_bytes());
+            } else {
+                data.extend_from_slice(format!("line {} of the log\n", state % 100).as_bytes());
+            }
+        }
+        data.truncate(len);
+        data
+    }
+
+    /// Builds a frame with the given flags around already encoded blocks
+    fn frame(flags: u8, content_size: Option<u64>, blocks: &[Vec<u8>], content: &[u8]) -> Vec<u8> {
+        let mut descriptor = vec![FLG_VERSION | flags, BLOCK_MAX_SIZE_ID << 4];
+        if let Some(size) = content_size {
+            descriptor[0] |= FLG_CONTENT_SIZE;
+            descriptor.extend_from_slice(&size.to_le_bytes());
+        }
+        let mut out = MAGIC.to_le_bytes().to_vec();
+        out.extend_from_slice(&descriptor);
+        out.push((xxh32(&descriptor, 0) >> 8) as u8);
+        for block in blocks {
+            out.extend_from_slice(&(block.len() as u32).to_le_bytes());
+            out.extend_from_slice(block);
+            if flags & FLG_BLOCK_CHECKSUM != 0 {
+                out.extend_from_slice(&xxh32(block, 0).to_le_bytes());
+            }
+        }
+        out.extend_from_slice(&0u32.to_le_bytes());
+        if flags & FLG_CONTENT_CHECKSUM != 0 {
+            out.extend_from_slice(&xxh32(content, 0).to_le_bytes());
+        }
+        out
+    }
+
+    #[test]
+    fn checksums_match_the_reference() {
+        assert_eq!(xxh32(b"", 0), 0x02CC_5D05);
+        assert_eq!(xxh32(b"abc", 0), 0x32D1_53FF);
+    }
+
+    #[test]
+    fn empty_input_matches_the_lz4_tool() {
+        // `lz4 -c < /dev/null`, which uses 64 KiB blocks
+        let reference = [
+            0x04, 0x22, 0x4D, 0x18, 0x64, 0x40, 0xA7, 0, 0, 0, 0, 0x05, 0x5D, 0xCC, 0x02,
+        ];
+        assert_eq!(decompress(&reference).unwrap(), b"");
+        assert_eq!(decompress(&compress(b"", 1)).unwrap(), b"");
+    }
+
+    #[test]
+    fn every_level_round_trips() {
+        let data = sample(200_000);
+        let mut sizes = Vec::new();
+        for level in 1..=MAX_LEVEL {
+            let compressed = compress(&data, level);
+            assert_eq!(decompress(&compressed).unwrap(), data, "level {}", level);
+            sizes.push(compressed.len());
+        }
+        assert!(sizes[MAX_LEVEL as usize - 1] < sizes[0], "{:?}", sizes);
+    }
+
+    #[test]
+    fn short_inputs_round_trip() {
+        // Around the minimum sizes of a match and of the literals that end a block
+        let data = b"abcdabcdabcdabcdabcdabcd";
+        for len in 0..=data.len() {
+            for level in [1, 2, MAX_LEVEL] {
+                let compressed = compress(&data[..len], level);
+                assert_eq!(
+                    decompress(&compressed).unwrap(),
+                    &data[..len],
+                    "{} bytes",
+                    len
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn block_boundaries_round_trip() {
+        for len in [
+            BLOCK_MAX_SIZE - 1,
+            BLOCK_MAX_SIZE,
+            BLOCK_MAX_SIZE + 1,
+            2 * BLOCK_MAX_SIZE + MIN_MATCH,
+        ] {
+            let data = sample(len);
+            for level in [1, 3] {
+                let compressed = compress(&data, level);
+                assert_eq!(decompress(&compressed).unwrap(), data, "{} bytes", len);
+            }
+        }
+
+        // Blocks that do not compress are stored as they are
+        let mut state = 1u32;
+        let noise: Vec<u8> = (0..BLOCK_MAX_SIZE + 10)
+            .map(|_| {
+                state = state.wrapping_mul(1_103_515_245).wrapping_add(12_345);
+                (state >> 24) as u8
+            })
+            .collect();
+        let compressed = compress(&noise, 1);
+        assert!(compressed.len() < noise.len() + 64);
+        assert_eq!(decompress(&compressed).unwrap(), noise);
+    }
+
+    #[test]
+    fn frames_of_other_encoders_are_read() {
+        let literals = |text: &[u8]| {
+            let mut block = Vec::new();
+            write_last_literals(&mut block, text);
+            block
+        };
+        // A second block that copies from the first, as linked blocks may, then an overlapping
+        // match that repeats the byte before it
+        let mut linked = Vec::new();
+        write_sequence(&mut linked, b"", 6, 6);
+        write_sequence(&mut linked, b"!", 1, 4);
+        write_last_literals(&mut linked, b"end");
+        let content = b"hello hello !!!!!end";
+        let flags = FLG_BLOCK_CHECKSUM | FLG_CONTENT_CHECKSUM;
+        let frame = frame(flags, Some(20), &[literals(b"hello "), linked], content);
+        assert_eq!(decompress(&frame).unwrap(), content);
+
+        // Skippable frames are skipped, and concatenated frames are joined
+        let mut data = SKIPPABLE_MAGIC.to_le_bytes().to_vec();
+        data.extend_from_slice(&3u32.to_le_bytes());
+        data.extend_from_slice(b"xyz");
+        data.extend_from_slice(&compress(b"one ", 1));
+        data.extend_from_slice(&frame);
+        data.extend_from_slice(&(SKIPPABLE_MAGIC | 0xF).to_le_bytes());
+        data.extend_from_slice(&0u32.to_le_bytes());
+        assert_eq!(decompress(&data).unwrap(), b"one hello hello !!!!!end");
+    }
+
+    #[test]
+    fn corrupt_frames_are_errors() {
+        let data = sample(5000);
+        let compressed = compress(&data, 4);
+        let message = |data: &[u8]| {
+            let message = decompress(data).unwrap_err().to_string();
+            message.replace("invalid LZ4 frame: ", "")
+        };
+
+        assert_eq!(message(b""), "unexpected end of input");
+        assert_eq!(message(b"\x1F\x8B\x08\x00"), "bad magic number");
+        let mut checksum = compressed.clone();
+        *checksum.last_mut().unwrap() ^= 1;
+        assert_eq!(message(&checksum), "content checksum mismatch");
+        let mut header = compressed.clone();
+        header[6] ^= 1;
+        assert_eq!(message(&header), "header checksum mismatch");
+        let mut version = compressed.clone();
+        version[4] ^= 0b1000_0000;
+        assert_eq!(message(&version), "unsupported version");
+        let wrong_size = frame(0, Some(3), &[], b"");
+        assert_eq!(message(&wrong_size), "content size mismatch");
+        let mut far = Vec::new();
+        write_sequence(&mut far, b"ab", 3, 4);
+        write_last_literals(&mut far, b"tail.");
+        let far = frame(0, None, &[far], b"");
+        assert_eq!(message(&far), "match offset out of range");
+
+        // Every truncation fails, and no corruption of a bit panics
+        for len in 0..compressed.len() {
+            let error = decompress(&compressed[..len]).unwrap_err();
+            assert_eq!(error.kind(), ErrorKind::InvalidData, "{} bytes", len);
+        }
+        for pos in 0..compressed.len() {
+            for bit in [0x01, 0x10, 0x80] {
+                let mut corrupt = compressed.clone();
+                corrupt[pos] ^= bit;
+                // A changed match offset may copy the same bytes, so any result but a panic will do
+                let _ = decompress(&corrupt);
+            }
+        }
+    }
+}
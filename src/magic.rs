@@ -0,0 +1,104 @@
+//! Recognizing compressed file formats by the magic bytes at the start of their contents, so that
+//! files are judged by what they contain rather than by their name
+
+use std::path::Path;
+use tokio::io::{AsyncRead, AsyncReadExt};
+
+/// The number of bytes at the start of a file needed to recognize any of the formats
+const SNIFF_LEN: usize = 12;
+
+/// A file format whose contents are already compressed
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Format {
+    Gzip,
+    Zstd,
+    Xz,
+    Bzip2,
+    Lz4,
+    Zip,
+    SevenZip,
+    Rar,
+    Png,
+    Jpeg,
+    Gif,
+    Webp,
+    Mp4,
+}
+
+impl Format {
+    pub fn name(self) -> &'static str {
+        match self {
+            Format::Gzip => "gzip",
+            Format::Zstd => "zstd",
+            Format::Xz => "xz",
+            Format::Bzip2 => "bzip2",
+            Format::Lz4 => "LZ4",
+            Format::Zip => "zip",
+            Format::SevenZip => "7z",
+            Format::Rar => "RAR",
+            Format::Png => "PNG",
+            Format::Jpeg => "JPEG",
+            Format::Gif => "GIF",
+            Format::Webp => "WebP",
+            Format::Mp4 => "MP4",
+        }
+    }
+}
+
+/// Recognizes the format of contents starting with these bytes, if it is a known one
+pub fn detect(start: &[u8]) -> Option<Format> {
+    let format = match start {
+        [0x1f, 0x8b, ..] => Format::Gzip,
+        [0x28, 0xb5, 0x2f, 0xfd, ..] => Format::Zstd,
+        [0xfd, b'7', b'z', b'X', b'Z', 0x00, ..] => Format::Xz,
+        [b'B', b'Z', b'h', b'1'..=b'9', ..] => Format::Bzip2,
+        [0x04, 0x22, 0x4d, 0x18, ..] => Format::Lz4,
+        [b'P', b'K', 0x03, 0x04, ..] | [b'P', b'K', 0x05, 0x06, ..] => Format::Zip,
+        [b'7', b'z', 0xbc, 0xaf, 0x27, 0x1c, ..] => Format::SevenZip,
+        [b'R', b'a', b'r', b'!', 0x1a, 0x07, ..] => Format::Rar,
+        [0x89, b'P', b'N', b'G', ..] => Format::Png,
+        [0xff, 0xd8, 0xff, ..] => Format::Jpeg,
+        [b'G', b'I', b'F', b'8', ..] => Format::Gif,
+        [b'R', b'I', b'F', b'F', _, _, _, _, b'W', b'E', b'B', b'P', ..] => Format::Webp,
+        [_, _, _, _, b'f', b't', b'y', b'p', ..] => Format::Mp4,
+        _ => return None,
+    };
+    Some(format)
+}
+
+/// Reads the start of the file at the path and recognizes its format, if it is a known one
+pub async fn sniff(path: &Path) -> std::io::Result<Option<Format>> {
+    let mut file = tokio::fs::File::open(path).await?;
+    let (format, _) = sniff_reader(&mut file).await?;
+    Ok(format)
+}
+
+/// Reads the start of the file at the path and recognizes its format, like [`sniff`]. Blocks.
+#[cfg(feature = "sync-engine")]
+pub fn sniff_file(path: &Path) -> std::io::Result<Option<Format>> {
+    use std::io::Read;
+
+    let mut start = Vec::with_capacity(SNIFF_LEN);
+    std::fs::File::open(path)?
+        .take(SNIFF_LEN as u64)
+        .read_to_end(&mut start)?;
+    Ok(detect(&start))
+}
+
+/// Reads the start of a stream and recognizes its format, if it is a known one. Returns the
+/// bytes that were read along with it, which the stream no longer yields.
+pub async fn sniff_reader<R: AsyncRead + Unpin>(
+    reader: &mut R,
+) -> std::io::Result<(Option<Format>, Vec<u8>)> {
+    let mut start = vec![0; SNIFF_LEN];
+    let mut len = 0;
+    while len < start.len() {
+        let read = reader.read(&mut start[len..]).await?;
+        if read == 0 {
+            break;
+        }
+        len += read;
+    }
+    start.truncate(len);
+    Ok((detect(&start), start))
+}
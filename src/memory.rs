@@ -0,0 +1,93 @@
+//! Accounting of the memory held by the files being processed at once, so that a run with many
+//! threads and large files stays within a budget.
+//!
+//! Each file reserves an estimate of its buffers before it is processed and holds the reservation
+//! until it is done. A file whose estimate exceeds the whole budget waits for all of it, so it is
+//! still processed, just on its own.
+
+use crate::codec::Codec;
+use crate::settings::Settings;
+use std::sync::Arc;
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+
+/// The buffers of a streaming (de)compression: the chunks in flight between the file I/O and the
+/// CPU pool, and the state of the deflate encoder or decoder
+const STREAM_MEMORY: u64 = 1024 * 1024;
+/// The ratio assumed when estimating how large a decompressed file will be
+pub(crate) const ASSUMED_RATIO: u64 = 4;
+
+/// The memory that the files being processed may reserve, counted in KiB
+#[derive(Debug)]
+pub struct MemoryBudget {
+    slots: Arc<Semaphore>,
+    total: u32,
+}
+
+/// Memory reserved from a budget, released when dropped
+#[derive(Debug)]
+pub struct Reservation {
+    _permit: OwnedSemaphorePermit,
+}
+
+impl MemoryBudget {
+    /// Creates a budget of this many bytes
+    pub fn new(bytes: u64) -> MemoryBudget {
+        let total = (bytes / 1024).clamp(1, Semaphore::MAX_PERMITS.min(u32::MAX as usize) as u64);
+        MemoryBudget {
+            slots: Arc::new(Semaphore::new(total as usize)),
+            total: total as u32,
+        }
+    }
+
+    /// Waits until this many bytes are free, and reserves them
+    pub async fn reserve(&self, bytes: u64) -> Reservation {
+        let kib = bytes.div_ceil(1024).clamp(1, self.total as u64) as u32;
+        let permit = Arc::clone(&self.slots)
+            .acquire_many_owned(kib)
+            .await
+            .expect("the memory budget is never closed");
+        Reservation { _permit: permit }
+    }
+}
+
+/// Estimates the memory needed to compress data of this size with the codec and settings
+fn compress_estimate(codec: Codec, settings: &Settings, input_size: u64) -> u64 {
+    match (codec, settings.gzip_block_size()) {
+        // Each block in flight is held along with its compressed copy, with at most two blocks
+        // per thread in flight
+        (Codec::Gzip, Some(block_size)) => {
+            // A block size of 0 is refused when compressing, but may still be set
+            let block_size = (block_size as u64 * 1024).max(1);
+            let blocks = (2 * settings.num_threads as u64).min(input_size / block_size + 1);
+            2 * block_size * blocks
+        }
+        (Codec::Gzip, None) => STREAM_MEMORY,
+        // LZ4 holds the whole input and output in memory, reading the input into a buffer that
+        // can grow to twice its size
+        (Codec::Lz4, _) => 3 * input_size,
+    }
+}
+
+/// Estimates the memory needed to process a file of this size (0 if unknown) with the settings
+pub fn estimate(settings: &Settings, input_size: u64) -> u64 {
+    let mut memory = match (settings.codec, settings.b_zip) {
+        (codec, true) => compress_estimate(codec, settings, input_size),
+        (Codec::Gzip, false) => STREAM_MEMORY,
+        (Codec::Lz4, false) => (2 + ASSUMED_RATIO) * input_size,
+    };
+    memory += (settings.read_buffer + settings.write_buffer) as u64;
+
+    // Recompressing also compresses the decompressed data as it comes out
+    if let (false, Some(target)) = (settings.b_zip, settings.recompress_to) {
+        memory += compress_estimate(target, settings, ASSUMED_RATIO * input_size);
+    }
+
+    // Output to stdout is held until every earlier file has been written
+    if settings.to_stdout && !settings.test_only {
+        memory += match settings.b_zip {
+            true => input_size,
+            false => ASSUMED_RATIO * input_size,
+        };
+    }
+    memory
+}
@@ -0,0 +1,194 @@
+//! Counters of the files a long-running watch or daemon has processed, served over HTTP in the
+//! Prometheus text format so that monitoring can alert on stalled or failing jobs.
+//!
+//! The server only answers `GET /metrics`, on its own threads, so that a scrape never waits for
+//! the runtime that is busy with the files.
+
+use crate::report::{FileReport, FileStatus};
+use crate::settings::Hooks;
+use std::collections::HashSet;
+use std::io::{BufRead, BufReader, Result, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime};
+
+/// How long a scrape may take to send its request before the connection is dropped
+const TIMEOUT: Duration = Duration::from_secs(10);
+
+/// The counters of a long-running process, updated as its files are done
+#[derive(Debug, Default)]
+pub struct Metrics {
+    files_processed: AtomicU64,
+    files_skipped: AtomicU64,
+    files_failed: AtomicU64,
+    bytes_read: AtomicU64,
+    bytes_written: AtomicU64,
+    /// The files (or jobs) waiting to be processed, as set by the owner
+    queue_depth: AtomicU64,
+    /// When the last file was done, in seconds since the Unix epoch, or 0 if none was yet
+    last_file_done: AtomicU64,
+    /// The files that started being processed and are not done yet
+    in_progress: Mutex<HashSet<PathBuf>>,
+}
+
+impl Metrics {
+    /// Records that a file started being processed
+    pub fn file_started(&self, path: &std::path::Path) {
+        self.in_progress.lock().unwrap().insert(path.to_path_buf());
+    }
+
+    /// Records that a file is done, whether it was processed, skipped, or failed
+    pub fn file_done(&self, report: &FileReport) {
+        self.in_progress.lock().unwrap().remove(&report.path);
+        let counter = match report.status {
+            FileStatus::Processed => {
+                self.bytes_read
+                    .fetch_add(report.input_size, Ordering::Relaxed);
+                self.bytes_written
+                    .fetch_add(report.output_size, Ordering::Relaxed);
+                &self.files_processed
+            }
+            FileStatus::Skipped(_) => &self.files_skipped,
+            FileStatus::Failed(_) => &self.files_failed,
+        };
+        counter.fetch_add(1, Ordering::Relaxed);
+        let now = SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .map_or(0, |duration| duration.as_secs());
+        self.last_file_done.store(now, Ordering::Relaxed);
+    }
+
+    /// Sets the number of files (or jobs) waiting to be processed
+    pub fn set_queue_depth(&self, depth: usize) {
+        self.queue_depth.store(depth as u64, Ordering::Relaxed);
+    }
+
+    /// Adds hooks that record the files of a run to the hooks of its settings, after any hooks
+    /// already there
+    pub fn attach(self: &Arc<Self>, hooks: &mut Hooks) {
+        let (metrics, earlier) = (Arc::clone(self), hooks.on_file_start.take());
+        hooks.on_file_start = Some(Arc::new(move |path| {
+            metrics.file_started(path);
+            if let Some(earlier) = &earlier {
+                earlier(path);
+            }
+        }));
+        let (metrics, earlier) = (Arc::clone(self), hooks.on_file_done.take());
+        hooks.on_file_done = Some(Arc::new(move |report| {
+            metrics.file_done(report);
+            if let Some(earlier) = &earlier {
+                earlier(report);
+            }
+        }));
+    }
+
+    /// Renders the metrics in the Prometheus text exposition format
+    pub fn render(&self) -> String {
+        let in_progress = self.in_progress.lock().unwrap().len() as u64;
+        let metrics = [
+            (
+                "files_processed_total",
+                "counter",
+                "Files compressed or decompressed",
+                self.files_processed.load(Ordering::Relaxed),
+            ),
+            (
+                "files_skipped_total",
+                "counter",
+                "Files skipped, e.g. because their output exists",
+                self.files_skipped.load(Ordering::Relaxed),
+            ),
+            (
+                "files_failed_total",
+                "counter",
+                "Files that could not be processed",
+                self.files_failed.load(Ordering::Relaxed),
+            ),
+            (
+                "bytes_read_total",
+                "counter",
+                "Bytes read from the processed files",
+                self.bytes_read.load(Ordering::Relaxed),
+            ),
+            (
+                "bytes_written_total",
+                "counter",
+                "Bytes written to the outputs of the processed files",
+                self.bytes_written.load(Ordering::Relaxed),
+            ),
+            (
+                "files_in_progress",
+                "gauge",
+                "Files being processed",
+                in_progress,
+            ),
+            (
+                "queue_depth",
+                "gauge",
+                "Files waiting to settle (watch) or jobs waiting to run (daemon)",
+                self.queue_depth.load(Ordering::Relaxed),
+            ),
+            (
+                "last_file_done_timestamp_seconds",
+                "gauge",
+                "When the last file was done, in seconds since the Unix epoch (0 if none was)",
+                self.last_file_done.load(Ordering::Relaxed),
+            ),
+        ];
+        let mut rendered = String::new();
+        for (name, kind, help, value) in metrics {
+            rendered.push_str(&format!(
+                "# HELP super_gunzip_{name} {help}\n# TYPE super_gunzip_{name} {kind}\nsuper_gunzip_{name} {value}\n"
+            ));
+        }
+        rendered
+    }
+
+    /// Answers a single request: the metrics for `GET /metrics`, and 404 for anything else
+    fn respond(&self, stream: TcpStream) -> Result<()> {
+        stream.set_read_timeout(Some(TIMEOUT))?;
+        let mut reader = BufReader::new(stream.try_clone()?);
+        let mut request = String::new();
+        reader.read_line(&mut request)?;
+        // The headers of the request are read and ignored
+        let mut header = String::new();
+        while reader.read_line(&mut header)? > 2 {
+            header.clear();
+        }
+
+        let mut parts = request.split_whitespace();
+        let (status, body) = match (parts.next(), parts.next()) {
+            (Some("GET"), Some("/metrics")) => ("200 OK", self.render()),
+            _ => ("404 Not Found", "Not found: try /metrics\n".to_string()),
+        };
+        let mut stream = stream;
+        write!(
+            stream,
+            "HTTP/1.1 {}\r\nContent-Type: text/plain; version=0.0.4\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+            status,
+            body.len(),
+            body
+        )?;
+        stream.flush()
+    }
+}
+
+/// Serves the metrics at `http://<address>/metrics` from a background thread for as long as the
+/// process runs. Fails right away if the address cannot be bound.
+pub fn serve(metrics: Arc<Metrics>, address: SocketAddr) -> Result<()> {
+    let listener = TcpListener::bind(address)?;
+    std::thread::spawn(move || {
+        for stream in listener.incoming() {
+            match stream {
+                Ok(stream) => {
+                    let metrics = Arc::clone(&metrics);
+                    std::thread::spawn(move || metrics.respond(stream));
+                }
+                Err(e) => eprintln!("Warning: failed to accept a metrics connection: {}", e),
+            }
+        }
+    });
+    Ok(())
+}
@@ -0,0 +1,88 @@
+//! Memory-mapped reading of large input files, which hands the encoder slices of the page cache
+//! instead of issuing a read system call for every buffer.
+//!
+//! A mapped file that is truncated by another process while it is being read makes the program
+//! crash with SIGBUS, so mapping is only done when asked for.
+
+use std::io::{Error, Result};
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use tokio::io::{AsyncRead, ReadBuf};
+
+/// Files smaller than this are read normally, since mapping them costs more than it saves
+pub const MIN_SIZE: u64 = 1024 * 1024;
+
+/// A read-only mapping of a whole file
+struct Mmap {
+    pointer: *mut libc::c_void,
+    length: usize,
+}
+
+// The mapping is read-only and owned by this struct alone
+unsafe impl Send for Mmap {}
+unsafe impl Sync for Mmap {}
+
+impl Mmap {
+    fn map(file: &std::fs::File, length: usize) -> Result<Mmap> {
+        use std::os::unix::io::AsRawFd;
+        let pointer = unsafe {
+            libc::mmap(
+                std::ptr::null_mut(),
+                length,
+                libc::PROT_READ,
+                libc::MAP_PRIVATE,
+                file.as_raw_fd(),
+                0,
+            )
+        };
+        if pointer == libc::MAP_FAILED {
+            return Err(Error::last_os_error());
+        }
+        // The file is read once from start to end, so the kernel may read ahead aggressively.
+        // This is only a hint, so failing to give it is not an error.
+        unsafe { libc::madvise(pointer, length, libc::MADV_SEQUENTIAL) };
+        Ok(Mmap { pointer, length })
+    }
+
+    fn as_slice(&self) -> &[u8] {
+        unsafe { std::slice::from_raw_parts(self.pointer as *const u8, self.length) }
+    }
+}
+
+impl Drop for Mmap {
+    fn drop(&mut self) {
+        unsafe { libc::munmap(self.pointer, self.length) };
+    }
+}
+
+/// Reads a memory-mapped file from start to end
+pub struct MmapReader {
+    map: Mmap,
+    position: usize,
+}
+
+impl MmapReader {
+    /// Maps the whole file, which must not be empty
+    pub fn new(file: &std::fs::File) -> Result<MmapReader> {
+        let length = usize::try_from(file.metadata()?.len()).map_err(Error::other)?;
+        Ok(MmapReader {
+            map: Mmap::map(file, length)?,
+            position: 0,
+        })
+    }
+}
+
+impl AsyncRead for MmapReader {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        _cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<Result<()>> {
+        let position = self.position;
+        let data = &self.map.as_slice()[position..];
+        let read = data.len().min(buf.remaining());
+        buf.put_slice(&data[..read]);
+        self.position += read;
+        Poll::Ready(Ok(()))
+    }
+}
@@ -0,0 +1,295 @@
+//! Minimal terminal progress bars: one overall bar counting finished files, plus a byte-level bar
+//! for each large file currently being processed. Bars are drawn to stderr and redrawn in place.
+//! The same progress is written to the event stream of `--progress-json`, if there is one.
+
+use crate::events::{EventStream, FileProgress};
+use std::collections::BTreeMap;
+use std::io::{IsTerminal, Write};
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll};
+use std::time::{Duration, Instant};
+use tokio::io::{AsyncRead, ReadBuf};
+
+/// Files at least this large get their own byte-level progress bar
+const LARGE_FILE_SIZE: u64 = 64 * 1024 * 1024;
+/// The maximum number of per-file bars shown at once
+const MAX_FILE_BARS: usize = 8;
+const BAR_WIDTH: usize = 30;
+const REDRAW_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Formats a byte count using binary units, e.g. `1.5 MiB`
+pub fn format_bytes(bytes: u64) -> String {
+    const UNITS: [&str; 5] = ["B", "KiB", "MiB", "GiB", "TiB"];
+    let mut value = bytes as f64;
+    let mut unit = 0;
+    while value >= 1024.0 && unit < UNITS.len() - 1 {
+        value /= 1024.0;
+        unit += 1;
+    }
+    if unit == 0 {
+        format!("{} B", bytes)
+    } else {
+        format!("{:.1} {}", value, UNITS[unit])
+    }
+}
+
+/// Parses a byte count with an optional binary unit, e.g. `512`, `64K`, `1.5GiB`, or `2g`
+pub fn parse_size(value: &str) -> Result<u64, String> {
+    let value = value.trim();
+    let split = value
+        .find(|c: char| !c.is_ascii_digit() && c != '.')
+        .unwrap_or(value.len());
+    let (number, unit) = value.split_at(split);
+    let number: f64 = number
+        .parse()
+        .map_err(|_| format!("`{}` is not a size", value))?;
+    let exponent = match unit.trim().to_ascii_lowercase().as_str() {
+        "" | "b" => 0,
+        "k" | "kb" | "kib" => 1,
+        "m" | "mb" | "mib" => 2,
+        "g" | "gb" | "gib" => 3,
+        "t" | "tb" | "tib" => 4,
+        _ => return Err(format!("`{}` is not a size", value)),
+    };
+    Ok((number * 1024f64.powi(exponent)) as u64)
+}
+
+/// Parses a duration with an optional unit, e.g. `90` or `90s`, `30m`, `12h`, `1.5d`, or `2w`
+pub fn parse_duration(value: &str) -> Result<Duration, String> {
+    let value = value.trim();
+    let split = value
+        .find(|c: char| !c.is_ascii_digit() && c != '.')
+        .unwrap_or(value.len());
+    let (number, unit) = value.split_at(split);
+    let number: f64 = number
+        .parse()
+        .map_err(|_| format!("`{}` is not a duration", value))?;
+    let seconds = match unit.trim().to_ascii_lowercase().as_str() {
+        "" | "s" => 1,
+        "m" => 60,
+        "h" => 60 * 60,
+        "d" => 24 * 60 * 60,
+        "w" => 7 * 24 * 60 * 60,
+        _ => return Err(format!("`{}` is not a duration", value)),
+    };
+    Ok(Duration::from_secs_f64(number * seconds as f64))
+}
+
+fn bar(done: u64, total: u64) -> String {
+    let filled = if total == 0 {
+        BAR_WIDTH
+    } else {
+        ((done.min(total) as f64 / total as f64) * BAR_WIDTH as f64) as usize
+    };
+    format!("[{}{}]", "#".repeat(filled), " ".repeat(BAR_WIDTH - filled))
+}
+
+struct FileBar {
+    name: String,
+    done: u64,
+    total: u64,
+}
+
+struct State {
+    total_files: usize,
+    done_files: usize,
+    next_id: usize,
+    files: BTreeMap<usize, FileBar>,
+    /// The progress of every file being read, when it is written to the event stream
+    events_files: BTreeMap<usize, FileProgress>,
+    lines_drawn: usize,
+    last_draw: Option<Instant>,
+}
+
+/// The progress display for a batch of files. All methods are no-ops when it is disabled.
+pub struct Progress {
+    enabled: bool,
+    events: Option<Arc<EventStream>>,
+    state: Mutex<State>,
+}
+
+impl Progress {
+    /// Creates the progress display, which is only enabled when both stdout and stderr are terminals
+    pub fn new(total_files: usize, allowed: bool) -> Progress {
+        Progress {
+            enabled: allowed && std::io::stdout().is_terminal() && std::io::stderr().is_terminal(),
+            events: None,
+            state: Mutex::new(State {
+                total_files,
+                done_files: 0,
+                next_id: 0,
+                files: BTreeMap::new(),
+                events_files: BTreeMap::new(),
+                lines_drawn: 0,
+                last_draw: None,
+            }),
+        }
+    }
+
+    /// Also writes the progress of the files to the event stream, if there is one
+    pub fn with_events(mut self, events: Option<Arc<EventStream>>) -> Progress {
+        self.events = events;
+        self
+    }
+
+    /// Adds files to the total, as they are discovered
+    pub fn add_files(&self, files: usize) {
+        let mut state = self.state.lock().unwrap();
+        state.total_files += files;
+        if self.enabled {
+            self.draw(&mut state, false);
+        }
+    }
+
+    /// Registers a file that started processing, returning the id used to report its progress
+    pub fn start_file(&self, name: String, size: u64) -> usize {
+        let mut state = self.state.lock().unwrap();
+        let id = state.next_id;
+        state.next_id += 1;
+        if let Some(events) = &self.events {
+            let file = events.started(name.clone(), size);
+            state.events_files.insert(id, file);
+        }
+        if self.enabled && size >= LARGE_FILE_SIZE {
+            state.files.insert(
+                id,
+                FileBar {
+                    name,
+                    done: 0,
+                    total: size,
+                },
+            );
+            self.draw(&mut state, true);
+        }
+        id
+    }
+
+    /// Records that `bytes` more bytes of a file were read
+    pub fn advance(&self, id: usize, bytes: u64) {
+        if !self.enabled && self.events.is_none() {
+            return;
+        }
+        let mut state = self.state.lock().unwrap();
+        if let (Some(events), Some(file)) = (&self.events, state.events_files.get_mut(&id)) {
+            events.advance(file, bytes);
+        }
+        if let Some(file) = state.files.get_mut(&id) {
+            file.done += bytes;
+            self.draw(&mut state, false);
+        }
+    }
+
+    /// Records that a file finished (or was skipped), whether or not it was registered
+    pub fn finish_file(&self, id: Option<usize>) {
+        let mut state = self.state.lock().unwrap();
+        if let Some(id) = id {
+            state.files.remove(&id);
+            state.events_files.remove(&id);
+        }
+        state.done_files += 1;
+        if self.enabled {
+            self.draw(&mut state, true);
+        }
+    }
+
+    /// Hides the bars while `f` prints to the terminal, then draws them again
+    pub fn suspend<T>(&self, f: impl FnOnce() -> T) -> T {
+        if !self.enabled {
+            return f();
+        }
+        let mut state = self.state.lock().unwrap();
+        self.clear(&mut state);
+        let result = f();
+        self.draw(&mut state, true);
+        result
+    }
+
+    /// Draws the final state of the overall bar and removes any per-file bars
+    pub fn finish(&self) {
+        if !self.enabled {
+            return;
+        }
+        let mut state = self.state.lock().unwrap();
+        state.files.clear();
+        self.draw(&mut state, true);
+    }
+
+    fn clear(&self, state: &mut State) {
+        let mut stderr = std::io::stderr().lock();
+        for _ in 0..state.lines_drawn {
+            let _ = write!(stderr, "\x1b[1A\x1b[2K");
+        }
+        let _ = stderr.flush();
+        state.lines_drawn = 0;
+    }
+
+    fn draw(&self, state: &mut State, force: bool) {
+        let now = Instant::now();
+        if !force
+            && state
+                .last_draw
+                .is_some_and(|last_draw| now - last_draw < REDRAW_INTERVAL)
+        {
+            return;
+        }
+        self.clear(state);
+
+        let mut lines = vec![format!(
+            "{} {}/{} files",
+            bar(state.done_files as u64, state.total_files as u64),
+            state.done_files,
+            state.total_files
+        )];
+        for file in state.files.values().take(MAX_FILE_BARS) {
+            lines.push(format!(
+                "  {} {} / {} {}",
+                bar(file.done, file.total),
+                format_bytes(file.done),
+                format_bytes(file.total),
+                file.name
+            ));
+        }
+
+        let mut stderr = std::io::stderr().lock();
+        for line in &lines {
+            let _ = writeln!(stderr, "{}", line);
+        }
+        let _ = stderr.flush();
+        state.lines_drawn = lines.len();
+        state.last_draw = Some(now);
+    }
+}
+
+/// A reader that reports the number of bytes read from a file to the progress display
+pub struct ProgressReader<R> {
+    inner: R,
+    progress: Arc<Progress>,
+    id: usize,
+}
+
+impl<R> ProgressReader<R> {
+    pub fn new(inner: R, progress: Arc<Progress>, id: usize) -> ProgressReader<R> {
+        ProgressReader {
+            inner,
+            progress,
+            id,
+        }
+    }
+}
+
+impl<R: AsyncRead + Unpin> AsyncRead for ProgressReader<R> {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<std::io::Result<()>> {
+        let before = buf.filled().len();
+        let poll = Pin::new(&mut self.inner).poll_read(cx, buf);
+        if let Poll::Ready(Ok(())) = poll {
+            self.progress
+                .advance(self.id, (buf.filled().len() - before) as u64);
+        }
+        poll
+    }
+}
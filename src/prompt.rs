@@ -0,0 +1,31 @@
+//! Questions asked on the terminal before destructive steps in interactive mode, like rm -i
+
+use std::io::{BufRead, Write};
+use std::sync::Mutex;
+
+/// Held while a question is asked, so that the questions of files finishing at the same time
+/// are asked one after the other
+static ASKING: Mutex<()> = Mutex::new(());
+
+/// Asks a yes/no question on stderr and reads the answer from stdin. Anything but y or yes,
+/// including the end of stdin, is a no.
+pub async fn confirm(question: String) -> bool {
+    let answer = tokio::task::spawn_blocking(move || {
+        let _asking = ASKING
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+        eprint!("super-gunzip: {} (y/N) ", question);
+        let _ = std::io::stderr().flush();
+        let mut answer = String::new();
+        match std::io::stdin().lock().read_line(&mut answer) {
+            Ok(0) | Err(_) => {
+                eprintln!();
+                String::new()
+            }
+            Ok(_) => answer,
+        }
+    })
+    .await
+    .unwrap_or_default();
+    matches!(answer.trim().to_lowercase().as_str(), "y" | "yes")
+}
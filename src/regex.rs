@@ -0,0 +1,665 @@
+//! A small regular expression engine for searching the lines of compressed files.
+//!
+//! It supports the common syntax of extended regular expressions: literals, `.`, bracket
+//! expressions like `[a-z_]` and `[^0-9]` along with the POSIX classes in them like `[[:digit:]]`
+//! (of ASCII characters), the classes `\d`, `\w`, and `\s` (and their negations `\D`, `\W`, and
+//! `\S`), the anchors `^` and `$`, groups, alternation with `|`, and the
+//! quantifiers `*`, `+`, `?`, and `{n,m}`. Patterns are compiled into an NFA that is simulated
+//! over the text (Thompson's construction), so searching takes time linear in the length of the
+//! text whatever the pattern, without backtracking.
+
+use std::iter::Peekable;
+use std::str::Chars;
+
+/// The most times a bounded repetition like `{n,m}` may repeat, since each repetition is
+/// compiled separately
+const MAX_REPEAT: u32 = 1000;
+/// The most instructions a compiled pattern may have, which nested repetitions multiply
+const MAX_PROGRAM: usize = 1 << 20;
+
+/// A set of characters, given as inclusive ranges
+#[derive(Clone, Debug)]
+struct Class {
+    ranges: Vec<(char, char)>,
+    negated: bool,
+}
+
+impl Class {
+    fn new(ranges: &[(char, char)], negated: bool) -> Class {
+        Class {
+            ranges: ranges.to_vec(),
+            negated,
+        }
+    }
+
+    fn digit() -> Vec<(char, char)> {
+        vec![('0', '9')]
+    }
+
+    fn word() -> Vec<(char, char)> {
+        vec![('a', 'z'), ('A', 'Z'), ('0', '9'), ('_', '_')]
+    }
+
+    fn space() -> Vec<(char, char)> {
+        vec![(' ', ' '), ('\t', '\r')]
+    }
+
+    /// The ranges of a POSIX class like `[:alpha:]`, given its name
+    fn posix(name: &str) -> Option<Vec<(char, char)>> {
+        let ranges = match name {
+            "alpha" => vec![('a', 'z'), ('A', 'Z')],
+            "digit" => Class::digit(),
+            "alnum" => vec![('a', 'z'), ('A', 'Z'), ('0', '9')],
+            "space" => Class::space(),
+            "blank" => vec![(' ', ' '), ('\t', '\t')],
+            "upper" => vec![('A', 'Z')],
+            "lower" => vec![('a', 'z')],
+            "punct" => vec![('!', '/'), (':', '@'), ('[', '`'), ('{', '~')],
+            "xdigit" => vec![('0', '9'), ('a', 'f'), ('A', 'F')],
+            "cntrl" => vec![('\0', '\x1f'), ('\x7f', '\x7f')],
+            "print" => vec![(' ', '~')],
+            "graph" => vec![('!', '~')],
+            _ => return None,
+        };
+        Some(ranges)
+    }
+
+    fn contains(&self, c: char, case_insensitive: bool) -> bool {
+        let in_ranges = |c: char| self.ranges.iter().any(|&(low, high)| low <= c && c <= high);
+        let found = in_ranges(c)
+            || case_insensitive
+                && (c.to_lowercase().any(in_ranges) || c.to_uppercase().any(in_ranges));
+        found != self.negated
+    }
+}
+
+/// A parsed pattern
+#[derive(Clone, Debug)]
+enum Node {
+    Char(char),
+    Any,
+    Class(Class),
+    Start,
+    End,
+    Concat(Vec<Node>),
+    Alternation(Vec<Node>),
+    Repeat {
+        node: Box<Node>,
+        min: u32,
+        max: Option<u32>,
+    },
+}
+
+impl Node {
+    /// The number of instructions the node compiles to
+    fn size(&self) -> usize {
+        match self {
+            Node::Concat(nodes) => nodes.iter().map(Node::size).fold(0, usize::saturating_add),
+            Node::Alternation(branches) => branches
+                .iter()
+                .map(|branch| branch.size().saturating_add(2))
+                .fold(0, usize::saturating_add),
+            Node::Repeat { node, min, max } => {
+                let copies = max.unwrap_or(*min).max(*min) as usize + 1;
+                node.size().saturating_add(2).saturating_mul(copies)
+            }
+            _ => 1,
+        }
+    }
+}
+
+struct Parser<'a> {
+    chars: Peekable<Chars<'a>>,
+}
+
+impl Parser<'_> {
+    fn alternation(&mut self) -> Result<Node, String> {
+        let mut branches = vec![self.concat()?];
+        while self.chars.next_if_eq(&'|').is_some() {
+            branches.push(self.concat()?);
+        }
+        Ok(match branches.len() {
+            1 => branches.pop().unwrap(),
+            _ => Node::Alternation(branches),
+        })
+    }
+
+    fn concat(&mut self) -> Result<Node, String> {
+        let mut nodes = Vec::new();
+        while let Some(&c) = self.chars.peek() {
+            if c == '|' || c == ')' {
+                break;
+            }
+            let atom = self.atom()?;
+            nodes.push(self.quantified(atom)?);
+        }
+        Ok(Node::Concat(nodes))
+    }
+
+    fn quantified(&mut self, mut node: Node) -> Result<Node, String> {
+        loop {
+            let (min, max) = match self.chars.peek() {
+                Some('*') => (0, None),
+                Some('+') => (1, None),
+                Some('?') => (0, Some(1)),
+                Some('{') => {
+                    self.chars.next();
+                    let (min, max) = self.bounds()?;
+                    node = repeat(node, min, max)?;
+                    continue;
+                }
+                _ => return Ok(node),
+            };
+            self.chars.next();
+            // Lazy quantifiers match the same lines, since only whether a line matches matters
+            self.chars.next_if_eq(&'?');
+            node = repeat(node, min, max)?;
+        }
+    }
+
+    fn number(&mut self) -> Option<u32> {
+        let mut digits = String::new();
+        while let Some(digit) = self.chars.next_if(char::is_ascii_digit) {
+            digits.push(digit);
+        }
+        digits.parse().ok()
+    }
+
+    /// Parses the bounds of `{n}`, `{n,}`, or `{n,m}` after the opening brace
+    fn bounds(&mut self) -> Result<(u32, Option<u32>), String> {
+        let min = self.number().ok_or("expected a number after {")?;
+        let max = match self.chars.next_if_eq(&',') {
+            Some(_) => self.number(),
+            None => Some(min),
+        };
+        if self.chars.next() != Some('}') {
+            return Err("unterminated repetition".to_string());
+        }
+        Ok((min, max))
+    }
+
+    fn atom(&mut self) -> Result<Node, String> {
+        let c = self.chars.next().expect("called before the end");
+        Ok(match c {
+            '(' => {
+                // Groups only group, so non-capturing ones are the same
+                if self.chars.next_if_eq(&'?').is_some() && self.chars.next() != Some(':') {
+                    return Err("unsupported group syntax".to_string());
+                }
+                let node = self.alternation()?;
+                if self.chars.next() != Some(')') {
+                    return Err("unclosed group".to_string());
+                }
+                node
+            }
+            '[' => Node::Class(self.class()?),
+            '.' => Node::Any,
+            '^' => Node::Start,
+            '$' => Node::End,
+            '\\' => self.escape()?,
+            '*' | '+' | '?' => return Err(format!("nothing to repeat before {}", c)),
+            c => Node::Char(c),
+        })
+    }
+
+    fn escape(&mut self) -> Result<Node, String> {
+        let c = self.chars.next().ok_or("trailing backslash")?;
+        Ok(match c {
+            'd' => Node::Class(Class::new(&Class::digit(), false)),
+            'D' => Node::Class(Class::new(&Class::digit(), true)),
+            'w' => Node::Class(Class::new(&Class::word(), false)),
+            'W' => Node::Class(Class::new(&Class::word(), true)),
+            's' => Node::Class(Class::new(&Class::space(), false)),
+            'S' => Node::Class(Class::new(&Class::space(), true)),
+            'b' | 'B' | 'A' | 'z' | 'Z' => return Err(format!("\\{} is not supported", c)),
+            c => Node::Char(escaped_char(c)),
+        })
+    }
+
+    /// Parses a bracket expression after the opening bracket
+    fn class(&mut self) -> Result<Class, String> {
+        let negated = self.chars.next_if_eq(&'^').is_some();
+        let mut ranges = Vec::new();
+        let mut first = true;
+        loop {
+            let c = self.chars.next().ok_or("unclosed bracket expression")?;
+            let is_first = std::mem::replace(&mut first, false);
+            let low = match c {
+                // A bracket right after the opening one is part of the set
+                ']' if !is_first => return Ok(Class { ranges, negated }),
+                '\\' => match self.chars.next().ok_or("trailing backslash")? {
+                    'd' => {
+                        ranges.extend(Class::digit());
+                        continue;
+                    }
+                    'w' => {
+                        ranges.extend(Class::word());
+                        continue;
+                    }
+                    's' => {
+                        ranges.extend(Class::space());
+                        continue;
+                    }
+                    class @ ('D' | 'W' | 'S') => {
+                        return Err(format!("\\{} is not supported in brackets", class))
+                    }
+                    c => escaped_char(c),
+                },
+                '[' if self.chars.next_if_eq(&':').is_some() => {
+                    ranges.extend(self.posix_class()?);
+                    continue;
+                }
+                c => c,
+            };
+            let high = match self.chars.peek() {
+                Some('-') => {
+                    self.chars.next();
+                    match self.chars.next() {
+                        // A dash at the end is part of the set
+                        Some(']') => {
+                            ranges.push((low, low));
+                            ranges.push(('-', '-'));
+                            return Ok(Class { ranges, negated });
+                        }
+                        Some('\\') => escaped_char(self.chars.next().ok_or("trailing backslash")?),
+                        Some(high) => high,
+                        None => return Err("unclosed bracket expression".to_string()),
+                    }
+                }
+                _ => low,
+            };
+            if high < low {
+                return Err(format!("invalid range {}-{}", low, high));
+            }
+            ranges.push((low, high));
+        }
+    }
+
+    /// Parses a POSIX class like `[:digit:]` in a bracket expression after its `[:`
+    fn posix_class(&mut self) -> Result<Vec<(char, char)>, String> {
+        let mut name = String::new();
+        loop {
+            match self.chars.next() {
+                Some(':') if self.chars.next_if_eq(&']').is_some() => break,
+                Some(c) if c.is_ascii_alphabetic() => name.push(c),
+                _ => return Err("unclosed character class, e.g. [[:digit:]]".to_string()),
+            }
+        }
+        Class::posix(&name).ok_or_else(|| format!("unknown character class [:{}:]", name))
+    }
+}
+
+fn escaped_char(c: char) -> char {
+    match c {
+        'n' => '\n',
+        't' => '\t',
+        'r' => '\r',
+        c => c,
+    }
+}
+
+fn repeat(node: Node, min: u32, max: Option<u32>) -> Result<Node, String> {
+    if matches!(node, Node::Start | Node::End) {
+        return Err("anchors cannot be repeated".to_string());
+    }
+    if let Some(max) = max.filter(|max| *max < min) {
+        return Err(format!("invalid repetition {{{},{}}}", min, max));
+    }
+    if min.max(max.unwrap_or(0)) > MAX_REPEAT {
+        return Err(format!(
+            "repetitions of more than {} are not supported",
+            MAX_REPEAT
+        ));
+    }
+    Ok(Node::Repeat {
+        node: Box::new(node),
+        min,
+        max,
+    })
+}
+
+/// A step of the compiled NFA
+#[derive(Clone, Debug)]
+enum Inst {
+    Char(char),
+    Any,
+    Class(Class),
+    Start,
+    End,
+    /// Continues at both targets
+    Split(usize, usize),
+    Jump(usize),
+    Match,
+}
+
+/// Compiles nodes into NFA instructions
+struct Compiler {
+    program: Vec<Inst>,
+    case_insensitive: bool,
+}
+
+impl Compiler {
+    fn emit(&mut self, inst: Inst) -> usize {
+        self.program.push(inst);
+        self.program.len() - 1
+    }
+
+    fn compile(&mut self, node: &Node) {
+        match node {
+            Node::Char(c) if self.case_insensitive => {
+                let lower = c.to_lowercase().next().unwrap_or(*c);
+                self.emit(Inst::Char(lower));
+            }
+            Node::Char(c) => {
+                self.emit(Inst::Char(*c));
+            }
+            Node::Any => {
+                self.emit(Inst::Any);
+            }
+            Node::Class(class) => {
+                self.emit(Inst::Class(class.clone()));
+            }
+            Node::Start => {
+                self.emit(Inst::Start);
+            }
+            Node::End => {
+                self.emit(Inst::End);
+            }
+            Node::Concat(nodes) => nodes.iter().for_each(|node| self.compile(node)),
+            Node::Alternation(branches) => {
+                let mut jumps = Vec::new();
+                for (index, branch) in branches.iter().enumerate() {
+                    if index + 1 == branches.len() {
+                        self.compile(branch);
+                        break;
+                    }
+                    let split = self.emit(Inst::Split(0, 0));
+                    self.compile(branch);
+                    jumps.push(self.emit(Inst::Jump(0)));
+                    let next = self.program.len();
+                    self.program[split] = Inst::Split(split + 1, next);
+                }
+                let end = self.program.len();
+                for jump in jumps {
+                    self.program[jump] = Inst::Jump(end);
+                }
+            }
+            Node::Repeat { node, min, max } => {
+                for _ in 0..*min {
+                    self.compile(node);
+                }
+                match max {
+                    // Any number more: loop back to a split before the node
+                    None => {
+                        let split = self.emit(Inst::Split(0, 0));
+                        self.compile(node);
+                        self.emit(Inst::Jump(split));
+                        let end = self.program.len();
+                        self.program[split] = Inst::Split(split + 1, end);
+                    }
+                    // Up to this many more, each of which may be skipped to the end
+                    Some(max) => {
+                        let mut splits = Vec::new();
+                        for _ in *min..*max {
+                            splits.push(self.emit(Inst::Split(0, 0)));
+                            self.compile(node);
+                        }
+                        let end = self.program.len();
+                        for split in splits {
+                            self.program[split] = Inst::Split(split + 1, end);
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// A compiled regular expression
+#[derive(Clone, Debug)]
+pub struct Regex {
+    program: Vec<Inst>,
+    case_insensitive: bool,
+}
+
+impl Regex {
+    /// Compiles the pattern, which matches letters of either case if `case_insensitive`
+    pub fn new(pattern: &str, case_insensitive: bool) -> Result<Regex, String> {
+        let mut parser = Parser {
+            chars: pattern.chars().peekable(),
+        };
+        let node = parser.alternation()?;
+        if parser.chars.next().is_some() {
+            return Err("unmatched )".to_string());
+        }
+        if node.size() > MAX_PROGRAM {
+            return Err("the pattern is too large".to_string());
+        }
+        let mut compiler = Compiler {
+            program: Vec::new(),
+            case_insensitive,
+        };
+        compiler.compile(&node);
+        compiler.emit(Inst::Match);
+        Ok(Regex {
+            program: compiler.program,
+            case_insensitive,
+        })
+    }
+
+    /// Adds the thread at `pc` to the list, following the jumps, splits, and anchors that do not
+    /// consume a character. Returns whether a match was reached.
+    fn add_thread(
+        &self,
+        list: &mut Vec<usize>,
+        marks: &mut [usize],
+        generation: usize,
+        pc: usize,
+        at_start: bool,
+        at_end: bool,
+    ) -> bool {
+        let mut stack = vec![pc];
+        while let Some(pc) = stack.pop() {
+            if marks[pc] == generation {
+                continue;
+            }
+            marks[pc] = generation;
+            match &self.program[pc] {
+                Inst::Match => return true,
+                Inst::Jump(target) => stack.push(*target),
+                Inst::Split(first, second) => {
+                    stack.push(*second);
+                    stack.push(*first);
+                }
+                Inst::Start if at_start => stack.push(pc + 1),
+                Inst::End if at_end => stack.push(pc + 1),
+                Inst::Start | Inst::End => {}
+                _ => list.push(pc),
+            }
+        }
+        false
+    }
+
+    /// Whether the pattern matches anywhere in the text
+    pub fn is_match(&self, text: &str) -> bool {
+        let mut marks = vec![usize::MAX; self.program.len()];
+        let mut current = Vec::new();
+        let mut next = Vec::new();
+        let mut generation = 0;
+        let mut chars = text.chars().peekable();
+        let mut at_start = true;
+        loop {
+            let at_end = chars.peek().is_none();
+            // A match may start at every position
+            if self.add_thread(&mut current, &mut marks, generation, 0, at_start, at_end) {
+                return true;
+            }
+            let Some(c) = chars.next() else {
+                return false;
+            };
+            let folded = match self.case_insensitive {
+                true => c.to_lowercase().next().unwrap_or(c),
+                false => c,
+            };
+            let at_end = chars.peek().is_none();
+            generation += 1;
+            for &pc in &current {
+                let matches = match &self.program[pc] {
+                    Inst::Char(expected) => *expected == folded,
+                    Inst::Any => c != '\n',
+                    Inst::Class(class) => class.contains(c, self.case_insensitive),
+                    _ => false,
+                };
+                if matches
+                    && self.add_thread(&mut next, &mut marks, generation, pc + 1, false, at_end)
+                {
+                    return true;
+                }
+            }
+            std::mem::swap(&mut current, &mut next);
+            next.clear();
+            at_start = false;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn posix_classes_match_like_grep() {
+        let digits = Regex::new("^[[:digit:]]+$", false).unwrap();
+        assert!(digits.is_match("2024"));
+        assert!(!digits.is_match("digit"));
+        let word = Regex::new("[[:upper:]][[:lower:]_]+", false).unwrap();
+        assert!(word.is_match("an Error_code"));
+        assert!(!word.is_match("no capitals"));
+        let hex = Regex::new("^0x[[:xdigit:]]+[^[:alnum:][:space:]]$", false).unwrap();
+        assert!(hex.is_match("0xBEEF;"));
+        assert!(!hex.is_match("0xBEEF "));
+    }
+
+    #[test]
+    fn unknown_or_unclosed_posix_classes_are_refused() {
+        assert!(Regex::new("[[:digits:]]", false).is_err());
+        assert!(Regex::new("[[:digit]", false).is_err());
+    }
+
+    /// Checks which of the lines the pattern matches
+    fn check(pattern: &str, case_insensitive: bool, matching: &[&str], other: &[&str]) {
+        let regex = Regex::new(pattern, case_insensitive).expect(pattern);
+        for text in matching {
+            assert!(regex.is_match(text), "{} should match {:?}", pattern, text);
+        }
+        for text in other {
+            assert!(
+                !regex.is_match(text),
+                "{} should not match {:?}",
+                pattern,
+                text
+            );
+        }
+    }
+
+    #[test]
+    fn alternation_tries_every_branch() {
+        check("cat|dog", false, &["a cat", "hotdog"], &["cow", "ca t"]);
+        check(
+            "^(error|warn):",
+            false,
+            &["error: x", "warn:"],
+            &["an error:", "info:"],
+        );
+        check("a(b|c|)d", false, &["abd", "acd", "ad"], &["aed", "abcd"]);
+        check("^(a|ab)(c|bcd)$", false, &["abcd", "ac", "abc"], &["abd"]);
+        check("x(|y)z", false, &["xz", "xyz"], &["xyyz"]);
+        check("(?:ab|cd)+e", false, &["abcdabe", "cde"], &["abce", "e"]);
+    }
+
+    #[test]
+    fn anchors_match_at_the_ends_only() {
+        check("^abc$", false, &["abc"], &["xabc", "abcx", "ab c"]);
+        check("^$", false, &[""], &[" "]);
+        check("abc$", false, &["xxabc"], &["abcx"]);
+        check("a^b", false, &[], &["ab", "a^b"]);
+        check("(^|,)x", false, &["x", "a,x"], &["ax"]);
+        check("x(,|$)", false, &["x", "x,a"], &["xa"]);
+        check("^", false, &["", "anything"], &[]);
+        check(r"\^\$", false, &["a^$b"], &["ab"]);
+    }
+
+    #[test]
+    fn repetitions_respect_their_bounds() {
+        check("^a{3}$", false, &["aaa"], &["aa", "aaaa"]);
+        check("^a{2,}$", false, &["aa", "aaaaaaaa"], &["a", ""]);
+        check("^a{2,4}$", false, &["aa", "aaa", "aaaa"], &["a", "aaaaa"]);
+        check("^ba{0}c$", false, &["bc"], &["bac"]);
+        check("^(ab){2}$", false, &["abab"], &["ab", "ababab"]);
+        check("^x?y+z*$", false, &["y", "xyy", "yzzz"], &["xx", "xz"]);
+        check("^a+?b*?$", false, &["a", "aabb"], &["b"]);
+        check("^(a*)*b$", false, &["b", "aaab"], &["aaa"]);
+        check("^a**$", false, &["", "aaa"], &["b"]);
+        let (thousand, more) = ("a".repeat(1000), "a".repeat(1001));
+        check("^a{1,1000}$", false, &["a", &thousand], &[&more]);
+
+        // Searching takes linear time, even for patterns that backtracking engines choke on
+        let text = "a".repeat(20_000);
+        assert!(!Regex::new("(a*)*b", false).unwrap().is_match(&text));
+        assert!(!Regex::new("(a|aa)+$x", false).unwrap().is_match(&text));
+    }
+
+    #[test]
+    fn case_folding_applies_to_letters_and_classes() {
+        check("Error", true, &["ERROR", "an error", "eRRoR"], &["err"]);
+        check("Error", false, &["Error"], &["ERROR", "error"]);
+        check("^[a-c]+$", true, &["ABC", "bAc"], &["abd"]);
+        check("^[A-C]+$", true, &["abc"], &["d"]);
+        check("[^a]", true, &["b"], &["a", "A", "aA"]);
+        check("^été$", true, &["ÉTÉ", "Été"], &["ete"]);
+        check("^[[:upper:]]$", true, &["a", "A"], &["1"]);
+        check(r"\d\w", true, &["1A", "2b"], &["AA"]);
+    }
+
+    #[test]
+    fn classes_and_escapes_match_their_characters() {
+        check("^a.c$", false, &["abc", "a c", "aéc"], &["ac", "a\nc"]);
+        check(r"a\.c", false, &["a.c"], &["abc"]);
+        check(r"^\d+\s\w+$", false, &["42 items"], &["42items", "x items"]);
+        check(r"^\D\S\W$", false, &["ab!"], &["1b!", "a !", "abc"]);
+        check(r"\t", false, &["a\tb"], &["atb"]);
+        check("^[]a]+$", false, &["]a]"], &["b"]);
+        check("^[a-]+$", false, &["a-a"], &["b"]);
+        check("^[^-]+$", false, &["abc"], &["a-c"]);
+        check(r"^[\]\\]+$", false, &[r"]\]"], &["a"]);
+        check(r"^[\d\s]+$", false, &["1 2"], &["1a"]);
+        check(r"^[\w]$", false, &["a"], &["]"]);
+        check(r"^[a-c\-x]+$", false, &["a-x"], &["d"]);
+    }
+
+    #[test]
+    fn malformed_patterns_are_refused() {
+        for (pattern, message) in [
+            ("(ab", "unclosed group"),
+            ("ab)", "unmatched )"),
+            ("[abc", "unclosed bracket expression"),
+            ("[a-", "unclosed bracket expression"),
+            ("[z-a]", "invalid range z-a"),
+            ("*a", "nothing to repeat before *"),
+            ("a|+", "nothing to repeat before +"),
+            (r"a\", "trailing backslash"),
+            (r"\bword", r"\b is not supported"),
+            (r"[\D]", r"\D is not supported in brackets"),
+            ("(?=a)", "unsupported group syntax"),
+            ("^*", "anchors cannot be repeated"),
+            ("a{3,2}", "invalid repetition {3,2}"),
+            ("a{x}", "expected a number after {"),
+            ("a{2", "unterminated repetition"),
+            ("a{1001}", "repetitions of more than 1000 are not supported"),
+            ("(a{1000}){1000}", "the pattern is too large"),
+        ] {
+            let error = Regex::new(pattern, false).unwrap_err();
+            assert_eq!(error, message, "{}", pattern);
+        }
+    }
+}
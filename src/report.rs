@@ -0,0 +1,258 @@
+//! Per-file outcomes of a run, along with their machine-readable (JSON and CSV) representations
+
+use crate::gzip_header::{self, GzipInfo};
+use crate::timings::Timings;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+/// What happened to a single discovered path
+#[derive(Debug)]
+pub enum FileStatus {
+    Processed,
+    Skipped(&'static str),
+    Failed(String),
+}
+
+/// The outcome of processing a single discovered path
+#[derive(Debug)]
+pub struct FileReport {
+    pub path: PathBuf,
+    pub output_path: Option<PathBuf>,
+    pub status: FileStatus,
+    pub input_size: u64,
+    pub output_size: u64,
+    pub duration: Duration,
+    /// The output of an earlier file that was reused for this file, because both are hard links to
+    /// the same file or (when deduplicating) have the same contents
+    pub duplicate_of: Option<PathBuf>,
+    /// Whether the reused output was hard-linked rather than copied
+    pub hard_linked: bool,
+    /// The time spent in each stage, if it was recorded
+    pub timings: Option<Timings>,
+}
+
+impl FileReport {
+    pub fn new(path: PathBuf) -> FileReport {
+        FileReport {
+            path,
+            output_path: None,
+            status: FileStatus::Processed,
+            input_size: 0,
+            output_size: 0,
+            duration: Duration::ZERO,
+            duplicate_of: None,
+            hard_linked: false,
+            timings: None,
+        }
+    }
+
+    /// Renders the report as a single-line JSON object
+    pub fn to_json(&self) -> String {
+        let (status, reason, error) = match &self.status {
+            FileStatus::Processed => ("ok", None, None),
+            FileStatus::Skipped(reason) => ("skipped", Some(*reason), None),
+            FileStatus::Failed(error) => ("failed", None, Some(error.as_str())),
+        };
+        let output_path = self.output_path.as_ref().map(|path| path.to_string_lossy());
+        let duplicate_of = self
+            .duplicate_of
+            .as_ref()
+            .map(|path| path.to_string_lossy());
+        let timings = self.timings.map_or_else(String::new, |timings| {
+            format!(",\"timings\":{}", timings.to_json())
+        });
+        format!(
+            "{{\"path\":{},\"output_path\":{},\"status\":\"{}\",\"reason\":{},\"error\":{},\"input_size\":{},\"output_size\":{},\"ratio\":{},\"duration_secs\":{},\"duplicate_of\":{}{}}}",
+            json_string(&self.path.to_string_lossy()),
+            json_optional_string(output_path.as_deref()),
+            status,
+            json_optional_string(reason),
+            json_optional_string(error),
+            self.input_size,
+            self.output_size,
+            json_optional_number(self.ratio()),
+            self.duration.as_secs_f64(),
+            json_optional_string(duplicate_of.as_deref()),
+            timings
+        )
+    }
+
+    /// The output size relative to the input size, if the file was processed
+    pub fn ratio(&self) -> Option<f64> {
+        match self.status {
+            FileStatus::Processed => ratio(self.input_size, self.output_size),
+            _ => None,
+        }
+    }
+}
+
+/// The combined sizes of every successfully processed file in a run
+#[derive(Debug, Default)]
+pub struct Totals {
+    pub files: usize,
+    pub input_size: u64,
+    pub output_size: u64,
+}
+
+impl Totals {
+    pub fn from_reports(reports: &[FileReport]) -> Totals {
+        reports
+            .iter()
+            .filter(|report| matches!(report.status, FileStatus::Processed))
+            .fold(Totals::default(), |totals, report| Totals {
+                files: totals.files + 1,
+                input_size: totals.input_size + report.input_size,
+                output_size: totals.output_size + report.output_size,
+            })
+    }
+
+    /// The total output size relative to the total input size
+    pub fn ratio(&self) -> Option<f64> {
+        ratio(self.input_size, self.output_size)
+    }
+
+    /// The rate at which input was consumed over the run, in megabytes (10^6 bytes) per second
+    pub fn throughput(&self, duration: Duration) -> f64 {
+        let secs = duration.as_secs_f64();
+        if secs > 0.0 {
+            self.input_size as f64 / 1e6 / secs
+        } else {
+            0.0
+        }
+    }
+}
+
+/// The output size relative to the input size, e.g. 0.25 when compressing to a quarter of the
+/// original size. Empty inputs have no meaningful ratio.
+pub fn ratio(input_size: u64, output_size: u64) -> Option<f64> {
+    if input_size == 0 {
+        None
+    } else {
+        Some(output_size as f64 / input_size as f64)
+    }
+}
+
+/// The timings of the reports summed, if any of them were recorded
+pub fn total_timings(reports: &[FileReport]) -> Option<Timings> {
+    reports
+        .iter()
+        .filter_map(|report| report.timings)
+        .reduce(|mut total, timings| {
+            total.add(&timings);
+            total
+        })
+}
+
+/// Renders the reports of a whole run as a single JSON document
+pub fn summary_json(reports: &[FileReport], duration: Duration) -> String {
+    let count = |matches: fn(&FileStatus) -> bool| {
+        reports
+            .iter()
+            .filter(|report| matches(&report.status))
+            .count()
+    };
+    let files: Vec<String> = reports.iter().map(FileReport::to_json).collect();
+    let totals = Totals::from_reports(reports);
+    let timings = total_timings(reports).map_or_else(String::new, |timings| {
+        format!("\"timings\":{},", timings.to_json())
+    });
+    format!(
+        "{{\"processed\":{},\"skipped\":{},\"failed\":{},\"input_size\":{},\"output_size\":{},\"ratio\":{},\"throughput_mb_per_sec\":{},\"duration_secs\":{},{}\"files\":[{}]}}",
+        count(|status| matches!(status, FileStatus::Processed)),
+        count(|status| matches!(status, FileStatus::Skipped(_))),
+        count(|status| matches!(status, FileStatus::Failed(_))),
+        totals.input_size,
+        totals.output_size,
+        json_optional_number(totals.ratio()),
+        totals.throughput(duration),
+        duration.as_secs_f64(),
+        timings,
+        files.join(",")
+    )
+}
+
+/// Renders the reports of a run as CSV for spreadsheets, with a header and one row per file. The
+/// action is what the run did to its files, e.g. `compress`; the error of skipped files is why
+/// they were skipped.
+pub fn csv(reports: &[FileReport], action: &str) -> String {
+    let mut csv =
+        String::from("path,action,input_size,output_size,ratio,duration_secs,status,error\n");
+    for report in reports {
+        let (status, error) = match &report.status {
+            FileStatus::Processed => ("ok", ""),
+            FileStatus::Skipped(reason) => ("skipped", *reason),
+            FileStatus::Failed(error) => ("failed", error.as_str()),
+        };
+        let ratio = report
+            .ratio()
+            .map_or(String::new(), |ratio| format!("{:.4}", ratio));
+        csv.push_str(&format!(
+            "{},{},{},{},{},{:.3},{},{}\n",
+            csv_field(&report.path.to_string_lossy()),
+            action,
+            report.input_size,
+            report.output_size,
+            ratio,
+            report.duration.as_secs_f64(),
+            status,
+            csv_field(error)
+        ));
+    }
+    csv
+}
+
+/// Quotes a CSV field if it holds a comma, quote, or line break, doubling its quotes
+fn csv_field(value: &str) -> String {
+    match value.contains([',', '"', '\n', '\r']) {
+        true => format!("\"{}\"", value.replace('"', "\"\"")),
+        false => value.to_string(),
+    }
+}
+
+/// Renders the metadata of a gzip file as a single-line JSON object
+pub fn list_json(path: &Path, info: &GzipInfo) -> String {
+    let name = info.name.as_ref().map(|name| String::from_utf8_lossy(name));
+    let comment = info
+        .comment
+        .as_ref()
+        .map(|comment| String::from_utf8_lossy(comment));
+    let extra = info.extra.as_deref().map(gzip_header::format_extra);
+    format!(
+        "{{\"path\":{},\"compressed_size\":{},\"uncompressed_size\":{},\"ratio\":{},\"name\":{},\"mtime\":{},\"comment\":{},\"extra\":{}}}",
+        json_string(&path.to_string_lossy()),
+        info.compressed_size,
+        info.uncompressed_size,
+        json_optional_number(ratio(info.uncompressed_size, info.compressed_size)),
+        json_optional_string(name.as_deref()),
+        info.mtime,
+        json_optional_string(comment.as_deref()),
+        json_optional_string(extra.as_deref())
+    )
+}
+
+/// Quotes and escapes a string for use in JSON
+pub fn json_string(value: &str) -> String {
+    let mut quoted = String::with_capacity(value.len() + 2);
+    quoted.push('"');
+    for c in value.chars() {
+        match c {
+            '"' => quoted.push_str("\\\""),
+            '\\' => quoted.push_str("\\\\"),
+            '\n' => quoted.push_str("\\n"),
+            '\r' => quoted.push_str("\\r"),
+            '\t' => quoted.push_str("\\t"),
+            c if (c as u32) < 0x20 => quoted.push_str(&format!("\\u{:04x}", c as u32)),
+            c => quoted.push(c),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
+pub(crate) fn json_optional_string(value: Option<&str>) -> String {
+    value.map_or_else(|| "null".to_string(), json_string)
+}
+
+fn json_optional_number(value: Option<f64>) -> String {
+    value.map_or_else(|| "null".to_string(), |value| value.to_string())
+}
@@ -0,0 +1,561 @@
+//! The settings shared by every file processed in a single run
+
+use crate::adaptive::{self, AdaptiveLevel};
+use crate::backend::Backend;
+use crate::cancel::CancellationToken;
+use crate::codec::{Codec, CpuPool};
+use crate::color::{self, ColorChoice, Status};
+use crate::engine::Engine;
+use crate::events::EventStream;
+use crate::gitignore::GitIgnore;
+use crate::log::{LogLevel, Logger, Value};
+use crate::memory::MemoryBudget;
+use crate::report::FileReport;
+use crate::space::SpaceGuard;
+use crate::throttle::RateLimiter;
+use crate::trash::Trash;
+use clap::ValueEnum;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, OnceLock};
+use std::time::Duration;
+use tokio::sync::Semaphore;
+
+/// The size of the buffers that files are read and written through, larger than the usual 8 KiB
+/// since small requests are slow on network file systems and spinning disks
+pub const DEFAULT_IO_BUFFER_SIZE: usize = 256 * 1024;
+
+/// What to do when the output of a file already exists or is also the output of another file
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CollisionPolicy {
+    Skip,
+    Force,
+    Rename,
+}
+
+/// What to do with symbolic links to files
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SymlinkPolicy {
+    /// Process the file the link points to, deleting only the link afterwards
+    Follow,
+    Skip,
+    /// Replace the link with a link to the output of the file it points to
+    Preserve,
+}
+
+/// What to do with files whose samples show that compressing them would save next to nothing
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Incompressible {
+    /// Compress them like any other file
+    Compress,
+    /// Leave them alone
+    Skip,
+    /// Store their contents in the gzip file without compressing them, which takes next to no
+    /// CPU time. LZ4 files are compressed as usual
+    Store,
+}
+
+/// The order files start being processed in, once all of them are discovered
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Order {
+    /// The biggest files first, so that the file taking the longest does not start last
+    LargestFirst,
+    SmallestFirst,
+    /// By path
+    Name,
+    /// The least recently modified files first
+    Mtime,
+}
+
+/// How the results of a run are printed
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum OutputFormat {
+    Text,
+    Json,
+    Ndjson,
+}
+
+/// How much a run tells about what it does
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Verbosity {
+    /// Only errors, for cron jobs (`-q`)
+    Quiet,
+    /// Warnings and the summary of the run
+    #[default]
+    Normal,
+    /// Also a line for each file as it starts and once it is done (`-v`)
+    Verbose,
+    /// Also the exact bytes, duration, and time in each stage of each file (`-vv`)
+    Detailed,
+    /// Also the settings the run resolved to, and every path that was passed over (`-vvv`)
+    Debug,
+}
+
+impl Verbosity {
+    /// The verbosity of the number of times `-v` was given, or of `-q`
+    pub fn new(verbose: u8, quiet: bool) -> Verbosity {
+        match (quiet, verbose) {
+            (true, _) => Verbosity::Quiet,
+            (false, 0) => Verbosity::Normal,
+            (false, 1) => Verbosity::Verbose,
+            (false, 2) => Verbosity::Detailed,
+            (false, _) => Verbosity::Debug,
+        }
+    }
+}
+
+/// A callback invoked when a file starts being processed
+pub type FileStartHook = Arc<dyn Fn(&Path) + Send + Sync>;
+/// A callback invoked when a file is done
+pub type FileDoneHook = Arc<dyn Fn(&FileReport) + Send + Sync>;
+
+/// Callbacks invoked as files are processed
+#[derive(Clone, Default)]
+pub struct Hooks {
+    /// Called when a file starts being (de)compressed
+    pub on_file_start: Option<FileStartHook>,
+    /// Called when a file is done, whether it was processed, skipped, or failed
+    pub on_file_done: Option<FileDoneHook>,
+}
+
+impl Hooks {
+    pub(crate) fn file_start(&self, path: &Path) {
+        if let Some(hook) = &self.on_file_start {
+            hook(path);
+        }
+    }
+
+    pub(crate) fn file_done(&self, report: &FileReport) {
+        if let Some(hook) = &self.on_file_done {
+            hook(report);
+        }
+    }
+}
+
+impl std::fmt::Debug for Hooks {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Hooks")
+            .field("on_file_start", &self.on_file_start.is_some())
+            .field("on_file_done", &self.on_file_done.is_some())
+            .finish()
+    }
+}
+
+/// The number of CPU cores available to the program, or 1 if it cannot be determined
+pub fn available_threads() -> usize {
+    std::thread::available_parallelism().map_or(1, |threads| threads.get())
+}
+
+/// The settings shared by every file processed in a single run
+#[derive(Debug)]
+pub struct Settings {
+    pub codec: Codec,
+    pub b_zip: bool,
+    /// The codec that the data decompressed with `codec` is compressed with again when
+    /// recompressing, which is only done when not compressing
+    pub recompress_to: Option<Codec>,
+    pub level: Option<u32>,
+    pub block_size: Option<usize>,
+    /// The most bytes each volume of a gzip output may have, splitting outputs into numbered
+    /// volumes that are each a valid gzip file, if set
+    pub split_size: Option<u64>,
+    /// Whether to compress gzip outputs in blocks whose level follows where the run is held up,
+    /// going down while the CPU is the bottleneck and up while I/O is
+    pub adaptive: bool,
+    /// Whether to write gzip outputs in the BGZF format, with an index of their blocks next to
+    /// them for tools that seek into them
+    pub bgzf: bool,
+    /// The size below which files are bundled into a compressed tar archive per directory when
+    /// compressing, instead of being compressed one by one
+    pub batch_small: Option<u64>,
+    /// The most files to put in a bundle, or `None` for all the small files of a directory
+    pub bundle_files: Option<usize>,
+    pub num_threads: usize,
+    /// The maximum number of threads doing CPU-bound (de)compression at once, across all files
+    pub cpu_threads: usize,
+    /// The engine that runs the batch
+    pub engine: Engine,
+    /// Cancels the run from elsewhere, like the caller of the library or the daemon
+    pub cancellation: CancellationToken,
+    /// The most memory, in bytes, that the buffers of the files being processed at once may use
+    pub max_memory: Option<u64>,
+    /// The most files that the files being processed at once may have open, counting the input
+    /// and output of each
+    pub max_open_files: Option<usize>,
+    /// Whether to memory-map large input files instead of reading them (Unix only)
+    pub mmap: bool,
+    /// The most bytes per second that the files of the run may be read and written at, combined
+    pub bwlimit: Option<u64>,
+    /// The size of the buffer that input files are read through
+    pub read_buffer: usize,
+    /// The size of the buffer that outputs are written through
+    pub write_buffer: usize,
+    /// Whether to record the time each file spends reading, (de)compressing, writing, and
+    /// queueing, and print a breakdown at the end of the run
+    pub timings: bool,
+    pub keep_original: bool,
+    /// Where to move originals instead of deleting them, if anywhere
+    pub trash: Option<Trash>,
+    /// Whether to overwrite originals with random data before deleting them
+    pub shred: bool,
+    /// The free space, in bytes, below which files wait before starting, if any
+    pub min_free: Option<u64>,
+    /// How much the run tells about what it does
+    pub verbosity: Verbosity,
+    pub to_stdout: bool,
+    pub recursive: bool,
+    pub max_depth: Option<usize>,
+    /// Files and directories whose name or path matches any of these are left out
+    pub excludes: Vec<glob::Pattern>,
+    /// Whether to leave out the files and directories that git ignores
+    pub respect_gitignore: bool,
+    /// Files smaller than this many bytes are skipped
+    pub min_size: Option<u64>,
+    /// Files larger than this many bytes are skipped
+    pub max_size: Option<u64>,
+    /// Files modified more recently than this long ago are skipped
+    pub older_than: Option<Duration>,
+    /// Files modified longer ago than this are skipped
+    pub newer_than: Option<Duration>,
+    /// Whether to compress files whose contents are already compressed (e.g. PNG or zip files)
+    /// instead of skipping them
+    pub force_compress: bool,
+    /// What to do with files whose samples barely compress when compressing
+    pub incompressible: Incompressible,
+    /// The least share of its size, in percent, that compressing a file must save for its output
+    /// to replace it: otherwise the output is removed and the original kept
+    pub min_savings: f64,
+    pub symlinks: SymlinkPolicy,
+    /// The order to process the files in, or `None` to process them as they are discovered
+    pub order: Option<Order>,
+    pub output_dir: Option<PathBuf>,
+    pub preserve_structure: bool,
+    /// The directory whose tree is mirrored into `output_dir`, the outputs keeping their paths
+    /// relative to it
+    pub mirror_root: Option<PathBuf>,
+    pub dry_run: bool,
+    pub format: OutputFormat,
+    /// When to color the messages
+    pub color: ColorChoice,
+    pub test_only: bool,
+    pub collision: CollisionPolicy,
+    /// Whether to skip files whose output exists and is at least as new as the file, and redo
+    /// the ones whose output is older, regardless of the collision policy
+    pub skip_newer: bool,
+    pub preserve_metadata: bool,
+    pub restore_name: bool,
+    pub store_name: bool,
+    /// Whether to make outputs only depend on the contents and settings, for build systems: no
+    /// name is stored, and the modification time stored is `source_date_epoch` (or 0)
+    pub reproducible: bool,
+    /// The time that reproducible outputs store, in seconds since the Unix epoch, from the
+    /// `SOURCE_DATE_EPOCH` environment variable
+    pub source_date_epoch: Option<u32>,
+    /// The comment to store in the gzip header of the outputs, e.g. the id of the job
+    pub comment: Option<String>,
+    /// The subfields of the extra field to store in the gzip header of the outputs, each a
+    /// two-letter id and its data
+    pub extra: Vec<([u8; 2], Vec<u8>)>,
+    /// The deflate implementation gzip outputs are compressed with
+    pub backend: Backend,
+    /// Whether to flush the gzip stream at points that only depend on the nearby contents, like
+    /// gzip --rsyncable, so that rsync can transfer the output of a changed file efficiently
+    pub rsyncable: bool,
+    /// Whether to decompress each compressed output again and compare it with the original
+    /// before the output is kept (and the original deleted)
+    pub verify: bool,
+    /// Whether to process files with the same contents only once, reusing the output of the
+    /// first one for the others
+    pub dedupe: bool,
+    /// Whether to leave holes in decompressed outputs where they have blocks of zeros, instead
+    /// of writing the zeros
+    pub sparse: bool,
+    /// The number of failed files after which no more files are started, if any
+    pub max_errors: Option<usize>,
+    /// The number of times a file is tried again after a transient error before it fails
+    pub retries: usize,
+    /// Whether to ask on the terminal before replacing an existing output or deleting an
+    /// original file
+    pub interactive: bool,
+    /// The suffix of compressed files, without the leading dot
+    pub suffix: String,
+    /// Whether to print nothing, for embedding in other programs
+    pub quiet: bool,
+    /// The journal that files completed by earlier runs are skipped by, and that completed files
+    /// are recorded in
+    pub journal: Option<PathBuf>,
+    /// The CSV file to write a row per file of the run to, with its sizes, duration, and outcome
+    pub report_csv: Option<PathBuf>,
+    /// Where the structured log events of the run go, if they are logged
+    pub log: Option<Logger>,
+    /// Where the progress events of the files go, for programs that draw their own progress
+    pub progress_json: Option<Arc<EventStream>>,
+    pub hooks: Hooks,
+    /// The pool the CPU-bound work runs on, created on first use
+    cpu_pool: OnceLock<CpuPool>,
+    /// The budget of `max_memory`, created on first use
+    memory_budget: OnceLock<MemoryBudget>,
+    /// The slots of `max_open_files`, created on first use
+    open_files: OnceLock<Arc<Semaphore>>,
+    /// The limiter of `bwlimit`, created on first use
+    rate_limiter: OnceLock<Arc<RateLimiter>>,
+    /// The guard of `min_free`, created on first use
+    space_guard: OnceLock<SpaceGuard>,
+    /// Whether the messages are colored, told on first use
+    colored: OnceLock<bool>,
+    adaptive_level: OnceLock<AdaptiveLevel>,
+    /// The matcher of `respect_gitignore`, created on first use
+    gitignore: OnceLock<GitIgnore>,
+}
+
+impl Settings {
+    /// Creates the default settings for compressing (`b_zip`) or decompressing with the codec:
+    /// one file at a time with one CPU thread per core, originals deleted, outputs written next
+    /// to the inputs
+    pub fn new(codec: Codec, b_zip: bool) -> Settings {
+        Settings {
+            codec,
+            b_zip,
+            recompress_to: None,
+            level: None,
+            block_size: None,
+            split_size: None,
+            adaptive: false,
+            bgzf: false,
+            batch_small: None,
+            bundle_files: None,
+            num_threads: 1,
+            cpu_threads: available_threads(),
+            engine: Engine::default(),
+            cancellation: CancellationToken::new(),
+            max_memory: None,
+            max_open_files: None,
+            mmap: false,
+            bwlimit: None,
+            read_buffer: DEFAULT_IO_BUFFER_SIZE,
+            write_buffer: DEFAULT_IO_BUFFER_SIZE,
+            timings: false,
+            keep_original: false,
+            trash: None,
+            shred: false,
+            min_free: None,
+            verbosity: Verbosity::Normal,
+            to_stdout: false,
+            recursive: false,
+            max_depth: None,
+            excludes: Vec::new(),
+            respect_gitignore: false,
+            min_size: None,
+            max_size: None,
+            older_than: None,
+            newer_than: None,
+            force_compress: false,
+            incompressible: Incompressible::Compress,
+            min_savings: 0.0,
+            symlinks: SymlinkPolicy::Skip,
+            order: None,
+            output_dir: None,
+            preserve_structure: false,
+            mirror_root: None,
+            dry_run: false,
+            format: OutputFormat::Text,
+            color: ColorChoice::Auto,
+            test_only: false,
+            collision: CollisionPolicy::Skip,
+            skip_newer: false,
+            preserve_metadata: true,
+            restore_name: false,
+            store_name: true,
+            reproducible: false,
+            source_date_epoch: None,
+            comment: None,
+            extra: Vec::new(),
+            backend: Backend::default(),
+            rsyncable: false,
+            verify: false,
+            dedupe: false,
+            sparse: true,
+            max_errors: None,
+            retries: 0,
+            interactive: false,
+            suffix: codec.extension().to_string(),
+            quiet: false,
+            journal: None,
+            report_csv: None,
+            log: None,
+            progress_json: None,
+            hooks: Hooks::default(),
+            cpu_pool: OnceLock::new(),
+            memory_budget: OnceLock::new(),
+            open_files: OnceLock::new(),
+            rate_limiter: OnceLock::new(),
+            space_guard: OnceLock::new(),
+            colored: OnceLock::new(),
+            adaptive_level: OnceLock::new(),
+            gitignore: OnceLock::new(),
+        }
+    }
+
+    /// The pool to run CPU-bound work on, with `cpu_threads` threads
+    pub fn cpu_pool(&self) -> &CpuPool {
+        self.cpu_pool.get_or_init(|| CpuPool::new(self.cpu_threads))
+    }
+
+    /// The budget that files reserve their memory from, if `max_memory` is set
+    pub fn memory_budget(&self) -> Option<&MemoryBudget> {
+        let max_memory = self.max_memory?;
+        Some(
+            self.memory_budget
+                .get_or_init(|| MemoryBudget::new(max_memory)),
+        )
+    }
+
+    /// The guard that files wait for space with, if `min_free` is set
+    pub fn space_guard(&self) -> Option<&SpaceGuard> {
+        let min_free = self.min_free?;
+        Some(self.space_guard.get_or_init(|| SpaceGuard::new(min_free)))
+    }
+
+    /// The slots that files take one of for each file they open, if `max_open_files` is set
+    pub fn open_files(&self) -> Option<&Arc<Semaphore>> {
+        let max_open_files = self.max_open_files?;
+        Some(
+            self.open_files
+                .get_or_init(|| Arc::new(Semaphore::new(max_open_files))),
+        )
+    }
+
+    /// The limiter that reads and writes count against, if `bwlimit` is set
+    pub fn rate_limiter(&self) -> Option<&Arc<RateLimiter>> {
+        let bwlimit = self.bwlimit?;
+        Some(
+            self.rate_limiter
+                .get_or_init(|| Arc::new(RateLimiter::new(bwlimit))),
+        )
+    }
+
+    /// The level shared by the blocks of the run when adapting it, which is only done when
+    /// compressing with gzip. It starts at the level of the settings.
+    pub fn adaptive_level(&self) -> Option<&AdaptiveLevel> {
+        if !(self.adaptive && self.b_zip && self.codec == Codec::Gzip) {
+            return None;
+        }
+        let level = self.level.unwrap_or(flate2::Compression::default().level());
+        Some(
+            self.adaptive_level
+                .get_or_init(|| AdaptiveLevel::new(level, 1, Codec::Gzip.max_level())),
+        )
+    }
+
+    /// The matcher of the files that git ignores, if `respect_gitignore` is set
+    pub fn gitignore(&self) -> Option<&GitIgnore> {
+        match self.respect_gitignore {
+            true => Some(self.gitignore.get_or_init(GitIgnore::new)),
+            false => None,
+        }
+    }
+
+    /// Whether the outputs of the run are compressed, i.e. it compresses or recompresses
+    pub fn writes_compressed(&self) -> bool {
+        self.b_zip || self.recompress_to.is_some()
+    }
+
+    /// The size of the volumes the outputs of the run are split into, if they are, which is only
+    /// done when compressing with gzip
+    pub fn volume_size(&self) -> Option<u64> {
+        match (self.b_zip, self.codec) {
+            (true, Codec::Gzip) => self.split_size,
+            _ => None,
+        }
+    }
+
+    /// Whether the outputs of the run are BGZF files, which is only done when compressing with
+    /// gzip
+    pub fn writes_bgzf(&self) -> bool {
+        self.bgzf && self.b_zip && self.codec == Codec::Gzip
+    }
+
+    /// The size in KiB of the blocks gzip outputs are compressed in, if they are: the block size
+    /// that was given, a size that fits several blocks in a volume when splitting, or one that
+    /// gives the level many chances to adapt
+    pub fn gzip_block_size(&self) -> Option<usize> {
+        self.block_size
+            .or_else(|| self.volume_size().map(crate::split::block_size))
+            .or_else(|| self.adaptive_level().map(|_| adaptive::BLOCK_SIZE))
+            .or_else(|| {
+                let whole_buffers = self.backend.whole_buffers();
+                whole_buffers.then_some(crate::archive::DEFAULT_BLOCK_SIZE)
+            })
+    }
+
+    /// Prints a status message, keeping stdout clean when it carries (de)compressed data or
+    /// machine-readable output
+    pub fn report(&self, message: &str) {
+        if self.verbosity == Verbosity::Quiet {
+            return;
+        }
+        self.report_error(message);
+    }
+
+    /// Prints an error, which is printed even with `-q`, unless `quiet`
+    pub fn report_error(&self, message: &str) {
+        if self.quiet {
+            return;
+        }
+        let message = color::paint_prefix(message, self.colored());
+        if self.reports_to_stderr() {
+            eprintln!("{}", message);
+        } else {
+            println!("{}", message);
+        }
+    }
+
+    /// Whether to print a line for each file, i.e. at least `-v`
+    pub fn verbose(&self) -> bool {
+        self.verbosity >= Verbosity::Verbose
+    }
+
+    /// Whether messages are printed to stderr rather than stdout, which carries the data or the
+    /// machine-readable results then
+    fn reports_to_stderr(&self) -> bool {
+        self.to_stdout || self.format != OutputFormat::Text
+    }
+
+    /// Whether the messages are colored, going by `color` and where they are printed
+    pub fn colored(&self) -> bool {
+        *self
+            .colored
+            .get_or_init(|| self.color.enabled(self.reports_to_stderr()))
+    }
+
+    /// The text in the color of the status, if the messages are colored
+    pub fn paint(&self, text: &str, status: Status) -> String {
+        color::paint(text, status, self.colored())
+    }
+
+    /// Logs an event of the run, about the file at the path if given, if logging is enabled
+    pub fn log(
+        &self,
+        level: LogLevel,
+        run: &str,
+        path: Option<&Path>,
+        message: &str,
+        fields: &[(&str, Value)],
+    ) {
+        if let Some(log) = &self.log {
+            log.event(level, run, path, message, fields);
+        }
+    }
+
+    /// Prints a line of machine-readable output, which goes to stderr when stdout carries
+    /// (de)compressed data
+    pub fn emit(&self, line: &str) {
+        if self.quiet {
+            return;
+        }
+        if self.to_stdout {
+            eprintln!("{}", line);
+        } else {
+            println!("{}", line);
+        }
+    }
+}
@@ -0,0 +1,70 @@
+//! Overwriting originals with random data before deleting them, for `--shred`.
+//!
+//! This is best-effort: copy-on-write file systems (btrfs, ZFS, APFS), SSDs that remap their
+//! blocks, snapshots, and backups can all keep the original data after it is overwritten.
+
+use std::io::{Result, Seek, SeekFrom, Write};
+use std::path::Path;
+use std::time::SystemTime;
+
+/// The size of the random data written at once
+const CHUNK_SIZE: usize = 64 * 1024;
+
+/// A fast pseudo-random generator (SplitMix64), which is plenty to bury the data under, since
+/// what matters is that the original bytes are gone rather than that the noise is unpredictable
+struct Noise(u64);
+
+impl Noise {
+    fn new() -> Noise {
+        let nanos = SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .map_or(0, |since| since.as_nanos() as u64);
+        Noise(nanos ^ ((std::process::id() as u64) << 32))
+    }
+
+    fn fill(&mut self, buffer: &mut [u8]) {
+        for chunk in buffer.chunks_mut(8) {
+            self.0 = self.0.wrapping_add(0x9E37_79B9_7F4A_7C15);
+            let mut z = self.0;
+            z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+            z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+            z ^= z >> 31;
+            chunk.copy_from_slice(&z.to_le_bytes()[..chunk.len()]);
+        }
+    }
+}
+
+/// Whether the file has other hard links, whose contents overwriting it would destroy too
+#[cfg(unix)]
+pub fn has_other_links(path: &Path) -> Result<bool> {
+    use std::os::unix::fs::MetadataExt;
+    Ok(std::fs::symlink_metadata(path)?.nlink() > 1)
+}
+
+#[cfg(not(unix))]
+pub fn has_other_links(_path: &Path) -> Result<bool> {
+    Ok(false)
+}
+
+/// Overwrites the file with a pass of random data, flushing it to the disk, and deletes it.
+/// Blocks.
+pub fn shred(path: &Path) -> Result<()> {
+    let mut file = std::fs::OpenOptions::new().write(true).open(path)?;
+    let size = file.metadata()?.len();
+    let mut noise = Noise::new();
+    let mut buffer = vec![0; CHUNK_SIZE];
+    let mut remaining = size;
+    file.seek(SeekFrom::Start(0))?;
+    while remaining > 0 {
+        let length = remaining.min(CHUNK_SIZE as u64) as usize;
+        noise.fill(&mut buffer[..length]);
+        file.write_all(&buffer[..length])?;
+        remaining -= length as u64;
+    }
+    file.sync_all()?;
+    // Leave no trace of the size either
+    file.set_len(0)?;
+    file.sync_all()?;
+    drop(file);
+    std::fs::remove_file(path)
+}
@@ -0,0 +1,160 @@
+//! Keeping a run from filling up the file systems it writes to, for `--min-free`.
+//!
+//! Before a run starts, the space its outputs may need is checked against the free space of each
+//! file system they go to. During the run, a file only starts while its file system has at least
+//! `--min-free` free, and otherwise waits for space to be freed, rather than every file failing
+//! with "no space left on device" in turn.
+
+use crate::progress::format_bytes;
+use std::collections::BTreeMap;
+use std::io::{Error, ErrorKind, Result};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Duration;
+
+/// How often a paused file checks whether there is enough space again
+const RECHECK_INTERVAL: Duration = Duration::from_secs(5);
+
+/// The free space of the file system a path is on
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct FreeSpace {
+    /// The file system, as the device id of its files
+    pub device: u64,
+    /// The bytes that unprivileged users can still write
+    pub free: u64,
+}
+
+/// The nearest ancestor of the path that exists, e.g. for an output directory that is only
+/// created once the first output is written
+fn existing_ancestor(path: &Path) -> &Path {
+    path.ancestors()
+        .find(|ancestor| !ancestor.as_os_str().is_empty() && ancestor.exists())
+        .unwrap_or(Path::new("."))
+}
+
+/// The free space of the file system that the path (or the nearest ancestor that exists) is on.
+/// `None` where this cannot be told (on platforms other than Unix).
+#[cfg(unix)]
+pub fn free_space(path: &Path) -> Result<Option<FreeSpace>> {
+    use std::os::unix::ffi::OsStrExt;
+    use std::os::unix::fs::MetadataExt;
+
+    let path = existing_ancestor(path);
+    let device = std::fs::metadata(path)?.dev();
+    let c_path = std::ffi::CString::new(path.as_os_str().as_bytes())
+        .map_err(|e| Error::new(ErrorKind::InvalidInput, e))?;
+    let mut stat = std::mem::MaybeUninit::<libc::statvfs>::uninit();
+    // SAFETY: the path is NUL-terminated and statvfs fills in the struct it is given
+    if unsafe { libc::statvfs(c_path.as_ptr(), stat.as_mut_ptr()) } != 0 {
+        return Err(Error::last_os_error());
+    }
+    // SAFETY: statvfs succeeded, so it filled in the struct
+    let stat = unsafe { stat.assume_init() };
+    // The fields are narrower than 64 bits on some platforms
+    #[allow(clippy::unnecessary_cast)]
+    let free = (stat.f_bavail as u64).saturating_mul(stat.f_frsize as u64);
+    Ok(Some(FreeSpace { device, free }))
+}
+
+#[cfg(not(unix))]
+pub fn free_space(_path: &Path) -> Result<Option<FreeSpace>> {
+    Ok(None)
+}
+
+/// The space that the outputs going to a file system may need
+#[derive(Debug)]
+pub struct Need {
+    /// A directory that outputs go to on the file system, to name it by
+    pub directory: PathBuf,
+    pub free: u64,
+    pub needed: u64,
+}
+
+/// Sums the space that the outputs may need per file system, given the directory each output
+/// goes to along with the size it may reach. Blocks.
+pub fn needs(outputs: impl IntoIterator<Item = (PathBuf, u64)>) -> Result<Vec<Need>> {
+    let mut needs: BTreeMap<u64, Need> = BTreeMap::new();
+    for (directory, size) in outputs {
+        let Some(space) = free_space(&directory)? else {
+            continue;
+        };
+        needs
+            .entry(space.device)
+            .or_insert(Need {
+                directory,
+                free: space.free,
+                needed: 0,
+            })
+            .needed += size;
+    }
+    Ok(needs.into_values().collect())
+}
+
+/// The error of a file system that already has less than `--min-free` free
+pub fn low_space_error(directory: &Path, free: u64, min_free: u64) -> Error {
+    Error::new(
+        ErrorKind::StorageFull,
+        format!(
+            "only {} is free on the file system of {}, less than --min-free {}",
+            format_bytes(free),
+            directory.to_string_lossy(),
+            format_bytes(min_free)
+        ),
+    )
+}
+
+/// Pauses the files of a run while the file systems they write to are low on space
+#[derive(Debug)]
+pub struct SpaceGuard {
+    min_free: u64,
+    /// The files waiting for space, to tell when the run pauses and resumes
+    waiting: AtomicUsize,
+}
+
+impl SpaceGuard {
+    pub fn new(min_free: u64) -> SpaceGuard {
+        SpaceGuard {
+            min_free,
+            waiting: AtomicUsize::new(0),
+        }
+    }
+
+    /// Waits until the file system of the directory has at least `--min-free` free, checking
+    /// again every few seconds, or until `stop` finishes. `report` is told when the run pauses
+    /// and resumes.
+    pub async fn wait(
+        &self,
+        directory: &Path,
+        stop: impl std::future::Future<Output = ()>,
+        report: impl Fn(&str),
+    ) -> Result<()> {
+        let check = |directory: PathBuf| async move {
+            tokio::task::spawn_blocking(move || free_space(&directory)).await?
+        };
+        let free = match check(directory.to_path_buf()).await? {
+            Some(space) if space.free < self.min_free => space.free,
+            _ => return Ok(()),
+        };
+        if self.waiting.fetch_add(1, Ordering::SeqCst) == 0 {
+            report(&format!(
+                "Paused: {}; waiting for space to be freed",
+                low_space_error(directory, free, self.min_free)
+            ));
+        }
+        tokio::pin!(stop);
+        let (result, resumed) = loop {
+            tokio::select! {
+                () = tokio::time::sleep(RECHECK_INTERVAL) => {}
+                () = &mut stop => break (Ok(()), false),
+            }
+            match check(directory.to_path_buf()).await {
+                Ok(Some(space)) if space.free < self.min_free => {}
+                result => break (result.map(|_| ()), true),
+            }
+        };
+        if self.waiting.fetch_sub(1, Ordering::SeqCst) == 1 && resumed {
+            report("Resumed: there is enough free space again");
+        }
+        result
+    }
+}
@@ -0,0 +1,240 @@
+//! Keeping sparse files sparse: runs of zeros in decompressed outputs are skipped over instead of
+//! written, leaving holes that take no space on disk, and the holes of inputs are not read at all
+//! on systems that can report where they are.
+
+use std::collections::VecDeque;
+use std::io::{Error, ErrorKind, Result, SeekFrom};
+use std::pin::Pin;
+use std::task::{ready, Context, Poll};
+use tokio::fs::File as AsyncFile;
+use tokio::io::{AsyncRead, AsyncSeek, AsyncWrite, ReadBuf};
+
+/// Only whole blocks of zeros this large are skipped over, since file systems allocate space in
+/// blocks and smaller holes would save nothing
+pub const BLOCK_SIZE: usize = 4096;
+
+fn is_zero(block: &[u8]) -> bool {
+    block.iter().all(|&byte| byte == 0)
+}
+
+/// A writer that seeks over the blocks of zeros it is given instead of writing them, so that the
+/// file it writes to gets holes there
+pub struct SparseWriter<W> {
+    inner: W,
+    /// The zeros skipped since the last write, which the file position still has to move past
+    hole: u64,
+    /// Whether a seek past the hole has been started and not completed yet
+    seeking: bool,
+    /// Whether the last byte of a hole at the end still has to be written, to give the file its
+    /// full length
+    last_byte: bool,
+}
+
+impl<W> SparseWriter<W> {
+    pub fn new(inner: W) -> SparseWriter<W> {
+        SparseWriter {
+            inner,
+            hole: 0,
+            seeking: false,
+            last_byte: false,
+        }
+    }
+}
+
+impl<W: AsyncWrite + AsyncSeek + Unpin> SparseWriter<W> {
+    /// Moves the file position past the zeros skipped so far
+    fn poll_skip_hole(&mut self, cx: &mut Context<'_>) -> Poll<Result<()>> {
+        if self.hole > 0 && !self.seeking {
+            // Seeks can only start once the writes before them are done
+            ready!(Pin::new(&mut self.inner).poll_complete(cx))?;
+            let hole =
+                i64::try_from(self.hole).map_err(|_| Error::from(ErrorKind::InvalidInput))?;
+            Pin::new(&mut self.inner).start_seek(SeekFrom::Current(hole))?;
+            self.hole = 0;
+            self.seeking = true;
+        }
+        if self.seeking {
+            ready!(Pin::new(&mut self.inner).poll_complete(cx))?;
+            self.seeking = false;
+        }
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl<W: AsyncWrite + AsyncSeek + Unpin> AsyncWrite for SparseWriter<W> {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<Result<usize>> {
+        let zero_blocks = buf
+            .chunks_exact(BLOCK_SIZE)
+            .take_while(|block| is_zero(block))
+            .count();
+        if zero_blocks > 0 {
+            self.hole += (zero_blocks * BLOCK_SIZE) as u64;
+            return Poll::Ready(Ok(zero_blocks * BLOCK_SIZE));
+        }
+
+        // Write up to the next block of zeros, which the next call skips
+        ready!(self.poll_skip_hole(cx))?;
+        let data_len = buf
+            .chunks_exact(BLOCK_SIZE)
+            .position(is_zero)
+            .map_or(buf.len(), |blocks| blocks * BLOCK_SIZE);
+        Pin::new(&mut self.inner).poll_write(cx, &buf[..data_len])
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<()>> {
+        Pin::new(&mut self.inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<()>> {
+        // Seeking past the end does not make the file longer, writing its last byte does
+        if self.hole > 0 && !self.last_byte {
+            self.hole -= 1;
+            self.last_byte = true;
+        }
+        ready!(self.poll_skip_hole(cx))?;
+        if self.last_byte {
+            if ready!(Pin::new(&mut self.inner).poll_write(cx, &[0]))? == 0 {
+                return Poll::Ready(Err(ErrorKind::WriteZero.into()));
+            }
+            self.last_byte = false;
+        }
+        Pin::new(&mut self.inner).poll_shutdown(cx)
+    }
+}
+
+/// The regions of the file that hold data, as start and end offsets in order, or `None` if the
+/// file has no holes or the system cannot tell where they are. Leaves the file position at the
+/// start.
+#[cfg(any(
+    target_os = "linux",
+    target_os = "android",
+    target_os = "freebsd",
+    target_os = "macos"
+))]
+fn data_extents(file: &std::fs::File, size: u64) -> Option<VecDeque<(u64, u64)>> {
+    use std::os::unix::io::AsRawFd;
+    let fd = file.as_raw_fd();
+    let seek = |offset: u64, whence| {
+        let offset = libc::off_t::try_from(offset).ok()?;
+        let result = unsafe { libc::lseek(fd, offset, whence) };
+        u64::try_from(result).ok()
+    };
+    let mut extents = VecDeque::new();
+    let mut offset = 0;
+    // There is no data after the last hole when seeking for data fails
+    while offset < size {
+        let Some(start) = seek(offset, libc::SEEK_DATA) else {
+            break;
+        };
+        let end = seek(start, libc::SEEK_HOLE)?;
+        extents.push_back((start, end));
+        offset = end;
+    }
+    seek(0, libc::SEEK_SET)?;
+    let dense = extents.len() == 1 && extents[0] == (0, size);
+    (!dense).then_some(extents)
+}
+
+#[cfg(not(any(
+    target_os = "linux",
+    target_os = "android",
+    target_os = "freebsd",
+    target_os = "macos"
+)))]
+fn data_extents(_file: &std::fs::File, _size: u64) -> Option<VecDeque<(u64, u64)>> {
+    None
+}
+
+/// A reader of a file with holes, which returns zeros for the holes instead of reading them
+pub struct SparseReader {
+    file: AsyncFile,
+    extents: VecDeque<(u64, u64)>,
+    size: u64,
+    position: u64,
+    /// The offset of the data after the hole just returned, which the file still has to seek to
+    seek_to: Option<u64>,
+    /// Whether a seek to the next data has been started and not completed yet
+    seeking: bool,
+}
+
+impl SparseReader {
+    /// Reads the file through its holes if it has any, returning it back otherwise
+    pub async fn new(file: AsyncFile, size: u64) -> std::result::Result<SparseReader, AsyncFile> {
+        let file = file.into_std().await;
+        match data_extents(&file, size) {
+            Some(extents) => Ok(SparseReader {
+                file: AsyncFile::from_std(file),
+                extents,
+                size,
+                position: 0,
+                seek_to: None,
+                seeking: false,
+            }),
+            None => Err(AsyncFile::from_std(file)),
+        }
+    }
+
+    /// Returns zeros for the hole up to the given offset, seeking to it once the hole is done
+    fn fill_hole(&mut self, buf: &mut ReadBuf<'_>, end: u64) {
+        let len = (end - self.position).min(buf.remaining() as u64) as usize;
+        buf.initialize_unfilled_to(len)[..len].fill(0);
+        buf.advance(len);
+        self.position += len as u64;
+        if self.position == end && end < self.size {
+            self.seek_to = Some(end);
+        }
+    }
+}
+
+impl AsyncRead for SparseReader {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<Result<()>> {
+        if let Some(offset) = self.seek_to {
+            // Seeks can only start once the reads before them are done
+            ready!(Pin::new(&mut self.file).poll_complete(cx))?;
+            Pin::new(&mut self.file).start_seek(SeekFrom::Start(offset))?;
+            self.seek_to = None;
+            self.seeking = true;
+        }
+        if self.seeking {
+            ready!(Pin::new(&mut self.file).poll_complete(cx))?;
+            self.seeking = false;
+        }
+        let position = self.position;
+        while self
+            .extents
+            .front()
+            .is_some_and(|&(_, end)| end <= position)
+        {
+            self.extents.pop_front();
+        }
+        match self.extents.front() {
+            // Reads may run past the end of the data into the next hole, which is harmless since
+            // holes read as zeros
+            Some(&(start, _)) if start <= position => {
+                let before = buf.filled().len();
+                ready!(Pin::new(&mut self.file).poll_read(cx, buf))?;
+                self.position += (buf.filled().len() - before) as u64;
+                Poll::Ready(Ok(()))
+            }
+            Some(&(start, _)) => {
+                self.fill_hole(buf, start);
+                Poll::Ready(Ok(()))
+            }
+            None => {
+                let size = self.size;
+                if position < size {
+                    self.fill_hole(buf, size);
+                }
+                Poll::Ready(Ok(()))
+            }
+        }
+    }
+}
@@ -0,0 +1,172 @@
+//! Splitting compressed outputs into numbered volumes of at most a given size, e.g. to fit the
+//! size limit of an upload or a storage medium, and finding the volumes again to join them.
+//!
+//! Inputs are compressed in blocks when splitting, and every volume ends at the end of a gzip
+//! member, so each volume is a valid gzip file by itself and the volumes joined in order are a
+//! valid multi-member gzip file of the whole input.
+
+use crate::batch::{copy_metadata, temp_path};
+use crate::codec;
+use crate::concat;
+use crate::error::SuperGzipError;
+use crate::settings::Settings;
+use std::io::{Read, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+
+/// The smallest volume size, which still fits several of the blocks the input is compressed in
+pub const MIN_SPLIT_SIZE: u64 = 64 * 1024;
+
+/// The size in KiB of the blocks inputs are compressed in when splitting without a block size
+const DEFAULT_BLOCK_SIZE: usize = 128;
+
+/// The block size in KiB to compress inputs in when splitting them into volumes of this many
+/// bytes: small enough that a volume fits several blocks even of incompressible data, so that
+/// little of it is left unused
+pub fn block_size(split_size: u64) -> usize {
+    DEFAULT_BLOCK_SIZE.min((split_size / 8 / 1024) as usize)
+}
+
+/// The path of a volume of the output, numbered from 0, e.g. `file.gz.000`
+pub fn volume_path(output_path: &Path, index: usize) -> PathBuf {
+    let mut path = output_path.as_os_str().to_owned();
+    path.push(format!(".{:03}", index));
+    path.into()
+}
+
+/// The output that a volume is part of, if the path looks like a volume, e.g. `file.gz` for
+/// `file.gz.001`
+pub fn volume_base(path: &Path) -> Option<PathBuf> {
+    let name = path.file_name()?.to_str()?;
+    let (base, number) = name.rsplit_once('.')?;
+    if base.is_empty() || number.len() < 3 || !number.bytes().all(|byte| byte.is_ascii_digit()) {
+        return None;
+    }
+    Some(path.with_file_name(base))
+}
+
+/// The volumes of the output that exist, in order, stopping at the first missing number
+pub fn volumes(output_path: &Path) -> Vec<PathBuf> {
+    (0..)
+        .map(|index| volume_path(output_path, index))
+        .take_while(|path| path.is_file())
+        .collect()
+}
+
+/// Groups the gzip members ending at these offsets into volumes of at most `split_size` bytes,
+/// returning the offset each volume ends at. A member larger than a volume gets one to itself.
+fn volume_ends(member_ends: &[u64], split_size: u64) -> Vec<u64> {
+    let mut ends = Vec::new();
+    let mut start = 0;
+    let mut end = 0;
+    for &member_end in member_ends {
+        if member_end - start > split_size && end > start {
+            ends.push(end);
+            start = end;
+        }
+        end = member_end;
+    }
+    ends.push(end);
+    ends
+}
+
+/// A volume written to its temporary path, to be moved into place once all volumes are written
+#[derive(Debug)]
+pub struct Volume {
+    pub path: PathBuf,
+    pub temp_path: PathBuf,
+    pub size: u64,
+}
+
+/// Splits the finished gzip file at the path into volumes of at most `split_size` bytes, which
+/// are numbered after the output path but written to temporary paths. The file is left alone.
+/// Blocks, so it is meant to run on the CPU pool.
+pub fn split_file(
+    path: &Path,
+    output_path: &Path,
+    split_size: u64,
+) -> std::io::Result<Vec<Volume>> {
+    let member_ends = codec::gzip_member_ends(path)?;
+    let mut input = std::fs::File::open(path)?;
+    let metadata = input.metadata()?;
+    let mut volumes = Vec::new();
+    let mut start = 0;
+    for (index, end) in volume_ends(&member_ends, split_size)
+        .into_iter()
+        .enumerate()
+    {
+        let path = volume_path(output_path, index);
+        let volume = Volume {
+            temp_path: temp_path(&path),
+            path,
+            size: end - start,
+        };
+        // Each volume gets the timestamps and permissions the output was given
+        let written = (|| {
+            let mut output = std::fs::File::create(&volume.temp_path)?;
+            input.seek(SeekFrom::Start(start))?;
+            std::io::copy(&mut (&mut input).take(volume.size), &mut output)?;
+            output.set_times(
+                std::fs::FileTimes::new()
+                    .set_accessed(metadata.accessed()?)
+                    .set_modified(metadata.modified()?),
+            )?;
+            output.set_permissions(metadata.permissions())
+        })();
+        volumes.push(volume);
+        if let Err(e) = written {
+            for volume in &volumes {
+                let _ = std::fs::remove_file(&volume.temp_path);
+            }
+            return Err(e);
+        }
+        start = end;
+    }
+    Ok(volumes)
+}
+
+/// Joins the volumes of the output back into it, after checking all of them, and deletes them
+/// unless they are kept. The output gets the timestamps and permissions of the first volume.
+/// Returns the number of volumes joined and the size of the output, or the errors of the volumes
+/// that could not be joined, which are reported as they are found.
+pub async fn join(
+    output_path: &Path,
+    keep: bool,
+    settings: &Settings,
+) -> Result<(usize, u64), Vec<SuperGzipError>> {
+    let volumes = volumes(output_path);
+    if volumes.is_empty() {
+        let e = SuperGzipError::IO(std::io::Error::new(
+            std::io::ErrorKind::NotFound,
+            "the first volume is missing",
+        ));
+        settings.report(&format!("{}: {}", output_path.to_string_lossy(), e));
+        return Err(vec![e]);
+    }
+    let (members, errors) = concat::check(volumes.clone(), output_path, settings).await;
+    if !errors.is_empty() {
+        settings.report(&format!(
+            "Nothing written to {}.",
+            output_path.to_string_lossy()
+        ));
+        return Err(errors);
+    }
+
+    let joined = async {
+        let written = concat::join(&members, output_path, false, settings).await?;
+        let (first, output) = (volumes[0].clone(), output_path.to_path_buf());
+        tokio::task::spawn_blocking(move || copy_metadata(&first, &output)).await??;
+        if !keep {
+            for volume in &volumes {
+                tokio::fs::remove_file(volume).await?;
+            }
+        }
+        Ok::<_, SuperGzipError>(written)
+    };
+    match joined.await {
+        Ok(written) => Ok((volumes.len(), written)),
+        Err(e) => {
+            settings.report(&format!("{}: {}", output_path.to_string_lossy(), e));
+            Err(vec![e])
+        }
+    }
+}
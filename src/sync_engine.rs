@@ -0,0 +1,609 @@
+//! The sync engine, built with the `sync-engine` cargo feature, which runs a batch without an
+//! async runtime: the files are processed on a pool of `--num-threads` plain threads, each one read,
+//! (de)compressed, and written with blocking I/O by the thread that picked it up.
+//!
+//! It covers the everyday runs: compressing, decompressing, and testing files, next to them or
+//! into an output directory, with the filters, `--force`, the text, JSON, and NDJSON output, the
+//! logs, and the hooks of the async engine, but without progress bars or sparse outputs. The
+//! options that only the async engine supports are refused before anything is done, naming the
+//! first one that is set.
+
+use crate::batch::{
+    self, collision_warning, copy_metadata, finished_line, output_path, record_done, saves_enough,
+    skipped_line, status_line, temp_path,
+};
+use crate::bundle;
+use crate::cancel::CancellationToken;
+use crate::codec::{stores_incompressible, transcode_blocking};
+use crate::color::Status;
+use crate::discovery::{self, decompressed_name, is_excluded, list_dir, SkipReason};
+use crate::entropy;
+use crate::error::{Operation, SuperGzipError};
+use crate::features;
+use crate::http;
+use crate::log::{self, LogLevel};
+use crate::magic;
+use crate::progress::format_bytes;
+use crate::report::{self, FileReport, FileStatus};
+use crate::settings::{
+    CollisionPolicy, Incompressible, OutputFormat, Settings, SymlinkPolicy, Verbosity,
+};
+use std::collections::HashSet;
+use std::ffi::OsString;
+use std::io::{BufReader, BufWriter, Error, ErrorKind, Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Instant;
+
+/// The first option of the run that only the async engine supports, as it is given on the
+/// command line, if any is set
+fn unsupported_option(settings: &Settings, patterns: &[OsString]) -> Option<&'static str> {
+    let is_url = |pattern: &OsString| pattern.to_str().is_some_and(http::is_url);
+    let options = [
+        (patterns == ["-"], "reading stdin"),
+        (patterns.iter().any(is_url), "URLs"),
+        (settings.recompress_to.is_some(), "recompress"),
+        (settings.to_stdout, "--stdout"),
+        (settings.dry_run, "--dry-run"),
+        (settings.block_size.is_some(), "--block-size"),
+        (settings.split_size.is_some(), "--split-size"),
+        (settings.adaptive, "--adaptive"),
+        (settings.bgzf, "--bgzf"),
+        (
+            settings.b_zip && settings.backend.whole_buffers(),
+            "--backend",
+        ),
+        (settings.batch_small.is_some(), "--batch-small"),
+        (settings.bwlimit.is_some(), "--bwlimit"),
+        (settings.trash.is_some(), "--trash"),
+        (settings.shred, "--shred"),
+        (settings.min_free.is_some(), "--min-free"),
+        (settings.journal.is_some(), "--resume"),
+        (settings.verify, "--verify"),
+        (settings.dedupe, "--dedupe"),
+        (settings.order.is_some(), "--order"),
+        (settings.interactive, "--interactive"),
+        (settings.collision == CollisionPolicy::Rename, "--rename"),
+        (settings.skip_newer, "--skip-newer"),
+        (settings.restore_name, "--restore-name"),
+        (
+            settings.symlinks == SymlinkPolicy::Preserve,
+            "--preserve-symlinks",
+        ),
+        (settings.retries > 0, "--retries"),
+        (settings.timings, "--timings"),
+    ];
+    options
+        .into_iter()
+        .find_map(|(set, option)| set.then_some(option))
+}
+
+/// The paths found so far, each only once and only if it is not excluded, along with the
+/// directories that could not be read
+struct Found<'a> {
+    settings: &'a Settings,
+    seen: HashSet<PathBuf>,
+    paths: Vec<PathBuf>,
+    errors: Vec<SuperGzipError>,
+}
+
+impl Found<'_> {
+    fn path(&mut self, path: PathBuf) {
+        if !is_excluded(&path, self.settings) && self.seen.insert(path.clone()) {
+            self.paths.push(path);
+        }
+    }
+
+    /// Walks a directory tree depth-first in name order, like the async engine, without
+    /// following symlinks to directories or entering excluded ones
+    fn walk_dir(&mut self, root: PathBuf) {
+        let mut stack = vec![(root, 1)];
+        while let Some((dir, depth)) = stack.pop() {
+            let (files, dirs) = match list_dir(&dir) {
+                Ok(listing) => listing,
+                Err(e) => {
+                    self.errors
+                        .push(SuperGzipError::IO(e).for_file(&dir, Operation::Read));
+                    continue;
+                }
+            };
+            for file in files {
+                self.path(file);
+            }
+
+            // Push subdirectories in reverse so they are popped in name order
+            if self
+                .settings
+                .max_depth
+                .is_none_or(|max_depth| depth < max_depth)
+            {
+                stack.extend(
+                    dirs.into_iter()
+                        .rev()
+                        .filter(|dir| !is_excluded(dir, self.settings))
+                        .map(|dir| (dir, depth + 1)),
+                );
+            }
+        }
+    }
+
+    /// Finds the paths the patterns expand to, in order, walking matched directories when
+    /// recursing
+    fn patterns(&mut self, patterns: &[OsString]) {
+        for pattern in patterns {
+            let matches: Vec<PathBuf> = match pattern.to_str() {
+                Some(pattern) => glob::glob(pattern)
+                    .expect("Invalid glob pattern provided. Please check your input.")
+                    .flatten()
+                    .collect(),
+                // Patterns that are not valid UTF-8 cannot be globs, so they name a path as it is
+                None if std::fs::symlink_metadata(pattern).is_ok() => vec![PathBuf::from(pattern)],
+                None => Vec::new(),
+            };
+            if matches.is_empty() {
+                self.errors.extend(discovery::missing_input(pattern));
+            }
+            for path in matches {
+                if self.settings.recursive && !is_excluded(&path, self.settings) && path.is_dir() {
+                    self.walk_dir(path);
+                } else {
+                    self.path(path);
+                }
+            }
+        }
+    }
+}
+
+/// Decides whether a discovered path should be processed, like [`discovery::skip_reason`],
+/// returning why it is skipped otherwise
+fn skip_reason(path: &Path, settings: &Settings) -> Option<SkipReason> {
+    let metadata = match std::fs::metadata(path) {
+        Ok(metadata) if metadata.is_file() => metadata,
+        _ => return Some(SkipReason::NotAFile),
+    };
+    let is_symlink =
+        std::fs::symlink_metadata(path).is_ok_and(|metadata| metadata.file_type().is_symlink());
+    if settings.symlinks == SymlinkPolicy::Skip && is_symlink {
+        return Some(SkipReason::Symlink);
+    }
+    let is_compressed = decompressed_name(path, settings).is_some();
+    if settings.b_zip && is_compressed {
+        return Some(SkipReason::AlreadyCompressed);
+    }
+    let is_manifest = path
+        .extension()
+        .is_some_and(|extension| extension == bundle::MANIFEST_EXTENSION)
+        && path.with_extension("").is_file();
+    if settings.b_zip && is_manifest {
+        return Some(SkipReason::BundleManifest);
+    }
+    // Unreadable files are left for processing to report
+    let format = magic::sniff_file(path).ok().flatten();
+    if !settings.b_zip && !is_compressed && format != Some(settings.codec.format()) {
+        return Some(SkipReason::NotCompressed);
+    }
+    if let Some(reason) = discovery::filter(&metadata, settings) {
+        return Some(reason);
+    }
+    if settings.b_zip && !settings.force_compress && format.is_some() {
+        return Some(SkipReason::CompressedContent);
+    }
+    if settings.b_zip
+        && settings.incompressible == Incompressible::Skip
+        && entropy::is_incompressible(path).unwrap_or(false)
+    {
+        return Some(SkipReason::Incompressible);
+    }
+    None
+}
+
+/// Picks the output path of a file given the outputs already claimed by earlier files, like
+/// [`batch::claim_output`] without renaming, warning about the files it skips
+fn claim_output(
+    path: &Path,
+    settings: &Settings,
+    claimed: &mut HashSet<PathBuf>,
+) -> Result<PathBuf, SkipReason> {
+    let output_path = output_path(path, settings);
+    let reason = if claimed.contains(&output_path) {
+        SkipReason::OutputCollision
+    } else if settings.collision != CollisionPolicy::Force
+        && std::fs::symlink_metadata(&output_path).is_ok()
+    {
+        SkipReason::OutputExists
+    } else {
+        claimed.insert(output_path.clone());
+        return Ok(output_path);
+    };
+    settings.report(&collision_warning(path, reason, &output_path));
+    Err(reason)
+}
+
+/// Where the (de)compressed version of a file ended up
+enum Output {
+    /// The output file and its size
+    File(PathBuf, u64),
+    /// The data was only decompressed to check its integrity, producing this many bytes
+    Discarded(u64),
+    /// The output saved too little to replace the original, so it was removed
+    NotCompressed,
+}
+
+/// Reads the input until the run is cancelled, and then fails, abandoning the file
+struct Cancellable<'a, R> {
+    inner: R,
+    cancellation: &'a CancellationToken,
+}
+
+impl<R: Read> Read for Cancellable<'_, R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        // Not ErrorKind::Interrupted, which readers retry
+        if self.cancellation.abandoning() {
+            return Err(Error::other("cancelled"));
+        }
+        self.inner.read(buf)
+    }
+}
+
+/// The error of a file, or `Interrupted` if it was abandoned because the run was cancelled
+fn abandoned(e: SuperGzipError, settings: &Settings) -> SuperGzipError {
+    match settings.cancellation.abandoning() {
+        true => SuperGzipError::Interrupted,
+        false => e,
+    }
+}
+
+/// Writes the (de)compressed data to the given file and carries the metadata of the original
+/// file over
+fn write_output(
+    input: &mut impl Read,
+    source: &Path,
+    target: &Path,
+    settings: &Settings,
+) -> Result<(), SuperGzipError> {
+    let file = std::fs::File::create(target)?;
+    let mut output = BufWriter::with_capacity(settings.write_buffer, file);
+    transcode_blocking(input, &mut output, Some(source), settings)?;
+    output.flush()?;
+    drop(output);
+    if settings.preserve_metadata {
+        copy_metadata(source, target)?;
+    }
+    Ok(())
+}
+
+/// Reads, (de)compresses, and writes a single file, into the output path unless it is only
+/// tested. The size of the input is stored in `input_size` as soon as it is known.
+fn process_file(
+    path: &Path,
+    output_path: Option<PathBuf>,
+    settings: &Settings,
+    input_size: &mut u64,
+) -> Result<Output, SuperGzipError> {
+    let file = std::fs::File::open(path)?;
+    *input_size = file.metadata()?.len();
+    let mut input = Cancellable {
+        inner: BufReader::with_capacity(settings.read_buffer, file),
+        cancellation: &settings.cancellation,
+    };
+
+    // Name the format of files compressed with another codec, rather than calling them corrupt
+    if !settings.b_zip {
+        match magic::sniff_file(path)? {
+            Some(format) if format != settings.codec.format() => {
+                return Err(SuperGzipError::WrongFormat(format, settings.codec));
+            }
+            _ => {}
+        }
+    }
+    let Some(output_path) = output_path else {
+        let written = transcode_blocking(&mut input, &mut std::io::sink(), Some(path), settings)
+            .map_err(|e| abandoned(e, settings))?;
+        return Ok(Output::Discarded(written));
+    };
+
+    // Write to a temporary file next to the output, so that a failed file never leaves a
+    // truncated output behind under the real name
+    if settings.output_dir.is_some() {
+        if let Some(parent) = output_path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+    }
+    let temp_path = temp_path(&output_path);
+    if let Err(e) = write_output(&mut input, path, &temp_path, settings) {
+        let _ = std::fs::remove_file(&temp_path);
+        return Err(abandoned(e, settings));
+    }
+
+    // Keep the original rather than replace it with an output that is barely smaller, or larger,
+    // unless the output stores the contents as asked with --incompressible store
+    if settings.b_zip && !settings.keep_original {
+        let output_size = std::fs::metadata(&temp_path)?.len();
+        let kept = saves_enough(*input_size, output_size, settings.min_savings)
+            || stores_incompressible(settings) && entropy::is_incompressible(path)?;
+        if !kept {
+            let _ = std::fs::remove_file(&temp_path);
+            return Ok(Output::NotCompressed);
+        }
+    }
+
+    // Refuse to replace a file that appeared since the output was claimed unless forced to
+    if settings.collision != CollisionPolicy::Force
+        && std::fs::symlink_metadata(&output_path).is_ok()
+    {
+        let _ = std::fs::remove_file(&temp_path);
+        return Err(SuperGzipError::IO(Error::new(
+            ErrorKind::AlreadyExists,
+            format!("{} already exists", output_path.to_string_lossy()),
+        )));
+    }
+    if let Err(e) = std::fs::rename(&temp_path, &output_path) {
+        let _ = std::fs::remove_file(&temp_path);
+        return Err(e.into());
+    }
+    let output_size = std::fs::symlink_metadata(&output_path)?.len();
+    // Once the run is cancelled, originals are kept
+    if !settings.keep_original && !settings.cancellation.abandoning() {
+        std::fs::remove_file(path)?;
+    }
+    Ok(Output::File(output_path, output_size))
+}
+
+/// Processes a single discovered file on a thread of the pool, given where its output goes or
+/// why it is skipped, returning its report along with its error if it failed. `failures` counts
+/// the files of the run that failed so far.
+fn process(
+    path: &Path,
+    plan: Result<Option<PathBuf>, SkipReason>,
+    settings: &Settings,
+    failures: &AtomicUsize,
+    run: &str,
+) -> (FileReport, Option<SuperGzipError>) {
+    let mut report = FileReport::new(path.to_path_buf());
+
+    // Leave the files that have not started yet alone once the run is interrupted or too many
+    // files have failed
+    let plan = match plan {
+        Ok(_) if settings.cancellation.stopping() => Err(SkipReason::Interrupted),
+        Ok(_)
+            if settings
+                .max_errors
+                .is_some_and(|max_errors| failures.load(Ordering::SeqCst) >= max_errors) =>
+        {
+            Err(SkipReason::Aborted)
+        }
+        plan => plan,
+    };
+    let output_path = match plan {
+        Ok(output_path) => output_path,
+        Err(reason) => {
+            if let Some(line) = skipped_line(settings, path, reason) {
+                settings.report(&line);
+            }
+            report.status = FileStatus::Skipped(reason.describe());
+            record_done(settings, run, &report);
+            return (report, None);
+        }
+    };
+
+    if settings.verbose() {
+        let action = batch::action(settings);
+        settings.report(&status_line(
+            settings,
+            action,
+            None,
+            &path.to_string_lossy(),
+        ));
+    }
+    settings.hooks.file_start(path);
+    settings.log(LogLevel::Debug, run, Some(path), "started", &[]);
+    let file_start = Instant::now();
+    let result = process_file(path, output_path, settings, &mut report.input_size)
+        .map_err(|e| e.for_file(path, batch::operation(settings)));
+    report.duration = file_start.elapsed();
+    if result.is_err() {
+        let failed = failures.fetch_add(1, Ordering::SeqCst) + 1;
+        if settings.max_errors == Some(failed) {
+            settings.log(
+                LogLevel::Warn,
+                run,
+                None,
+                "aborted",
+                &[("failed", failed.into())],
+            );
+            settings.report(&format!(
+                "Aborting after {} failed files: no more files are started",
+                failed
+            ));
+        }
+    }
+
+    // Record the outcome for the machine-readable output
+    match &result {
+        Ok(Output::File(output_path, output_size)) => {
+            report.output_path = Some(output_path.clone());
+            report.output_size = *output_size;
+        }
+        Ok(Output::Discarded(written)) => report.output_size = *written,
+        Ok(Output::NotCompressed) => {
+            report.status = FileStatus::Skipped(SkipReason::NoSavings.describe())
+        }
+        Err(e) => report.status = FileStatus::Failed(e.inner().to_string()),
+    }
+
+    // Name the corrupt files when testing, since that is the point of the test
+    if let (true, Err(e)) = (settings.test_only, &result) {
+        settings.report_error(&settings.paint(&e.to_string(), Status::Failed));
+    }
+    if settings.verbose() && settings.test_only && result.is_ok() {
+        let line = status_line(settings, "OK", Some(Status::Ok), &path.to_string_lossy());
+        settings.report(&line);
+    } else if settings.verbose() && matches!(result, Ok(Output::NotCompressed)) {
+        let message = format!(
+            "{}: not compressed, since compressing it saved too little",
+            path.to_string_lossy()
+        );
+        settings.report(&status_line(
+            settings,
+            "Kept",
+            Some(Status::Skipped),
+            &message,
+        ));
+    } else if settings.verbose() && matches!(report.status, FileStatus::Processed) {
+        settings.report(&finished_line(settings, &report));
+    }
+    if settings.format == OutputFormat::Ndjson {
+        settings.emit(&report.to_json());
+    }
+    record_done(settings, run, &report);
+    (report, result.err())
+}
+
+/// Processes every file matching the patterns on a pool of threads, like
+/// [`run_batch`](crate::batch::run_batch), returning the report of each discovered path in the
+/// order they were matched. Blocks.
+pub fn run(settings: &Settings, patterns: &[OsString]) -> Result<Vec<FileReport>, SuperGzipError> {
+    let start = Instant::now();
+    if let Some(option) = unsupported_option(settings, patterns) {
+        return Err(SuperGzipError::IO(Error::new(
+            ErrorKind::Unsupported,
+            format!(
+                "the sync engine does not support {}: use --engine async",
+                option
+            ),
+        )));
+    }
+    if let Some(feature) = settings.codec.feature() {
+        features::require(&[feature])?;
+    }
+    if settings.verbosity >= Verbosity::Debug {
+        settings.report(&format!(
+            "Settings: {} {} on the sync engine, level {}, {} files at once, {} reads and {} writes",
+            batch::mode(settings),
+            settings.codec.name(),
+            settings
+                .level
+                .map_or_else(|| "default".to_string(), |level| level.to_string()),
+            settings.num_threads,
+            format_bytes(settings.read_buffer as u64),
+            format_bytes(settings.write_buffer as u64)
+        ));
+    }
+
+    let mut found = Found {
+        settings,
+        seen: HashSet::new(),
+        paths: Vec::new(),
+        errors: Vec::new(),
+    };
+    found.patterns(patterns);
+    let Found {
+        paths, mut errors, ..
+    } = found;
+    let run = log::run_id();
+    settings.log(
+        LogLevel::Info,
+        &run,
+        None,
+        "started",
+        &[
+            ("codec", settings.codec.extension().into()),
+            ("mode", batch::mode(settings).into()),
+        ],
+    );
+
+    // Decide where each file goes up front, in the order the files were matched, so that
+    // outputs are claimed deterministically
+    let mut claimed = HashSet::new();
+    let plans: Vec<_> = paths
+        .into_iter()
+        .map(|path| {
+            if let Some(events) = &settings.progress_json {
+                events.discovered(&path);
+            }
+            let plan = match skip_reason(&path, settings) {
+                Some(reason) => Err(reason),
+                None if settings.test_only => Ok(None),
+                None => claim_output(&path, settings, &mut claimed).map(Some),
+            };
+            (path, plan)
+        })
+        .collect();
+
+    // Each thread takes the next file that no thread has taken yet, until none are left
+    let (next, failures) = (AtomicUsize::new(0), AtomicUsize::new(0));
+    let (plans, next, failures, run) = (&plans, &next, &failures, run.as_str());
+    let mut done: Vec<(usize, FileReport, Option<SuperGzipError>)> = std::thread::scope(|scope| {
+        let workers: Vec<_> = (0..settings.num_threads.clamp(1, plans.len().max(1)))
+            .map(|_| {
+                scope.spawn(move || {
+                    let mut done = Vec::new();
+                    loop {
+                        let index = next.fetch_add(1, Ordering::SeqCst);
+                        let Some((path, plan)) = plans.get(index) else {
+                            return done;
+                        };
+                        let (report, error) = process(path, plan.clone(), settings, failures, run);
+                        done.push((index, report, error));
+                    }
+                })
+            })
+            .collect();
+        workers
+            .into_iter()
+            .flat_map(|worker| {
+                worker
+                    .join()
+                    .unwrap_or_else(|panic| std::panic::resume_unwind(panic))
+            })
+            .collect()
+    });
+    done.sort_by_key(|(index, _, _)| *index);
+    let mut reports = Vec::with_capacity(done.len());
+    for (_, report, error) in done {
+        reports.push(report);
+        errors.extend(error);
+    }
+    let cancelled_files = errors
+        .iter()
+        .any(|error| matches!(error, SuperGzipError::Interrupted));
+    if settings.cancellation.stopping() && !cancelled_files {
+        errors.push(SuperGzipError::Interrupted);
+    }
+
+    let totals = batch::report_totals(settings, &reports, start.elapsed());
+    if settings.verbose() {
+        settings.report(&format!(
+            "Finished in {} seconds",
+            start.elapsed().as_secs_f64()
+        ));
+    }
+    if let Some(report_csv) = &settings.report_csv {
+        let csv = report::csv(&reports, batch::mode(settings));
+        if let Err(e) = std::fs::write(report_csv, csv) {
+            errors.push(SuperGzipError::IO(e).for_file(report_csv, Operation::Write));
+        }
+    }
+    let succeeded = reports
+        .iter()
+        .filter(|report| matches!(report.status, FileStatus::Processed))
+        .count();
+    settings.log(
+        match errors.is_empty() {
+            true => LogLevel::Info,
+            false => LogLevel::Error,
+        },
+        run,
+        None,
+        "finished",
+        &[
+            ("files", reports.len().into()),
+            ("processed", succeeded.into()),
+            ("failed", errors.len().into()),
+            ("input_size", totals.input_size.into()),
+            ("output_size", totals.output_size.into()),
+            ("duration_secs", start.elapsed().into()),
+        ],
+    );
+    batch::summarize_errors(settings, errors, succeeded).map(|()| reports)
+}
@@ -0,0 +1,713 @@
+//! A small, dependency-free reader and writer for the POSIX tar format.
+//!
+//! See <https://pubs.opengroup.org/onlinepubs/9699919799/utilities/pax.html> for the
+//! specification. Entries are written as ustar headers, with a pax extended header in front of
+//! any entry whose path or link target does not fit, and sizes of 8 GiB or more use the GNU
+//! base-256 encoding, which GNU tar, bsdtar, and Python's tarfile all read. The reader also
+//! understands the GNU long name entries written by GNU tar.
+
+use crate::error::{file_error, Operation};
+use std::io::{Error, ErrorKind, Result};
+use std::path::{Path, PathBuf};
+use tokio::fs::File as AsyncFile;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+
+pub const BLOCK_SIZE: usize = 512;
+
+const REGULAR: u8 = b'0';
+const OLD_REGULAR: u8 = b'\0';
+const HARD_LINK: u8 = b'1';
+const SYMLINK: u8 = b'2';
+const DIRECTORY: u8 = b'5';
+const CONTIGUOUS: u8 = b'7';
+const PAX_HEADER: u8 = b'x';
+const PAX_GLOBAL_HEADER: u8 = b'g';
+const GNU_LONG_NAME: u8 = b'L';
+const GNU_LONG_LINK: u8 = b'K';
+
+const NAME_SIZE: usize = 100;
+const PREFIX_SIZE: usize = 155;
+/// The largest size that fits in the 11 octal digits of a ustar size field
+const MAX_OCTAL_SIZE: u64 = 0o77777777777;
+
+/// The kind of an entry in an archive
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum EntryKind {
+    File,
+    Directory,
+    Symlink(PathBuf),
+    /// A hard link to an earlier entry of the archive
+    HardLink(PathBuf),
+    /// Any other type of entry (e.g. a device or FIFO), by its type flag
+    Other(u8),
+}
+
+/// The metadata of a single archive entry
+#[derive(Clone, Debug)]
+pub struct Entry {
+    /// The path inside the archive, using `/` as the separator
+    pub name: String,
+    pub kind: EntryKind,
+    pub size: u64,
+    pub mode: u32,
+    pub mtime: u64,
+}
+
+fn write_octal(field: &mut [u8], value: u64) {
+    // Leave room for the terminating NUL
+    let digits = format!("{:0width$o}", value, width = field.len() - 1);
+    field[..digits.len()].copy_from_slice(digits.as_bytes());
+}
+
+fn write_size(field: &mut [u8], size: u64) {
+    if size <= MAX_OCTAL_SIZE {
+        write_octal(field, size);
+    } else {
+        // GNU base-256: the high bit of the first byte is set and the rest is big-endian
+        field.fill(0);
+        field[0] = 0x80;
+        let bytes = size.to_be_bytes();
+        let len = field.len();
+        field[len - bytes.len()..].copy_from_slice(&bytes);
+    }
+}
+
+/// Splits a path into the ustar prefix and name fields, if it fits
+fn split_name(name: &str) -> Option<(&str, &str)> {
+    if name.len() <= NAME_SIZE {
+        return Some(("", name));
+    }
+    name.match_indices('/').find_map(|(index, _)| {
+        let (prefix, rest) = (&name[..index], &name[index + 1..]);
+        (prefix.len() <= PREFIX_SIZE && rest.len() <= NAME_SIZE && !rest.is_empty())
+            .then_some((prefix, rest))
+    })
+}
+
+fn header(name: &str, typeflag: u8, size: u64, mode: u32, mtime: u64, link: &str) -> Vec<u8> {
+    let mut header = vec![0; BLOCK_SIZE];
+    // Names that do not fit are carried by a pax header, so truncating them here is harmless
+    let (prefix, short_name) = split_name(name).unwrap_or(("", name));
+    let short_name = &short_name.as_bytes()[..short_name.len().min(NAME_SIZE)];
+    header[..short_name.len()].copy_from_slice(short_name);
+    write_octal(&mut header[100..108], mode as u64 & 0o7777);
+    write_octal(&mut header[108..116], 0);
+    write_octal(&mut header[116..124], 0);
+    write_size(&mut header[124..136], size);
+    write_octal(&mut header[136..148], mtime.min(MAX_OCTAL_SIZE));
+    header[156] = typeflag;
+    let link = &link.as_bytes()[..link.len().min(NAME_SIZE)];
+    header[157..157 + link.len()].copy_from_slice(link);
+    header[257..263].copy_from_slice(b"ustar\0");
+    header[263..265].copy_from_slice(b"00");
+    header[345..345 + prefix.len()].copy_from_slice(prefix.as_bytes());
+
+    // The checksum is computed with the checksum field itself filled with spaces
+    header[148..156].fill(b' ');
+    let checksum: u32 = header.iter().map(|&byte| byte as u32).sum();
+    let digits = format!("{:06o}\0 ", checksum);
+    header[148..156].copy_from_slice(digits.as_bytes());
+    header
+}
+
+/// Formats a pax extended header record, whose length prefix counts itself
+fn pax_record(key: &str, value: &str) -> String {
+    let content_len = key.len() + value.len() + 3;
+    let mut len = content_len + content_len.to_string().len();
+    if len.to_string().len() + content_len != len {
+        len += 1;
+    }
+    format!("{} {}={}\n", len, key, value)
+}
+
+/// The number of zero bytes needed to pad content of the given size to a whole block
+pub fn padding(size: u64) -> usize {
+    (BLOCK_SIZE - (size % BLOCK_SIZE as u64) as usize) % BLOCK_SIZE
+}
+
+/// Renders the header blocks of an entry, including a pax header when its path or link target
+/// is too long for the ustar fields
+pub fn entry_header(entry: &Entry) -> Vec<u8> {
+    let (typeflag, size, link) = match &entry.kind {
+        EntryKind::File => (REGULAR, entry.size, String::new()),
+        EntryKind::Directory => (DIRECTORY, 0, String::new()),
+        EntryKind::Symlink(target) => (SYMLINK, 0, target.to_string_lossy().into_owned()),
+        EntryKind::HardLink(target) => (HARD_LINK, 0, target.to_string_lossy().into_owned()),
+        EntryKind::Other(typeflag) => (*typeflag, entry.size, String::new()),
+    };
+
+    let mut records = String::new();
+    if split_name(&entry.name).is_none() {
+        records.push_str(&pax_record("path", &entry.name));
+    }
+    if link.len() > NAME_SIZE {
+        records.push_str(&pax_record("linkpath", &link));
+    }
+
+    let mut blocks = Vec::new();
+    if !records.is_empty() {
+        blocks.extend(header(
+            "././@PaxHeader",
+            PAX_HEADER,
+            records.len() as u64,
+            0o644,
+            entry.mtime,
+            "",
+        ));
+        blocks.extend_from_slice(records.as_bytes());
+        blocks.resize(blocks.len() + padding(records.len() as u64), 0);
+    }
+    blocks.extend(header(
+        &entry.name,
+        typeflag,
+        size,
+        entry.mode,
+        entry.mtime,
+        &link,
+    ));
+    blocks
+}
+
+/// Reads the archive metadata of the file at the given path, without following symlinks
+async fn read_entry(path: &Path, name: String) -> Result<Entry> {
+    let metadata = tokio::fs::symlink_metadata(path)
+        .await
+        .map_err(file_error(path, Operation::Read))?;
+    let kind = if metadata.is_dir() {
+        EntryKind::Directory
+    } else if metadata.file_type().is_symlink() {
+        let target = tokio::fs::read_link(path).await;
+        EntryKind::Symlink(target.map_err(file_error(path, Operation::Read))?)
+    } else {
+        EntryKind::File
+    };
+    #[cfg(unix)]
+    let mode = std::os::unix::fs::PermissionsExt::mode(&metadata.permissions());
+    #[cfg(not(unix))]
+    let mode = if metadata.is_dir() { 0o755 } else { 0o644 };
+    let mtime = metadata
+        .modified()
+        .ok()
+        .and_then(|modified| modified.duration_since(std::time::UNIX_EPOCH).ok())
+        .map_or(0, |duration| duration.as_secs());
+    let size = if kind == EntryKind::File {
+        metadata.len()
+    } else {
+        0
+    };
+    Ok(Entry {
+        name,
+        kind,
+        size,
+        mode,
+        mtime,
+    })
+}
+
+/// Appends a file's header and contents, padding or truncating the contents to the size in the
+/// header in case the file changed while it was being archived
+async fn write_file<W>(writer: &mut W, path: &Path, entry: &Entry) -> Result<()>
+where
+    W: AsyncWrite + Unpin,
+{
+    writer.write_all(&entry_header(entry)).await?;
+    let file = AsyncFile::open(path)
+        .await
+        .map_err(file_error(path, Operation::Read))?;
+    let copied = tokio::io::copy(&mut file.take(entry.size), writer).await?;
+    let missing = (entry.size - copied) as usize + padding(entry.size);
+    writer.write_all(&vec![0; missing]).await?;
+    Ok(())
+}
+
+/// Writes a tar archive of the directory tree (or single file) at `root` into the writer, naming
+/// the entries relative to the parent of `root` like `tar -cf` does. Entries are written in name
+/// order, symlinks are stored rather than followed, and the paths in `exclude` are left out (e.g.
+/// the archive itself). `on_entry` is called with each entry as it is written.
+pub async fn write_tree<W>(
+    root: &Path,
+    writer: &mut W,
+    exclude: &[PathBuf],
+    mut on_entry: impl FnMut(&Entry),
+) -> Result<()>
+where
+    W: AsyncWrite + Unpin,
+{
+    let root_name = root
+        .file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .ok_or_else(|| {
+            Error::new(
+                ErrorKind::InvalidInput,
+                format!("{} has no name to archive it under", root.display()),
+            )
+        })?;
+
+    let mut stack = vec![(root.to_path_buf(), root_name)];
+    while let Some((path, name)) = stack.pop() {
+        if exclude.contains(&path) {
+            continue;
+        }
+        let mut entry = read_entry(&path, name).await?;
+        match entry.kind {
+            EntryKind::File => write_file(writer, &path, &entry).await?,
+            EntryKind::Symlink(_) | EntryKind::HardLink(_) | EntryKind::Other(_) => {
+                writer.write_all(&entry_header(&entry)).await?
+            }
+            EntryKind::Directory => {
+                entry.name.push('/');
+                writer.write_all(&entry_header(&entry)).await?;
+
+                // Push the children in reverse so that they are popped in name order
+                let mut children = Vec::new();
+                let listed = async {
+                    let mut read_dir = tokio::fs::read_dir(&path).await?;
+                    while let Some(child) = read_dir.next_entry().await? {
+                        children.push(child.file_name());
+                    }
+                    Ok(())
+                };
+                listed.await.map_err(file_error(&path, Operation::Read))?;
+                children.sort();
+                for child in children.into_iter().rev() {
+                    let child_name = format!("{}{}", entry.name, child.to_string_lossy());
+                    stack.push((path.join(child), child_name));
+                }
+            }
+        }
+        on_entry(&entry);
+    }
+
+    // The archive ends with two zero blocks
+    writer.write_all(&[0; 2 * BLOCK_SIZE]).await?;
+    Ok(())
+}
+
+/// Writes a tar archive of the files, under the given names, into the writer. `on_entry` is
+/// called with each entry as it is written, along with where its contents start in the archive so
+/// that they can be found again without reading the entries before them.
+pub async fn write_files<W>(
+    files: &[(PathBuf, String)],
+    writer: &mut W,
+    mut on_entry: impl FnMut(&Entry, u64),
+) -> Result<()>
+where
+    W: AsyncWrite + Unpin,
+{
+    let mut offset = 0;
+    for (path, name) in files {
+        let entry = read_entry(path, name.clone()).await?;
+        offset += entry_header(&entry).len() as u64;
+        write_file(writer, path, &entry).await?;
+        on_entry(&entry, offset);
+        offset += entry.size + padding(entry.size) as u64;
+    }
+    writer.write_all(&[0; 2 * BLOCK_SIZE]).await?;
+    Ok(())
+}
+
+fn invalid(message: &str) -> Error {
+    Error::new(
+        ErrorKind::InvalidData,
+        format!("invalid tar archive: {}", message),
+    )
+}
+
+/// Reads a NUL-terminated string field
+fn parse_string(field: &[u8]) -> String {
+    let end = field
+        .iter()
+        .position(|&byte| byte == 0)
+        .unwrap_or(field.len());
+    String::from_utf8_lossy(&field[..end]).into_owned()
+}
+
+/// Reads an octal number field, or a GNU base-256 one
+fn parse_number(field: &[u8]) -> Result<u64> {
+    if field[0] & 0x80 != 0 {
+        return Ok(field[1..]
+            .iter()
+            .fold((field[0] & 0x7f) as u64, |value, &byte| {
+                (value << 8) | byte as u64
+            }));
+    }
+    let digits = parse_string(field);
+    let digits = digits.trim_matches(|c: char| c == ' ' || c == '\0');
+    if digits.is_empty() {
+        return Ok(0);
+    }
+    u64::from_str_radix(digits, 8).map_err(|_| invalid("bad number field"))
+}
+
+/// Applies the records of a pax extended header to the overrides for the next entry
+fn parse_pax(data: &[u8], overrides: &mut Overrides) -> Result<()> {
+    let mut rest = data;
+    while !rest.is_empty() {
+        let space = rest
+            .iter()
+            .position(|&byte| byte == b' ')
+            .ok_or_else(|| invalid("bad pax record"))?;
+        let len: usize = std::str::from_utf8(&rest[..space])
+            .ok()
+            .and_then(|len| len.parse().ok())
+            .filter(|&len| len >= space + 2 && len <= rest.len())
+            .ok_or_else(|| invalid("bad pax record length"))?;
+        if rest[len - 1] != b'\n' {
+            return Err(invalid("bad pax record"));
+        }
+        let record = String::from_utf8_lossy(&rest[space + 1..len - 1]);
+        if let Some((key, value)) = record.split_once('=') {
+            match key {
+                "path" => overrides.name = Some(value.to_string()),
+                "linkpath" => overrides.link = Some(value.to_string()),
+                "size" => {
+                    overrides.size = Some(value.parse().map_err(|_| invalid("bad pax size"))?)
+                }
+                _ => {}
+            }
+        }
+        rest = &rest[len..];
+    }
+    Ok(())
+}
+
+/// Values from extended headers that replace those of the next entry's header
+#[derive(Default)]
+struct Overrides {
+    name: Option<String>,
+    link: Option<String>,
+    size: Option<u64>,
+}
+
+/// Reads the entries of a tar archive one by one
+pub struct Reader<R> {
+    inner: R,
+    /// The unread contents and padding of the current entry
+    remaining: u64,
+    /// The unread contents of the current entry
+    contents: u64,
+}
+
+impl<R: AsyncRead + Unpin> Reader<R> {
+    pub fn new(inner: R) -> Reader<R> {
+        Reader {
+            inner,
+            remaining: 0,
+            contents: 0,
+        }
+    }
+
+    /// Skips the rest of the current entry
+    async fn skip_rest(&mut self) -> Result<()> {
+        let skipped = tokio::io::copy(
+            &mut (&mut self.inner).take(self.remaining),
+            &mut tokio::io::sink(),
+        )
+        .await?;
+        if skipped < self.remaining {
+            return Err(invalid("unexpected end of archive"));
+        }
+        self.remaining = 0;
+        self.contents = 0;
+        Ok(())
+    }
+
+    /// Reads the contents of an extended header entry
+    async fn read_extension(&mut self, size: u64) -> Result<Vec<u8>> {
+        let mut data = Vec::new();
+        (&mut self.inner).take(size).read_to_end(&mut data).await?;
+        if (data.len() as u64) < size {
+            return Err(invalid("unexpected end of archive"));
+        }
+        self.remaining = padding(size) as u64;
+        self.skip_rest().await?;
+        Ok(data)
+    }
+
+    /// Reads the header of the next entry, skipping whatever is left of the current one.
+    /// Returns `None` at the end of the archive.
+    pub async fn next_entry(&mut self) -> Result<Option<Entry>> {
+        self.skip_rest().await?;
+        let mut overrides = Overrides::default();
+        loop {
+            let mut block = [0; BLOCK_SIZE];
+            match self.inner.read_exact(&mut block).await {
+                Ok(_) => {}
+                // Some writers leave out the end-of-archive blocks
+                Err(e) if e.kind() == ErrorKind::UnexpectedEof => return Ok(None),
+                Err(e) => return Err(e),
+            }
+            if block.iter().all(|&byte| byte == 0) {
+                return Ok(None);
+            }
+
+            let checksum = parse_number(&block[148..156])?;
+            let mut blanked = block;
+            blanked[148..156].fill(b' ');
+            if blanked.iter().map(|&byte| byte as u64).sum::<u64>() != checksum {
+                return Err(invalid("bad header checksum"));
+            }
+
+            let typeflag = block[156];
+            let size = parse_number(&block[124..136])?;
+            match typeflag {
+                PAX_HEADER => {
+                    let data = self.read_extension(size).await?;
+                    parse_pax(&data, &mut overrides)?;
+                    continue;
+                }
+                PAX_GLOBAL_HEADER => {
+                    self.read_extension(size).await?;
+                    continue;
+                }
+                GNU_LONG_NAME | GNU_LONG_LINK => {
+                    let value = parse_string(&self.read_extension(size).await?);
+                    match typeflag {
+                        GNU_LONG_NAME => overrides.name = Some(value),
+                        _ => overrides.link = Some(value),
+                    }
+                    continue;
+                }
+                _ => {}
+            }
+
+            let mut name = parse_string(&block[..NAME_SIZE]);
+            let prefix = parse_string(&block[345..345 + PREFIX_SIZE]);
+            if &block[257..262] == b"ustar" && !prefix.is_empty() {
+                name = format!("{}/{}", prefix, name);
+            }
+            let name = overrides.name.take().unwrap_or(name);
+            let link = PathBuf::from(
+                overrides
+                    .link
+                    .take()
+                    .unwrap_or_else(|| parse_string(&block[157..157 + NAME_SIZE])),
+            );
+            let size = overrides.size.unwrap_or(size);
+            let kind = match typeflag {
+                REGULAR | OLD_REGULAR | CONTIGUOUS if name.ends_with('/') => EntryKind::Directory,
+                REGULAR | OLD_REGULAR | CONTIGUOUS => EntryKind::File,
+                DIRECTORY => EntryKind::Directory,
+                SYMLINK => EntryKind::Symlink(link),
+                HARD_LINK => EntryKind::HardLink(link),
+                other => EntryKind::Other(other),
+            };
+
+            // Only regular files and unknown entries carry contents
+            let contents = match kind {
+                EntryKind::File | EntryKind::Other(_) => size,
+                _ => 0,
+            };
+            self.contents = contents;
+            self.remaining = contents + padding(contents) as u64;
+            return Ok(Some(Entry {
+                name,
+                kind,
+                size: contents,
+                mode: parse_number(&block[100..108])? as u32,
+                mtime: parse_number(&block[136..148])?,
+            }));
+        }
+    }
+
+    /// Copies the contents of the current entry into the writer
+    pub async fn copy_contents<W>(&mut self, writer: &mut W) -> Result<u64>
+    where
+        W: AsyncWrite + Unpin,
+    {
+        let copied = tokio::io::copy(&mut (&mut self.inner).take(self.contents), writer).await?;
+        if copied < self.contents {
+            return Err(invalid("unexpected end of archive"));
+        }
+        self.remaining -= copied;
+        self.contents = 0;
+        Ok(copied)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Reads every entry of an archive along with its contents
+    async fn read_all(archive: &[u8]) -> Result<Vec<(Entry, Vec<u8>)>> {
+        let mut reader = Reader::new(archive);
+        let mut entries = Vec::new();
+        while let Some(entry) = reader.next_entry().await? {
+            let mut contents = Vec::new();
+            reader.copy_contents(&mut contents).await?;
+            entries.push((entry, contents));
+        }
+        Ok(entries)
+    }
+
+    fn entry(name: &str, kind: EntryKind, size: u64) -> Entry {
+        Entry {
+            name: name.to_string(),
+            kind,
+            size,
+            mode: 0o640,
+            mtime: 1_700_000_000,
+        }
+    }
+
+    /// An archive of the entries, with contents of their size
+    fn archive(entries: &[Entry]) -> Vec<u8> {
+        let mut archive = Vec::new();
+        for entry in entries {
+            archive.extend(entry_header(entry));
+            if entry.kind == EntryKind::File {
+                archive.extend(vec![b'x'; entry.size as usize]);
+                archive.resize(archive.len() + padding(entry.size), 0);
+            }
+        }
+        archive.extend([0; 2 * BLOCK_SIZE]);
+        archive
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn trees_round_trip() {
+        let directory =
+            std::env::temp_dir().join(format!("super-gunzip-tar-{}", std::process::id()));
+        let root = directory.join("tree");
+        // A path that only fits with the ustar prefix, and a name that does not fit at all
+        let deep = root.join("d".repeat(60)).join("e".repeat(60));
+        let long_name = "f".repeat(150);
+        let long_target = format!("../{}", "t".repeat(120));
+        std::fs::create_dir_all(&deep).unwrap();
+        std::fs::write(root.join("a.txt"), "first").unwrap();
+        std::fs::write(deep.join("nested.txt"), vec![7; 1000]).unwrap();
+        std::fs::write(root.join(&long_name), "").unwrap();
+        std::os::unix::fs::symlink(&long_target, root.join("link")).unwrap();
+        std::os::unix::fs::symlink("a.txt", root.join("short-link")).unwrap();
+
+        let mut archive = Vec::new();
+        let mut written = Vec::new();
+        write_tree(&root, &mut archive, &[], |entry| {
+            written.push(entry.name.clone())
+        })
+        .await
+        .unwrap();
+        assert_eq!(archive.len() % BLOCK_SIZE, 0);
+        let entries = read_all(&archive).await.unwrap();
+        std::fs::remove_dir_all(&directory).unwrap();
+
+        let names: Vec<_> = entries
+            .iter()
+            .map(|(entry, _)| entry.name.clone())
+            .collect();
+        assert_eq!(names, written);
+        let find = |name: &str| {
+            entries
+                .iter()
+                .find(|(entry, _)| entry.name == name)
+                .unwrap_or_else(|| panic!("{} is missing from {:?}", name, names))
+        };
+        assert_eq!(find("tree/").0.kind, EntryKind::Directory);
+        assert_eq!(find("tree/a.txt").1, b"first");
+        let nested = format!("tree/{}/{}/nested.txt", "d".repeat(60), "e".repeat(60));
+        assert!(nested.len() > NAME_SIZE);
+        assert_eq!(find(&nested).1, vec![7; 1000]);
+        assert_eq!(find(&format!("tree/{}", long_name)).0.size, 0);
+        let link = &find("tree/link").0;
+        assert_eq!(link.kind, EntryKind::Symlink(PathBuf::from(long_target)));
+        assert_eq!(link.size, 0);
+        let short_link = &find("tree/short-link").0;
+        assert_eq!(short_link.kind, EntryKind::Symlink(PathBuf::from("a.txt")));
+    }
+
+    #[tokio::test]
+    async fn links_and_long_names_round_trip() {
+        let long_name = format!("{}/{}", "p".repeat(200), "n".repeat(120));
+        let long_target = "h".repeat(NAME_SIZE + 1);
+        let entries = [
+            entry("a/file", EntryKind::File, 600),
+            entry("a/hard", EntryKind::HardLink(PathBuf::from("a/file")), 0),
+            entry(&long_name, EntryKind::File, 3),
+            entry("a/far", EntryKind::HardLink(PathBuf::from(&long_target)), 0),
+            entry("a/fifo", EntryKind::Other(b'6'), 0),
+        ];
+        let read = read_all(&archive(&entries)).await.unwrap();
+        assert_eq!(read.len(), entries.len());
+        for ((read, contents), entry) in read.iter().zip(&entries) {
+            assert_eq!(read.name, entry.name);
+            assert_eq!(read.kind, entry.kind);
+            assert_eq!(read.size, entry.size);
+            assert_eq!(read.mode, entry.mode);
+            assert_eq!(read.mtime, entry.mtime);
+            assert_eq!(contents.len() as u64, entry.size);
+        }
+    }
+
+    #[tokio::test]
+    async fn gnu_long_names_and_pax_sizes_are_read() {
+        let long_name = "g".repeat(300);
+        let mut archive = header("././@LongLink", GNU_LONG_NAME, 301, 0, 0, "");
+        archive.extend(long_name.as_bytes());
+        archive.push(0);
+        archive.resize(archive.len() + padding(301), 0);
+        archive.extend(header("short", REGULAR, 2, 0o644, 0, ""));
+        archive.extend(b"hi");
+        archive.resize(archive.len() + padding(2), 0);
+
+        // A pax size replaces the one in the header, and unknown records are ignored
+        let records = format!("{}{}", pax_record("size", "5"), pax_record("mtime", "1.5"));
+        archive.extend(header("pax", PAX_HEADER, records.len() as u64, 0, 0, ""));
+        archive.extend(records.as_bytes());
+        archive.resize(archive.len() + padding(records.len() as u64), 0);
+        archive.extend(header("sized", REGULAR, 0, 0o644, 0, ""));
+        archive.extend(b"hello");
+        archive.resize(archive.len() + padding(5), 0);
+
+        let read = read_all(&archive).await.unwrap();
+        assert_eq!(read[0].0.name, long_name);
+        assert_eq!(read[0].1, b"hi");
+        assert_eq!(read[1].0.name, "sized");
+        assert_eq!(read[1].1, b"hello");
+        // Some writers leave out the blocks that end the archive
+        assert_eq!(read.len(), 2);
+    }
+
+    #[test]
+    fn numbers_and_records_are_encoded() {
+        // The length of a pax record counts its own digits, even when that adds one
+        for value_len in 0..120 {
+            let record = pax_record("path", &"v".repeat(value_len));
+            let (len, _) = record.split_once(' ').unwrap();
+            assert_eq!(len.parse::<usize>().unwrap(), record.len(), "{}", record);
+        }
+
+        let mut field = [0; 12];
+        for size in [0, 1, MAX_OCTAL_SIZE, MAX_OCTAL_SIZE + 1, 1 << 40] {
+            write_size(&mut field, size);
+            assert_eq!(parse_number(&field).unwrap(), size);
+        }
+        assert_eq!(parse_number(b"     \0\0\0").unwrap(), 0);
+        assert!(parse_number(b"0000009\0").is_err());
+
+        assert_eq!(padding(0), 0);
+        assert_eq!(padding(1), BLOCK_SIZE - 1);
+        assert_eq!(padding(BLOCK_SIZE as u64), 0);
+    }
+
+    #[tokio::test]
+    async fn damaged_archives_are_errors() {
+        let good = archive(&[entry("file", EntryKind::File, 700)]);
+        let message = |result: Result<Vec<(Entry, Vec<u8>)>>| result.unwrap_err().to_string();
+
+        let mut checksum = good.clone();
+        checksum[0] ^= 1;
+        assert!(message(read_all(&checksum).await).ends_with("bad header checksum"));
+        // Cut off in the contents, and in the padding after them
+        for len in [BLOCK_SIZE + 100, BLOCK_SIZE + 710] {
+            let error = message(read_all(&good[..len]).await);
+            assert!(error.ends_with("unexpected end of archive"), "{}", error);
+        }
+        let records = b"99 path=x\n";
+        let mut pax = header("pax", PAX_HEADER, records.len() as u64, 0, 0, "");
+        pax.extend(records);
+        pax.resize(2 * BLOCK_SIZE, 0);
+        assert!(message(read_all(&pax).await).ends_with("bad pax record length"));
+    }
+}
@@ -0,0 +1,106 @@
+//! Limiting the combined read and write throughput of a run, so that a batch job leaves disk
+//! bandwidth for the other programs on the machine.
+
+use std::future::Future;
+use std::io::Result;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::task::{ready, Context, Poll};
+use std::time::{Duration, Instant};
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+use tokio::time::Sleep;
+
+/// A rate shared by every reader and writer of a run. Each transfer books the time it takes at
+/// the rate, and the next transfer of any reader or writer waits until that time has passed.
+#[derive(Debug)]
+pub struct RateLimiter {
+    bytes_per_second: u64,
+    /// When the transfers booked so far are done at the rate
+    next: Mutex<Instant>,
+}
+
+impl RateLimiter {
+    pub fn new(bytes_per_second: u64) -> RateLimiter {
+        RateLimiter {
+            bytes_per_second: bytes_per_second.max(1),
+            next: Mutex::new(Instant::now()),
+        }
+    }
+
+    /// Books a transfer of this many bytes, returning when the next transfer may start
+    fn book(&self, bytes: usize) -> Instant {
+        let mut next = self.next.lock().unwrap();
+        let duration = Duration::from_secs_f64(bytes as f64 / self.bytes_per_second as f64);
+        *next = (*next).max(Instant::now()) + duration;
+        *next
+    }
+}
+
+/// A reader or writer whose transfers count against a rate limit
+pub struct Throttled<T> {
+    inner: T,
+    limiter: Arc<RateLimiter>,
+    /// The wait before the next transfer, if the rate has been exceeded
+    delay: Option<Pin<Box<Sleep>>>,
+}
+
+impl<T> Throttled<T> {
+    pub fn new(inner: T, limiter: Arc<RateLimiter>) -> Throttled<T> {
+        Throttled {
+            inner,
+            limiter,
+            delay: None,
+        }
+    }
+
+    fn poll_delay(&mut self, cx: &mut Context<'_>) -> Poll<()> {
+        if let Some(delay) = &mut self.delay {
+            ready!(delay.as_mut().poll(cx));
+            self.delay = None;
+        }
+        Poll::Ready(())
+    }
+
+    fn book(&mut self, bytes: usize) {
+        let until = self.limiter.book(bytes);
+        if until > Instant::now() {
+            self.delay = Some(Box::pin(tokio::time::sleep_until(until.into())));
+        }
+    }
+}
+
+impl<T: AsyncRead + Unpin> AsyncRead for Throttled<T> {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<Result<()>> {
+        ready!(self.poll_delay(cx));
+        let before = buf.filled().len();
+        ready!(Pin::new(&mut self.inner).poll_read(cx, buf))?;
+        let read = buf.filled().len() - before;
+        self.book(read);
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl<T: AsyncWrite + Unpin> AsyncWrite for Throttled<T> {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<Result<usize>> {
+        ready!(self.poll_delay(cx));
+        let written = ready!(Pin::new(&mut self.inner).poll_write(cx, buf))?;
+        self.book(written);
+        Poll::Ready(Ok(written))
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<()>> {
+        Pin::new(&mut self.inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<()>> {
+        Pin::new(&mut self.inner).poll_shutdown(cx)
+    }
+}
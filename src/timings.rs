@@ -0,0 +1,227 @@
+//! Where the time of processing each file goes, for `--timings`: reading the input, the
+//! (de)compression on the CPU pool, writing the output, and queueing for a thread, the memory
+//! budget, or open files.
+//!
+//! The stages of a file overlap, so their times add up to more than the time the file took. What
+//! matters is which stage dominates, which tells whether the run is held up by the disks or the
+//! CPU.
+
+use std::cell::Cell;
+use std::future::Future;
+use std::io::Result;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::task::{Context, Poll};
+use std::time::{Duration, Instant};
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+
+/// The stages that the time of a file is split into
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Stage {
+    Read,
+    Compress,
+    Write,
+    /// Waiting for a thread, a CPU pool thread, memory, or open files
+    Queue,
+}
+
+/// The time a file spent in each stage
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Timings {
+    pub read: Duration,
+    pub compress: Duration,
+    pub write: Duration,
+    pub queue: Duration,
+}
+
+impl Timings {
+    pub fn add(&mut self, other: &Timings) {
+        self.read += other.read;
+        self.compress += other.compress;
+        self.write += other.write;
+        self.queue += other.queue;
+    }
+
+    /// Renders the timings as a single-line JSON object
+    pub fn to_json(&self) -> String {
+        format!(
+            "{{\"read_secs\":{},\"compress_secs\":{},\"write_secs\":{},\"queue_secs\":{}}}",
+            self.read.as_secs_f64(),
+            self.compress.as_secs_f64(),
+            self.write.as_secs_f64(),
+            self.queue.as_secs_f64()
+        )
+    }
+
+    /// Renders the breakdown of the timings of the files of a run, ending with which side held
+    /// it up. `codec` names the CPU stage, e.g. `compressing`.
+    pub fn render_text(&self, files: usize, codec: &str) -> String {
+        let stages = [
+            ("reading", self.read),
+            (codec, self.compress),
+            ("writing", self.write),
+            ("queued", self.queue),
+        ];
+        let total: Duration = stages.iter().map(|(_, time)| *time).sum();
+        let mut text = format!(
+            "Time spent per stage, summed over {} file{}:\n",
+            files,
+            if files == 1 { "" } else { "s" }
+        );
+        for (name, time) in stages {
+            let share = match total.is_zero() {
+                true => 0.0,
+                false => time.as_secs_f64() / total.as_secs_f64() * 100.0,
+            };
+            text.push_str(&format!(
+                "  {:<14} {:>9.3}s {:>6.1}%\n",
+                name,
+                time.as_secs_f64(),
+                share
+            ));
+        }
+        let io = self.read + self.write;
+        text.push_str(if self.queue > io.max(self.compress) {
+            "Mostly queued: files waited for threads, --max-memory, or --max-open-files"
+        } else if self.compress > io {
+            "Mostly CPU-bound: more --cpu-threads, or a lower --level, would help the most"
+        } else {
+            "Mostly disk-bound: faster disks, or a higher --level when compressing, would help"
+        });
+        text
+    }
+}
+
+/// Collects the time of a single file in each stage, from the tasks and threads working on it
+#[derive(Debug, Default)]
+pub struct Recorder {
+    nanos: [AtomicU64; 4],
+}
+
+impl Recorder {
+    pub fn record(&self, stage: Stage, time: Duration) {
+        self.nanos[stage as usize].fetch_add(time.as_nanos() as u64, Ordering::Relaxed);
+    }
+
+    pub fn timings(&self) -> Timings {
+        let time =
+            |stage: Stage| Duration::from_nanos(self.nanos[stage as usize].load(Ordering::Relaxed));
+        Timings {
+            read: time(Stage::Read),
+            compress: time(Stage::Compress),
+            write: time(Stage::Write),
+            queue: time(Stage::Queue),
+        }
+    }
+}
+
+tokio::task_local! {
+    /// The recorder of the file that the current task is processing
+    static RECORDER: Arc<Recorder>;
+}
+
+thread_local! {
+    /// How long the function running on this CPU pool thread has waited for data to come in or
+    /// be taken out, which is not CPU time
+    static IDLE: Cell<Duration> = const { Cell::new(Duration::ZERO) };
+}
+
+/// Runs the future with the recorder, if any, as the one its stages are recorded in
+pub async fn scope<F: Future>(recorder: Option<Arc<Recorder>>, future: F) -> F::Output {
+    match recorder {
+        Some(recorder) => RECORDER.scope(recorder, future).await,
+        None => future.await,
+    }
+}
+
+/// The recorder of the file that the current task is processing, if its timings are recorded
+pub fn current() -> Option<Arc<Recorder>> {
+    RECORDER.try_with(Arc::clone).ok()
+}
+
+/// Records time spent in a stage of the file that the current task is processing, if any
+pub fn record(stage: Stage, time: Duration) {
+    let _ = RECORDER.try_with(|recorder| recorder.record(stage, time));
+}
+
+/// Runs a function on a CPU pool thread, recording the time it took as compressing, less the
+/// time it reported as idle
+pub(crate) fn on_thread<T>(recorder: Option<Arc<Recorder>>, function: impl FnOnce() -> T) -> T {
+    let Some(recorder) = recorder else {
+        return function();
+    };
+    IDLE.set(Duration::ZERO);
+    let start = Instant::now();
+    let result = function();
+    let busy = start.elapsed().saturating_sub(IDLE.get());
+    recorder.record(Stage::Compress, busy);
+    result
+}
+
+/// Reports time that a function running on a CPU pool thread spent waiting rather than working
+pub(crate) fn idle(time: Duration) {
+    IDLE.set(IDLE.get() + time);
+}
+
+/// A reader or writer that records the time its transfers take as reading or writing. A
+/// transfer lasts from when it is first polled until it is ready.
+pub struct Timed<T> {
+    inner: T,
+    recorder: Option<Arc<Recorder>>,
+    /// When the transfer in progress was first polled
+    started: Option<Instant>,
+}
+
+impl<T> Timed<T> {
+    /// Times the transfers of the reader or writer for the file that the current task is
+    /// processing, if its timings are recorded
+    pub fn new(inner: T) -> Timed<T> {
+        Timed {
+            inner,
+            recorder: current(),
+            started: None,
+        }
+    }
+
+    fn time<R>(&mut self, stage: Stage, poll: impl FnOnce(&mut T) -> Poll<R>) -> Poll<R> {
+        let Some(recorder) = &self.recorder else {
+            return poll(&mut self.inner);
+        };
+        let started = *self.started.get_or_insert_with(Instant::now);
+        let result = poll(&mut self.inner);
+        if result.is_ready() {
+            recorder.record(stage, started.elapsed());
+            self.started = None;
+        }
+        result
+    }
+}
+
+impl<T: AsyncRead + Unpin> AsyncRead for Timed<T> {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<Result<()>> {
+        self.time(Stage::Read, |inner| Pin::new(inner).poll_read(cx, buf))
+    }
+}
+
+impl<T: AsyncWrite + Unpin> AsyncWrite for Timed<T> {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<Result<usize>> {
+        self.time(Stage::Write, |inner| Pin::new(inner).poll_write(cx, buf))
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<()>> {
+        self.time(Stage::Write, |inner| Pin::new(inner).poll_flush(cx))
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<()>> {
+        self.time(Stage::Write, |inner| Pin::new(inner).poll_shutdown(cx))
+    }
+}
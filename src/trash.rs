@@ -0,0 +1,421 @@
+//! Moving originals to the trash instead of deleting them, for `--trash`, and putting them back
+//! with the `undo` subcommand.
+//!
+//! The platform trash is the freedesktop.org trash (`~/.local/share/Trash`) on Linux and other
+//! Unix systems, with the `.trashinfo` files that file managers restore from, and `~/.Trash` on
+//! macOS. A quarantine directory can be given instead, which is the only choice on Windows.
+//!
+//! Every trashed file is also recorded in a log, `supergzip/trash.log` in `$XDG_STATE_HOME` (by
+//! default `~/.local/state`), with one line per file: when it was trashed, the run that trashed
+//! it, where it is in the trash, where it came from, and the output it was replaced by,
+//! separated by tabs. Tabs, line breaks, and `%` in the paths are written as `%09`, `%0A`, `%0D`,
+//! and `%25`, since they are allowed in file names. `undo` restores the files of the latest run
+//! in the log.
+
+use crate::journal::{path_from_bytes, path_to_bytes};
+use crate::log;
+use std::io::{Error, ErrorKind, Result, Write};
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+/// How a trash directory is laid out
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Layout {
+    /// The freedesktop.org trash: the files in `files`, and where they came from in `info`
+    Freedesktop,
+    /// The files directly in the directory
+    Flat,
+}
+
+/// Where the originals of a run are moved instead of being deleted
+#[derive(Clone, Debug)]
+pub struct Trash {
+    directory: PathBuf,
+    layout: Layout,
+    log: PathBuf,
+    /// The run that the trashed files are recorded under
+    run: String,
+}
+
+/// A trashed file, as recorded in the log
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Entry {
+    /// When the file was trashed, in seconds since the Unix epoch
+    pub time: u64,
+    pub run: String,
+    /// Where the file is in the trash
+    pub trashed: PathBuf,
+    /// Where the file was
+    pub original: PathBuf,
+    /// The output that replaced the file
+    pub output: PathBuf,
+}
+
+fn home() -> Option<PathBuf> {
+    std::env::var_os("HOME")
+        .filter(|home| !home.is_empty())
+        .map(PathBuf::from)
+}
+
+/// An XDG base directory, or its default under the home directory
+fn xdg_dir(variable: &str, default: &str) -> Option<PathBuf> {
+    std::env::var_os(variable)
+        .filter(|dir| !dir.is_empty())
+        .map(PathBuf::from)
+        .or_else(|| home().map(|home| home.join(default)))
+}
+
+/// The path of the log of trashed files, if the home directory is known
+pub fn log_path() -> Option<PathBuf> {
+    Some(xdg_dir("XDG_STATE_HOME", ".local/state")?.join("supergzip/trash.log"))
+}
+
+impl Trash {
+    /// The trash of the platform
+    pub fn platform() -> Result<Trash> {
+        let not_found = || {
+            Error::new(
+                ErrorKind::NotFound,
+                "the trash cannot be found without $HOME",
+            )
+        };
+        let (directory, layout) = if cfg!(target_os = "macos") {
+            (home().ok_or_else(not_found)?.join(".Trash"), Layout::Flat)
+        } else if cfg!(unix) {
+            let data_home = xdg_dir("XDG_DATA_HOME", ".local/share").ok_or_else(not_found)?;
+            (data_home.join("Trash"), Layout::Freedesktop)
+        } else {
+            return Err(Error::new(
+                ErrorKind::Unsupported,
+                "the trash of this platform is not supported, so a directory has to be given",
+            ));
+        };
+        Trash::new(directory, layout)
+    }
+
+    /// A quarantine directory, created if it does not exist
+    pub fn directory(directory: &Path) -> Result<Trash> {
+        Trash::new(std::path::absolute(directory)?, Layout::Flat)
+    }
+
+    fn new(directory: PathBuf, layout: Layout) -> Result<Trash> {
+        let log = log_path().ok_or_else(|| {
+            Error::new(
+                ErrorKind::NotFound,
+                "the log of trashed files cannot be found without $HOME",
+            )
+        })?;
+        // Process ids are reused, so the time tells runs of the same process id apart
+        let started = SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .map_or(0, |since| since.as_secs());
+        Ok(Trash {
+            directory,
+            layout,
+            log,
+            run: format!("{}-{}", started, log::run_id()),
+        })
+    }
+
+    /// Where the trashed files themselves go
+    fn files(&self) -> PathBuf {
+        match self.layout {
+            Layout::Freedesktop => self.directory.join("files"),
+            Layout::Flat => self.directory.clone(),
+        }
+    }
+
+    /// Moves the file to the trash, under its name or, if that is taken, its name with a number
+    /// appended, and records it in the log. Blocks.
+    pub fn put(&self, original: &Path, output: &Path) -> Result<PathBuf> {
+        let original = std::path::absolute(original)?;
+        let name = original
+            .file_name()
+            .ok_or_else(|| Error::new(ErrorKind::InvalidInput, "the file has no name"))?;
+        let files = self.files();
+        std::fs::create_dir_all(&files)?;
+        let time = SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .map_or(0, |since| since.as_secs());
+
+        // Claim a free name, along with its info file in the freedesktop trash
+        let mut number = 1;
+        let trashed = loop {
+            let mut candidate = name.to_owned();
+            if number > 1 {
+                candidate.push(format!(".{}", number));
+            }
+            number += 1;
+            let trashed = files.join(&candidate);
+            if trashed.symlink_metadata().is_ok() {
+                continue;
+            }
+            if self.layout == Layout::Freedesktop {
+                let info = info_path(&trashed);
+                std::fs::create_dir_all(info.parent().unwrap())?;
+                match std::fs::OpenOptions::new()
+                    .write(true)
+                    .create_new(true)
+                    .open(&info)
+                {
+                    Ok(mut file) => file.write_all(trash_info(&original, time).as_bytes())?,
+                    Err(e) if e.kind() == ErrorKind::AlreadyExists => continue,
+                    Err(e) => return Err(e),
+                }
+            }
+            break trashed;
+        };
+        if let Err(e) = move_file(&original, &trashed) {
+            if self.layout == Layout::Freedesktop {
+                let _ = std::fs::remove_file(info_path(&trashed));
+            }
+            return Err(e);
+        }
+
+        let entry = Entry {
+            time,
+            run: self.run.clone(),
+            trashed: trashed.clone(),
+            original,
+            output: std::path::absolute(output)?,
+        };
+        if let Some(parent) = self.log.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        std::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.log)?
+            .write_all(&entry.to_line())?;
+        Ok(trashed)
+    }
+}
+
+impl Entry {
+    fn to_line(&self) -> Vec<u8> {
+        let mut line = format!("{}\t{}\t", self.time, self.run).into_bytes();
+        for (path, separator) in [
+            (&self.trashed, b'\t'),
+            (&self.original, b'\t'),
+            (&self.output, b'\n'),
+        ] {
+            line.extend_from_slice(&escape(&path_to_bytes(path)));
+            line.push(separator);
+        }
+        line
+    }
+
+    /// Parses a line of the log, ignoring lines that are incomplete because the run was killed
+    /// while writing them
+    fn parse(line: &[u8]) -> Option<Entry> {
+        let mut fields = line.split(|&byte| byte == b'\t');
+        let time = std::str::from_utf8(fields.next()?).ok()?.parse().ok()?;
+        let run = String::from_utf8(fields.next()?.to_vec()).ok()?;
+        let mut path = || {
+            Some(path_from_bytes(&unescape(
+                fields.next().filter(|path| !path.is_empty())?,
+            )))
+        };
+        let (trashed, original, output) = (path()?, path()?, path()?);
+        Some(Entry {
+            time,
+            run,
+            trashed,
+            original,
+            output,
+        })
+    }
+
+    /// Moves the file back from the trash to where it was, and deletes the output it was
+    /// replaced by (or the volumes of the output), unless `keep_output`. An existing file in
+    /// its place is only replaced if `force`. Blocks.
+    pub fn restore(&self, force: bool, keep_output: bool) -> Result<()> {
+        if !force && self.original.symlink_metadata().is_ok() {
+            return Err(Error::new(
+                ErrorKind::AlreadyExists,
+                "another file is in its place (--force replaces it)",
+            ));
+        }
+        if self.trashed.symlink_metadata().is_err() {
+            return Err(Error::new(
+                ErrorKind::NotFound,
+                format!(
+                    "it is no longer in the trash at {}",
+                    self.trashed.to_string_lossy()
+                ),
+            ));
+        }
+        if let Some(parent) = self.original.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        move_file(&self.trashed, &self.original)?;
+        let _ = std::fs::remove_file(info_path(&self.trashed));
+        if !keep_output {
+            match std::fs::remove_file(&self.output) {
+                Err(e) if e.kind() == ErrorKind::NotFound => {
+                    for volume in crate::split::volumes(&self.output) {
+                        std::fs::remove_file(volume)?;
+                    }
+                }
+                result => result?,
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Escapes the bytes of a path that would end its field or line in the log
+fn escape(path: &[u8]) -> Vec<u8> {
+    let mut escaped = Vec::with_capacity(path.len());
+    for &byte in path {
+        match byte {
+            b'\t' | b'\n' | b'\r' | b'%' => {
+                escaped.extend_from_slice(format!("%{:02X}", byte).as_bytes())
+            }
+            byte => escaped.push(byte),
+        }
+    }
+    escaped
+}
+
+/// Undoes [`escape`], keeping a `%` that is not followed by two hex digits as it is
+fn unescape(field: &[u8]) -> Vec<u8> {
+    let mut path = Vec::with_capacity(field.len());
+    let mut rest = field;
+    while let Some((&byte, after)) = rest.split_first() {
+        let decoded = match after {
+            [high, low, ..]
+                if byte == b'%' && high.is_ascii_hexdigit() && low.is_ascii_hexdigit() =>
+            {
+                std::str::from_utf8(&[*high, *low])
+                    .ok()
+                    .and_then(|hex| u8::from_str_radix(hex, 16).ok())
+            }
+            _ => None,
+        };
+        match decoded {
+            Some(decoded) => {
+                path.push(decoded);
+                rest = &after[2..];
+            }
+            None => {
+                path.push(byte);
+                rest = after;
+            }
+        }
+    }
+    path
+}
+
+/// Reads the entries of the log at the path, in the order the files were trashed. A missing log
+/// has no entries.
+pub fn read_log(path: &Path) -> Result<Vec<Entry>> {
+    let contents = match std::fs::read(path) {
+        Ok(contents) => contents,
+        Err(e) if e.kind() == ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(e),
+    };
+    // Only complete lines count, since the last one may have been cut short
+    let end = contents
+        .iter()
+        .rposition(|&byte| byte == b'\n')
+        .map_or(0, |end| end + 1);
+    Ok(contents[..end]
+        .split(|&byte| byte == b'\n')
+        .filter_map(Entry::parse)
+        .collect())
+}
+
+/// Replaces the log at the path with the entries, e.g. after some of them were restored
+pub fn write_log(path: &Path, entries: &[Entry]) -> Result<()> {
+    let contents: Vec<u8> = entries.iter().flat_map(Entry::to_line).collect();
+    let temp_path = crate::batch::temp_path(path);
+    std::fs::write(&temp_path, contents)?;
+    std::fs::rename(&temp_path, path)
+}
+
+/// Where the freedesktop.org trash keeps the origin of a trashed file (which is only there for
+/// files in that trash)
+fn info_path(trashed: &Path) -> PathBuf {
+    let mut name = trashed.file_name().unwrap_or_default().to_owned();
+    name.push(".trashinfo");
+    let trash = trashed
+        .parent()
+        .and_then(Path::parent)
+        .unwrap_or(Path::new(""));
+    trash.join("info").join(name)
+}
+
+/// The contents of the `.trashinfo` file of a file trashed at the time, which file managers
+/// restore it from
+fn trash_info(original: &Path, time: u64) -> String {
+    let mut path = String::new();
+    for &byte in path_to_bytes(original).iter() {
+        match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'/' | b'-' | b'_' | b'.' | b'~' => {
+                path.push(byte as char)
+            }
+            byte => path.push_str(&format!("%{:02X}", byte)),
+        }
+    }
+    // The date is in UTC rather than local time, which is off by the time zone at worst
+    let date = crate::gzip_header::format_mtime(time.min(u32::MAX as u64) as u32).replace(' ', "T");
+    format!("[Trash Info]\nPath={}\nDeletionDate={}\n", path, date)
+}
+
+/// Moves a file, copying it (along with its permissions) and deleting the original when the
+/// destination is on another file system
+fn move_file(from: &Path, to: &Path) -> Result<()> {
+    match std::fs::rename(from, to) {
+        Err(e) if e.kind() == ErrorKind::CrossesDevices => {
+            if let Err(e) = std::fs::copy(from, to) {
+                let _ = std::fs::remove_file(to);
+                return Err(e);
+            }
+            std::fs::remove_file(from)
+        }
+        result => result,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Names with tabs, line breaks, and percent signs survive the log, and can be restored
+    #[cfg(unix)]
+    #[test]
+    fn odd_names_survive_the_log() {
+        let directory =
+            std::env::temp_dir().join(format!("super-gunzip-trash-{}", std::process::id()));
+        let trash = directory.join("trash");
+        std::fs::create_dir_all(&trash).unwrap();
+        let name = "a\tb\nc\r%41%.txt";
+        let entry = Entry {
+            time: 1,
+            run: "1-run".to_string(),
+            trashed: trash.join(name),
+            original: directory.join(name),
+            output: directory.join(format!("{}.gz", name)),
+        };
+        std::fs::write(&entry.trashed, "contents").unwrap();
+
+        let log = directory.join("trash.log");
+        write_log(&log, &[entry.clone(), entry.clone()]).unwrap();
+        let line = entry.to_line();
+        assert_eq!(line.iter().filter(|&&byte| byte == b'\n').count(), 1);
+        assert_eq!(read_log(&log).unwrap(), vec![entry.clone(), entry.clone()]);
+
+        entry.restore(false, true).unwrap();
+        assert_eq!(std::fs::read(&entry.original).unwrap(), b"contents");
+        std::fs::remove_dir_all(&directory).unwrap();
+    }
+
+    /// A percent sign that does not start an escape is kept as it is
+    #[test]
+    fn stray_percent_signs_are_kept() {
+        assert_eq!(unescape(b"100%"), b"100%");
+        assert_eq!(unescape(b"%+1%zz%4"), b"%+1%zz%4");
+        assert_eq!(unescape(b"%2541"), b"%41");
+    }
+}
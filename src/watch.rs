@@ -0,0 +1,141 @@
+//! Watching for new files matching the patterns and processing them once they stop changing.
+//!
+//! The file system is polled rather than subscribed to, which works the same on every platform
+//! and on network file systems, at the cost of noticing new files up to one interval late.
+
+use crate::batch::{is_temp_path, process_paths};
+use crate::discovery::discover;
+use crate::error::SuperGzipError;
+use crate::interrupt;
+use crate::metrics::{self, Metrics};
+use crate::settings::Settings;
+use std::collections::{HashMap, HashSet};
+use std::ffi::OsString;
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{Duration, Instant, SystemTime};
+
+/// How often to look for new files and how long they must stay unchanged
+#[derive(Clone, Copy, Debug)]
+pub struct WatchOptions {
+    /// How often the patterns are matched against the file system again
+    pub interval: Duration,
+    /// How long the size and modification time of a new file must stay the same before it is
+    /// processed, so that files are not processed while they are still being written
+    pub settle: Duration,
+    /// Whether to also process the files that already exist when watching starts
+    pub existing: bool,
+    /// Where to serve the metrics of the watch over HTTP, if anywhere
+    pub metrics: Option<SocketAddr>,
+}
+
+/// The size and modification time of a file, which change while it is being written
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+struct FileState {
+    size: u64,
+    modified: Option<SystemTime>,
+}
+
+/// Reads the state of the path if it is a regular file
+async fn file_state(path: &Path) -> Option<FileState> {
+    let metadata = tokio::fs::metadata(path).await.ok()?;
+    metadata.is_file().then(|| FileState {
+        size: metadata.len(),
+        modified: metadata.modified().ok(),
+    })
+}
+
+/// Watches for files matching the patterns until the process is stopped, processing each new (or
+/// changed) file according to the settings once it has settled. Errors are reported as they
+/// happen and do not stop the watch.
+pub async fn watch(
+    mut settings: Settings,
+    patterns: Vec<OsString>,
+    options: WatchOptions,
+) -> Result<(), SuperGzipError> {
+    let metrics = match options.metrics {
+        Some(address) => {
+            let metrics = Arc::new(Metrics::default());
+            metrics::serve(Arc::clone(&metrics), address)?;
+            metrics.attach(&mut settings.hooks);
+            settings.report(&format!("Serving metrics on http://{}/metrics", address));
+            Some(metrics)
+        }
+        None => None,
+    };
+    let settings = Arc::new(settings);
+    // The files that were processed (or existed at the start), in the state they were in then
+    let mut handled: HashMap<PathBuf, FileState> = HashMap::new();
+    // The files waiting to settle, along with when they were first seen in their current state
+    let mut pending: HashMap<PathBuf, (FileState, Instant)> = HashMap::new();
+    let mut interval = tokio::time::interval(options.interval);
+    let mut first_scan = true;
+    loop {
+        // Stop watching once interrupted, after the files in progress are done
+        tokio::select! {
+            _ = interval.tick() => {}
+            () = interrupt::stop_requested() => return Ok(()),
+        }
+        let mut errors = Vec::new();
+        let paths = discover(&patterns, &settings, &mut errors).await;
+        for error in errors {
+            // The files being watched for need not exist yet
+            let missing = matches!(
+                error.inner(),
+                SuperGzipError::IO(e) if e.kind() == std::io::ErrorKind::NotFound
+            );
+            if !missing {
+                settings.report(&format!("Warning: {}", error));
+            }
+        }
+
+        let mut seen = HashSet::new();
+        let mut ready = Vec::new();
+        for path in paths {
+            if is_temp_path(&path) {
+                continue;
+            }
+            let Some(state) = file_state(&path).await else {
+                continue;
+            };
+            seen.insert(path.clone());
+            if first_scan && !options.existing {
+                handled.insert(path, state);
+                continue;
+            }
+            if handled.get(&path) == Some(&state) {
+                continue;
+            }
+            match pending.get(&path) {
+                Some((pending_state, since)) if *pending_state == state => {
+                    if since.elapsed() >= options.settle {
+                        pending.remove(&path);
+                        handled.insert(path.clone(), state);
+                        ready.push(path);
+                    }
+                }
+                _ => {
+                    pending.insert(path, (state, Instant::now()));
+                }
+            }
+        }
+        first_scan = false;
+
+        // Forget the files that are gone, so that new files with the same names are processed
+        handled.retain(|path, _| seen.contains(path));
+        pending.retain(|path, _| seen.contains(path));
+
+        if let Some(metrics) = &metrics {
+            metrics.set_queue_depth(pending.len() + ready.len());
+        }
+
+        // The errors of a batch have already been reported by the time it returns
+        if !ready.is_empty() {
+            let _ = process_paths(&settings, ready, Vec::new(), Instant::now()).await;
+            if let Some(metrics) = &metrics {
+                metrics.set_queue_depth(pending.len());
+            }
+        }
+    }
+}
@@ -0,0 +1,1019 @@
+//! A small reader and writer for ZIP archives with deflated entries.
+//!
+//! See <https://pkware.cachefly.net/webdocs/casestudies/APPNOTE.TXT> for the specification.
+//! Archives written here store each file's Unix permissions and UTC modification time (in an
+//! Info-ZIP extended timestamp field), and switch to ZIP64 records for files, archives, or entry
+//! counts too large for the original format, so Windows Explorer, Info-ZIP `unzip`, and
+//! Python's zipfile can all read them.
+
+use crate::error::{file_error, Operation};
+use flate2::read::DeflateDecoder;
+use flate2::write::DeflateEncoder;
+use flate2::{Compression, Crc};
+use std::fs::File;
+use std::io::{BufReader, BufWriter, Error, ErrorKind, Read, Result, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+
+const LOCAL_HEADER_SIGNATURE: u32 = 0x0403_4b50;
+const CENTRAL_HEADER_SIGNATURE: u32 = 0x0201_4b50;
+const END_SIGNATURE: u32 = 0x0605_4b50;
+const ZIP64_END_SIGNATURE: u32 = 0x0606_4b50;
+const ZIP64_LOCATOR_SIGNATURE: u32 = 0x0706_4b50;
+
+const STORED: u16 = 0;
+const DEFLATED: u16 = 8;
+/// The entry name is UTF-8
+const FLAG_UTF8: u16 = 1 << 11;
+const VERSION_DEFLATE: u16 = 20;
+const VERSION_ZIP64: u16 = 45;
+/// Made by a Unix system, so the upper external attributes hold the file mode
+const MADE_BY_UNIX: u16 = 3 << 8;
+
+const ZIP64_EXTRA: u16 = 0x0001;
+const TIMESTAMP_EXTRA: u16 = 0x5455;
+/// The MS-DOS attribute marking a directory
+const DOS_DIRECTORY: u32 = 0x10;
+/// Files at least this large get ZIP64 sizes, leaving headroom for deflate's worst case growth
+const ZIP64_THRESHOLD: u64 = 0xFFFF_0000;
+const MAX_U32: u64 = 0xFFFF_FFFF;
+const MAX_U16: usize = 0xFFFF;
+
+const S_IFMT: u32 = 0o170000;
+const S_IFDIR: u32 = 0o040000;
+const S_IFREG: u32 = 0o100000;
+
+fn invalid(message: &str) -> Error {
+    Error::new(
+        ErrorKind::InvalidData,
+        format!("invalid ZIP archive: {}", message),
+    )
+}
+
+fn u16_at(data: &[u8], pos: usize) -> u16 {
+    u16::from_le_bytes([data[pos], data[pos + 1]])
+}
+
+fn u32_at(data: &[u8], pos: usize) -> u32 {
+    u32::from_le_bytes(data[pos..pos + 4].try_into().unwrap())
+}
+
+fn u64_at(data: &[u8], pos: usize) -> u64 {
+    u64::from_le_bytes(data[pos..pos + 8].try_into().unwrap())
+}
+
+/// Converts seconds since the Unix epoch to an MS-DOS date and time, treating them as local time
+fn dos_datetime(mtime: u64) -> (u16, u16) {
+    let days = (mtime / 86400) as i64;
+    let seconds = mtime % 86400;
+    let (year, month, day) = civil_from_days(days);
+    if year < 1980 {
+        // The earliest time MS-DOS dates can represent
+        return (0, (1 << 5) | 1);
+    }
+    let time = ((seconds / 3600) << 11) | ((seconds / 60 % 60) << 5) | ((seconds % 60) / 2);
+    let date = (((year - 1980).min(127) as u64) << 9) | ((month as u64) << 5) | day as u64;
+    (time as u16, date as u16)
+}
+
+/// Converts an MS-DOS date and time to seconds since the Unix epoch
+fn from_dos_datetime(time: u16, date: u16) -> u64 {
+    let year = 1980 + (date >> 9) as i64;
+    let month = ((date >> 5) & 0xF).clamp(1, 12) as i64;
+    let day = (date & 0x1F).max(1) as i64;
+    let days = days_from_civil(year, month, day);
+    let seconds =
+        (time >> 11) as i64 * 3600 + ((time >> 5) & 0x3F) as i64 * 60 + (time & 0x1F) as i64 * 2;
+    (days * 86400 + seconds).max(0) as u64
+}
+
+/// Howard Hinnant's algorithm for converting days since the Unix epoch to a civil date
+fn civil_from_days(days: i64) -> (i64, u32, u32) {
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let day_of_era = z - era * 146097;
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let shifted_month = (5 * day_of_year + 2) / 153;
+    let day = (day_of_year - (153 * shifted_month + 2) / 5 + 1) as u32;
+    let month = if shifted_month < 10 {
+        shifted_month + 3
+    } else {
+        shifted_month - 9
+    } as u32;
+    let year = year_of_era + era * 400 + if month <= 2 { 1 } else { 0 };
+    (year, month, day)
+}
+
+/// The inverse of [`civil_from_days`]
+fn days_from_civil(year: i64, month: i64, day: i64) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year - era * 400;
+    let shifted_month = if month > 2 { month - 3 } else { month + 9 };
+    let day_of_year = (153 * shifted_month + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    era * 146097 + day_of_era - 719468
+}
+
+/// An entry to add to an archive
+#[derive(Clone, Debug)]
+pub struct NewEntry {
+    /// The file (or directory) to read the contents and metadata from
+    pub path: PathBuf,
+    /// The name inside the archive, using `/` as the separator and ending with `/` for
+    /// directories
+    pub name: String,
+}
+
+/// Lists the entries for the directory tree (or single file) at `root`, naming them relative to
+/// the parent of `root` like `zip -r` does, in name order. Symlinks are left out since ZIP has no
+/// portable way to store them, as are the paths in `exclude` (e.g. the archive itself).
+pub fn collect_tree(root: &Path, exclude: &[PathBuf], entries: &mut Vec<NewEntry>) -> Result<()> {
+    let root_name = root
+        .file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .ok_or_else(|| {
+            Error::new(
+                ErrorKind::InvalidInput,
+                format!("{} has no name to archive it under", root.display()),
+            )
+        })?;
+
+    let mut stack = vec![(root.to_path_buf(), root_name)];
+    while let Some((path, mut name)) = stack.pop() {
+        if exclude.contains(&path) {
+            continue;
+        }
+        let metadata =
+            std::fs::symlink_metadata(&path).map_err(file_error(&path, Operation::Read))?;
+        if metadata.is_dir() {
+            name.push('/');
+            // Push the children in reverse so that they are popped in name order
+            let mut children = std::fs::read_dir(&path)
+                .and_then(|read_dir| {
+                    read_dir
+                        .map(|child| child.map(|child| child.file_name()))
+                        .collect::<Result<Vec<_>>>()
+                })
+                .map_err(file_error(&path, Operation::Read))?;
+            children.sort();
+            for child in children.into_iter().rev() {
+                let child_name = format!("{}{}", name, child.to_string_lossy());
+                stack.push((path.join(child), child_name));
+            }
+        } else if !metadata.is_file() {
+            continue;
+        }
+        entries.push(NewEntry { path, name });
+    }
+    Ok(())
+}
+
+/// The metadata of an entry that was written, kept for the central directory
+struct Written {
+    name: String,
+    crc: u32,
+    compressed_size: u64,
+    size: u64,
+    offset: u64,
+    mode: u32,
+    mtime: u64,
+    is_dir: bool,
+}
+
+impl Written {
+    /// Directories have no contents to compress
+    fn method(&self) -> u16 {
+        if self.is_dir {
+            STORED
+        } else {
+            DEFLATED
+        }
+    }
+}
+
+/// Counts the bytes written through it, to know the offset of every record
+struct CountingWriter<W> {
+    inner: W,
+    count: u64,
+}
+
+impl<W: Write> Write for CountingWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> Result<usize> {
+        let written = self.inner.write(buf)?;
+        self.count += written as u64;
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// Reads and checksums the data passing through it
+struct CrcReader<R> {
+    inner: R,
+    crc: Crc,
+}
+
+impl<R: Read> Read for CrcReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
+        let read = self.inner.read(buf)?;
+        self.crc.update(&buf[..read]);
+        Ok(read)
+    }
+}
+
+fn timestamp_extra(mtime: u64) -> Vec<u8> {
+    let mut extra = Vec::with_capacity(9);
+    extra.extend_from_slice(&TIMESTAMP_EXTRA.to_le_bytes());
+    extra.extend_from_slice(&5u16.to_le_bytes());
+    // Only the modification time is present
+    extra.push(1);
+    extra.extend_from_slice(&(mtime.min(u32::MAX as u64) as u32).to_le_bytes());
+    extra
+}
+
+/// Writes the local header of an entry, whose checksum and sizes are filled in afterwards
+fn write_local_header<W: Write>(writer: &mut W, entry: &Written, zip64: bool) -> Result<()> {
+    let (time, date) = dos_datetime(entry.mtime);
+    let mut extra = timestamp_extra(entry.mtime);
+    if zip64 {
+        extra.extend_from_slice(&ZIP64_EXTRA.to_le_bytes());
+        extra.extend_from_slice(&16u16.to_le_bytes());
+        extra.extend_from_slice(&entry.size.to_le_bytes());
+        extra.extend_from_slice(&entry.compressed_size.to_le_bytes());
+    }
+    let (size, compressed_size) = if zip64 {
+        (MAX_U32 as u32, MAX_U32 as u32)
+    } else {
+        (entry.size as u32, entry.compressed_size as u32)
+    };
+
+    writer.write_all(&LOCAL_HEADER_SIGNATURE.to_le_bytes())?;
+    writer.write_all(
+        &(if zip64 {
+            VERSION_ZIP64
+        } else {
+            VERSION_DEFLATE
+        })
+        .to_le_bytes(),
+    )?;
+    writer.write_all(&FLAG_UTF8.to_le_bytes())?;
+    writer.write_all(&entry.method().to_le_bytes())?;
+    writer.write_all(&time.to_le_bytes())?;
+    writer.write_all(&date.to_le_bytes())?;
+    writer.write_all(&entry.crc.to_le_bytes())?;
+    writer.write_all(&compressed_size.to_le_bytes())?;
+    writer.write_all(&size.to_le_bytes())?;
+    writer.write_all(&(entry.name.len() as u16).to_le_bytes())?;
+    writer.write_all(&(extra.len() as u16).to_le_bytes())?;
+    writer.write_all(entry.name.as_bytes())?;
+    writer.write_all(&extra)
+}
+
+fn write_central_header<W: Write>(writer: &mut W, entry: &Written) -> Result<()> {
+    let (time, date) = dos_datetime(entry.mtime);
+    let mut extra = timestamp_extra(entry.mtime);
+    let mut zip64 = Vec::new();
+    let field = |value: u64, zip64: &mut Vec<u8>| {
+        if value >= MAX_U32 {
+            zip64.extend_from_slice(&value.to_le_bytes());
+            MAX_U32 as u32
+        } else {
+            value as u32
+        }
+    };
+    let size = field(entry.size, &mut zip64);
+    let compressed_size = field(entry.compressed_size, &mut zip64);
+    let offset = field(entry.offset, &mut zip64);
+    if !zip64.is_empty() {
+        extra.extend_from_slice(&ZIP64_EXTRA.to_le_bytes());
+        extra.extend_from_slice(&(zip64.len() as u16).to_le_bytes());
+        extra.extend_from_slice(&zip64);
+    }
+    let version = if zip64.is_empty() {
+        VERSION_DEFLATE
+    } else {
+        VERSION_ZIP64
+    };
+
+    writer.write_all(&CENTRAL_HEADER_SIGNATURE.to_le_bytes())?;
+    writer.write_all(&(MADE_BY_UNIX | version).to_le_bytes())?;
+    writer.write_all(&version.to_le_bytes())?;
+    writer.write_all(&FLAG_UTF8.to_le_bytes())?;
+    writer.write_all(&entry.method().to_le_bytes())?;
+    writer.write_all(&time.to_le_bytes())?;
+    writer.write_all(&date.to_le_bytes())?;
+    writer.write_all(&entry.crc.to_le_bytes())?;
+    writer.write_all(&compressed_size.to_le_bytes())?;
+    writer.write_all(&size.to_le_bytes())?;
+    writer.write_all(&(entry.name.len() as u16).to_le_bytes())?;
+    writer.write_all(&(extra.len() as u16).to_le_bytes())?;
+    // No comment, starting on disk 0, no internal attributes
+    writer.write_all(&[0; 6])?;
+    let attributes = if entry.is_dir {
+        ((S_IFDIR | (entry.mode & 0o7777)) << 16) | DOS_DIRECTORY
+    } else {
+        (S_IFREG | (entry.mode & 0o7777)) << 16
+    };
+    writer.write_all(&attributes.to_le_bytes())?;
+    writer.write_all(&offset.to_le_bytes())?;
+    writer.write_all(entry.name.as_bytes())?;
+    writer.write_all(&extra)
+}
+
+fn write_end_records<W: Write>(
+    writer: &mut W,
+    entries: usize,
+    directory_offset: u64,
+    directory_size: u64,
+) -> Result<()> {
+    let zip64 = entries >= MAX_U16 || directory_offset >= MAX_U32 || directory_size >= MAX_U32;
+    if zip64 {
+        let end_offset = directory_offset + directory_size;
+        writer.write_all(&ZIP64_END_SIGNATURE.to_le_bytes())?;
+        // The size of the rest of this record
+        writer.write_all(&44u64.to_le_bytes())?;
+        writer.write_all(&(MADE_BY_UNIX | VERSION_ZIP64).to_le_bytes())?;
+        writer.write_all(&VERSION_ZIP64.to_le_bytes())?;
+        writer.write_all(&[0; 8])?;
+        writer.write_all(&(entries as u64).to_le_bytes())?;
+        writer.write_all(&(entries as u64).to_le_bytes())?;
+        writer.write_all(&directory_size.to_le_bytes())?;
+        writer.write_all(&directory_offset.to_le_bytes())?;
+
+        writer.write_all(&ZIP64_LOCATOR_SIGNATURE.to_le_bytes())?;
+        writer.write_all(&0u32.to_le_bytes())?;
+        writer.write_all(&end_offset.to_le_bytes())?;
+        writer.write_all(&1u32.to_le_bytes())?;
+    }
+
+    let entries = entries.min(MAX_U16) as u16;
+    writer.write_all(&END_SIGNATURE.to_le_bytes())?;
+    writer.write_all(&[0; 4])?;
+    writer.write_all(&entries.to_le_bytes())?;
+    writer.write_all(&entries.to_le_bytes())?;
+    writer.write_all(&(directory_size.min(MAX_U32) as u32).to_le_bytes())?;
+    writer.write_all(&(directory_offset.min(MAX_U32) as u32).to_le_bytes())?;
+    // No comment
+    writer.write_all(&[0; 2])
+}
+
+/// What went into an archive
+#[derive(Debug, Default)]
+pub struct ZipSummary {
+    pub entries: usize,
+    /// The total uncompressed size of the entries
+    pub size: u64,
+    /// The total compressed size of the entries
+    pub compressed_size: u64,
+}
+
+/// Writes a ZIP archive holding the given files to the output, deflating each at the given level
+/// (1 to 9). `on_entry` is called with the name of each entry as it is added.
+pub fn write_zip(
+    entries: &[NewEntry],
+    output: &Path,
+    level: Option<u32>,
+    mut on_entry: impl FnMut(&str),
+) -> Result<ZipSummary> {
+    let compression = level.map_or(Compression::default(), Compression::new);
+    let mut file = File::create(output)?;
+    let mut written = Vec::with_capacity(entries.len());
+    let mut summary = ZipSummary::default();
+    let mut offset = 0;
+    for new_entry in entries {
+        if new_entry.name.len() > MAX_U16 {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                format!("{} has too long a name for a ZIP archive", new_entry.name),
+            ));
+        }
+        let metadata = std::fs::metadata(&new_entry.path)
+            .map_err(file_error(&new_entry.path, Operation::Read))?;
+        #[cfg(unix)]
+        let mode = std::os::unix::fs::PermissionsExt::mode(&metadata.permissions());
+        #[cfg(not(unix))]
+        let mode = if metadata.is_dir() { 0o755 } else { 0o644 };
+        let mut entry = Written {
+            name: new_entry.name.clone(),
+            crc: 0,
+            compressed_size: 0,
+            size: 0,
+            offset,
+            mode,
+            mtime: metadata
+                .modified()
+                .ok()
+                .and_then(|modified| modified.duration_since(std::time::UNIX_EPOCH).ok())
+                .map_or(0, |duration| duration.as_secs()),
+            is_dir: metadata.is_dir(),
+        };
+
+        let mut writer = CountingWriter {
+            inner: BufWriter::new(&mut file),
+            count: 0,
+        };
+        if entry.is_dir {
+            write_local_header(&mut writer, &entry, false)?;
+            writer.flush()?;
+            offset += writer.count;
+        } else {
+            // Write a placeholder header, stream the deflated contents, then fill in the header
+            entry.size = metadata.len();
+            let zip64 = entry.size >= ZIP64_THRESHOLD;
+            write_local_header(&mut writer, &entry, zip64)?;
+            let header_size = writer.count;
+            let mut reader = CrcReader {
+                inner: BufReader::new(
+                    File::open(&new_entry.path)
+                        .map_err(file_error(&new_entry.path, Operation::Read))?,
+                ),
+                crc: Crc::new(),
+            };
+            let mut encoder = DeflateEncoder::new(writer, compression);
+            let size = std::io::copy(&mut reader, &mut encoder)?;
+            let mut writer = encoder.finish()?;
+            writer.flush()?;
+            entry.crc = reader.crc.sum();
+            entry.compressed_size = writer.count - header_size;
+            if size != entry.size || (!zip64 && entry.compressed_size >= MAX_U32) {
+                return Err(Error::other(format!(
+                    "{} changed while it was being archived",
+                    new_entry.path.display()
+                )));
+            }
+            let entry_size = writer.count;
+            drop(writer);
+            file.seek(SeekFrom::Start(offset))?;
+            write_local_header(&mut file, &entry, zip64)?;
+            offset += entry_size;
+            file.seek(SeekFrom::Start(offset))?;
+        }
+
+        on_entry(&entry.name);
+        summary.entries += 1;
+        summary.size += entry.size;
+        summary.compressed_size += entry.compressed_size;
+        written.push(entry);
+    }
+
+    let mut writer = CountingWriter {
+        inner: BufWriter::new(&mut file),
+        count: 0,
+    };
+    for entry in &written {
+        write_central_header(&mut writer, entry)?;
+    }
+    let directory_size = writer.count;
+    write_end_records(&mut writer, written.len(), offset, directory_size)?;
+    writer.flush()?;
+    Ok(summary)
+}
+
+/// An entry of an existing archive, as described by its central directory
+#[derive(Clone, Debug)]
+pub struct Entry {
+    pub name: String,
+    pub is_dir: bool,
+    /// Whether the entry is a file (or directory) rather than e.g. a symlink
+    pub is_supported: bool,
+    pub method: u16,
+    pub crc: u32,
+    pub compressed_size: u64,
+    pub size: u64,
+    /// The Unix permissions, if the archive was made on Unix
+    pub mode: Option<u32>,
+    pub mtime: u64,
+    local_header_offset: u64,
+}
+
+/// Finds the end of central directory record, which is followed by a comment of up to 64 KiB
+fn find_end_record(file: &mut File) -> Result<(u64, Vec<u8>)> {
+    let file_size = file.seek(SeekFrom::End(0))?;
+    let search_size = file_size.min(22 + MAX_U16 as u64);
+    file.seek(SeekFrom::Start(file_size - search_size))?;
+    let mut tail = vec![0; search_size as usize];
+    file.read_exact(&mut tail)?;
+    let position = (0..tail.len().saturating_sub(21))
+        .rev()
+        .find(|&pos| u32_at(&tail, pos) == END_SIGNATURE)
+        .ok_or_else(|| invalid("no end of central directory record"))?;
+    Ok((
+        file_size - search_size + position as u64,
+        tail[position..].to_vec(),
+    ))
+}
+
+/// Reads the central directory of the archive
+pub fn read_entries(file: &mut File) -> Result<Vec<Entry>> {
+    let (end_offset, end) = find_end_record(file)?;
+    let mut count = u16_at(&end, 10) as u64;
+    let mut directory_size = u32_at(&end, 12) as u64;
+    let mut directory_offset = u32_at(&end, 16) as u64;
+
+    // ZIP64 archives keep the real values in a separate record found through a locator
+    if end_offset >= 20 {
+        let mut locator = [0; 20];
+        file.seek(SeekFrom::Start(end_offset - 20))?;
+        file.read_exact(&mut locator)?;
+        if u32_at(&locator, 0) == ZIP64_LOCATOR_SIGNATURE {
+            let mut record = [0; 56];
+            file.seek(SeekFrom::Start(u64_at(&locator, 8)))?;
+            file.read_exact(&mut record)?;
+            if u32_at(&record, 0) != ZIP64_END_SIGNATURE {
+                return Err(invalid("bad ZIP64 end of central directory record"));
+            }
+            count = u64_at(&record, 32);
+            directory_size = u64_at(&record, 40);
+            directory_offset = u64_at(&record, 48);
+        }
+    }
+
+    let mut directory = Vec::new();
+    file.seek(SeekFrom::Start(directory_offset))?;
+    file.take(directory_size).read_to_end(&mut directory)?;
+    let mut entries = Vec::new();
+    let mut pos = 0;
+    for _ in 0..count {
+        if directory.len() < pos + 46 || u32_at(&directory, pos) != CENTRAL_HEADER_SIGNATURE {
+            return Err(invalid("bad central directory entry"));
+        }
+        let header = &directory[pos..];
+        let name_len = u16_at(header, 28) as usize;
+        let extra_len = u16_at(header, 30) as usize;
+        let comment_len = u16_at(header, 32) as usize;
+        if header.len() < 46 + name_len + extra_len + comment_len {
+            return Err(invalid("bad central directory entry"));
+        }
+        let name = String::from_utf8_lossy(&header[46..46 + name_len]).into_owned();
+        let extra = &header[46 + name_len..46 + name_len + extra_len];
+
+        let mut size = u32_at(header, 24) as u64;
+        let mut compressed_size = u32_at(header, 20) as u64;
+        let mut local_header_offset = u32_at(header, 42) as u64;
+        let mut mtime = from_dos_datetime(u16_at(header, 12), u16_at(header, 14));
+        let mut extra_pos = 0;
+        while extra_pos + 4 <= extra.len() {
+            let id = u16_at(extra, extra_pos);
+            let len = u16_at(extra, extra_pos + 2) as usize;
+            let data = &extra[extra_pos + 4..(extra_pos + 4 + len).min(extra.len())];
+            match id {
+                ZIP64_EXTRA => {
+                    // Only the fields that overflowed are present, in this order
+                    let mut fields = data.chunks_exact(8).map(|field| u64_at(field, 0));
+                    for value in [&mut size, &mut compressed_size, &mut local_header_offset] {
+                        if *value == MAX_U32 {
+                            *value = fields.next().ok_or_else(|| invalid("bad ZIP64 field"))?;
+                        }
+                    }
+                }
+                TIMESTAMP_EXTRA if data.len() >= 5 && data[0] & 1 != 0 => {
+                    mtime = u32_at(data, 1) as u64;
+                }
+                _ => {}
+            }
+            extra_pos += 4 + len;
+        }
+
+        let made_by_unix = u16_at(header, 4) >> 8 == 3;
+        let unix_mode = u32_at(header, 38) >> 16;
+        let is_dir = name.ends_with('/') || (made_by_unix && unix_mode & S_IFMT == S_IFDIR);
+        let is_supported = !made_by_unix
+            || unix_mode & S_IFMT == 0
+            || matches!(unix_mode & S_IFMT, S_IFREG | S_IFDIR);
+        entries.push(Entry {
+            name,
+            is_dir,
+            is_supported,
+            method: u16_at(header, 10),
+            crc: u32_at(header, 16),
+            compressed_size,
+            size,
+            mode: (made_by_unix && unix_mode != 0).then_some(unix_mode & 0o7777),
+            mtime,
+            local_header_offset,
+        });
+        pos += 46 + name_len + extra_len + comment_len;
+    }
+    Ok(entries)
+}
+
+/// Decompresses an entry's contents into the writer, checking its size and checksum
+pub fn extract_entry<W: Write>(file: &mut File, entry: &Entry, writer: &mut W) -> Result<u64> {
+    let mut header = [0; 30];
+    file.seek(SeekFrom::Start(entry.local_header_offset))?;
+    file.read_exact(&mut header)?;
+    if u32_at(&header, 0) != LOCAL_HEADER_SIGNATURE {
+        return Err(invalid("bad local file header"));
+    }
+    let data_offset =
+        entry.local_header_offset + 30 + u16_at(&header, 26) as u64 + u16_at(&header, 28) as u64;
+    file.seek(SeekFrom::Start(data_offset))?;
+
+    let compressed = BufReader::new(file.take(entry.compressed_size));
+    let mut reader = CrcReader {
+        inner: match entry.method {
+            STORED => Box::new(compressed) as Box<dyn Read>,
+            DEFLATED => Box::new(DeflateDecoder::new(compressed)),
+            _ => {
+                return Err(Error::new(
+                    ErrorKind::Unsupported,
+                    format!(
+                        "{} uses an unsupported compression method ({})",
+                        entry.name, entry.method
+                    ),
+                ))
+            }
+        },
+        crc: Crc::new(),
+    };
+    let size = std::io::copy(&mut reader, writer)?;
+    if size != entry.size || reader.crc.sum() != entry.crc {
+        return Err(invalid(&format!("{} is corrupt", entry.name)));
+    }
+    Ok(size)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const S_IFLNK: u32 = 0o120000;
+
+    /// An entry of an archive built byte by byte, the way other writers may lay it out
+    struct Raw<'a> {
+        name: &'a str,
+        contents: &'a [u8],
+        method: u16,
+        /// Leaves the checksum and sizes out of the local header and puts them after the data
+        descriptor: bool,
+        mode: u32,
+    }
+
+    impl<'a> Raw<'a> {
+        fn file(name: &'a str, contents: &'a [u8]) -> Raw<'a> {
+            Raw {
+                name,
+                contents,
+                method: DEFLATED,
+                descriptor: false,
+                mode: S_IFREG | 0o644,
+            }
+        }
+    }
+
+    /// Lays out an archive of the entries, with ZIP64 sizes, offsets, and end records if asked to
+    fn raw_zip(entries: &[Raw], zip64: bool) -> Vec<u8> {
+        let mut out = Vec::new();
+        let mut directory = Vec::new();
+        for entry in entries {
+            let offset = out.len() as u64;
+            let data = match entry.method {
+                DEFLATED => {
+                    let mut encoder = DeflateEncoder::new(Vec::new(), Compression::default());
+                    encoder.write_all(entry.contents).unwrap();
+                    encoder.finish().unwrap()
+                }
+                _ => entry.contents.to_vec(),
+            };
+            let mut crc = Crc::new();
+            crc.update(entry.contents);
+            let (crc, size, compressed_size) =
+                (crc.sum(), entry.contents.len() as u32, data.len() as u32);
+            let flags = FLAG_UTF8 | if entry.descriptor { 1 << 3 } else { 0 };
+
+            out.extend(LOCAL_HEADER_SIGNATURE.to_le_bytes());
+            out.extend(VERSION_DEFLATE.to_le_bytes());
+            out.extend(flags.to_le_bytes());
+            out.extend(entry.method.to_le_bytes());
+            out.extend([0, 0, 0x21, 0]);
+            for value in [crc, compressed_size, size] {
+                out.extend(if entry.descriptor { 0 } else { value }.to_le_bytes());
+            }
+            out.extend((entry.name.len() as u16).to_le_bytes());
+            out.extend(0u16.to_le_bytes());
+            out.extend(entry.name.as_bytes());
+            out.extend(&data);
+            if entry.descriptor {
+                out.extend(0x0807_4b50u32.to_le_bytes());
+                out.extend(crc.to_le_bytes());
+                out.extend(compressed_size.to_le_bytes());
+                out.extend(size.to_le_bytes());
+            }
+
+            let mut extra = Vec::new();
+            if zip64 {
+                extra.extend(ZIP64_EXTRA.to_le_bytes());
+                extra.extend(24u16.to_le_bytes());
+                extra.extend((size as u64).to_le_bytes());
+                extra.extend((compressed_size as u64).to_le_bytes());
+                extra.extend(offset.to_le_bytes());
+            }
+            let overflowed = |value: u32| if zip64 { MAX_U32 as u32 } else { value };
+            directory.extend(CENTRAL_HEADER_SIGNATURE.to_le_bytes());
+            directory.extend((MADE_BY_UNIX | VERSION_ZIP64).to_le_bytes());
+            directory.extend(VERSION_ZIP64.to_le_bytes());
+            directory.extend(flags.to_le_bytes());
+            directory.extend(entry.method.to_le_bytes());
+            directory.extend([0, 0, 0x21, 0]);
+            directory.extend(crc.to_le_bytes());
+            directory.extend(overflowed(compressed_size).to_le_bytes());
+            directory.extend(overflowed(size).to_le_bytes());
+            directory.extend((entry.name.len() as u16).to_le_bytes());
+            directory.extend((extra.len() as u16).to_le_bytes());
+            directory.extend([0; 6]);
+            directory.extend((entry.mode << 16).to_le_bytes());
+            directory.extend(overflowed(offset as u32).to_le_bytes());
+            directory.extend(entry.name.as_bytes());
+            directory.extend(extra);
+        }
+
+        let (directory_offset, directory_size) = (out.len() as u64, directory.len() as u64);
+        out.extend(directory);
+        if zip64 {
+            let end_offset = out.len() as u64;
+            out.extend(ZIP64_END_SIGNATURE.to_le_bytes());
+            out.extend(44u64.to_le_bytes());
+            out.extend([VERSION_ZIP64 as u8, 3, VERSION_ZIP64 as u8, 0]);
+            out.extend([0; 8]);
+            out.extend((entries.len() as u64).to_le_bytes());
+            out.extend((entries.len() as u64).to_le_bytes());
+            out.extend(directory_size.to_le_bytes());
+            out.extend(directory_offset.to_le_bytes());
+            out.extend(ZIP64_LOCATOR_SIGNATURE.to_le_bytes());
+            out.extend(0u32.to_le_bytes());
+            out.extend(end_offset.to_le_bytes());
+            out.extend(1u32.to_le_bytes());
+        }
+        let count = if zip64 { MAX_U16 } else { entries.len() } as u16;
+        let overflowed = |value: u64| if zip64 { MAX_U32 } else { value } as u32;
+        out.extend(END_SIGNATURE.to_le_bytes());
+        out.extend([0; 4]);
+        out.extend(count.to_le_bytes());
+        out.extend(count.to_le_bytes());
+        out.extend(overflowed(directory_size).to_le_bytes());
+        out.extend(overflowed(directory_offset).to_le_bytes());
+        out.extend([0; 2]);
+        out
+    }
+
+    /// A path for a test file that is unique to this process
+    fn temp_file(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("super-gunzip-zip-{}-{}", name, std::process::id()))
+    }
+
+    /// Reads the entries of an archive and the contents of each
+    fn read_all(archive: &[u8], name: &str) -> Result<Vec<(Entry, Vec<u8>)>> {
+        let path = temp_file(name);
+        std::fs::write(&path, archive).unwrap();
+        let mut file = File::open(&path).unwrap();
+        let read = read_entries(&mut file).and_then(|entries| {
+            entries
+                .into_iter()
+                .map(|entry| {
+                    let mut contents = Vec::new();
+                    extract_entry(&mut file, &entry, &mut contents)?;
+                    Ok((entry, contents))
+                })
+                .collect()
+        });
+        std::fs::remove_file(&path).unwrap();
+        read
+    }
+
+    #[test]
+    fn dos_times_round_trip() {
+        let round_trip = |mtime| {
+            let (time, date) = dos_datetime(mtime);
+            from_dos_datetime(time, date)
+        };
+        // 2024-02-29 13:37:42 UTC, and a second later, which MS-DOS times cannot hold
+        assert_eq!(round_trip(1_709_213_862), 1_709_213_862);
+        assert_eq!(round_trip(1_709_213_863), 1_709_213_862);
+        assert_eq!(civil_from_days(19_782), (2024, 2, 29));
+        for days in [0, 3652, 10_957, 19_782, 47_481] {
+            let (year, month, day) = civil_from_days(days);
+            assert_eq!(days_from_civil(year, month as i64, day as i64), days);
+        }
+        // Times before 1980 are clamped to the earliest date
+        assert_eq!(round_trip(0), 315_532_800);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn archives_round_trip() {
+        use std::os::unix::fs::PermissionsExt;
+        let directory = temp_file("tree");
+        let root = directory.join("tree");
+        std::fs::create_dir_all(root.join("empty")).unwrap();
+        std::fs::create_dir_all(root.join("sub")).unwrap();
+        let text = "a line of text that repeats\n".repeat(5000);
+        std::fs::write(root.join("sub/text.txt"), &text).unwrap();
+        std::fs::write(root.join("nothing"), "").unwrap();
+        std::fs::write(root.join("script.sh"), "#!/bin/sh\n").unwrap();
+        std::fs::set_permissions(
+            root.join("script.sh"),
+            std::fs::Permissions::from_mode(0o750),
+        )
+        .unwrap();
+        let mtime = std::time::UNIX_EPOCH + std::time::Duration::from_secs(1_600_000_001);
+        File::options()
+            .write(true)
+            .open(root.join("nothing"))
+            .unwrap()
+            .set_modified(mtime)
+            .unwrap();
+        std::os::unix::fs::symlink("sub/text.txt", root.join("link")).unwrap();
+        std::fs::write(root.join("excluded"), "").unwrap();
+
+        let mut entries = Vec::new();
+        collect_tree(&root, &[root.join("excluded")], &mut entries).unwrap();
+        let names: Vec<_> = entries.iter().map(|entry| entry.name.as_str()).collect();
+        assert_eq!(
+            names,
+            [
+                "tree/",
+                "tree/empty/",
+                "tree/nothing",
+                "tree/script.sh",
+                "tree/sub/",
+                "tree/sub/text.txt"
+            ]
+        );
+        let archive = directory.join("tree.zip");
+        let mut added = Vec::new();
+        let summary = write_zip(&entries, &archive, Some(9), |name| {
+            added.push(name.to_string())
+        })
+        .unwrap();
+        assert_eq!(added, names);
+        assert_eq!(summary.entries, 6);
+        assert_eq!(summary.size, text.len() as u64 + 10);
+        assert!(summary.compressed_size < summary.size / 10);
+
+        let read = read_all(&std::fs::read(&archive).unwrap(), "round-trip").unwrap();
+        std::fs::remove_dir_all(&directory).unwrap();
+        let find = |name: &str| read.iter().find(|(entry, _)| entry.name == name).unwrap();
+        assert!(find("tree/empty/").0.is_dir);
+        assert_eq!(find("tree/sub/text.txt").1, text.as_bytes());
+        assert_eq!(find("tree/sub/text.txt").0.method, DEFLATED);
+        assert_eq!(find("tree/script.sh").0.mode, Some(0o750));
+        assert_eq!(find("tree/nothing").0.mtime, 1_600_000_001);
+        assert!(find("tree/nothing").1.is_empty());
+        assert!(read.iter().all(|(entry, _)| entry.is_supported));
+    }
+
+    #[test]
+    fn data_descriptors_are_read() {
+        let entries = [
+            Raw {
+                descriptor: true,
+                ..Raw::file("deflated.txt", b"deflated deflated deflated")
+            },
+            Raw {
+                descriptor: true,
+                method: STORED,
+                ..Raw::file("stored.txt", b"stored")
+            },
+            Raw::file("plain.txt", b"plain"),
+        ];
+        let read = read_all(&raw_zip(&entries, false), "descriptor").unwrap();
+        let contents: Vec<_> = read
+            .iter()
+            .map(|(entry, contents)| (entry.name.as_str(), contents.as_slice()))
+            .collect();
+        assert_eq!(
+            contents,
+            [
+                ("deflated.txt", &b"deflated deflated deflated"[..]),
+                ("stored.txt", b"stored"),
+                ("plain.txt", b"plain")
+            ]
+        );
+        assert_eq!(read[1].0.method, STORED);
+    }
+
+    #[test]
+    fn zip64_records_are_read() {
+        let entries = [Raw::file("a.txt", b"first"), Raw::file("b.txt", b"second")];
+        let read = read_all(&raw_zip(&entries, true), "zip64").unwrap();
+        assert_eq!(read.len(), 2);
+        assert_eq!((read[1].0.name.as_str(), read[1].0.size), ("b.txt", 6));
+        assert_eq!(read[1].1, b"second");
+
+        // The writer switches to ZIP64 end records when the entries do not fit in 16 bits
+        let count = MAX_U16 + 1;
+        let mut archive = Vec::new();
+        for index in 0..count {
+            let entry = Written {
+                name: format!("{}/", index),
+                crc: 0,
+                compressed_size: 0,
+                size: 0,
+                offset: 0,
+                mode: 0o755,
+                mtime: 0,
+                is_dir: true,
+            };
+            write_central_header(&mut archive, &entry).unwrap();
+        }
+        let directory_size = archive.len() as u64;
+        write_end_records(&mut archive, count, 0, directory_size).unwrap();
+        let path = temp_file("many");
+        std::fs::write(&path, &archive).unwrap();
+        let read = read_entries(&mut File::open(&path).unwrap()).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(read.len(), count);
+        assert_eq!(read[count - 1].name, format!("{}/", count - 1));
+    }
+
+    #[test]
+    fn damaged_archives_are_errors() {
+        let contents = b"contents that deflate well, contents that deflate well";
+        let good = raw_zip(&[Raw::file("a.txt", contents)], false);
+        let message = |archive: &[u8]| read_all(archive, "damaged").unwrap_err().to_string();
+
+        assert!(message(&good[..good.len() - 22]).ends_with("no end of central directory record"));
+        assert!(message(b"").ends_with("no end of central directory record"));
+        let mut count = good.clone();
+        let end = count.len() - 22;
+        count[end + 10] = 2;
+        assert!(message(&count).ends_with("bad central directory entry"));
+        let mut crc = good.clone();
+        let directory = good.len() - 22 - 46 - 5;
+        crc[directory + 16] ^= 1;
+        assert!(message(&crc).ends_with("a.txt is corrupt"));
+        let mut offset = good.clone();
+        offset[directory + 42] = 1;
+        assert!(message(&offset).ends_with("bad local file header"));
+
+        let unsupported = raw_zip(
+            &[Raw {
+                method: 12,
+                ..Raw::file("b.bz2", b"")
+            }],
+            false,
+        );
+        let path = temp_file("unsupported");
+        std::fs::write(&path, unsupported).unwrap();
+        let mut file = File::open(&path).unwrap();
+        let entries = read_entries(&mut file).unwrap();
+        let error = extract_entry(&mut file, &entries[0], &mut Vec::new()).unwrap_err();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(error.kind(), ErrorKind::Unsupported);
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn hostile_entry_names_are_skipped() {
+        use crate::codec::Codec;
+        use crate::settings::Settings;
+        use std::sync::Arc;
+
+        let directory = temp_file("hostile");
+        let dest = directory.join("dest");
+        std::fs::create_dir_all(&directory).unwrap();
+        let entries = [
+            Raw::file("ok.txt", b"ok"),
+            Raw::file("../escaped.txt", b"escaped"),
+            Raw::file("sub/../../escaped.txt", b"escaped"),
+            Raw::file("/absolute.txt", b"absolute"),
+            Raw {
+                mode: S_IFLNK | 0o777,
+                ..Raw::file("link", b"/etc")
+            },
+            Raw {
+                mode: S_IFLNK | 0o777,
+                ..Raw::file("up", b"..")
+            },
+            Raw::file("link/passwd", b"planted"),
+            Raw {
+                mode: S_IFDIR | 0o755,
+                ..Raw::file("dir/", b"")
+            },
+        ];
+        let archive = directory.join("hostile.zip");
+        std::fs::write(&archive, raw_zip(&entries, false)).unwrap();
+        let settings = Arc::new(Settings::new(Codec::Gzip, false));
+        let summary = crate::archive::extract_zip(&archive, &dest, &settings)
+            .await
+            .unwrap();
+
+        assert_eq!(std::fs::read(dest.join("ok.txt")).unwrap(), b"ok");
+        assert_eq!(
+            std::fs::read(dest.join("absolute.txt")).unwrap(),
+            b"absolute"
+        );
+        assert!(dest.join("dir").is_dir());
+        assert!(!directory.join("escaped.txt").exists());
+        // Symlinks are not created, so nothing can be written through them
+        assert!(std::fs::symlink_metadata(dest.join("up")).is_err());
+        assert!(dest.join("link").symlink_metadata().unwrap().is_dir());
+        assert_eq!(std::fs::read(dest.join("link/passwd")).unwrap(), b"planted");
+        assert_eq!((summary.entries, summary.skipped), (4, 4));
+        std::fs::remove_dir_all(&directory).unwrap();
+    }
+}